@@ -18,6 +18,7 @@ use futures_util::StreamExt;
 use reqwest::header::{HeaderValue, AUTHORIZATION};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Row};
+use std::collections::HashSet;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
@@ -66,7 +67,7 @@ pub struct SelectedServer {
     pub auth: AuthMethod,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transcoding {
     pub enabled: bool,
     pub bitrate: u32,
@@ -526,7 +527,7 @@ impl Client {
                 ("SortOrder", "Ascending"),
                 ("Recursive", "true"),
                 ("ImageTypeLimit", "1"),
-                ("Fields", "DateCreated"),
+                ("Fields", "DateCreated,ProviderIds"),
                 ("StartIndex", "0"),
             ]);
 
@@ -590,7 +591,7 @@ impl Client {
                         ("SortOrder", "Ascending"),
                         ("Recursive", "true"),
                         ("IncludeItemTypes", "MusicAlbum"),
-                        ("Fields", "DateCreated,ParentId,ProductionYear,PremiereDate"),
+                        ("Fields", "DateCreated,ParentId,ProductionYear,PremiereDate,ProviderIds"),
                         ("StartIndex", &start_index.to_string()),
                         ("Limit", &limit.to_string()),
                     ]);
@@ -868,6 +869,78 @@ impl Client {
         Ok(songs)
     }
 
+    /// Returns random tracks tagged with the given genre, used as a radio seed
+    ///
+    pub async fn genre_tracks(
+        &self,
+        genre: &str,
+        tracks_n: usize,
+    ) -> Result<Vec<DiscographySong>, Box<dyn Error>> {
+        let url = format!("{}/Users/{}/Items", self.base_url, self.user_id);
+
+        let limit_str = tracks_n.to_string();
+        let req = self
+            .http_client
+            .get(&url)
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .header("Content-Type", "application/json")
+            .query(&[
+                ("SortBy", "Random"),
+                ("Recursive", "true"),
+                ("Fields", "Genres, DateCreated, MediaSources, ParentId, ProviderIds"),
+                ("IncludeItemTypes", "Audio"),
+                ("Genres", genre),
+                ("Limit", &limit_str),
+            ]);
+
+        let discog: Discography = match self.get_json_with_retry(req).await {
+            Ok(d) => d,
+            Err(e) => {
+                log::error!("Genre tracks request failed: {}", e);
+                return Ok(vec![]);
+            }
+        };
+
+        Ok(discog.items.into_iter().filter(|s| !s.album_artists.is_empty()).collect())
+    }
+
+    /// Returns all favorite tracks, or the `limit` most recently added ones when `favorites` is false.
+    /// Used by the auto-download rules
+    ///
+    pub async fn sync_rule_tracks(
+        &self,
+        favorites: bool,
+        limit: Option<usize>,
+    ) -> Result<Vec<DiscographySong>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/Users/{}/Items", self.base_url, self.user_id);
+
+        let mut query_params: Vec<(&str, String)> = vec![
+            ("Recursive", "true".into()),
+            ("Fields", "Genres, DateCreated, MediaSources, ParentId, UserData".into()),
+            ("IncludeItemTypes", "Audio".into()),
+            ("SortBy", "DateCreated,SortName".into()),
+            ("SortOrder", "Descending".into()),
+        ];
+        if favorites {
+            query_params.push(("Filters", "IsFavorite".into()));
+        }
+        if let Some(limit) = limit {
+            query_params.push(("Limit", limit.to_string()));
+        }
+
+        let req = self
+            .http_client
+            .get(&url)
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .header("Content-Type", "application/json")
+            .query(&query_params);
+
+        // unlike the radio helpers this one must fail loudly, an empty result would remove downloads
+        let discog: Discography = self.get_json_with_retry(req).await?;
+
+        Ok(discog.items.into_iter().filter(|s| !s.album_artists.is_empty()).collect())
+    }
+
     /// Returns a instant playlist based on a give item
     ///
     pub async fn instant_playlist(
@@ -1007,28 +1080,52 @@ impl Client {
     pub async fn set_favorite(&self, item_id: &str, favorite: bool) -> Result<(), reqwest::Error> {
         let id = item_id.replace("_album_", "");
         let url = format!("{}/Users/{}/FavoriteItems/{}", self.base_url, self.user_id, id);
-        let response = if favorite {
-            self.http_client
-                .post(url)
-                .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
-                .header("Content-Type", "application/json")
-                .send()
-                .await
-        } else {
-            self.http_client
-                .delete(url)
-                .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
-                .header("Content-Type", "application/json")
-                .send()
-                .await
-        };
+        let request =
+            if favorite { self.http_client.post(url) } else { self.http_client.delete(url) };
+        request
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .header("Content-Type", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
 
-        match response {
-            Ok(_) => {}
-            Err(_) => {
-                return Ok(());
-            }
+        Ok(())
+    }
+
+    /// Current name of an item, `None` if it doesn't exist (anymore)
+    ///
+    pub async fn item_name(&self, item_id: &str) -> Result<Option<String>, reqwest::Error> {
+        let url = format!("{}/Items/{}", self.base_url, item_id);
+        let response = self
+            .http_client
+            .get(url)
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .query(&[("userId", self.user_id.as_str())])
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
         }
+        let item = response.error_for_status()?.json::<serde_json::Value>().await?;
+
+        Ok(item["Name"].as_str().map(|name| name.to_string()))
+    }
+
+    /// Marks a track as played at the given time, this is how plays made offline reach the server
+    ///
+    pub async fn mark_played(
+        &self,
+        item_id: &str,
+        date_played: &str,
+    ) -> Result<(), reqwest::Error> {
+        let url = format!("{}/Users/{}/PlayedItems/{}", self.base_url, self.user_id, item_id);
+        self.http_client
+            .post(url)
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .query(&[("DatePlayed", date_played)])
+            .send()
+            .await?
+            .error_for_status()?;
 
         Ok(())
     }
@@ -1089,6 +1186,146 @@ impl Client {
         Ok(all_playlists)
     }
 
+    /// One page after another of the items of a type that changed since `since` (ISO 8601). With `by_user` set this
+    /// asks for user data changes (favorites, play counts) instead of metadata changes.
+    ///
+    async fn items_changed_since<T: serde::de::DeserializeOwned>(
+        &self,
+        item_type: &str,
+        fields: &str,
+        parent_id: Option<&String>,
+        since: &str,
+        by_user: bool,
+    ) -> Result<Vec<T>, reqwest::Error> {
+        const LIMIT: usize = 200;
+
+        let url = format!("{}/Users/{}/Items", self.base_url, self.user_id);
+        let filter = if by_user { "MinDateLastSavedForUser" } else { "MinDateLastSaved" };
+
+        let mut items = Vec::new();
+        let mut start_index = 0;
+
+        loop {
+            let mut req = self
+                .http_client
+                .get(&url)
+                .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+                .query(&[
+                    ("SortBy", "SortName"),
+                    ("SortOrder", "Ascending"),
+                    ("Recursive", "true"),
+                    ("IncludeItemTypes", item_type),
+                    ("Fields", fields),
+                    (filter, since),
+                    ("StartIndex", &start_index.to_string()),
+                    ("Limit", &LIMIT.to_string()),
+                ]);
+            if let Some(parent_id) = parent_id {
+                req = req.query(&[("ParentId", parent_id)]);
+            }
+
+            // unlike the full fetches errors are passed on, a partial delta must not move the sync cursor
+            let page: ItemsPage<T> = self.get_json_with_retry(req).await?;
+            let count = page.items.len();
+            items.extend(page.items);
+
+            if count < LIMIT {
+                break;
+            }
+            start_index += LIMIT;
+        }
+
+        Ok(items)
+    }
+
+    /// Metadata and user data changes of one type, each item once
+    ///
+    async fn changed_since<T: serde::de::DeserializeOwned + Searchable>(
+        &self,
+        item_type: &str,
+        fields: &str,
+        parent_id: Option<&String>,
+        since: &str,
+    ) -> Result<Vec<T>, reqwest::Error> {
+        let mut items: Vec<T> =
+            self.items_changed_since(item_type, fields, parent_id, since, false).await?;
+        let user_changes: Vec<T> =
+            self.items_changed_since(item_type, fields, parent_id, since, true).await?;
+
+        let mut seen: HashSet<String> = items.iter().map(|i| i.id().to_string()).collect();
+        for item in user_changes {
+            if seen.insert(item.id().to_string()) {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    /// Artists added or changed since the last sync, used by the incremental library update
+    ///
+    pub async fn artists_changed_since(&self, since: &str) -> Result<Vec<Artist>, reqwest::Error> {
+        self.changed_since("MusicArtist", "DateCreated,ProviderIds", None, since).await
+    }
+
+    /// Albums of a library added or changed since the last sync
+    ///
+    pub async fn albums_changed_since(
+        &self,
+        library_id: &String,
+        since: &str,
+    ) -> Result<Vec<Album>, reqwest::Error> {
+        self.changed_since(
+            "MusicAlbum",
+            "DateCreated,ParentId,ProductionYear,PremiereDate,ProviderIds",
+            Some(library_id),
+            since,
+        )
+        .await
+    }
+
+    /// Playlists added or changed since the last sync, a playlist is saved again whenever its tracks change
+    ///
+    pub async fn playlists_changed_since(
+        &self,
+        since: &str,
+    ) -> Result<Vec<Playlist>, reqwest::Error> {
+        self.changed_since(
+            "Playlist",
+            "ChildCount,Genres,DateCreated,ParentId,Overview",
+            None,
+            since,
+        )
+        .await
+    }
+
+    /// Number of items of a type on the server without fetching them. A delta query cannot return removed items,
+    /// so the incremental sync compares this to the cache to notice them
+    ///
+    pub async fn item_count(
+        &self,
+        item_type: &str,
+        parent_id: Option<&String>,
+    ) -> Result<u64, reqwest::Error> {
+        let url = format!("{}/Users/{}/Items", self.base_url, self.user_id);
+
+        let mut req = self
+            .http_client
+            .get(&url)
+            .header(self.authorization_header.0.as_str(), self.authorization_header.1.as_str())
+            .query(&[
+                ("Recursive", "true"),
+                ("IncludeItemTypes", item_type),
+                ("EnableTotalRecordCount", "true"),
+                ("Limit", "0"),
+            ]);
+        if let Some(parent_id) = parent_id {
+            req = req.query(&[("ParentId", parent_id)]);
+        }
+
+        let count: ItemCount = self.get_json_with_retry(req).await?;
+        Ok(count.total_record_count)
+    }
+
     /// Gets a single playlist
     ///
     pub async fn playlist(
@@ -1277,6 +1514,19 @@ impl Client {
             .await
     }
 
+    /// Adds many tracks to a playlist, chunked so the query string stays reasonably sized
+    ///
+    pub async fn add_tracks_to_playlist(
+        &self,
+        track_ids: &[String],
+        playlist_id: &String,
+    ) -> Result<(), reqwest::Error> {
+        for chunk in track_ids.chunks(100) {
+            self.add_to_playlist(&chunk.join(","), playlist_id).await?.error_for_status()?;
+        }
+        Ok(())
+    }
+
     /// Removes a track from a playlist
     ///
     pub async fn remove_from_playlist(
@@ -1294,6 +1544,19 @@ impl Client {
             .send()
             .await
     }
+
+    /// Removes many entries from a playlist, chunked like `add_tracks_to_playlist`
+    ///
+    pub async fn remove_tracks_from_playlist(
+        &self,
+        entry_ids: &[String],
+        playlist_id: &String,
+    ) -> Result<(), reqwest::Error> {
+        for chunk in entry_ids.chunks(100) {
+            self.remove_from_playlist(&chunk.join(","), playlist_id).await?.error_for_status()?;
+        }
+        Ok(())
+    }
     // POST /Playlists/{playlistId}/Items/{itemId}/Move/{newIndex}
     pub async fn move_playlist_item(
         &self,
@@ -1600,6 +1863,10 @@ pub struct Artist {
     media_type: String,
     #[serde(rename = "DateCreated", default)]
     pub date_created: String,
+    #[serde(rename = "ServerId", default)]
+    pub server_id: String,
+    #[serde(rename = "ProviderIds", default)]
+    pub provider_ids: serde_json::Value,
 }
 
 impl Searchable for Artist {
@@ -1727,8 +1994,8 @@ pub struct DiscographySong {
     // type_: String,
     #[serde(rename = "UserData", default)]
     pub user_data: DiscographySongUserData,
-    #[serde(rename = "ProviderIds", default, deserialize_with = "de_musicbrainz_album_id")]
-    pub musicbrainz_album_id: Option<String>,
+    #[serde(rename = "ProviderIds", default, deserialize_with = "de_provider_ids")]
+    pub provider_ids: TrackProviderIds,
     /// our own fields
     #[serde(default)]
     pub download_status: DownloadStatus,
@@ -1749,12 +2016,30 @@ fn index_default() -> u64 {
     1
 }
 
-fn de_musicbrainz_album_id<'de, D: serde::Deserializer<'de>>(
-    d: D,
-) -> Result<Option<String>, D::Error> {
-    let map: std::collections::HashMap<String, serde_json::Value> =
-        serde::Deserialize::deserialize(d).unwrap_or_default();
-    Ok(map.get("MusicBrainzAlbum").and_then(|v| v.as_str()).map(|s| s.to_string()))
+/// The MusicBrainz ids of a track, the album one finds covers and the track one matches tracks across servers
+///
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct TrackProviderIds {
+    #[serde(rename = "MusicBrainzAlbum", skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_album: Option<String>,
+    #[serde(rename = "MusicBrainzTrack", skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_track: Option<String>,
+}
+
+/// Older caches stored only the album id as a plain string
+///
+fn de_provider_ids<'de, D: serde::Deserializer<'de>>(d: D) -> Result<TrackProviderIds, D::Error> {
+    let value: serde_json::Value = serde::Deserialize::deserialize(d).unwrap_or_default();
+    let get = |key: &str| value.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    Ok(match &value {
+        serde_json::Value::String(album) => {
+            TrackProviderIds { musicbrainz_album: Some(album.clone()), musicbrainz_track: None }
+        }
+        _ => TrackProviderIds {
+            musicbrainz_album: get("MusicBrainzAlbum"),
+            musicbrainz_track: get("MusicBrainzTrack"),
+        },
+    })
 }
 
 impl<'r> FromRow<'r, sqlx::sqlite::SqliteRow> for DiscographySong {
@@ -1811,7 +2096,7 @@ impl<'r> FromRow<'r, sqlx::sqlite::SqliteRow> for DiscographySong {
             download_status: serde_json::from_str(row.get::<&str, _>("download_status"))
                 .unwrap_or(DownloadStatus::NotDownloaded),
             disliked: row.get::<i32, _>("disliked") != 0,
-            musicbrainz_album_id: None,
+            provider_ids: TrackProviderIds::default(),
         })
     }
 }
@@ -1838,9 +2123,9 @@ struct ViewsResponse {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MediaSource {
     #[serde(rename = "Container", default)]
-    container: String,
+    pub container: String,
     #[serde(rename = "Size", default)]
-    size: u64,
+    pub size: u64,
     #[serde(rename = "MediaStreams", default)]
     media_streams: Vec<MediaStream>,
 }
@@ -1953,6 +2238,18 @@ pub struct ProgressReportInternal {
     pub current_index: usize,
 }
 
+#[derive(Debug, Deserialize)]
+struct ItemsPage<T> {
+    #[serde(rename = "Items", default = "Vec::new")]
+    items: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemCount {
+    #[serde(rename = "TotalRecordCount", default)]
+    total_record_count: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Albums {
     #[serde(rename = "Items", default)]
@@ -1983,6 +2280,10 @@ pub struct Album {
     pub production_year: u64,
     #[serde(rename = "PremiereDate", default)]
     pub premiere_date: String,
+    #[serde(rename = "ServerId", default)]
+    pub server_id: String,
+    #[serde(rename = "ProviderIds", default)]
+    pub provider_ids: serde_json::Value,
 }
 
 impl Searchable for Album {
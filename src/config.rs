@@ -1,4 +1,4 @@
-use crate::client::{AuthMethod, SelectedServer};
+use crate::client::{AuthMethod, SelectedServer, Transcoding};
 use crate::themes::dialoguer::DialogTheme;
 use dialoguer::{Confirm, Input, Password};
 use dirs::{config_dir, data_dir};
@@ -81,6 +81,108 @@ pub fn get_config() -> Result<(PathBuf, serde_yaml::Value), Box<dyn std::error::
     Ok((config_file, d))
 }
 
+/// Download related settings, read from the config when the app starts
+///
+#[derive(Debug, Clone)]
+pub struct DownloadSettings {
+    /// `download_quota_gb` in bytes. Downloads above it get evicted, least recently played first
+    pub quota: Option<i64>,
+    /// `download_concurrency`, how many tracks are downloaded at once
+    pub concurrency: usize,
+    /// `download_transcoding`, the offline quality. Originals are downloaded when unset
+    pub transcoding: Option<Transcoding>,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self { quota: None, concurrency: 2, transcoding: None }
+    }
+}
+
+impl DownloadSettings {
+    pub fn from_config(config: &serde_yaml::Value) -> Self {
+        let quota = config
+            .get("download_quota_gb")
+            .and_then(|v| v.as_f64())
+            .filter(|gb| *gb > 0.0)
+            .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as i64);
+        let concurrency = config
+            .get("download_concurrency")
+            .and_then(|v| v.as_u64())
+            .map(|n| n.clamp(1, 8) as usize)
+            .unwrap_or(2);
+        let transcoding = config.get("download_transcoding").map(|t| Transcoding {
+            enabled: true,
+            bitrate: t["bitrate"].as_u64().and_then(|v| u32::try_from(v).ok()).unwrap_or(128),
+            container: t["container"].as_str().unwrap_or("opus").to_string(),
+        });
+
+        Self { quota, concurrency, transcoding }
+    }
+
+    /// Reads the config from disk, used by the database thread
+    ///
+    pub fn load() -> Self {
+        get_config().map(|(_, config)| Self::from_config(&config)).unwrap_or_default()
+    }
+
+    /// The stored quality of a download, e.g. `opus 128k`, or `original`
+    ///
+    pub fn quality_label(container: Option<&str>, bitrate: Option<u32>) -> String {
+        match (container, bitrate) {
+            (Some(container), Some(bitrate)) => format!("{} {}k", container, bitrate),
+            (Some(container), None) => container.to_string(),
+            _ => "original".to_string(),
+        }
+    }
+
+    pub fn target_label(&self) -> String {
+        match &self.transcoding {
+            Some(t) => Self::quality_label(Some(&t.container), Some(t.bitrate)),
+            None => Self::quality_label(None, None),
+        }
+    }
+}
+
+/// Where and how `Export downloads` lays out the offline files, read from `download_export`
+///
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub path: String,
+    /// Path of every track relative to `path`, without the extension
+    pub template: String,
+    /// Hardlink instead of copying when the target is on the same filesystem
+    pub hardlink: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            path: dirs::home_dir()
+                .map(|h| h.join("Music").join("jellyfin-tui").to_string_lossy().to_string())
+                .unwrap_or_else(|| "jellyfin-tui-music".to_string()),
+            template: "{artist}/{album} ({year})/{disc}-{track} - {title}".to_string(),
+            hardlink: false,
+        }
+    }
+}
+
+impl ExportSettings {
+    pub fn from_config(config: &serde_yaml::Value) -> Self {
+        let default = Self::default();
+        let export = &config["download_export"];
+        Self {
+            path: export["path"].as_str().map(|s| s.to_string()).unwrap_or(default.path),
+            template: export["template"]
+                .as_str()
+                .filter(|t| !t.trim().is_empty())
+                .map(|s| s.to_string())
+                .unwrap_or(default.template),
+            hardlink: export["hardlink"].as_bool().unwrap_or(default.hardlink),
+        }
+    }
+}
+
 pub fn select_server(
     config: &serde_yaml::Value,
     force_server_select: bool,
@@ -115,6 +217,126 @@ pub fn select_server(
     Some(parse_server(server))
 }
 
+/// `unified_library: true` logs into every configured server and merges their libraries
+///
+pub fn unified_library(config: &serde_yaml::Value) -> bool {
+    config.get("unified_library").and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// Every configured server along with its name, used to log into the rest of a unified library.
+/// Misconfigured servers are left out
+///
+pub fn configured_servers(config: &serde_yaml::Value) -> Vec<(String, SelectedServer)> {
+    config["servers"]
+        .as_sequence()
+        .map(|servers| {
+            servers
+                .iter()
+                .filter_map(|s| match try_parse_server(s) {
+                    Ok(server) => {
+                        Some((s["name"].as_str().unwrap_or("Unnamed").to_string(), server))
+                    }
+                    Err(e) => {
+                        log::warn!("Skipping server {:?}: {}", s["name"].as_str(), e);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The server named `name` in the config, used by the server switcher
+///
+pub fn find_server(config: &serde_yaml::Value, name: &str) -> Result<SelectedServer, String> {
+    config["servers"]
+        .as_sequence()
+        .and_then(|servers| servers.iter().find(|s| s["name"].as_str() == Some(name)))
+        .ok_or_else(|| format!("Server {} is not in the config", name))
+        .and_then(try_parse_server)
+}
+
+/// Adds a server to the `servers` list of the config file. The entry is inserted as text so the rest of the file,
+/// comments included, stays as it is
+///
+pub fn add_server(
+    config_path: &std::path::Path,
+    name: &str,
+    server: &SelectedServer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entry = match &server.auth {
+        AuthMethod::UserPass { username, password } => serde_json::json!({
+            "name": name.trim(),
+            "url": server.url.trim(),
+            "username": username.trim(),
+            "password": password,
+        }),
+        AuthMethod::QuickConnect => serde_json::json!({
+            "name": name.trim(),
+            "url": server.url.trim(),
+            "quick_connect": true,
+        }),
+    };
+    let entry = serde_yaml::to_string(&serde_json::json!([entry]))?;
+
+    let content = std::fs::read_to_string(config_path)?;
+    let lines = content.lines().collect::<Vec<_>>();
+    let Some(at) = lines.iter().position(|l| l.trim_end() == "servers:") else {
+        return Err("Could not find the servers list in the config file".into());
+    };
+    // match the indentation of the existing entries
+    let indent = lines[at + 1..]
+        .iter()
+        .find(|l| l.trim_start().starts_with("- "))
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .unwrap_or("  ");
+
+    let mut updated = lines[..=at].join("\n");
+    for line in entry.lines() {
+        updated.push('\n');
+        updated.push_str(indent);
+        updated.push_str(line);
+    }
+    for line in &lines[at + 1..] {
+        updated.push('\n');
+        updated.push_str(line);
+    }
+    updated.push('\n');
+    std::fs::write(config_path, updated)?;
+    Ok(())
+}
+
+/// Replaces top level keys of the config file with the given YAML, used when importing a profile. Like `add_server`
+/// this works on the text, the other keys and comments stay as they are. The previous file is kept as `config.yaml.bak`
+///
+pub fn replace_config_keys(
+    config_path: &std::path::Path,
+    keys: &[&str],
+    yaml: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(config_path)?;
+    std::fs::write(config_path.with_extension("yaml.bak"), &content)?;
+
+    let mut updated = String::new();
+    let mut skipping = false;
+    for line in content.lines() {
+        let top_level = !line.is_empty() && !line.starts_with([' ', '\t', '-']);
+        if top_level {
+            skipping = keys.iter().any(|key| line.starts_with(&format!("{}:", key)));
+        }
+        if !skipping {
+            updated.push_str(line);
+            updated.push('\n');
+        }
+    }
+    if !updated.ends_with("\n\n") {
+        updated.push('\n');
+    }
+    updated.push_str(yaml);
+    std::fs::write(config_path, updated)?;
+    Ok(())
+}
+
 fn select_server_interactively(servers: &[serde_yaml::Value]) -> Option<&serde_yaml::Value> {
     let mut names: Vec<String> = servers
         .iter()
@@ -143,43 +365,39 @@ fn select_server_interactively(servers: &[serde_yaml::Value]) -> Option<&serde_y
 }
 
 fn parse_server(server: &serde_yaml::Value) -> SelectedServer {
+    try_parse_server(server).unwrap_or_else(|e| {
+        println!(" ! {}", e);
+        std::process::exit(1);
+    })
+}
+
+fn try_parse_server(server: &serde_yaml::Value) -> Result<SelectedServer, String> {
     let url = match server["url"].as_str() {
         Some(url) if !url.ends_with('/') => url.to_string(),
-        Some(_) => {
-            println!(" ! Server URL must not end with a trailing slash");
-            std::process::exit(1);
-        }
-        None => {
-            println!(" ! Selected server does not have a URL configured");
-            std::process::exit(1);
-        }
+        Some(_) => return Err("Server URL must not end with a trailing slash".to_string()),
+        None => return Err("Selected server does not have a URL configured".to_string()),
     };
 
     if let None = server["name"].as_str() {
-        println!(" ! Selected server does not have a name configured");
-        std::process::exit(1);
+        return Err("Selected server does not have a name configured".to_string());
     }
 
     let auth = match server["username"].as_str() {
         Some(username) => {
             let password = match (server["password"].as_str(), server["password_file"].as_str()) {
                 (None, Some(password_file)) => std::fs::read_to_string(password_file)
-                    .unwrap_or_else(|e| {
-                        println!(" ! Error reading password file '{}': {}", password_file, e);
-                        std::process::exit(1);
-                    })
+                    .map_err(|e| format!("Error reading password file '{}': {}", password_file, e))?
                     .trim_matches(&['\n', '\r'])
                     .to_string(),
                 (Some(p), None) => p.to_string(),
                 (Some(_), Some(_)) => {
-                    println!(
-                        " ! Selected server has password and password_file configured, only choose one"
+                    return Err(
+                        "Selected server has password and password_file configured, only choose one"
+                            .to_string(),
                     );
-                    std::process::exit(1);
                 }
                 (None, None) => {
-                    println!(" ! Selected server does not have a password configured");
-                    std::process::exit(1);
+                    return Err("Selected server does not have a password configured".to_string());
                 }
             };
 
@@ -189,13 +407,12 @@ fn parse_server(server: &serde_yaml::Value) -> SelectedServer {
             if server["quick_connect"].as_bool().unwrap_or(false) {
                 AuthMethod::QuickConnect
             } else {
-                println!(" ! Selected server does not have a username configured");
-                std::process::exit(1);
+                return Err("Selected server does not have a username configured".to_string());
             }
         }
     };
 
-    SelectedServer { url, auth }
+    Ok(SelectedServer { url, auth })
 }
 
 enum OnboardingAuth {
@@ -1,7 +1,34 @@
+use super::export::{t_export_downloads, ExportSummary};
 use super::extension::{
-    get_last_library_update, insert_lyrics, query_download_track, set_last_library_update,
+    get_download_usage, get_last_library_update, insert_lyrics, query_download_track,
+    set_last_library_update,
 };
+use super::library_sync::{
+    assign_track_libraries, delta_cursor, delta_updater, finish_full_sync, save_libraries,
+    upsert_albums, upsert_artists, upsert_playlists, ARTIST_MISSING_THRESHOLD,
+    PLAYLIST_MISSING_THRESHOLD,
+};
+use super::maintenance::{t_maintenance, MaintenanceReport};
+use super::outbox::{
+    flush_outbox, pending_playlist_tracks, push_mutation, resolve_conflicts, t_flush_outbox,
+    OutboxCommand, OutboxConflict,
+};
+use super::pending_plays::{record_pending_play, t_flush_pending_plays};
+use super::playlist_history::{
+    baselined_playlists, forget_playlist, is_baselined, record_rename, record_track_change,
+    set_baseline,
+};
+use super::search::t_search_server;
+use super::stats::log_play;
+use super::sync_rules::{
+    add_sync_rule, apply_sync_rules, cleanup_synced_downloads, queue_pinned_artist_tracks,
+    remove_sync_rule, SyncRuleCommand,
+};
+use super::tombstones::{t_tombstone, TombstoneCommand, ALBUM_MISSING_THRESHOLD};
+use super::unified::{claim_unowned_items, merged_discography, peer_updater};
 use crate::client::{NetworkQuality, ProgressReport};
+use crate::config::{DownloadSettings, ExportSettings};
+use crate::servers::Servers;
 use crate::{
     client::{Artist, Client, DiscographySong},
     database::extension::{
@@ -9,7 +36,9 @@ use crate::{
     },
 };
 use core::panic;
-use reqwest::header::CONTENT_LENGTH;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
 use sqlx::{Pool, Sqlite, SqlitePool};
 use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
@@ -20,7 +49,7 @@ use tokio::sync::broadcast;
 use tokio::time::Instant;
 use tokio::{
     fs,
-    io::AsyncWriteExt,
+    io::{AsyncReadExt, AsyncWriteExt},
     sync::mpsc::{Receiver, Sender},
     sync::Mutex,
 };
@@ -34,55 +63,173 @@ pub enum Command {
     Delete(DeleteCommand), // delete on the jellyfin server
     CancelDownloads,
     Jellyfin(JellyfinCommand),
-    DislikeTrack { track_id: String, disliked: bool },
+    DislikeTrack {
+        track_id: String,
+        disliked: bool,
+    },
+    PinTracks {
+        track_ids: Vec<String>,
+        pinned: bool,
+    },
+    SyncRule(SyncRuleCommand),
+    ExportDownloads {
+        settings: ExportSettings,
+    },
+    /// A play that can't be reported right now, kept until the server is reachable
+    RecordPlay {
+        track_id: String,
+        position_ticks: u64,
+    },
+    Outbox(OutboxCommand),
+    /// A finished play for the local play log, only kept if it counts as a play
+    LogPlay {
+        track_id: String,
+        position_ticks: u64,
+    },
+    /// Server side track search, the results come back as `Status::SearchResults`
+    SearchServer {
+        term: String,
+    },
+    /// Prunes orphaned rows and unused covers and vacuums, reported with `Status::MaintenanceFinished`
+    Maintenance,
+    /// Decides what happens to an album that was removed from the server, see `tombstones.rs`
+    Tombstone(TombstoneCommand),
 }
 
 pub enum Status {
-    TrackQueued { id: String },
-    TrackDownloading { track: DiscographySong },
-    TrackDownloaded { id: String },
-    TrackDeleted { id: String },
-    CoverArtDownloaded { item_id: Option<String> },
+    TrackQueued {
+        id: String,
+    },
+    TrackDownloading {
+        track: DiscographySong,
+    },
+    TrackDownloaded {
+        id: String,
+    },
+    TrackDeleted {
+        id: String,
+    },
+    CoverArtDownloaded {
+        item_id: Option<String>,
+    },
 
     ArtistsUpdated,
     AlbumsUpdated,
     PlaylistsUpdated,
 
-    DiscographyUpdated { id: String },
-    PlaylistUpdated { id: String },
+    DiscographyUpdated {
+        id: String,
+    },
+    PlaylistUpdated {
+        id: String,
+    },
+    /// Changes to the playlist were recorded in its history
+    PlaylistChangesUpdated {
+        id: String,
+    },
 
     UpdateStarted,
     UpdateFinished,
-    UpdateFailed { error: String },
+    UpdateFailed {
+        error: String,
+    },
 
-    ProgressUpdate { progress: f32 },
+    ProgressUpdate {
+        id: String,
+        progress: f32,
+    },
     AllDownloaded,
 
     NetworkQualityChanged(NetworkQuality),
+    DownloadUsage {
+        bytes: i64,
+    },
+    /// Queued downloads wait, the quota is used up by downloads that can't be evicted
+    DownloadQuotaFull,
+    SyncRulesUpdated,
+    OfflineRepairFinished {
+        summary: RepairSummary,
+    },
+    DownloadsExported {
+        summary: ExportSummary,
+    },
+    OutboxConflicts {
+        conflicts: Vec<OutboxConflict>,
+    },
+    SearchResults {
+        term: String,
+        tracks: Vec<DiscographySong>,
+    },
+    MaintenanceFinished {
+        report: MaintenanceReport,
+    },
+    TombstonesUpdated,
+    TombstoneResolved {
+        message: String,
+    },
+
+    Error {
+        error: String,
+    },
+}
 
-    Error { error: String },
+/// What the offline repair pass found, shown to the user once it finishes
+#[derive(Debug, Default)]
+pub struct RepairSummary {
+    pub checked: usize,
+    pub missing: usize,
+    pub requeued: usize,
+    pub fingerprinted: usize,
 }
 
 #[derive(Debug)]
 pub struct DownloadItem {
+    pub id: String,
     pub name: String,
     pub progress: f32,
 }
 
 #[derive(Debug)]
 pub enum DownloadCommand {
-    Track { track: DiscographySong, playlist_id: Option<String> },
-    Tracks { tracks: Vec<DiscographySong> },
-    CoverArt { item_id: String },
+    Track {
+        track: DiscographySong,
+        playlist_id: Option<String>,
+    },
+    Tracks {
+        tracks: Vec<DiscographySong>,
+    },
+    CoverArt {
+        item_id: String,
+        server_id: String,
+    },
+    /// Downloads the tracks again at the current offline quality
+    Redownload {
+        track_ids: Vec<String>,
+    },
+    /// Re-downloads every track whose offline copy doesn't match the current offline quality
+    RedownloadMismatched,
 }
 
 #[derive(Debug)]
 pub enum UpdateCommand {
-    SongPlayed { track_id: String },
-    Discography { artist_id: String },
-    Playlist { playlist_id: String },
-    Library,
-    OfflineRepair,
+    SongPlayed {
+        track_id: String,
+    },
+    Discography {
+        artist_id: String,
+    },
+    Playlist {
+        playlist_id: String,
+    },
+    /// `full` skips the incremental sync and reconciles the whole library
+    Library {
+        full: bool,
+    },
+    /// Verifies the offline copies. Only a `requested` repair reports what it found
+    OfflineRepair {
+        requested: bool,
+    },
+    SyncRules,
 }
 
 #[derive(Debug)]
@@ -103,9 +250,30 @@ pub enum RenameCommand {
 
 #[derive(Debug)]
 pub enum JellyfinCommand {
-    Stopped { id: Option<String>, position_ticks: Option<u64> },
-    Playing { progress_report: ProgressReport },
-    ReportProgress { progress_report: ProgressReport },
+    Stopped { id: Option<String>, position_ticks: Option<u64>, server_id: String },
+    Playing { progress_report: ProgressReport, server_id: String },
+    ReportProgress { progress_report: ProgressReport, server_id: String },
+}
+
+/// Everything the database thread spawns. Dropping it aborts the tasks still running
+///
+#[derive(Default)]
+struct SpawnedTasks(Vec<tokio::task::AbortHandle>);
+
+impl SpawnedTasks {
+    fn track<T>(&mut self, handle: tokio::task::JoinHandle<T>) -> tokio::task::JoinHandle<T> {
+        self.0.retain(|task| !task.is_finished());
+        self.0.push(handle.abort_handle());
+        handle
+    }
+}
+
+impl Drop for SpawnedTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
 }
 
 /// This is the main background thread. It queues and processes downloads and background updates.
@@ -114,20 +282,25 @@ pub async fn t_database<'a>(
     pool: Arc<Pool<Sqlite>>,
     mut rx: Receiver<Command>,
     tx: Sender<Status>,
-    online: bool,
-    client: Option<Arc<Client>>,
+    servers: Servers,
     server_id: String,
     network_quality: NetworkQuality,
 ) {
     let data_dir = dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads");
+    let download_settings = DownloadSettings::load();
+    // older versions shared a single part file for every download
+    let _ = fs::remove_file(data_dir.join("jellyfin-tui-track.part")).await;
 
     let mut db_interval = tokio::time::interval(Duration::from_secs(1));
+    // aborted with this thread, so nothing keeps writing to the database once the app quits or switches servers
+    let mut spawned = SpawnedTasks::default();
+
     let mut large_update_interval = tokio::time::interval_at(
         tokio::time::Instant::now() + Duration::from_secs(60 * 10),
         Duration::from_secs(60 * 10),
     );
 
-    if !online || client.is_none() {
+    if servers.primary().is_none() {
         let mut active_task: Option<tokio::task::JoinHandle<()>> = None;
 
         loop {
@@ -164,7 +337,7 @@ pub async fn t_database<'a>(
                                                 .execute(&*pool)
                                                 .await;
                                         }
-                                        UpdateCommand::OfflineRepair { .. } => {
+                                        UpdateCommand::OfflineRepair { requested } => {
                                             let should_spawn = match &active_task {
                                                 Some(handle) if !handle.is_finished() => false,
                                                 _ => true,
@@ -172,12 +345,13 @@ pub async fn t_database<'a>(
 
                                             if should_spawn {
                                                 log::info!("Spawning offline track checker...");
-                                                let handle = tokio::spawn(t_offline_tracks_checker(
+                                                let handle = spawned.track(tokio::spawn(t_offline_tracks_checker(
                                                     Arc::clone(&pool),
                                                     tx.clone(),
                                                     data_dir.clone(),
                                                     server_id.clone(),
-                                                ));
+                                                    requested,
+                                                )));
                                                 active_task = Some(handle);
                                             } else {
                                                 log::debug!("Offline track checker is already running.");
@@ -210,6 +384,43 @@ pub async fn t_database<'a>(
                                         log::error!("Failed to mark track {} as disliked: {}", track_id, e);
                                     }
                                 }
+                                Command::PinTracks { track_ids, pinned } => {
+                                    if let Err(e) = set_tracks_pinned(&pool, &track_ids, pinned).await {
+                                        log::error!("Failed to update pinned tracks: {}", e);
+                                    }
+                                }
+                                Command::SyncRule(rule_cmd) => {
+                                    handle_sync_rule_command(rule_cmd, &pool, &tx, &data_dir).await;
+                                }
+                                Command::ExportDownloads { settings } => {
+                                    spawned.track(tokio::spawn(t_export_downloads((*pool).clone(), tx.clone(), data_dir.clone(), settings)));
+                                }
+                                Command::Maintenance => {
+                                    spawned.track(tokio::spawn(t_maintenance((*pool).clone(), tx.clone(), server_id.clone())));
+                                }
+                                Command::Tombstone(cmd) => {
+                                    spawned.track(tokio::spawn(t_tombstone((*pool).clone(), tx.clone(), None, cmd)));
+                                }
+                                Command::RecordPlay { track_id, position_ticks } => {
+                                    if let Err(e) = record_pending_play(&pool, &track_id, position_ticks).await {
+                                        log::error!("Failed to record offline play of {}: {}", track_id, e);
+                                    }
+                                }
+                                Command::LogPlay { track_id, position_ticks } => {
+                                    if let Err(e) = log_play(&pool, &track_id, position_ticks).await {
+                                        log::error!("Failed to log play of {}: {}", track_id, e);
+                                    }
+                                }
+                                Command::Outbox(outbox_cmd) => {
+                                    // applied the next time we are online
+                                    let result = match &outbox_cmd {
+                                        OutboxCommand::Push { mutation } => push_mutation(&pool, mutation).await,
+                                        _ => resolve_conflicts(&pool, &outbox_cmd).await.map_err(|e| e.into()),
+                                    };
+                                    if let Err(e) = result {
+                                        log::error!("Failed to update the outbox: {}", e);
+                                    }
+                                }
                                 _ => {
                                     log::warn!("Received unsupported command: {:?}", cmd);
                                 }
@@ -237,11 +448,22 @@ pub async fn t_database<'a>(
         }
     }
 
-    let client = client.unwrap();
+    let client = servers.primary().cloned().unwrap();
+    if servers.is_unified() {
+        if let Err(e) = claim_unowned_items(&pool, &client.server_id).await {
+            log::error!("Failed to claim items for the primary server: {}", e);
+        }
+    }
 
     // queue for managing discography updates with priority
     let task_queue: Arc<Mutex<VecDeque<UpdateCommand>>> = Arc::new(Mutex::new(VecDeque::new()));
     let mut active_task = None;
+    // downloads run next to the active task, up to `download_settings.concurrency` at once
+    let mut downloads: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
+    // plays recorded while offline (also in --offline sessions) are replayed now and whenever the connection recovers
+    let mut play_sync =
+        spawned.track(tokio::spawn(t_flush_pending_plays(Arc::clone(&pool), servers.clone())));
+    spawned.track(tokio::spawn(t_flush_outbox((*pool).clone(), servers.clone(), tx.clone())));
 
     // The first task run is the complete Library update, to see changes made while the app was closed
     // Only do it every 10 minutes by default including across restarts.
@@ -249,23 +471,33 @@ pub async fn t_database<'a>(
         if let Some(last) = get_last_library_update(&pool).await {
             let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
             if now - last >= 600 {
-                active_task = Some(tokio::spawn(t_data_updater(
+                active_task = Some(spawned.track(tokio::spawn(t_data_updater(
                     Arc::clone(&pool),
                     tx.clone(),
-                    client.clone(),
-                )));
+                    servers.clone(),
+                    false,
+                ))));
             } else {
                 log::debug!("skipping library update on startup");
             }
         } else {
-            active_task =
-                Some(tokio::spawn(t_data_updater(Arc::clone(&pool), tx.clone(), client.clone())));
+            active_task = Some(spawned.track(tokio::spawn(t_data_updater(
+                Arc::clone(&pool),
+                tx.clone(),
+                servers.clone(),
+                false,
+            ))));
         }
     }
 
     // rx/tx to stop downloads in progress
     let (cancel_tx, _) = broadcast::channel::<Vec<String>>(4);
 
+    // so a full quota is reported once, not on every tick
+    let mut quota_full = false;
+    // a play makes a download evictable, a full quota is checked again after one
+    let mut played_since_check = false;
+
     // intervals for checking network quality
     let mut netcheck_interval = tokio::time::interval(Duration::from_secs(120));
     let mut last_quality = network_quality; // or NetworkQuality::Normal
@@ -290,7 +522,23 @@ pub async fn t_database<'a>(
                                     let _ = tx.send(Status::TrackQueued { id: track.id }).await;
                                 }
                             }
-                            DownloadCommand::CoverArt { item_id } => {
+                            DownloadCommand::Redownload { track_ids } => {
+                                match requeue_downloads(&pool, Some(&track_ids), &download_settings).await {
+                                    Ok(ids) => log::info!("Queued {} re-downloads", ids.len()),
+                                    Err(e) => log::error!("Failed to queue re-downloads: {}", e),
+                                }
+                            }
+                            DownloadCommand::RedownloadMismatched => {
+                                match requeue_downloads(&pool, None, &download_settings).await {
+                                    Ok(ids) => log::info!("Queued {} re-downloads", ids.len()),
+                                    Err(e) => log::error!("Failed to queue re-downloads: {}", e),
+                                }
+                            }
+                            DownloadCommand::CoverArt { item_id, server_id } => {
+                                let Some(client) = servers.client_for(&server_id) else {
+                                    let _ = tx.send(Status::CoverArtDownloaded { item_id: None }).await;
+                                    continue;
+                                };
                                 if let Err(e) = client.download_cover_art(&item_id).await {
                                     let _ = tx.send(Status::CoverArtDownloaded { item_id: None }).await;
                                     log::error!("Failed to download cover art for {}: {}", item_id, e);
@@ -308,6 +556,7 @@ pub async fn t_database<'a>(
                                 if let Err(e) = remove_track_download(&pool, &track, &data_dir).await {
                                     log::error!("Failed to remove track download: {}", e);
                                 }
+                                let _ = tx.send(Status::DownloadUsage { bytes: get_download_usage(&pool).await }).await;
                             }
                             RemoveCommand::Tracks { tracks } => {
                                 let _ = cancel_tx.send(tracks.iter().map(|t| t.id.clone()).collect());
@@ -317,10 +566,14 @@ pub async fn t_database<'a>(
                                 for track in &tracks {
                                     let _ = tx.send(Status::TrackDeleted { id: track.id.clone() }).await;
                                 }
+                                let _ = tx.send(Status::DownloadUsage { bytes: get_download_usage(&pool).await }).await;
                             }
                         }
                     },
                     Command::Update(update_cmd) => {
+                        if matches!(update_cmd, UpdateCommand::SongPlayed { .. }) {
+                            played_since_check = true;
+                        }
                         let (should_start, next_update) = {
                             let mut queue = task_queue.lock().await;
                             queue.push_front(update_cmd);
@@ -335,7 +588,7 @@ pub async fn t_database<'a>(
 
                         if should_start {
                             if let Some(update_cmd) = next_update {
-                                active_task = handle_update(update_cmd, Arc::clone(&pool), tx.clone(), client.clone()).await;
+                                active_task = handle_update(update_cmd, Arc::clone(&pool), tx.clone(), servers.clone()).await.map(|handle| spawned.track(handle));
                             }
                         }
                     }
@@ -359,17 +612,31 @@ pub async fn t_database<'a>(
                     }
                     Command::Jellyfin(jellyfin_cmd) => {
                         match jellyfin_cmd {
-                            JellyfinCommand::Stopped { id, position_ticks } => {
-                                if let Err(e) = client.stopped(id, position_ticks).await {
+                            JellyfinCommand::Stopped { id, position_ticks, server_id } => {
+                                let Some(client) = servers.client_for(&server_id) else {
+                                    continue;
+                                };
+                                if let Err(e) = client.stopped(id.clone(), position_ticks).await {
                                     log::error!("Failed to send stopped report to jellyfin: {}", e);
+                                    // the server wasn't reachable at all, don't lose the play. A timeout may
+                                    // still have arrived, so those aren't recorded to avoid counting it twice
+                                    if let (true, Some(id)) = (e.is_connect(), id) {
+                                        let _ = record_pending_play(&pool, &id, position_ticks.unwrap_or_default()).await;
+                                    }
                                 }
                             }
-                            JellyfinCommand::Playing { progress_report } => {
+                            JellyfinCommand::Playing { progress_report, server_id } => {
+                                let Some(client) = servers.client_for(&server_id) else {
+                                    continue;
+                                };
                                 if let Err(e) = client.playing(&progress_report).await {
                                     log::error!("Failed to send playing report to jellyfin: {}", e);
                                 }
                             }
-                            JellyfinCommand::ReportProgress { progress_report } => {
+                            JellyfinCommand::ReportProgress { progress_report, server_id } => {
+                                let Some(client) = servers.client_for(&server_id) else {
+                                    continue;
+                                };
                                 if let Err(e) = client.report_progress(&progress_report).await {
                                     log::error!("Failed to report progress to jellyfin: {}", e);
                                 }
@@ -386,6 +653,52 @@ pub async fn t_database<'a>(
                             log::error!("Failed to mark track {} as disliked: {}", track_id, e);
                         }
                     }
+                    Command::PinTracks { track_ids, pinned } => {
+                        if let Err(e) = set_tracks_pinned(&pool, &track_ids, pinned).await {
+                            log::error!("Failed to update pinned tracks: {}", e);
+                        }
+                        // unpinning may have made room for eviction
+                        if !pinned {
+                            enforce_download_quota(&pool, &data_dir, download_settings.quota, None, &tx).await;
+                        }
+                    }
+                    Command::SyncRule(rule_cmd) => {
+                        handle_sync_rule_command(rule_cmd, &pool, &tx, &data_dir).await;
+                    }
+                    Command::ExportDownloads { settings } => {
+                        spawned.track(tokio::spawn(t_export_downloads((*pool).clone(), tx.clone(), data_dir.clone(), settings)));
+                    }
+                    Command::RecordPlay { track_id, position_ticks } => {
+                        if let Err(e) = record_pending_play(&pool, &track_id, position_ticks).await {
+                            log::error!("Failed to record play of {}: {}", track_id, e);
+                        }
+                    }
+                    Command::Outbox(outbox_cmd) => {
+                        let result = match &outbox_cmd {
+                            OutboxCommand::Push { mutation } => push_mutation(&pool, mutation).await,
+                            _ => resolve_conflicts(&pool, &outbox_cmd).await.map_err(|e| e.into()),
+                        };
+                        if let Err(e) = result {
+                            log::error!("Failed to update the outbox: {}", e);
+                        }
+                        if !matches!(outbox_cmd, OutboxCommand::Discard { .. }) {
+                            spawned.track(tokio::spawn(t_flush_outbox((*pool).clone(), servers.clone(), tx.clone())));
+                        }
+                    }
+                    Command::LogPlay { track_id, position_ticks } => {
+                        if let Err(e) = log_play(&pool, &track_id, position_ticks).await {
+                            log::error!("Failed to log play of {}: {}", track_id, e);
+                        }
+                    }
+                    Command::SearchServer { term } => {
+                        spawned.track(tokio::spawn(t_search_server(servers.clone(), tx.clone(), term)));
+                    }
+                    Command::Maintenance => {
+                        spawned.track(tokio::spawn(t_maintenance((*pool).clone(), tx.clone(), server_id.clone())));
+                    }
+                    Command::Tombstone(cmd) => {
+                        spawned.track(tokio::spawn(t_tombstone((*pool).clone(), tx.clone(), Some(client.clone()), cmd)));
+                    }
                 }
             },
             _ = db_interval.tick() => {
@@ -397,16 +710,41 @@ pub async fn t_database<'a>(
                     };
 
                     if let Some(update_cmd) = next_update {
-                        active_task = handle_update(update_cmd, Arc::clone(&pool), tx.clone(), client.clone()).await;
+                        active_task = handle_update(update_cmd, Arc::clone(&pool), tx.clone(), servers.clone()).await.map(|handle| spawned.track(handle));
                     } else if last_quality != NetworkQuality::CzechTrain {
-                        active_task = track_process_queued_download(&pool, &tx, &client, &data_dir, &cancel_tx).await;
+                        downloads.retain(|_, handle| !handle.is_finished());
+                        // downloads played since the queue stopped can make room now
+                        if quota_full && played_since_check {
+                            enforce_download_quota(&pool, &data_dir, download_settings.quota, None, &tx).await;
+                        }
+                        played_since_check = false;
+                        let full = download_quota_full(&pool, download_settings.quota).await;
+                        if full && !quota_full {
+                            let _ = tx.send(Status::DownloadQuotaFull).await;
+                        }
+                        quota_full = full;
+                        while !quota_full && downloads.len() < download_settings.concurrency {
+                            match track_process_queued_download(&pool, &tx, &servers, &data_dir, &cancel_tx, &download_settings, &downloads).await {
+                                Some((id, handle)) => {
+                                    downloads.insert(id, spawned.track(handle));
+                                }
+                                None => break,
+                            }
+                        }
+                        if downloads.is_empty() && !has_queued_downloads(&pool).await {
+                            // that's all folks!
+                            let _ = tx.send(Status::AllDownloaded).await;
+                        }
                     }
                 }
             },
             _ = large_update_interval.tick() => {
+                if last_quality != NetworkQuality::CzechTrain && play_sync.is_finished() {
+                    play_sync = spawned.track(tokio::spawn(t_flush_pending_plays(Arc::clone(&pool), servers.clone())));
+                }
                 if last_quality == NetworkQuality::Normal {
                     if active_task.is_none() {
-                        active_task = Some(tokio::spawn(t_data_updater(Arc::clone(&pool), tx.clone(), client.clone())));
+                        active_task = Some(spawned.track(tokio::spawn(t_data_updater(Arc::clone(&pool), tx.clone(), servers.clone(), false))));
                     }
                 }
             },
@@ -425,6 +763,10 @@ pub async fn t_database<'a>(
                     match new_quality {
                         NetworkQuality::Normal => {
                             netcheck_interval = tokio::time::interval(Duration::from_secs(180));
+                            if play_sync.is_finished() {
+                                play_sync = spawned.track(tokio::spawn(t_flush_pending_plays(Arc::clone(&pool), servers.clone())));
+                            }
+                            spawned.track(tokio::spawn(t_flush_outbox((*pool).clone(), servers.clone(), tx.clone())));
                         }
                         NetworkQuality::Slow => {
                             netcheck_interval = tokio::time::interval(Duration::from_secs(90));
@@ -457,11 +799,13 @@ async fn handle_update(
     update_cmd: UpdateCommand,
     pool: Arc<Pool<Sqlite>>,
     tx: Sender<Status>,
-    client: Arc<Client>,
+    servers: Servers,
 ) -> Option<tokio::task::JoinHandle<()>> {
+    let client = Arc::clone(servers.primary()?);
     match update_cmd {
         UpdateCommand::Discography { artist_id } => Some(tokio::spawn(async move {
-            if let Err(e) = t_discography_updater(pool, artist_id.clone(), tx.clone(), client).await
+            if let Err(e) =
+                t_discography_updater(pool, artist_id.clone(), tx.clone(), servers).await
             {
                 let _ = tx.send(Status::UpdateFailed { error: e.to_string() }).await;
                 log::error!("Failed to update discography for artist {}: {}", artist_id, e);
@@ -474,17 +818,18 @@ async fn handle_update(
                 .await;
             None
         }
-        UpdateCommand::Library => {
-            Some(tokio::spawn(t_data_updater(Arc::clone(&pool), tx.clone(), client)))
+        UpdateCommand::Library { full } => {
+            Some(tokio::spawn(t_data_updater(Arc::clone(&pool), tx.clone(), servers, full)))
         }
         UpdateCommand::Playlist { playlist_id } => Some(tokio::spawn(async move {
-            if let Err(e) = t_playlist_updater(pool, playlist_id.clone(), tx.clone(), client).await
+            if let Err(e) = t_playlist_updater(pool, playlist_id.clone(), tx.clone(), servers).await
             {
                 let _ = tx.send(Status::UpdateFailed { error: e.to_string() }).await;
                 log::error!("Failed to update playlist {}: {}", playlist_id, e);
             }
         })),
-        UpdateCommand::OfflineRepair => {
+        UpdateCommand::SyncRules => Some(tokio::spawn(t_sync_rules(pool, tx, servers))),
+        UpdateCommand::OfflineRepair { requested } => {
             let data_dir = match dirs::data_dir() {
                 Some(dir) => dir.join("jellyfin-tui").join("downloads"),
                 None => {
@@ -497,6 +842,7 @@ async fn handle_update(
                 tx.clone(),
                 data_dir,
                 client.server_id.clone(),
+                requested,
             )))
         }
     }
@@ -504,11 +850,57 @@ async fn handle_update(
 
 /// This is a thread that gets spawned at the start of the application to fetch all artists/playlists and update them
 /// in the DB and also emit the status to the UI to reload the data.
+/// Only the changes since the last run are fetched, unless `full` is set or a full update is due.
 ///
-pub async fn t_data_updater(pool: Arc<Pool<Sqlite>>, tx: Sender<Status>, client: Arc<Client>) {
+pub async fn t_data_updater(
+    pool: Arc<Pool<Sqlite>>,
+    tx: Sender<Status>,
+    servers: Servers,
+    full: bool,
+) {
+    let Some(client) = servers.primary().cloned() else {
+        return;
+    };
     let _ = tx.send(Status::UpdateStarted).await;
-    match data_updater(pool, Some(tx.clone()), client).await {
+    // local changes go first, otherwise the update would overwrite them with the old server state
+    if let Err(e) = flush_outbox(&pool, &servers, &tx).await {
+        log::error!("Failed to flush the outbox: {}", e);
+    }
+    let cursor = if full { None } else { delta_cursor(&pool).await };
+    let delta_complete = match cursor {
+        Some(since) => delta_updater(&pool, &tx, &client, since).await,
+        None => Ok(false),
+    };
+    let result = match delta_complete {
+        Ok(true) => Ok(()),
+        Ok(false) => data_updater(Arc::clone(&pool), Some(tx.clone()), Arc::clone(&client)).await,
+        Err(e) => Err(e),
+    };
+    match result {
         Ok(_) => {
+            // the other servers are merged into what the primary brought in
+            for peer in servers.peers() {
+                if let Err(e) = peer_updater(&pool, &tx, peer).await {
+                    log::warn!("Failed to sync server {}: {}", peer.server_id, e);
+                }
+            }
+            // playlists that were opened before are compared with the server to fill their history
+            for playlist_id in baselined_playlists(&pool).await.unwrap_or_default() {
+                if let Err(e) = t_playlist_updater(
+                    Arc::clone(&pool),
+                    playlist_id.clone(),
+                    tx.clone(),
+                    servers.clone(),
+                )
+                .await
+                {
+                    log::warn!("Failed to refresh playlist {}: {}", playlist_id, e);
+                }
+            }
+            let data_dir = dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads");
+            if let Err(e) = apply_sync_rules(&pool, &tx, &servers, &data_dir).await {
+                log::error!("Failed to apply sync rules: {}", e);
+            }
             let _ = tx.send(Status::UpdateFinished).await;
         }
         Err(e) => {
@@ -518,6 +910,16 @@ pub async fn t_data_updater(pool: Arc<Pool<Sqlite>>, tx: Sender<Status>, client:
     }
 }
 
+/// Evaluates the auto-download rules without a full library update, e.g. right after a rule was added
+///
+async fn t_sync_rules(pool: Arc<Pool<Sqlite>>, tx: Sender<Status>, servers: Servers) {
+    let data_dir = dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads");
+    if let Err(e) = apply_sync_rules(&pool, &tx, &servers, &data_dir).await {
+        let _ = tx.send(Status::UpdateFailed { error: e.to_string() }).await;
+        log::error!("Failed to apply sync rules: {}", e);
+    }
+}
+
 /// This fixes offline tracks, checking if they are still present on the filesystem and updating their status in the DB. Sometimes necessary to run
 /// when the user deletes files manually or moves them around. Auto-triggered if something weird is detected, runnable by user.
 async fn t_offline_tracks_checker(
@@ -525,10 +927,15 @@ async fn t_offline_tracks_checker(
     tx: Sender<Status>,
     data_dir: std::path::PathBuf,
     server_id: String,
+    requested: bool,
 ) {
     let _ = tx.send(Status::UpdateStarted).await;
-    match offline_tracks_checker(pool, tx.clone(), data_dir, server_id).await {
-        Ok(_) => {
+    // a repair the user asked for reads every file, the periodic check trusts the ones untouched since
+    match offline_tracks_checker(pool, tx.clone(), data_dir, server_id, requested).await {
+        Ok(summary) => {
+            if requested {
+                let _ = tx.send(Status::OfflineRepairFinished { summary }).await;
+            }
             let _ = tx.send(Status::UpdateFinished).await;
         }
         Err(e) => {
@@ -546,6 +953,7 @@ pub async fn data_updater(
     log::info!("Starting global data updater...");
 
     let start_time = Instant::now();
+    let sync_started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
 
     let music_libs = client.music_libraries().await?;
     if music_libs.is_empty() {
@@ -564,54 +972,10 @@ pub async fn data_updater(
 
     let mut albums_complete = true;
 
-    let batch_size = 250;
-
     // save our libs first
-    {
-        let mut tx_db = pool.begin().await?;
-        for lib in &music_libs {
-            sqlx::query(
-                r#"
-                INSERT INTO libraries (id, name, collection_type, last_seen, selected)
-                VALUES (?, ?, ?, CURRENT_TIMESTAMP, 1)
-                ON CONFLICT(id) DO UPDATE SET
-                    name = excluded.name,
-                    last_seen = CURRENT_TIMESTAMP;
-                "#,
-            )
-            .bind(&lib.id)
-            .bind(&lib.name)
-            .bind(&lib.collection_type)
-            .execute(&mut *tx_db)
-            .await?;
-        }
-        tx_db.commit().await?;
-    }
-
-    let mut tx_db = pool.begin().await?;
-
-    for (i, artist) in artists.iter().enumerate() {
-        if i != 0 && i % batch_size == 0 {
-            tokio::task::yield_now().await;
-        }
+    save_libraries(&pool, &music_libs).await?;
 
-        let artist_json = serde_json::to_string(&artist)?;
-
-        sqlx::query(
-            r#"
-            INSERT INTO artists (id, artist)
-            VALUES (?, ?)
-            ON CONFLICT(id) DO UPDATE SET artist = excluded.artist
-            WHERE artists.artist != excluded.artist;
-            "#,
-        )
-        .bind(&artist.id)
-        .bind(&artist_json)
-        .execute(&mut *tx_db)
-        .await?;
-    }
-
-    tx_db.commit().await?;
+    upsert_artists(&pool, &artists).await?;
 
     if let Some(tx) = &tx {
         log::info!("Artists updated, sending notification to UI");
@@ -647,65 +1011,9 @@ pub async fn data_updater(
             log::warn!("Library '{}' (id={}) returned ZERO albums from Jellyfin", lib.name, lib.id);
         }
 
-        for (i, album) in albums.iter().enumerate() {
-            if i != 0 && i % batch_size == 0 {
-                tokio::task::yield_now().await;
-            }
-
-            let album_json = serde_json::to_string(&album)?;
+        upsert_albums(&mut tx_db, &lib.id, &albums).await?;
+        remote_album_ids.extend(albums.iter().map(|album| album.id.clone()));
 
-            let result = sqlx::query(
-                r#"
-                INSERT INTO albums (id, album, library_id)
-                VALUES (?, ?, ?)
-                ON CONFLICT(id) DO UPDATE SET
-                    album = excluded.album,
-                    library_id = excluded.library_id
-                WHERE albums.album != excluded.album
-                   OR albums.library_id IS NULL
-                   OR albums.library_id != excluded.library_id;
-                "#,
-            )
-            .bind(&album.id)
-            .bind(&album_json)
-            .bind(&lib.id)
-            .execute(&mut *tx_db)
-            .await?;
-
-            if result.rows_affected() > 0 {
-                log::debug!("Album updated: {:?}", album);
-            }
-
-            remote_album_ids.push(album.id.clone());
-
-            sqlx::query("DELETE FROM album_artist WHERE album_id = ?")
-                .bind(&album.id)
-                .execute(&mut *tx_db)
-                .await?;
-
-            for artist in &album.album_artists {
-                let canonical = sqlx::query_scalar::<_, Option<String>>(
-                    "SELECT id FROM artists WHERE json_extract(artist, '$.Name') = ?",
-                )
-                .bind(&artist.name)
-                .fetch_optional(&mut *tx_db)
-                .await?
-                .flatten();
-
-                let canonical_id = canonical.unwrap_or_else(|| artist.id.clone());
-
-                sqlx::query(
-                    r#"
-                    INSERT OR IGNORE INTO album_artist (album_id, artist_id)
-                    VALUES (?, ?)
-                    "#,
-                )
-                .bind(&album.id)
-                .bind(&canonical_id)
-                .execute(&mut *tx_db)
-                .await?;
-            }
-        }
         log::info!(
             "Finished processing library '{}' (id={}), total albums processed={}",
             lib.name,
@@ -723,72 +1031,55 @@ pub async fn data_updater(
         DELETE FROM album_artist
         WHERE artist_id NOT IN (
             SELECT value FROM json_each(json(?))
+        )
+          AND album_id IN (
+            SELECT id FROM albums
+            WHERE server_id IN ('', ?)
         );
         "#,
         )
         .bind(&remote_json)
+        .bind(&client.server_id)
         .execute(&mut *tx_db)
         .await?;
         tx_db.commit().await?;
     }
 
-    mark_missing(&pool, &tx, "artist", &artist_ids, &client.server_id, 4).await?;
-
-    tx_db = pool.begin().await?;
-    sqlx::query(
-        r#"
-        UPDATE tracks
-        SET library_id = (
-            SELECT library_id FROM albums WHERE albums.id = tracks.album_id
-        )
-        WHERE library_id IS NULL
-          AND EXISTS (
-              SELECT 1 FROM albums WHERE albums.id = tracks.album_id
-          )
-        "#,
-    )
-    .execute(&mut *tx_db)
-    .await?;
+    mark_missing(&pool, &tx, "artist", &artist_ids, &client.server_id, ARTIST_MISSING_THRESHOLD)
+        .await?;
 
-    tx_db.commit().await?;
+    assign_track_libraries(&pool).await?;
 
     if let Some(tx) = &tx {
         tx.send(Status::AlbumsUpdated).await?;
     }
 
     if albums_complete {
-        mark_missing(&pool, &tx, "album", &remote_album_ids, &client.server_id, 3).await?;
-    } else {
-        log::warn!("skipping album deletion pass: album list incomplete (some libraries failed).");
-    }
-
-    let mut tx_db = pool.begin().await?;
-
-    for (i, playlist) in playlists.iter().enumerate() {
-        if i != 0 && i % batch_size == 0 {
-            tokio::task::yield_now().await;
-        }
-
-        let playlist_json = serde_json::to_string(&playlist)?;
-
-        sqlx::query(
-            r#"
-            INSERT OR REPLACE INTO playlists (id, playlist)
-            VALUES (?, ?)
-            ON CONFLICT(id) DO UPDATE SET playlist = excluded.playlist
-            WHERE playlists.playlist != excluded.playlist;
-            "#,
+        mark_missing(
+            &pool,
+            &tx,
+            "album",
+            &remote_album_ids,
+            &client.server_id,
+            ALBUM_MISSING_THRESHOLD,
         )
-        .bind(&playlist.id)
-        .bind(&playlist_json)
-        .execute(&mut *tx_db)
         .await?;
+    } else {
+        log::warn!("skipping album deletion pass: album list incomplete (some libraries failed).");
     }
 
-    tx_db.commit().await?;
+    upsert_playlists(&pool, &playlists).await?;
 
     let remote_playlist_ids: Vec<String> = playlists.iter().map(|p| p.id.clone()).collect();
-    mark_missing(&pool, &tx, "playlist", &remote_playlist_ids, &client.server_id, 3).await?;
+    mark_missing(
+        &pool,
+        &tx,
+        "playlist",
+        &remote_playlist_ids,
+        &client.server_id,
+        PLAYLIST_MISSING_THRESHOLD,
+    )
+    .await?;
 
     if let Some(tx) = &tx {
         tx.send(Status::PlaylistsUpdated).await?;
@@ -797,6 +1088,9 @@ pub async fn data_updater(
     log::info!("Global data updater took {:.2}s", start_time.elapsed().as_secs_f32());
 
     set_last_library_update(&pool).await;
+    if albums_complete {
+        finish_full_sync(&pool, sync_started).await;
+    }
 
     Ok(())
 }
@@ -808,17 +1102,25 @@ pub async fn t_discography_updater(
     pool: Arc<Pool<Sqlite>>,
     artist_id: String,
     tx: Sender<Status>,
-    client: Arc<Client>,
+    servers: Servers,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let data_dir = match dirs::data_dir() {
-        Some(dir) => dir.join("jellyfin-tui").join("downloads").join(&client.server_id),
+        Some(dir) => dir.join("jellyfin-tui").join("downloads"),
         None => return Ok(()),
     };
 
-    let discography = match client.discography(&artist_id).await {
+    let owner = super::unified::artist_server(&pool, &artist_id).await;
+    let Some(client) = servers.client_for(&owner) else {
+        return Ok(());
+    };
+    let mut discography = match client.discography(&artist_id).await {
         Ok(discography) => discography,
         Err(_) => return Ok(()),
     };
+    // in a unified library the artist's twins on the other servers add their tracks
+    let (merged, complete) = merged_discography(&pool, &servers, &artist_id).await?;
+    discography.extend(merged);
+    let pinned_tracks = discography.clone();
 
     let mut dirty = false;
 
@@ -834,7 +1136,7 @@ pub async fn t_discography_updater(
     .await?;
 
     for (track_id,) in rows {
-        if !server_ids.contains(&track_id) {
+        if complete && !server_ids.contains(&track_id) {
             // Remove memberships
             sqlx::query("DELETE FROM artist_membership WHERE artist_id = ? AND track_id = ?")
                 .bind(&artist_id)
@@ -861,7 +1163,7 @@ pub async fn t_discography_updater(
             ) VALUES (?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 album_id = excluded.album_id,
-                track = json_set(excluded.track, '$.download_status', tracks.download_status)
+                track = excluded.track
             WHERE tracks.track != excluded.track;
             "#,
         )
@@ -896,7 +1198,7 @@ pub async fn t_discography_updater(
                 .fetch_optional(&mut *tx_db)
                 .await?
         {
-            let file_path = data_dir.join(&track.album_id).join(&track.id);
+            let file_path = data_dir.join(&track.server_id).join(&track.album_id).join(&track.id);
             if matches!(download_status, DownloadStatus::Downloaded) && !file_path.exists() {
                 // if the user deleted the file, we set the download status to NotDownloaded
                 sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
@@ -935,6 +1237,9 @@ pub async fn t_discography_updater(
 
     tx_db.commit().await?;
 
+    // new releases of a pinned artist are downloaded right away
+    queue_pinned_artist_tracks(&pool, &tx, &artist_id, &pinned_tracks).await?;
+
     if dirty {
         tx.send(Status::DiscographyUpdated { id: artist_id }).await.ok();
     }
@@ -947,8 +1252,17 @@ pub async fn t_playlist_updater(
     pool: Arc<Pool<Sqlite>>,
     playlist_id: String,
     tx: Sender<Status>,
-    client: Arc<Client>,
+    servers: Servers,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let playlist_server: Option<String> = sqlx::query_scalar(
+        "SELECT COALESCE(json_extract(playlist, '$.ServerId'), '') FROM playlists WHERE id = ?",
+    )
+    .bind(&playlist_id)
+    .fetch_optional(&*pool)
+    .await?;
+    let Some(client) = servers.client_for(&playlist_server.unwrap_or_default()).cloned() else {
+        return Ok(());
+    };
     let playlist = match client.playlist(&playlist_id, None).await {
         Ok(playlist) => playlist,
         Err(_) => return Ok(()),
@@ -965,8 +1279,17 @@ pub async fn t_playlist_updater(
     .fetch_all(&*pool)
     .await?;
 
-    let ids_to_remove: Vec<String> =
-        rows.into_iter().map(|(id,)| id).filter(|id| !server_ids.contains(id)).collect();
+    let local_ids: Vec<String> = rows.into_iter().map(|(id,)| id).collect();
+    // changes made here that didn't reach the server yet are left as they are
+    let pending = pending_playlist_tracks(&pool, &playlist_id).await?;
+    let ids_to_remove: Vec<String> = local_ids
+        .iter()
+        .filter(|id| !server_ids.contains(id) && !pending.contains(*id))
+        .cloned()
+        .collect();
+    // the first fetch is the baseline of the history, before it the local membership may be incomplete
+    let baselined = is_baselined(&pool, &playlist_id).await?;
+    let mut changes = 0;
 
     let data_dir = match dirs::data_dir() {
         Some(dir) => dir.join("jellyfin-tui").join("downloads").join(&client.server_id),
@@ -1006,6 +1329,9 @@ pub async fn t_playlist_updater(
             .bind(id)
             .execute(&mut *tx_db)
             .await?;
+        if baselined && record_track_change(&mut tx_db, &playlist_id, "removed", id, false).await? {
+            changes += 1;
+        }
         dirty = true;
     }
 
@@ -1020,7 +1346,7 @@ pub async fn t_playlist_updater(
             ) VALUES (?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 album_id = excluded.album_id,
-                track = json_set(excluded.track, '$.download_status', tracks.download_status)
+                track = excluded.track
             WHERE tracks.track != excluded.track;
             "#,
         )
@@ -1059,13 +1385,20 @@ pub async fn t_playlist_updater(
             dirty = true;
         }
 
+        if pending.contains(&track.id) {
+            continue;
+        }
+
         let result = sqlx::query(
             r#"
-            INSERT OR REPLACE INTO playlist_membership (
+            INSERT INTO playlist_membership (
                 playlist_id,
                 track_id,
                 position
             ) VALUES (?, ?, ?)
+            ON CONFLICT(playlist_id, track_id) DO UPDATE SET
+                position = excluded.position
+            WHERE playlist_membership.position != excluded.position
             "#,
         )
         .bind(&playlist_id)
@@ -1078,10 +1411,21 @@ pub async fn t_playlist_updater(
             // log::debug!("Updated playlist membership for track: {}", track.id);
             dirty = true;
         }
+
+        if baselined
+            && !local_ids.contains(&track.id)
+            && record_track_change(&mut tx_db, &playlist_id, "added", &track.id, false).await?
+        {
+            changes += 1;
+        }
     }
 
+    set_baseline(&mut tx_db, &playlist_id).await?;
     tx_db.commit().await?;
 
+    if changes > 0 {
+        let _ = tx.send(Status::PlaylistChangesUpdated { id: playlist_id.clone() }).await;
+    }
     if dirty {
         let _ = tx.send(Status::PlaylistUpdated { id: playlist_id }).await;
     }
@@ -1089,64 +1433,177 @@ pub async fn t_playlist_updater(
     Ok(())
 }
 
-/// This will go over all downloaded tracks, make sure they exist (if not, set their status to NotDownloaded), and emit the correct status updates to the UI. Also, make sure it won't block the db while checking the files. It takes a long time
+#[derive(sqlx::FromRow)]
+struct OfflineTrackRow {
+    id: String,
+    album_id: String,
+    server_id: String,
+    track: String,
+    stored_size: Option<i64>,
+    stored_checksum: Option<String>,
+    stored_mtime: Option<i64>,
+    container: Option<String>,
+    bitrate: Option<i64>,
+}
+
+/// This will go over all downloaded tracks and verify them. Missing files are marked NotDownloaded, files that are
+/// empty, truncated or don't match their stored checksum are deleted and queued again. Older downloads without a
+/// checksum get one. Also, make sure it won't block the db while checking the files. It takes a long time.
+/// Without `full`, files verified before and not modified since are not hashed again
 async fn offline_tracks_checker(
     pool: Arc<Pool<Sqlite>>,
     tx: Sender<Status>,
     data_dir: std::path::PathBuf,
     server_id: String,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    full: bool,
+) -> Result<RepairSummary, Box<dyn std::error::Error + Send + Sync>> {
     let start_time = Instant::now();
 
-    let mut tx_db = pool.begin().await?;
-
-    // Fetch track IDs and album IDs
-    let tracks: Vec<(String, String)> =
-        sqlx::query_as("SELECT id, album_id FROM tracks WHERE download_status = 'Downloaded';")
-            .fetch_all(&mut *tx_db)
-            .await?;
-    tx_db.commit().await?;
-
-    // Group tracks by album_id
-    let mut grouped_tracks: HashMap<String, Vec<String>> = HashMap::new();
-    for (id, album_id) in tracks {
-        grouped_tracks.entry(album_id).or_default().push(id);
-    }
+    let tracks: Vec<OfflineTrackRow> = sqlx::query_as(
+        "SELECT id, album_id, COALESCE(NULLIF(json_extract(track, '$.ServerId'), ''), ?) AS server_id,
+                    track, download_size_bytes AS stored_size,
+                    download_checksum AS stored_checksum, download_mtime AS stored_mtime,
+                    download_container AS container, download_bitrate AS bitrate
+             FROM tracks WHERE download_status = 'Downloaded';",
+    )
+    .bind(&server_id)
+    .fetch_all(&*pool)
+    .await?;
 
+    let mut summary = RepairSummary { checked: tracks.len(), ..Default::default() };
     let mut missing_ids = Vec::new();
+    let mut corrupt = Vec::new();
+    let mut checksums = Vec::new();
+    let mut verified = Vec::new();
+
+    for OfflineTrackRow {
+        id,
+        album_id,
+        server_id,
+        track,
+        stored_size,
+        stored_checksum,
+        stored_mtime,
+        container,
+        bitrate,
+    } in tracks
+    {
+        // every track lives in the directory of the server it came from
+        let file_path = data_dir.join(&server_id).join(&album_id).join(&id);
+        let Ok(metadata) = fs::metadata(&file_path).await else {
+            missing_ids.push(id);
+            continue;
+        };
+        let size = metadata.len();
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+
+        // older downloads without a stored size are compared to the source file, but only if they are a copy of it.
+        // A transcoded copy of the same container still has another size
+        let expected_size = match stored_size {
+            Some(_) => None,
+            None => serde_json::from_str::<DiscographySong>(&track)
+                .ok()
+                .and_then(|t| t.media_sources.first().cloned())
+                .filter(|source| source.size > 0 && bitrate.is_none())
+                .filter(|source| {
+                    container.as_ref().is_none_or(|c| c.eq_ignore_ascii_case(&source.container))
+                })
+                .map(|source| source.size),
+        };
+        let truncated = size == 0
+            || stored_size.is_some_and(|s| s > 0 && s as u64 != size)
+            || expected_size.is_some_and(|s| s != size);
+        if truncated {
+            corrupt.push((id, file_path));
+            continue;
+        }
+
+        // verified before and untouched since, hashing every file on every run takes a long time
+        if !full && stored_checksum.is_some() && mtime.is_some() && stored_mtime == mtime {
+            continue;
+        }
 
-    // Check file existence per album
-    for (album_id, track_ids) in &grouped_tracks {
-        let album_path = data_dir.join(&server_id).join(&album_id);
-        for id in track_ids {
-            let file_path = album_path.join(&id);
-            if tokio::fs::metadata(&file_path).await.is_err() {
-                missing_ids.push(id.clone());
-                let _ = tx.send(Status::TrackDeleted { id: id.clone() }).await;
+        let checksum = match file_checksum(&file_path).await {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                log::warn!("Failed to read offline track {}: {}", id, e);
+                corrupt.push((id, file_path));
+                continue;
             }
+        };
+        match stored_checksum {
+            Some(stored) if stored != checksum => corrupt.push((id, file_path)),
+            Some(_) => verified.push((id, mtime)),
+            None => checksums.push((id, checksum, size, mtime)),
         }
     }
 
-    // Update DB only if there are missing files
-    if !missing_ids.is_empty() {
-        let mut tx_db = pool.begin().await?;
-        for id in missing_ids {
-            sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
-                .bind(&id)
-                .execute(&mut *tx_db)
-                .await?;
-        }
-        tx_db.commit().await?;
+    summary.missing = missing_ids.len();
+    summary.requeued = corrupt.len();
+    summary.fingerprinted = checksums.len();
+
+    // remove corrupt files before queueing them, so a download that starts right away can't be deleted
+    for (_, file_path) in &corrupt {
+        let _ = fs::remove_file(file_path).await;
+    }
+
+    let mut tx_db = pool.begin().await?;
+    for id in &missing_ids {
+        sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx_db)
+            .await?;
+    }
+    for (id, _) in &corrupt {
+        sqlx::query(
+            "UPDATE tracks SET download_status = 'Queued', download_checksum = NULL WHERE id = ?",
+        )
+        .bind(id)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    for (id, checksum, size, mtime) in &checksums {
+        sqlx::query(
+            "UPDATE tracks SET download_checksum = ?, download_size_bytes = ?, download_mtime = ?
+             WHERE id = ?",
+        )
+        .bind(checksum)
+        .bind(*size as i64)
+        .bind(mtime)
+        .bind(id)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    for (id, mtime) in &verified {
+        sqlx::query("UPDATE tracks SET download_mtime = ? WHERE id = ?")
+            .bind(mtime)
+            .bind(id)
+            .execute(&mut *tx_db)
+            .await?;
+    }
+    tx_db.commit().await?;
+
+    for id in missing_ids {
+        let _ = tx.send(Status::TrackDeleted { id }).await;
+    }
+    for (id, _) in corrupt {
+        let _ = tx.send(Status::TrackQueued { id }).await;
     }
 
-    let elapsed_time = start_time.elapsed();
     log::info!(
-        "Offline tracks checker finished. Checked {} tracks in {:.2}s.",
-        grouped_tracks.iter().map(|(_, v)| v.len()).sum::<usize>(),
-        elapsed_time.as_secs_f32()
+        "Offline tracks checker finished. Checked {} tracks in {:.2}s. {} missing, {} re-queued, {} fingerprinted.",
+        summary.checked,
+        start_time.elapsed().as_secs_f32(),
+        summary.missing,
+        summary.requeued,
+        summary.fingerprinted
     );
 
-    Ok(())
+    Ok(summary)
 }
 
 /// Deletes local albums for the given server that are not present in the remote list.
@@ -1187,189 +1644,254 @@ async fn offline_tracks_checker(
 //     Ok(deleted_albums.len())
 // }
 
+/// Picks the next queued track that is not already in flight and spawns its download.
+/// `Downloading` rows that are not in flight were interrupted (e.g. the app was closed) and resume first.
+///
 async fn track_process_queued_download(
     pool: &SqlitePool,
     tx: &Sender<Status>,
-    client: &Client,
+    servers: &Servers,
     data_dir: &std::path::PathBuf,
     cancel_tx: &broadcast::Sender<Vec<String>>,
-) -> Option<tokio::task::JoinHandle<()>> {
+    download_settings: &DownloadSettings,
+    in_flight: &HashMap<String, tokio::task::JoinHandle<()>>,
+) -> Option<(String, tokio::task::JoinHandle<()>)> {
     let mut cancel_rx = cancel_tx.subscribe();
 
-    if let Ok(record) = sqlx::query_as::<_, (String, String, String)>(
+    let records = sqlx::query_as::<_, (String, String, String)>(
         "
         SELECT id, album_id, track
         FROM tracks
-        WHERE download_status = 'Queued' OR download_status = 'Downloading'
+        WHERE download_status = 'Queued' OR download_status = 'Downloading' OR redownload = 1
         ORDER BY
-            COALESCE(CAST(json_extract(track, '$.IndexNumber') AS INTEGER), 999999) ASC,
+            COALESCE(index_number, 999999) ASC,
             CASE download_status
                 WHEN 'Downloading' THEN 0
                 WHEN 'Queued' THEN 1
                 ELSE 2
            END ASC
-        LIMIT 1
+        LIMIT ?
         ",
     )
-    .fetch_optional(pool)
+    .bind(in_flight.len() as i64 + 1)
+    .fetch_all(pool)
     .await
-    {
-        // downloads using transcoded files not implemented yet. Future me problem?
-        let transcoding = None;
-
-        if let Some((id, album_id, track_str)) = record {
-            let track: DiscographySong = match serde_json::from_str(&track_str) {
-                Ok(track) => track,
-                Err(_) => {
-                    log::error!("Failed to deserialize track: {}", track_str);
-                    return None;
-                }
-            };
+    .ok()?;
+
+    let transcoding = download_settings.transcoding.clone();
+    let quality = download_settings.target_label();
+    let download_quota = download_settings.quota;
+
+    let (id, album_id, track_str) =
+        records.into_iter().find(|(id, _, _)| !in_flight.contains_key(id))?;
+    let track: DiscographySong = match serde_json::from_str(&track_str) {
+        Ok(track) => track,
+        Err(_) => {
+            log::error!("Failed to deserialize track: {}", track_str);
+            return None;
+        }
+    };
 
-            let pool = pool.clone();
-            let tx = tx.clone();
-            let url = client.song_url_sync(&track.id, transcoding);
-            let file_dir = data_dir.join(&track.server_id).join(album_id);
-            if !file_dir.exists() {
-                if fs::create_dir_all(&file_dir).await.is_err() {
-                    log::error!("Failed to create directory for track: {}", file_dir.display());
-                    return None;
-                }
-            }
+    let Some(client) = servers.client_for(&track.server_id) else {
+        // its server could not be reached this session, the track is queued again on the next start
+        log::warn!("No connection to server {} to download {}", track.server_id, track.id);
+        let _ = sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
+            .bind(&track.id)
+            .execute(pool)
+            .await;
+        let _ = tx.send(Status::TrackDeleted { id: track.id }).await;
+        return None;
+    };
 
-            // this will pull it if it doesn't exist already. // TODO: use the cache...
-            let _ = client.download_cover_art(&track.parent_id).await;
-            let lyrics = client.lyrics(&track.id).await;
-            if let Ok(lyrics) = lyrics.as_ref() {
-                let _ = insert_lyrics(&pool, &track.id, lyrics).await;
-            }
+    let pool = pool.clone();
+    let tx = tx.clone();
+    let data_dir = data_dir.clone();
+    let url = client.song_url_sync(&track.id, transcoding.as_ref());
+    let file_dir = data_dir.join(&track.server_id).join(album_id);
+    if !file_dir.exists() {
+        if fs::create_dir_all(&file_dir).await.is_err() {
+            log::error!("Failed to create directory for track: {}", file_dir.display());
+            return None;
+        }
+    }
 
-            return Some(tokio::spawn(async move {
-                if let Err(e) = track_download_and_update(
-                    &pool,
-                    &id,
-                    &url,
-                    &file_dir,
-                    &track,
-                    &tx,
-                    &mut cancel_rx,
-                )
+    // this will pull it if it doesn't exist already. // TODO: use the cache...
+    let _ = client.download_cover_art(&track.parent_id).await;
+    let lyrics = client.lyrics(&track.id).await;
+    if let Ok(lyrics) = lyrics.as_ref() {
+        let _ = insert_lyrics(&pool, &track.id, lyrics).await;
+    }
+
+    let in_flight_id = track.id.clone();
+    let handle = tokio::spawn(async move {
+        if let Err(e) =
+            track_download_and_update(&pool, &quality, &url, &file_dir, &track, &tx, &mut cancel_rx)
                 .await
-                {
-                    let _ = sqlx::query(
-                        "UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?",
-                    )
-                    .bind(&id)
-                    .execute(&pool)
-                    .await;
-                    log::error!("Failed to download track {}: {} Error: {}", id, url, e);
-                    let _ = tx.send(Status::TrackDeleted { id: track.id }).await;
-                }
-            }));
-        } else {
-            // that's all folks!
-            let _ = tx.send(Status::AllDownloaded).await;
+        {
+            log::error!("Failed to download track {}: {} Error: {}", id, url, e);
+            if !abandon_download(&pool, &id).await {
+                let _ = tx.send(Status::TrackDeleted { id: track.id }).await;
+            }
+            return;
         }
-    }
-    None
+        let _ = sqlx::query(
+            "UPDATE tracks SET download_container = ?, download_bitrate = ?
+             WHERE id = ? AND download_status = 'Downloaded'",
+        )
+        .bind(transcoding.as_ref().map(|t| t.container.clone()))
+        .bind(transcoding.as_ref().map(|t| t.bitrate as i64))
+        .bind(&id)
+        .execute(&pool)
+        .await;
+        enforce_download_quota(&pool, &data_dir, download_quota, Some(&id), &tx).await;
+    });
+
+    Some((in_flight_id, handle))
 }
 
+/// How many times a download is retried before giving up, each retry resumes from the part file
+const DOWNLOAD_ATTEMPTS: u32 = 6;
+
+/// Gives up on a download. A re-download leaves the offline copy as it was, returns true if it was one
+///
+async fn abandon_download(pool: &SqlitePool, id: &str) -> bool {
+    let redownload =
+        sqlx::query_scalar::<_, bool>("SELECT redownload != 0 FROM tracks WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or(false);
+    let _ = sqlx::query(
+        "UPDATE tracks
+         SET download_status = IIF(redownload != 0, download_status, 'NotDownloaded'),
+             redownload = 0, part_quality = NULL
+         WHERE id = ?",
+    )
+    .bind(id)
+    .execute(pool)
+    .await;
+    redownload
+}
+
+/// Downloads a queued track, or the new copy of a track queued for a re-download. `quality` is the target quality,
+/// a part file started at another quality is thrown away instead of resumed
+///
 async fn track_download_and_update(
     pool: &SqlitePool,
-    id: &str,
+    quality: &str,
     url: &str,
     file_dir: &Path,
     track: &DiscographySong,
     tx: &Sender<Status>,
     cancel_rx: &mut broadcast::Receiver<Vec<String>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let path = dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads");
-    let temp_file = path.join("jellyfin-tui-track.part");
-    if temp_file.exists() {
-        let _ = fs::remove_file(&temp_file).await;
-    }
+    let id = track.id.as_str();
+    // every track gets its own part file next to the final one, so concurrent downloads don't collide
+    // and an interrupted transfer can pick up where it left off
+    let part_file = file_dir.join(format!("{}.part", track.id));
     if let Ok(cancelled_ids) = cancel_rx.try_recv() {
         if cancelled_ids.contains(&track.id) {
             return Ok(());
         }
     }
 
-    // T1 set Downloading status
+    // T1 set Downloading status. A re-download stays Downloaded, the old copy plays until the new one replaces it
     {
         let mut tx_db = pool.begin().await?;
-        sqlx::query("UPDATE tracks SET download_status = 'Downloading' WHERE id = ?")
-            .bind(id)
-            .execute(&mut *tx_db)
-            .await?;
+        let part_quality =
+            sqlx::query_scalar::<_, Option<String>>("SELECT part_quality FROM tracks WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx_db)
+                .await?
+                .flatten();
+        if part_quality.as_deref() != Some(quality) {
+            let _ = fs::remove_file(&part_file).await;
+        }
+        sqlx::query(
+            "UPDATE tracks
+             SET download_status = IIF(redownload != 0, download_status, 'Downloading'),
+                 part_quality = ?
+             WHERE id = ?",
+        )
+        .bind(quality)
+        .bind(id)
+        .execute(&mut *tx_db)
+        .await?;
         tx_db.commit().await?;
 
         tx.send(Status::TrackDownloading { track: track.clone() }).await?;
     }
 
-    // Download a song
-    let mut total_size: i64 = 0;
-    let download_result = async {
-        let mut downloaded: u64 = 0;
-        let mut response = reqwest::get(url).await?;
-        if let Some(content_length) = response.headers().get(CONTENT_LENGTH) {
-            total_size = content_length.to_str()?.parse()?;
-        }
-        let mut last_update = Instant::now();
-        let mut file = fs::File::create(&temp_file).await?;
-        while let Some(chunk) = response.chunk().await? {
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
-
-            if last_update.elapsed() >= Duration::from_secs_f64(0.2) {
-                // this lets the user cancel a download in progress
-                match cancel_rx.try_recv() {
-                    Ok(to_cancel) if to_cancel.contains(&track.id) => {
-                        let _ = tx.send(Status::TrackDeleted { id: track.id.to_string() }).await?;
-                        sqlx::query(
-                            "UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?",
-                        )
-                        .bind(id)
-                        .execute(pool)
-                        .await?;
-                        return Ok(());
-                    }
-                    _ => {} // let's keep going, this should be fine :3
+    // Download a song, backing off between attempts
+    let http_client = reqwest::Client::new();
+    let mut attempt = 0;
+    let download_result = loop {
+        match download_to_part_file(&http_client, url, &part_file, track, tx, cancel_rx).await {
+            Ok(Some(0)) => {
+                // a zero-byte file would play as an instant skip, treat it like a failed attempt
+                let _ = fs::remove_file(&part_file).await;
+                attempt += 1;
+                if attempt >= DOWNLOAD_ATTEMPTS {
+                    break Err("Server returned an empty file".into());
+                }
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt).min(60))).await;
+            }
+            Ok(Some(total_size)) => break Ok(total_size),
+            Ok(None) => {
+                // cancelled by the user
+                let _ = fs::remove_file(&part_file).await;
+                if !abandon_download(pool, id).await {
+                    let _ = tx.send(Status::TrackDeleted { id: track.id.to_string() }).await;
                 }
-                let progress = if total_size > 0 {
-                    downloaded as f32 / total_size as f32 * 100.0
-                } else {
-                    0.0
-                };
-                let _ = tx.send(Status::ProgressUpdate { progress }).await;
-                last_update = Instant::now();
+                return Ok(());
+            }
+            Err(e) => {
+                attempt += 1;
+                if attempt >= DOWNLOAD_ATTEMPTS {
+                    let _ = fs::remove_file(&part_file).await;
+                    break Err(e);
+                }
+                let delay = Duration::from_secs(2u64.pow(attempt).min(60));
+                log::warn!(
+                    "Download of {} failed (attempt {}/{}), retrying in {}s: {}",
+                    track.id,
+                    attempt,
+                    DOWNLOAD_ATTEMPTS,
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
             }
         }
-        Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
-    }
-    .await;
+    };
 
-    let _ = tx.send(Status::ProgressUpdate { progress: 99.9 }).await;
+    let _ = tx.send(Status::ProgressUpdate { id: track.id.clone(), progress: 99.9 }).await;
 
     // T2 update final status
     {
         let mut tx_db = pool.begin().await?;
         match download_result {
-            Ok(_) => {
-                let record = sqlx::query_as::<_, DownloadStatus>(
-                    "SELECT download_status FROM tracks WHERE id = ?",
+            Ok(total_size) => {
+                // still wanted, i.e. not removed or cancelled in the meantime
+                let wanted = sqlx::query_scalar::<_, bool>(
+                    "SELECT download_status = 'Downloading'
+                            OR (download_status = 'Downloaded' AND redownload != 0)
+                     FROM tracks WHERE id = ?",
                 )
                 .bind(id)
                 .fetch_one(&mut *tx_db)
                 .await;
 
+                let checksum = file_checksum(&part_file).await?;
                 let file_path = file_dir.join(format!("{}", track.id));
-                if let Err(e) = fs::rename(&temp_file, file_path).await {
+                if let Err(e) = fs::rename(&part_file, file_path).await {
                     return Err(Box::new(e));
                 }
 
-                if let Ok(record) = record {
-                    if !matches!(record, DownloadStatus::Downloading) {
-                        let _ = fs::remove_file(&temp_file).await;
+                if let Ok(wanted) = wanted {
+                    if !wanted {
                         return Ok(());
                     }
                     sqlx::query(
@@ -1377,25 +1899,24 @@ async fn track_download_and_update(
                         UPDATE tracks
                         SET download_status = 'Downloaded',
                             download_size_bytes = ?,
-                            downloaded_at = CURRENT_TIMESTAMP
+                            download_checksum = ?,
+                            downloaded_at = CURRENT_TIMESTAMP,
+                            redownload = 0,
+                            part_quality = NULL
                         WHERE id = ?
                         "#,
                     )
                     .bind(total_size)
+                    .bind(checksum)
                     .bind(id)
                     .execute(&mut *tx_db)
                     .await?;
 
                     tx.send(Status::TrackDownloaded { id: track.id.to_string() }).await?;
-                } else {
-                    let _ = fs::remove_file(&temp_file).await;
                 }
             }
             Err(e) => {
-                sqlx::query("UPDATE tracks SET download_status = 'Queued' WHERE id = ?")
-                    .bind(id)
-                    .execute(&mut *tx_db)
-                    .await?;
+                // the caller gives up on it
                 tx_db.commit().await?;
                 return Err(e);
             }
@@ -1407,6 +1928,86 @@ async fn track_download_and_update(
     Ok(())
 }
 
+/// Hex encoded sha256 of a downloaded file, read in chunks so large files don't end up in memory
+///
+async fn file_checksum(path: &Path) -> Result<String, std::io::Error> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Streams the track into its part file. If an earlier attempt left data behind, only the rest is requested
+/// with an HTTP Range header. Returns the total size in bytes, or `None` if the download got cancelled.
+///
+async fn download_to_part_file(
+    http_client: &reqwest::Client,
+    url: &str,
+    part_file: &Path,
+    track: &DiscographySong,
+    tx: &Sender<Status>,
+    cancel_rx: &mut broadcast::Receiver<Vec<String>>,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let offset = fs::metadata(part_file).await.map(|m| m.len()).unwrap_or(0);
+
+    let mut request = http_client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let response = request.send().await?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // the part file doesn't match what the server has anymore, the next attempt starts over
+        fs::remove_file(part_file).await?;
+        return Err("stale part file, restarting download".into());
+    }
+    let mut response = response.error_for_status()?;
+
+    // servers that ignore the Range header send the whole file again
+    let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut downloaded: u64 = if resumed { offset } else { 0 };
+    let total_size = response.content_length().map(|len| len + downloaded).unwrap_or(0);
+    let mut file = if resumed {
+        log::info!("Resuming download of {} at {} bytes", track.id, offset);
+        fs::OpenOptions::new().append(true).open(part_file).await?
+    } else {
+        fs::File::create(part_file).await?
+    };
+
+    let mut last_update = Instant::now();
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+
+        if last_update.elapsed() >= Duration::from_secs_f64(0.2) {
+            // this lets the user cancel a download in progress
+            match cancel_rx.try_recv() {
+                Ok(to_cancel) if to_cancel.contains(&track.id) => return Ok(None),
+                _ => {} // let's keep going, this should be fine :3
+            }
+            let progress =
+                if total_size > 0 { downloaded as f32 / total_size as f32 * 100.0 } else { 0.0 };
+            let _ = tx.send(Status::ProgressUpdate { id: track.id.clone(), progress }).await;
+            last_update = Instant::now();
+        }
+    }
+    file.flush().await?;
+
+    if total_size > 0 && downloaded != total_size {
+        return Err(
+            format!("incomplete download, got {} of {} bytes", downloaded, total_size).into()
+        );
+    }
+
+    Ok(Some(downloaded as i64))
+}
+
 async fn cancel_all_downloads(
     pool: &SqlitePool,
     tx: Sender<Status>,
@@ -1422,11 +2023,18 @@ async fn cancel_all_downloads(
     .await?;
 
     let affected_ids: Vec<String> = rows.into_iter().map(|row| row.0).collect();
+    // re-downloads keep their offline copy
+    let redownloads = sqlx::query_as::<_, (String,)>(
+        "UPDATE tracks SET redownload = 0, part_quality = NULL WHERE redownload != 0 RETURNING id",
+    )
+    .fetch_all(&mut *tx_db)
+    .await?;
 
     tx_db.commit().await?;
 
     // send a cancel signal to all downloads
-    let _ = cancel_tx.send(affected_ids.clone()).unwrap_or_default();
+    let cancelled = affected_ids.iter().cloned().chain(redownloads.into_iter().map(|row| row.0));
+    let _ = cancel_tx.send(cancelled.collect()).unwrap_or_default();
     let _ = tx.send(Status::AllDownloaded).await;
 
     for id in affected_ids {
@@ -1441,12 +2049,20 @@ async fn rename_playlist(
     playlist_id: &str,
     new_name: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let old_name: Option<String> = sqlx::query_scalar("SELECT name FROM playlists WHERE id = ?")
+        .bind(playlist_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
     let mut tx_db = pool.begin().await?;
     sqlx::query("UPDATE playlists SET playlist = json_set(playlist, '$.Name', ?) WHERE id = ?")
         .bind(&new_name)
         .bind(&playlist_id)
         .execute(&mut *tx_db)
         .await?;
+    if let Some(old_name) = old_name.filter(|old| old != new_name) {
+        record_rename(&mut tx_db, playlist_id, &old_name, new_name, true).await?;
+    }
     tx_db.commit().await?;
 
     Ok(())
@@ -1462,6 +2078,7 @@ async fn delete_playlist(
         .bind(playlist_id)
         .execute(&mut *tx_db)
         .await?;
+    forget_playlist(&mut tx_db, playlist_id).await?;
 
     sqlx::query("DELETE FROM playlists WHERE id = ?")
         .bind(playlist_id)
@@ -1489,6 +2106,183 @@ async fn mark_track_as_disliked(
     Ok(())
 }
 
+/// Adding a rule only stores it, the UI follows up with `UpdateCommand::SyncRules` to evaluate it.
+/// Removing one cleans up right away since that does not need the server
+///
+async fn handle_sync_rule_command(
+    rule_cmd: SyncRuleCommand,
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    data_dir: &PathBuf,
+) {
+    let result = match rule_cmd {
+        SyncRuleCommand::Add { kind } => add_sync_rule(pool, &kind).await,
+        SyncRuleCommand::Remove { id } => match remove_sync_rule(pool, id).await {
+            Ok(_) => cleanup_synced_downloads(pool, tx, data_dir).await,
+            Err(e) => Err(e),
+        },
+    };
+    if let Err(e) = result {
+        log::error!("Failed to update sync rules: {}", e);
+    }
+    let _ = tx.send(Status::SyncRulesUpdated).await;
+}
+
+/// Marks downloaded tracks for a re-download, so they get replaced with a copy at the current offline quality.
+/// With `track_ids` set only those are marked, otherwise every copy that doesn't match the quality.
+/// They stay Downloaded, the old file plays until the new one is renamed over it.
+///
+async fn requeue_downloads(
+    pool: &SqlitePool,
+    track_ids: Option<&[String]>,
+    download_settings: &DownloadSettings,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let container = download_settings.transcoding.as_ref().map(|t| t.container.clone());
+    let bitrate = download_settings.transcoding.as_ref().map(|t| t.bitrate as i64);
+
+    let mut tx_db = pool.begin().await?;
+    let mut requeued = vec![];
+    match track_ids {
+        Some(track_ids) => {
+            for id in track_ids {
+                let row = sqlx::query_as::<_, (String,)>(
+                    "UPDATE tracks SET redownload = 1
+                     WHERE id = ? AND download_status = 'Downloaded'
+                     RETURNING id",
+                )
+                .bind(id)
+                .fetch_optional(&mut *tx_db)
+                .await?;
+                requeued.extend(row.map(|r| r.0));
+            }
+        }
+        None => {
+            let rows = sqlx::query_as::<_, (String,)>(
+                "UPDATE tracks SET redownload = 1
+                 WHERE download_status = 'Downloaded'
+                   AND (download_container IS NOT ? OR download_bitrate IS NOT ?)
+                 RETURNING id",
+            )
+            .bind(container)
+            .bind(bitrate)
+            .fetch_all(&mut *tx_db)
+            .await?;
+            requeued.extend(rows.into_iter().map(|r| r.0));
+        }
+    }
+    tx_db.commit().await?;
+
+    Ok(requeued)
+}
+
+async fn set_tracks_pinned(
+    pool: &SqlitePool,
+    track_ids: &[String],
+    pinned: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut tx_db = pool.begin().await?;
+    for track_id in track_ids {
+        sqlx::query("UPDATE tracks SET pinned = ? WHERE id = ?")
+            .bind(pinned as i64)
+            .bind(track_id)
+            .execute(&mut *tx_db)
+            .await?;
+    }
+    tx_db.commit().await?;
+
+    Ok(())
+}
+
+/// Evicts the least recently played downloads until the total size fits in the quota.
+/// Pinned tracks, the tracks of pinned artists, albums and playlists, downloads that were not played since they were
+/// downloaded and the track in `keep` (usually the one that just finished downloading) are never evicted.
+///
+async fn enforce_download_quota(
+    pool: &SqlitePool,
+    data_dir: &PathBuf,
+    download_quota: Option<i64>,
+    keep: Option<&str>,
+    tx: &Sender<Status>,
+) {
+    let mut usage = get_download_usage(pool).await;
+
+    if let Some(quota) = download_quota.filter(|quota| usage > *quota) {
+        // evicting a download nobody listened to yet would let a long download queue replace its own tracks
+        // forever, so those are kept and the queue waits instead, see `download_quota_full`
+        let candidates = sqlx::query_as::<_, (String, i64)>(
+            "
+            SELECT track, COALESCE(download_size_bytes, 0)
+            FROM tracks
+            WHERE download_status = 'Downloaded' AND pinned = 0 AND id != ?
+              AND last_played IS NOT NULL
+              AND (downloaded_at IS NULL OR last_played >= downloaded_at)
+              AND id NOT IN (
+                  SELECT track_id FROM sync_rule_tracks
+                  WHERE rule_id IN (SELECT id FROM sync_rules WHERE key LIKE 'pin:%')
+              )
+            ORDER BY last_played ASC
+            ",
+        )
+        .bind(keep.unwrap_or_default())
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default();
+
+        for (track_str, size) in candidates {
+            if usage <= quota {
+                break;
+            }
+            let Ok(mut track) = serde_json::from_str::<DiscographySong>(&track_str) else {
+                continue;
+            };
+            // only downloaded tracks are candidates, the JSON doesn't carry the status
+            track.download_status = DownloadStatus::Downloaded;
+            let removed = remove_track_download(pool, &track, data_dir).await.is_ok();
+            if removed {
+                log::info!(
+                    "Evicted download {} ({} bytes) to stay within the quota",
+                    track.id,
+                    size
+                );
+                usage -= size;
+                let _ = tx.send(Status::TrackDeleted { id: track.id }).await;
+            }
+        }
+
+        if usage > quota {
+            log::warn!(
+                "Downloads use {} bytes, above the {} byte quota. Everything else is pinned or not played yet",
+                usage,
+                quota
+            );
+        }
+    }
+
+    let _ = tx.send(Status::DownloadUsage { bytes: usage }).await;
+}
+
+/// True while downloads are queued but the quota has no room left for them
+///
+async fn download_quota_full(pool: &SqlitePool, download_quota: Option<i64>) -> bool {
+    let Some(quota) = download_quota else {
+        return false;
+    };
+    // the same limit eviction works towards, so a queue that waits here can always be freed by it
+    if get_download_usage(pool).await <= quota {
+        return false;
+    }
+    has_queued_downloads(pool).await
+}
+
+async fn has_queued_downloads(pool: &SqlitePool) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT 1 FROM tracks WHERE download_status = 'Queued' LIMIT 1")
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .is_some()
+}
+
 /// This cleans up duplicates in the queue. Avoids the user triggering N wasteful updates that produce identical output.
 fn prune_update_queue(queue: &mut VecDeque<UpdateCommand>) {
     let mut seen = Vec::new();
@@ -1509,13 +2303,16 @@ fn prune_update_queue(queue: &mut VecDeque<UpdateCommand>) {
         _ => true,
     });
 
+    // one library update is enough, but it has to be a full one if any of them asked for it
+    let any_full = queue.iter().any(|cmd| matches!(cmd, UpdateCommand::Library { full: true }));
     let mut seen_library = false;
-    queue.retain(|cmd| match cmd {
-        UpdateCommand::Library => {
+    queue.retain_mut(|cmd| match cmd {
+        UpdateCommand::Library { full } => {
             if seen_library {
                 false
             } else {
                 seen_library = true;
+                *full = any_full;
                 true
             }
         }
@@ -1523,6 +2320,38 @@ fn prune_update_queue(queue: &mut VecDeque<UpdateCommand>) {
     });
 }
 
+/// Deletes an album with its tracks and everything pointing at them. The downloaded files are left to the caller
+///
+pub async fn delete_album(tx: &mut sqlx::Transaction<'_, Sqlite>, id: &str) -> sqlx::Result<u64> {
+    // delete playlist + artist memberships that refer to tracks on this album
+    sqlx::query(
+        r#"DELETE FROM playlist_membership
+           WHERE track_id IN (SELECT id FROM tracks WHERE album_id = ?)"#,
+    )
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        r#"DELETE FROM artist_membership
+           WHERE track_id IN (SELECT id FROM tracks WHERE album_id = ?)"#,
+    )
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+
+    // delete tracks and album relations
+    sqlx::query("DELETE FROM tracks WHERE album_id = ?").bind(id).execute(&mut **tx).await?;
+
+    sqlx::query("DELETE FROM album_artist WHERE album_id = ?").bind(id).execute(&mut **tx).await?;
+
+    Ok(sqlx::query("DELETE FROM albums WHERE id = ?")
+        .bind(id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected())
+}
+
 pub async fn mark_missing(
     pool: &SqlitePool,
     db_thread_tx: &Option<Sender<Status>>,
@@ -1561,11 +2390,13 @@ pub async fn mark_missing(
                           SELECT id FROM artists
                           WHERE id NOT IN (SELECT value FROM json_each(json(?)))
                             AND id NOT IN (SELECT artist_id FROM album_artist)
+                            AND server_id IN ('', ?)
                       );
                     "#,
             )
             .bind(now)
             .bind(&remote_json)
+            .bind(server_id)
             .execute(&mut *tx)
             .await?;
 
@@ -1576,6 +2407,7 @@ pub async fn mark_missing(
                     FROM artists
                     WHERE id NOT IN (SELECT value FROM json_each(json(?)))
                       AND id NOT IN (SELECT artist_id FROM album_artist)
+                      AND server_id IN ('', ?)
                       AND NOT EXISTS (
                           SELECT 1 FROM missing_counters mc
                           WHERE mc.entity_type = 'artist' AND mc.id = artists.id
@@ -1584,6 +2416,7 @@ pub async fn mark_missing(
             )
                 .bind(now)
                 .bind(&remote_json)
+                .bind(server_id)
                 .execute(&mut *tx)
                 .await?
                 .rows_affected();
@@ -1602,11 +2435,13 @@ pub async fn mark_missing(
                   AND id IN (
                       SELECT id FROM albums
                       WHERE id NOT IN (SELECT value FROM json_each(json(?)))
+                        AND server_id IN ('', ?)
                   );
                 "#,
             )
             .bind(now)
             .bind(&remote_json)
+            .bind(server_id)
             .execute(&mut *tx)
             .await?;
 
@@ -1616,6 +2451,7 @@ pub async fn mark_missing(
                 SELECT 'album', id, 1, ?
                 FROM albums
                 WHERE id NOT IN (SELECT value FROM json_each(json(?)))
+                  AND server_id IN ('', ?)
                   AND NOT EXISTS (
                       SELECT 1 FROM missing_counters mc
                       WHERE mc.entity_type = 'album' AND mc.id = albums.id
@@ -1624,6 +2460,7 @@ pub async fn mark_missing(
             )
             .bind(now)
             .bind(&remote_json)
+            .bind(server_id)
             .execute(&mut *tx)
             .await?
             .rows_affected();
@@ -1674,6 +2511,12 @@ pub async fn mark_missing(
 
         _ => {}
     }
+    // new rows were inserted with last_checked_at = now
+    sqlx::query(
+        "UPDATE missing_counters SET missing_since = last_checked_at WHERE missing_since IS NULL",
+    )
+    .execute(&mut *tx)
+    .await?;
 
     // reset missing counters for items that appeared again remotely
     sqlx::query(
@@ -1693,7 +2536,16 @@ pub async fn mark_missing(
         r#"
         SELECT id FROM missing_counters
         WHERE entity_type = ?
-          AND missing_seen_count >= ?;
+          AND missing_seen_count >= ?
+          AND local_only = 0
+          -- albums with downloads are held back as tombstones, the user decides
+          AND NOT (
+              entity_type = 'album'
+              AND EXISTS (
+                  SELECT 1 FROM tracks
+                  WHERE album_id = missing_counters.id AND download_status = 'Downloaded'
+              )
+          );
         "#,
     )
     .bind(entity_type)
@@ -1705,39 +2557,7 @@ pub async fn mark_missing(
     for (id,) in stale {
         match entity_type {
             "album" => {
-                // delete playlist + artist memberships that refer to tracks on this album
-                sqlx::query(
-                    r#"DELETE FROM playlist_membership
-                       WHERE track_id IN (SELECT id FROM tracks WHERE album_id = ?)"#,
-                )
-                .bind(&id)
-                .execute(&mut *tx)
-                .await?;
-
-                sqlx::query(
-                    r#"DELETE FROM artist_membership
-                       WHERE track_id IN (SELECT id FROM tracks WHERE album_id = ?)"#,
-                )
-                .bind(&id)
-                .execute(&mut *tx)
-                .await?;
-
-                // delete tracks and album relations
-                sqlx::query("DELETE FROM tracks WHERE album_id = ?")
-                    .bind(&id)
-                    .execute(&mut *tx)
-                    .await?;
-
-                sqlx::query("DELETE FROM album_artist WHERE album_id = ?")
-                    .bind(&id)
-                    .execute(&mut *tx)
-                    .await?;
-
-                let rows_affected = sqlx::query("DELETE FROM albums WHERE id = ?")
-                    .bind(&id)
-                    .execute(&mut *tx)
-                    .await?
-                    .rows_affected();
+                let rows_affected = delete_album(&mut tx, &id).await?;
 
                 if rows_affected > 0 {
                     sqlx::query("DELETE FROM missing_counters WHERE entity_type = ? AND id = ?")
@@ -1785,6 +2605,7 @@ pub async fn mark_missing(
                     .bind(&id)
                     .execute(&mut *tx)
                     .await?;
+                forget_playlist(&mut tx, &id).await?;
                 sqlx::query("DELETE FROM playlists WHERE id = ?")
                     .bind(&id)
                     .execute(&mut *tx)
@@ -1819,6 +2640,9 @@ pub async fn mark_missing(
         if deleted_playlists {
             db_thread_tx.send(Status::PlaylistsUpdated).await.ok();
         }
+        if entity_type == "album" {
+            db_thread_tx.send(Status::TombstonesUpdated).await.ok();
+        }
     }
 
     Ok(())
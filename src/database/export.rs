@@ -0,0 +1,249 @@
+/* --------------------------
+Export of offline downloads into a regular music folder
+    - Downloads are stored as <server_id>/<album_id>/<track_id>, which no other player understands
+    - The export lays them out by a path template, with the extension of the stored container
+    - cover art and lyrics (.lrc) from the cache end up next to the tracks
+    - Existing files of the same size are skipped, so running it again only adds what is new
+    - Tracks the template gives the same path get a numbered suffix instead of overwriting each other
+-------------------------- */
+
+use super::database::Status;
+use super::extension::{get_lyrics, DownloadStatus};
+use crate::client::{DiscographySong, Lyric};
+use crate::config::ExportSettings;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::mpsc::Sender;
+
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub exported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub path: String,
+}
+
+/// Spawned by the database thread, reports back with `Status::DownloadsExported`
+///
+pub async fn t_export_downloads(
+    pool: SqlitePool,
+    tx: Sender<Status>,
+    data_dir: PathBuf,
+    settings: ExportSettings,
+) {
+    match export_downloads(&pool, &data_dir, &settings).await {
+        Ok(summary) => {
+            let _ = tx.send(Status::DownloadsExported { summary }).await;
+        }
+        Err(e) => {
+            log::error!("Failed to export downloads: {}", e);
+            let _ = tx.send(Status::Error { error: format!("Export failed: {}", e) }).await;
+        }
+    }
+}
+
+async fn export_downloads(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    settings: &ExportSettings,
+) -> Result<ExportSummary, Box<dyn std::error::Error + Send + Sync>> {
+    let root = crate::queue_io::expand_path(&settings.path);
+    fs::create_dir_all(&root).await?;
+
+    let rows: Vec<(String, String, Option<String>)> = sqlx::query_as(
+        // a stable order, so the numbered duplicates keep their names between runs
+        "SELECT album_id, track, download_container FROM tracks WHERE download_status = 'Downloaded'
+         ORDER BY album_id, disc_number, index_number, id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut summary =
+        ExportSummary { path: root.to_string_lossy().to_string(), ..Default::default() };
+    let mut album_dirs = HashSet::new();
+    let mut targets = HashSet::new();
+
+    for (album_id, track_str, container) in rows {
+        let Ok(mut track) = serde_json::from_str::<DiscographySong>(&track_str) else {
+            summary.failed += 1;
+            continue;
+        };
+        // the query only reads downloaded tracks, the JSON doesn't carry the status
+        track.download_status = DownloadStatus::Downloaded;
+        let source = data_dir.join(&track.server_id).join(&album_id).join(&track.id);
+        let relative = render_template(&settings.template, &track);
+        let extension = extension(&track, container.as_deref());
+        let mut target = root.join(format!("{}.{}", relative, extension));
+        let mut n = 1;
+        while !targets.insert(target.clone()) {
+            n += 1;
+            target = root.join(format!("{} ({}).{}", relative, n, extension));
+        }
+
+        match export_file(&source, &target, settings.hardlink).await {
+            Ok(true) => summary.exported += 1,
+            Ok(false) => summary.skipped += 1,
+            Err(e) => {
+                log::warn!("Failed to export {} to {}: {}", track.id, target.display(), e);
+                summary.failed += 1;
+                continue;
+            }
+        }
+
+        let lyrics = get_lyrics(pool, &track.id).await.unwrap_or_default();
+        if !lyrics.is_empty() {
+            let _ = fs::write(target.with_extension("lrc"), to_lrc(&lyrics)).await;
+        }
+
+        if let Some(dir) = target.parent() {
+            if album_dirs.insert(dir.to_path_buf()) {
+                export_cover(&track.parent_id, dir).await;
+            }
+        }
+    }
+
+    log::info!(
+        "Exported {} downloads to {} ({} up to date, {} failed)",
+        summary.exported,
+        summary.path,
+        summary.skipped,
+        summary.failed
+    );
+
+    Ok(summary)
+}
+
+/// Fills in `{artist}`, `{album}`, `{year}`, `{disc}`, `{track}` and `{title}`. Every path component is
+/// sanitized on its own, so a `/` in a title doesn't create directories
+///
+fn render_template(template: &str, track: &DiscographySong) -> String {
+    let artist = if track.album_artist.is_empty() {
+        track.artists.first().cloned().unwrap_or_default()
+    } else {
+        track.album_artist.clone()
+    };
+    // "Album ()" looks broken, drop the parentheses together with a missing year
+    let template = if track.production_year == 0 {
+        template.replace(" ({year})", "").replace("{year}", "")
+    } else {
+        template.to_string()
+    };
+
+    template
+        .split('/')
+        .map(|component| {
+            sanitize(
+                &component
+                    .replace("{artist}", &artist)
+                    .replace("{album}", &track.album)
+                    .replace("{year}", &track.production_year.to_string())
+                    .replace("{disc}", &track.parent_index_number.max(1).to_string())
+                    .replace("{track}", &format!("{:02}", track.index_number))
+                    .replace("{title}", &track.name),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Keeps names valid on FAT formatted players and car stereos
+///
+fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_end_matches('.').trim();
+    if cleaned.is_empty() {
+        "Unknown".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Transcoded downloads use the requested container, originals the one of the source file.
+/// Jellyfin reports some containers as a list, e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+///
+fn extension(track: &DiscographySong, container: Option<&str>) -> String {
+    let container = container
+        .map(|c| c.to_string())
+        .or_else(|| track.media_sources.first().map(|m| m.container.clone()))
+        .unwrap_or_default()
+        .to_lowercase();
+    if container.split(',').any(|c| c == "m4a") {
+        return "m4a".to_string();
+    }
+    match container.split(',').next() {
+        Some("") | None => "audio".to_string(),
+        Some(ext) => ext.to_string(),
+    }
+}
+
+/// Returns whether the file was written, `false` means an identical sized file was already there
+///
+async fn export_file(source: &Path, target: &Path, hardlink: bool) -> Result<bool, std::io::Error> {
+    let source_len = fs::metadata(source).await?.len();
+    if let Ok(existing) = fs::metadata(target).await {
+        if existing.len() == source_len {
+            return Ok(false);
+        }
+        fs::remove_file(target).await?;
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // hardlinks fail across filesystems, e.g. when exporting to an sd card
+    if hardlink && fs::hard_link(source, target).await.is_ok() {
+        return Ok(true);
+    }
+    fs::copy(source, target).await?;
+    Ok(true)
+}
+
+/// Copies the cached album art as `cover.jpg` (or `cover.png` / `cover.webp`) if there is none yet
+///
+async fn export_cover(album_id: &str, dir: &Path) {
+    let Some(cover_dir) = dirs::data_dir().map(|d| d.join("jellyfin-tui").join("covers")) else {
+        return;
+    };
+    for (ext, target) in [("jpg", "jpg"), ("jpeg", "jpg"), ("png", "png"), ("webp", "webp")] {
+        let source = cover_dir.join(format!("{}.{}", album_id, ext));
+        if fs::metadata(&source).await.is_err() {
+            continue;
+        }
+        let target = dir.join(format!("cover.{}", target));
+        if fs::metadata(&target).await.is_err() {
+            let _ = fs::copy(&source, &target).await;
+        }
+        return;
+    }
+}
+
+/// Synced lyrics get `[mm:ss.xx]` timestamps, unsynced ones are written as plain lines
+///
+fn to_lrc(lyrics: &[Lyric]) -> String {
+    let synced = lyrics.iter().any(|l| l.start > 0);
+    lyrics
+        .iter()
+        .map(|l| {
+            if !synced {
+                return format!("{}\n", l.text);
+            }
+            // jellyfin ticks are 100ns
+            let centis = l.start / 100_000;
+            format!(
+                "[{:02}:{:02}.{:02}]{}\n",
+                centis / 6000,
+                (centis / 100) % 60,
+                centis % 100,
+                l.text
+            )
+        })
+        .collect()
+}
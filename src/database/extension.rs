@@ -1,17 +1,29 @@
-use super::database::{DownloadItem, Status};
+use super::database::{Command, DownloadCommand, DownloadItem, Status, UpdateCommand};
+use super::integrity::{check_database, prepare_rebuild, restore};
+use super::outbox::{
+    add_to_local_playlist, has_pending_changes, remove_from_local_playlist, reorder_local_playlist,
+    ChangeOutcome, Mutation,
+};
+use super::playlist_history::record_own_change;
+use super::sync_rules::{get_sync_rules, get_synced_track_ids, SyncRuleCommand, SyncRuleKind};
+use super::tombstones::get_tombstones;
 use crate::client::LibraryView;
 use crate::{
     client::{Album, Artist, Client, DiscographySong, Lyric, Playlist},
+    config::DownloadSettings,
     database::database::data_updater,
-    keyboard::ActiveSection,
+    helpers::format_size,
+    keyboard::{ActiveSection, SearchSection},
     popup::PopupMenu,
+    servers::Servers,
     tui,
 };
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use sqlx::migrate::Migrator;
 use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
 use sqlx::{migrate::MigrateDatabase, FromRow, Pool, Row, Sqlite, SqlitePool};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -41,15 +53,23 @@ impl fmt::Display for DownloadStatus {
     }
 }
 
+impl DownloadStatus {
+    /// The status as stored in the `download_status` column. The track JSON does not carry it
+    ///
+    pub fn from_column(status: &str) -> Self {
+        match status {
+            "Downloaded" => DownloadStatus::Downloaded,
+            "Queued" => DownloadStatus::Queued,
+            "Downloading" => DownloadStatus::Downloading,
+            _ => DownloadStatus::NotDownloaded,
+        }
+    }
+}
+
 impl<'r> FromRow<'r, sqlx::sqlite::SqliteRow> for DownloadStatus {
     fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
         let status: String = row.get(0);
-        match status.as_str() {
-            "Downloaded" => Ok(DownloadStatus::Downloaded),
-            "Queued" => Ok(DownloadStatus::Queued),
-            "Downloading" => Ok(DownloadStatus::Downloading),
-            _ => Ok(DownloadStatus::NotDownloaded),
-        }
+        Ok(DownloadStatus::from_column(&status))
     }
 }
 
@@ -89,6 +109,30 @@ impl tui::App {
             Status::NetworkQualityChanged(network_quality) => {
                 self.network_quality = network_quality;
             }
+            Status::DownloadUsage { bytes } => {
+                self.download_usage = bytes;
+            }
+            Status::DownloadQuotaFull => {
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                }
+                self.set_generic_message(
+                    "Download quota full",
+                    &format!(
+                        "Downloads use {}. The rest of the queue waits until there is room again: play or remove downloads, or raise download_quota_gb.",
+                        format_size(self.download_usage)
+                    ),
+                );
+            }
+            Status::SyncRulesUpdated => {
+                self.sync_rules = get_sync_rules(&self.db.pool).await.unwrap_or_default();
+                self.synced_ids = get_synced_track_ids(&self.db.pool)
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+            }
             Status::AllDownloaded => {
                 // pretty nifty huh
                 if let Some(popup) = &mut self.popup.current_menu {
@@ -96,10 +140,10 @@ impl tui::App {
                         *downloading = false;
                     }
                 }
-                self.download_item = None;
+                self.downloads.clear();
             }
-            Status::ProgressUpdate { progress } => {
-                if let Some(download_item) = &mut self.download_item {
+            Status::ProgressUpdate { id, progress } => {
+                if let Some(download_item) = self.downloads.iter_mut().find(|d| d.id == id) {
                     download_item.progress = progress;
                 }
             }
@@ -115,8 +159,12 @@ impl tui::App {
                 }
             }
             Status::TrackDownloaded { id } => {
-                if let Some(download_item) = &mut self.download_item {
-                    download_item.progress = 100.0;
+                self.downloads.retain(|d| d.id != id);
+                if self.download_settings.transcoding.is_some() {
+                    self.download_qualities
+                        .insert(id.clone(), self.download_settings.target_label());
+                } else {
+                    self.download_qualities.remove(&id);
                 }
                 if let Some(track) = self.tracks.iter_mut().find(|t| t.id == id) {
                     track.download_status = DownloadStatus::Downloaded;
@@ -129,23 +177,32 @@ impl tui::App {
                 }
             }
             Status::TrackDownloading { track } => {
-                self.download_item = Some(DownloadItem { name: track.name, progress: 0.0 });
+                self.downloads.retain(|d| d.id != track.id);
+                self.downloads.push(DownloadItem {
+                    id: track.id.clone(),
+                    name: track.name.clone(),
+                    progress: 0.0,
+                });
                 if let Some(popup) = &mut self.popup.current_menu {
                     if let PopupMenu::GlobalRoot { downloading, .. } = popup {
                         *downloading = true;
                     }
                 }
-                if let Some(track) = self.tracks.iter_mut().find(|t| t.id == track.id) {
-                    track.download_status = DownloadStatus::Downloading;
-                }
-                if let Some(track) = self.album_tracks.iter_mut().find(|t| t.id == track.id) {
-                    track.download_status = DownloadStatus::Downloading;
-                }
-                if let Some(track) = self.playlist_tracks.iter_mut().find(|t| t.id == track.id) {
-                    track.download_status = DownloadStatus::Downloading;
+                // a re-download keeps the offline copy until it is done
+                for t in self
+                    .tracks
+                    .iter_mut()
+                    .chain(self.album_tracks.iter_mut())
+                    .chain(self.playlist_tracks.iter_mut())
+                    .filter(|t| t.id == track.id)
+                    .filter(|t| !matches!(t.download_status, DownloadStatus::Downloaded))
+                {
+                    t.download_status = DownloadStatus::Downloading;
                 }
             }
             Status::TrackDeleted { id } => {
+                self.downloads.retain(|d| d.id != id);
+                self.download_qualities.remove(&id);
                 if let Some(track) = self.tracks.iter_mut().find(|t| t.id == id) {
                     track.download_status = DownloadStatus::NotDownloaded;
                 }
@@ -187,6 +244,15 @@ impl tui::App {
                 self.original_playlists =
                     get_all_playlists(&self.db.pool).await.unwrap_or_default();
                 self.reorder_lists();
+                self.reload_playlist_activity().await;
+            }
+            Status::PlaylistChangesUpdated { id } => {
+                // the open playlist is being looked at
+                if self.state.current_playlist.id == id {
+                    self.mark_playlist_seen(&id).await;
+                } else {
+                    self.reload_playlist_activity().await;
+                }
             }
             Status::DiscographyUpdated { id } => {
                 if self.state.current_artist.id == id {
@@ -263,6 +329,86 @@ impl tui::App {
                 );
                 self.db_updating = false;
             }
+            Status::OfflineRepairFinished { summary } => {
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                }
+                let message = if summary.missing + summary.requeued == 0 {
+                    format!("All {} offline tracks are intact.", summary.checked)
+                } else {
+                    format!(
+                        "Checked {} offline tracks. {} missing, {} corrupt and queued for download again.",
+                        summary.checked, summary.missing, summary.requeued
+                    )
+                };
+                self.set_generic_message("Offline repair finished", &message);
+            }
+            Status::SearchResults { term, tracks } => {
+                // a newer search replaced these results already
+                if term != self.search_term_last {
+                    return;
+                }
+                let known =
+                    self.search_result_tracks.iter().map(|t| t.id.clone()).collect::<HashSet<_>>();
+                self.search_result_tracks
+                    .extend(tracks.into_iter().filter(|t| !known.contains(&t.id)));
+                self.state.search_track_scroll_state = self
+                    .state
+                    .search_track_scroll_state
+                    .content_length(self.search_result_tracks.len());
+                if self.search_result_artists.is_empty() && self.search_result_albums.is_empty() {
+                    self.state.search_section = SearchSection::Tracks;
+                }
+            }
+            Status::OutboxConflicts { conflicts } => {
+                self.outbox_conflicts = conflicts;
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                    self.popup.global = true;
+                    self.popup.current_menu = Some(PopupMenu::GlobalOutboxConflicts {
+                        conflicts: self.outbox_conflicts.clone(),
+                    });
+                    self.popup.selected.select_first();
+                }
+            }
+            Status::DownloadsExported { summary } => {
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                }
+                let mut message = format!(
+                    "{} tracks written to {}, {} already up to date.",
+                    summary.exported, summary.path, summary.skipped
+                );
+                if summary.failed > 0 {
+                    message += &format!(" {} failed, see the log for details.", summary.failed);
+                }
+                self.set_generic_message("Downloads exported", &message);
+            }
+            Status::TombstonesUpdated => {
+                self.tombstones = get_tombstones(&self.db.pool).await.unwrap_or_default();
+                if let Some(PopupMenu::GlobalTombstones { tombstones }) =
+                    &mut self.popup.current_menu
+                {
+                    *tombstones = self.tombstones.clone();
+                }
+            }
+            Status::TombstoneResolved { message } => {
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                }
+                self.set_generic_message("Removed from server", &message);
+            }
+            Status::MaintenanceFinished { report } => {
+                if self.state.active_section != ActiveSection::Popup {
+                    self.state.last_section = self.state.active_section;
+                    self.state.active_section = ActiveSection::Popup;
+                }
+                self.set_generic_message("Maintenance finished", &report.summary());
+            }
             Status::Error { error } => {
                 self.state.last_section = self.state.active_section;
                 self.state.active_section = ActiveSection::Popup;
@@ -271,12 +417,167 @@ impl tui::App {
         }
     }
 
+    /// Pinned downloads are skipped when the download quota evicts tracks
+    ///
+    pub async fn set_pinned(&mut self, track_ids: Vec<String>, pinned: bool) {
+        for id in &track_ids {
+            if pinned {
+                self.pinned_ids.insert(id.clone());
+            } else {
+                self.pinned_ids.remove(id);
+            }
+        }
+        let _ = self.db.cmd_tx.send(Command::PinTracks { track_ids, pinned }).await;
+    }
+
+    /// Queues downloaded tracks again so they are fetched at the configured offline quality
+    ///
+    pub async fn redownload(&mut self, track_ids: Vec<String>) {
+        let _ =
+            self.db.cmd_tx.send(Command::Download(DownloadCommand::Redownload { track_ids })).await;
+    }
+
+    /// Quality of the offline copy of a downloaded track, `None` when there is none
+    ///
+    pub fn offline_copy(&self, track: &DiscographySong) -> Option<String> {
+        if !matches!(track.download_status, DownloadStatus::Downloaded) {
+            return None;
+        }
+        Some(
+            self.download_qualities
+                .get(&track.id)
+                .cloned()
+                .unwrap_or_else(|| DownloadSettings::quality_label(None, None)),
+        )
+    }
+
+    /// Stores the rule and evaluates it right away when online, otherwise on the next library update
+    ///
+    pub async fn add_sync_rule(&mut self, kind: SyncRuleKind) {
+        let _ = self.db.cmd_tx.send(Command::SyncRule(SyncRuleCommand::Add { kind })).await;
+        if self.client.is_some() {
+            let _ = self.db.cmd_tx.send(Command::Update(UpdateCommand::SyncRules)).await;
+        }
+    }
+
+    pub async fn remove_sync_rule(&mut self, id: i64) {
+        let _ = self.db.cmd_tx.send(Command::SyncRule(SyncRuleCommand::Remove { id })).await;
+    }
+
+    /// The rule pinning an artist, album or playlist
+    ///
+    pub fn pin_rule(&self, item_id: &str) -> Option<i64> {
+        self.sync_rules
+            .iter()
+            .find(|rule| rule.kind.pinned_id() == Some(item_id))
+            .map(|rule| rule.id)
+    }
+
+    /// Pinned items are fetched from their own server, so a pin can only be added while it is connected
+    ///
+    pub fn can_pin(&self, server_id: &str) -> bool {
+        self.servers.client_for(server_id).is_some()
+    }
+
+    pub fn pinned_items(&self) -> HashSet<String> {
+        self.sync_rules
+            .iter()
+            .filter_map(|rule| rule.kind.pinned_id().map(|id| id.to_string()))
+            .collect()
+    }
+
+    pub fn playlist_sync_rule(&self, playlist_id: &str) -> Option<i64> {
+        self.sync_rules.iter().find_map(|rule| match &rule.kind {
+            SyncRuleKind::Playlist { id, .. } if id == playlist_id => Some(rule.id),
+            _ => None,
+        })
+    }
+
+    /// Sends a playlist change to the server. Without a connection the change is saved in the local cache
+    /// and queued in the outbox, it is sent once the server is reachable again. The same happens while older
+    /// changes to the playlist are still waiting there, so they reach the server in order
+    ///
+    pub async fn send_playlist_change(&mut self, mutation: Mutation) -> ChangeOutcome {
+        let waiting =
+            has_pending_changes(&self.db.pool, mutation.target_id()).await.unwrap_or(false);
+        if let Some(client) = self.client.clone().filter(|_| !waiting) {
+            let result = match &mutation {
+                Mutation::Favorite { .. } => Ok(()),
+                Mutation::PlaylistAdd { playlist_id, track_ids } => {
+                    client.add_tracks_to_playlist(track_ids, playlist_id).await
+                }
+                Mutation::PlaylistRemove { playlist_id, track_ids } => {
+                    let entry_ids = self
+                        .playlist_tracks
+                        .iter()
+                        .filter(|t| track_ids.contains(&t.id))
+                        .map(|t| t.playlist_item_id.clone())
+                        .collect::<Vec<_>>();
+                    client.remove_tracks_from_playlist(&entry_ids, playlist_id).await
+                }
+                Mutation::PlaylistMove { playlist_id, item_id, new_index } => client
+                    .move_playlist_item(item_id, playlist_id, *new_index)
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map(|_| ()),
+                Mutation::PlaylistRename { playlist_id, new_name, .. } => {
+                    let playlist = Playlist {
+                        id: playlist_id.clone(),
+                        name: new_name.clone(),
+                        ..Default::default()
+                    };
+                    client
+                        .update_playlist(&playlist)
+                        .await
+                        .and_then(|r| r.error_for_status())
+                        .map(|_| ())
+                }
+            };
+            match result {
+                Ok(()) if !matches!(mutation, Mutation::Favorite { .. }) => {
+                    if let Err(e) = record_own_change(&self.db.pool, &mutation).await {
+                        log::error!("Failed to record the playlist change: {}", e);
+                    }
+                    return ChangeOutcome::Sent;
+                }
+                Err(e) if !e.is_connect() && !e.is_timeout() => {
+                    log::error!("Failed to apply '{}': {}", mutation.describe(), e);
+                    return ChangeOutcome::Failed;
+                }
+                _ => {}
+            }
+        }
+
+        let local = match &mutation {
+            Mutation::PlaylistAdd { playlist_id, track_ids } => {
+                add_to_local_playlist(&self.db.pool, playlist_id, track_ids).await
+            }
+            Mutation::PlaylistRemove { playlist_id, track_ids } => {
+                remove_from_local_playlist(&self.db.pool, playlist_id, track_ids).await
+            }
+            Mutation::PlaylistMove { playlist_id, .. } => {
+                let order = self.playlist_tracks.iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+                reorder_local_playlist(&self.db.pool, playlist_id, &order).await
+            }
+            Mutation::Favorite { .. } | Mutation::PlaylistRename { .. } => Ok(()),
+        };
+        if let Err(e) = local {
+            log::error!("Failed to update the local playlist: {}", e);
+        }
+        if let Err(e) = record_own_change(&self.db.pool, &mutation).await {
+            log::error!("Failed to record the playlist change: {}", e);
+        }
+        let _ = self.db.cmd_tx.send(mutation.into()).await;
+        ChangeOutcome::Queued
+    }
+
     /// Create a database if it doesn't exist. Perform any necessary initialization / migrations etc
     ///
     pub async fn init_db(
-        client: &Option<Arc<Client>>,
+        servers: &Servers,
         db_path: &String,
     ) -> Result<Arc<Pool<Sqlite>>, Box<dyn std::error::Error>> {
+        let client = &servers.primary().cloned();
         if !Sqlite::database_exists(db_path).await.unwrap_or(false) {
             if client.is_none() {
                 return Err("Database does not exist and you are offline. Please connect to the internet and try again.".into());
@@ -307,33 +608,37 @@ impl tui::App {
             .journal_mode(SqliteJournalMode::Wal)
             .busy_timeout(Duration::from_secs(5));
 
-        let pool = Arc::new(
-            SqlitePoolOptions::new().max_connections(4).connect_with(options).await.unwrap_or_else(
-                |_| core::panic!("Fatal error, failed to connect to database: {}", db_path),
-            ),
-        );
-        run_migrations(&*pool).await?;
+        let (pool, problems) =
+            match SqlitePoolOptions::new().max_connections(4).connect_with(options).await {
+                Ok(pool) => {
+                    let problems = match run_migrations(&pool).await {
+                        Ok(()) => check_database(&pool).await,
+                        Err(e) => vec![format!("the migrations failed: {}", e)],
+                    };
+                    (Some(Arc::new(pool)), problems)
+                }
+                // e.g. the file is not a database at all
+                Err(e) => (None, vec![format!("the database could not be opened: {}", e)]),
+            };
+        let pool = match pool {
+            Some(pool) if problems.is_empty() => pool,
+            pool => {
+                if let Some(pool) = pool {
+                    pool.close().await;
+                }
+                let salvage = prepare_rebuild(client, db_path, &problems).await?;
+                // the file is gone now, this creates a new one and fetches the library
+                let pool = Box::pin(Self::init_db(servers, db_path)).await?;
+                restore(&pool, servers, salvage).await?;
+                return Ok(pool);
+            }
+        };
 
         log::info!(" - Database connected: {}", db_path);
 
-        let total_download_size: i64 = sqlx::query_scalar(
-            "SELECT SUM(download_size_bytes) FROM tracks WHERE download_status = 'Downloaded'",
-        )
-        .fetch_one(&*pool)
-        .await
-        .unwrap_or(0);
-
+        let total_download_size = get_download_usage(&pool).await;
         if total_download_size > 0 {
-            let total_download_size_human = if total_download_size < 1024 {
-                format!("{} B", total_download_size)
-            } else if total_download_size < 1024 * 1024 {
-                format!("{:.2} KB", total_download_size as f64 / 1024.0)
-            } else if total_download_size < 1024 * 1024 * 1024 {
-                format!("{:.2} MB", total_download_size as f64 / (1024.0 * 1024.0))
-            } else {
-                format!("{:.2} GB", total_download_size as f64 / (1024.0 * 1024.0 * 1024.0))
-            };
-            println!(" - Library size (this server): {}", total_download_size_human);
+            println!(" - Library size (this server): {}", format_size(total_download_size));
         }
 
         // 1.2.6 -> 1.3.0; this should basically always be 1 at least, if not we update anyway which is fine
@@ -493,7 +798,7 @@ pub async fn query_download_track(
             track
         ) VALUES (?, ?, ?, ?)
         ON CONFLICT(id) DO UPDATE
-          SET download_status = excluded.download_status;
+          SET download_status = excluded.download_status, synced = 0;
         "#,
     )
     .bind(&track.id)
@@ -558,7 +863,7 @@ pub async fn query_download_tracks(
                 track
             ) VALUES (?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE
-              SET download_status = excluded.download_status;
+              SET download_status = excluded.download_status, synced = 0;
             "#,
         )
         .bind(&track.id)
@@ -598,7 +903,7 @@ pub async fn remove_track_download(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut tx = pool.begin().await?;
     let _: (String,) = sqlx::query_as(
-        "UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ? RETURNING id",
+        "UPDATE tracks SET download_status = 'NotDownloaded', redownload = 0, synced = 0 WHERE id = ? RETURNING id",
     )
     .bind(&track.id)
     .fetch_one(&mut *tx)
@@ -608,6 +913,8 @@ pub async fn remove_track_download(
         .join(&track.server_id)
         .join(&track.album_id)
         .join(&track.id);
+    // leftovers of an interrupted download
+    let _ = tokio::fs::remove_file(file_path.with_extension("part")).await;
     if file_path.exists() {
         tokio::fs::remove_file(&file_path).await?;
 
@@ -631,10 +938,12 @@ pub async fn remove_tracks_downloads(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut tx = pool.begin().await?;
     for track in tracks {
-        sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
-            .bind(&track.id)
-            .execute(&mut *tx)
-            .await?;
+        sqlx::query(
+            "UPDATE tracks SET download_status = 'NotDownloaded', redownload = 0, synced = 0 WHERE id = ?",
+        )
+        .bind(&track.id)
+        .execute(&mut *tx)
+        .await?;
     }
 
     tx.commit().await?;
@@ -644,6 +953,7 @@ pub async fn remove_tracks_downloads(
             .join(&track.server_id)
             .join(&track.album_id)
             .join(&track.id);
+        let _ = tokio::fs::remove_file(file_path.with_extension("part")).await;
         if file_path.exists() {
             tokio::fs::remove_file(&file_path).await?;
 
@@ -790,12 +1100,7 @@ pub async fn get_discography(
     let mut tracks = Vec::new();
     for (json_str, download_status, disliked) in records {
         let mut track: DiscographySong = serde_json::from_str(&json_str).unwrap();
-        track.download_status = match download_status.as_str() {
-            "Downloaded" => DownloadStatus::Downloaded,
-            "Queued" => DownloadStatus::Queued,
-            "Downloading" => DownloadStatus::Downloading,
-            _ => DownloadStatus::NotDownloaded,
-        };
+        track.download_status = DownloadStatus::from_column(&download_status);
         track.disliked = disliked != 0;
         tracks.push(track);
     }
@@ -837,12 +1142,7 @@ pub async fn get_album_tracks(
 
     for (json_str, download_status, disliked) in records {
         let mut track: DiscographySong = serde_json::from_str(&json_str)?;
-        track.download_status = match download_status.as_str() {
-            "Downloaded" => DownloadStatus::Downloaded,
-            "Queued" => DownloadStatus::Queued,
-            "Downloading" => DownloadStatus::Downloading,
-            _ => DownloadStatus::NotDownloaded,
-        };
+        track.download_status = DownloadStatus::from_column(&download_status);
         track.disliked = disliked != 0;
         out.push(track);
     }
@@ -859,10 +1159,10 @@ pub async fn get_playlist_tracks(
     playlist_id: &str,
     client: Option<&Arc<Client>>,
 ) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error>> {
-    let records: Vec<(String, i64)> = if client.is_some() {
+    let records: Vec<(String, String, i64)> = if client.is_some() {
         sqlx::query_as(
             r#"
-            SELECT t.track, t.disliked
+            SELECT t.track, t.download_status, t.disliked
             FROM tracks t
             JOIN playlist_membership pm ON t.id = pm.track_id
             WHERE pm.playlist_id = ?
@@ -875,7 +1175,7 @@ pub async fn get_playlist_tracks(
     } else {
         sqlx::query_as(
             r#"
-            SELECT t.track, t.disliked
+            SELECT t.track, t.download_status, t.disliked
             FROM tracks t
             JOIN playlist_membership pm ON t.id = pm.track_id
             WHERE pm.playlist_id = ?
@@ -890,8 +1190,9 @@ pub async fn get_playlist_tracks(
 
     let mut tracks = Vec::new();
 
-    for (json_str, disliked) in records {
+    for (json_str, download_status, disliked) in records {
         let mut track: DiscographySong = serde_json::from_str(&json_str)?;
+        track.download_status = DownloadStatus::from_column(&download_status);
         track.disliked = disliked != 0;
         tracks.push(track);
     }
@@ -1057,77 +1358,89 @@ pub async fn get_playlists_with_tracks(
     Ok(playlists)
 }
 
-pub async fn get_tracks(
+pub async fn get_tracks_by_ids(
     pool: &SqlitePool,
-    search_term: &str,
+    ids: &Vec<String>,
 ) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error>> {
-    let libs = selected_library_ids(pool).await;
-    if libs.is_empty() {
+    if ids.is_empty() {
         return Ok(vec![]);
     }
 
-    let placeholders = vec!["?"; libs.len()].join(",");
+    let id_placeholders = vec!["?"; ids.len()].join(",");
 
     let sql = format!(
         r#"
-        SELECT track
+        SELECT track, download_status, disliked
         FROM tracks
-        WHERE track LIKE ?
-          AND download_status = 'Downloaded'
-          AND library_id IN ({})
+        WHERE id IN ({})
         "#,
-        placeholders
+        id_placeholders
     );
 
-    let mut query = sqlx::query_as::<_, (String,)>(&sql).bind(format!("%{}%", search_term));
+    let mut query = sqlx::query_as::<_, (String, String, i64)>(&sql);
 
-    for lib in libs {
-        query = query.bind(lib);
+    for id in ids {
+        query = query.bind(id);
     }
 
     let rows = query.fetch_all(pool).await?;
 
     let tracks = rows
         .into_iter()
-        .map(|(json,)| serde_json::from_str::<DiscographySong>(&json).unwrap())
+        .map(|(json, download_status, disliked)| {
+            let mut track = serde_json::from_str::<DiscographySong>(&json).unwrap();
+            track.download_status = DownloadStatus::from_column(&download_status);
+            track.disliked = disliked != 0;
+            track
+        })
         .collect();
 
     Ok(tracks)
 }
 
-pub async fn get_tracks_by_ids(
+/// Looks up a track by its metadata, used when importing playlist files.
+/// MusicBrainz album id + title wins, otherwise title + artist (normalized)
+///
+pub async fn find_track_by_metadata(
     pool: &SqlitePool,
-    ids: &Vec<String>,
-) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error>> {
-    if ids.is_empty() {
-        return Ok(vec![]);
-    }
-
-    let id_placeholders = vec!["?"; ids.len()].join(",");
-
-    let sql = format!(
+    title: &str,
+    artist: &str,
+    musicbrainz_album_id: Option<&str>,
+) -> Result<Option<DiscographySong>, Box<dyn std::error::Error>> {
+    let rows = sqlx::query_as::<_, (String, String)>(
         r#"
-        SELECT track
+        SELECT track, download_status
         FROM tracks
-        WHERE id IN ({})
+        WHERE name = ? COLLATE NOCASE
         "#,
-        id_placeholders
-    );
+    )
+    .bind(title)
+    .fetch_all(pool)
+    .await?;
 
-    let mut query = sqlx::query_as::<_, (String,)>(&sql);
+    let candidates = rows
+        .into_iter()
+        .filter_map(|(json, download_status)| {
+            let mut track = serde_json::from_str::<DiscographySong>(&json).ok()?;
+            track.download_status = DownloadStatus::from_column(&download_status);
+            Some(track)
+        })
+        .collect::<Vec<DiscographySong>>();
 
-    for id in ids {
-        query = query.bind(id);
+    if let Some(mbid) = musicbrainz_album_id.filter(|m| !m.is_empty()) {
+        if let Some(track) =
+            candidates.iter().find(|t| t.provider_ids.musicbrainz_album.as_deref() == Some(mbid))
+        {
+            return Ok(Some(track.clone()));
+        }
     }
 
-    let rows = query.fetch_all(pool).await?;
-
-    let tracks = rows
-        .into_iter()
-        .map(|(json,)| serde_json::from_str::<DiscographySong>(&json).unwrap())
-        .collect();
-
-    Ok(tracks)
+    let artist = crate::helpers::normalize_for_search(artist);
+    Ok(candidates.into_iter().find(|t| {
+        artist.is_empty()
+            || crate::helpers::normalize_for_search(&t.album_artist) == artist
+            || t.artists.iter().any(|a| crate::helpers::normalize_for_search(a) == artist)
+    }))
 }
 
 /// Favorite toggles
@@ -1243,6 +1556,54 @@ pub async fn get_recent_track_ids(
     Ok(rows.into_iter().map(|r| r.0).collect())
 }
 
+pub async fn get_disliked_track_ids(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let rows: Vec<(String,)> =
+        sqlx::query_as("SELECT id FROM tracks WHERE disliked = 1").fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+pub async fn get_pinned_track_ids(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let rows: Vec<(String,)> =
+        sqlx::query_as("SELECT id FROM tracks WHERE pinned = 1").fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// Quality labels of downloads that were transcoded, originals are left out
+///
+pub async fn get_download_qualities(
+    pool: &SqlitePool,
+) -> Result<HashMap<String, String>, sqlx::Error> {
+    let rows: Vec<(String, String, Option<i64>)> = sqlx::query_as(
+        "SELECT id, download_container, download_bitrate FROM tracks
+         WHERE download_status = 'Downloaded' AND download_container IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, container, bitrate)| {
+            let bitrate = bitrate.and_then(|b| u32::try_from(b).ok());
+            (id, DownloadSettings::quality_label(Some(&container), bitrate))
+        })
+        .collect())
+}
+
+/// Total size of all downloaded tracks in bytes
+///
+pub async fn get_download_usage(pool: &SqlitePool) -> i64 {
+    sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT SUM(download_size_bytes) FROM tracks WHERE download_status = 'Downloaded'",
+    )
+    .fetch_one(pool)
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or(0)
+}
+
 pub async fn get_last_library_update(pool: &Pool<Sqlite>) -> Option<i64> {
     sqlx::query_scalar::<_, i64>("SELECT value FROM meta WHERE key = 'last_library_update'")
         .fetch_optional(pool)
@@ -1264,6 +1625,8 @@ pub async fn set_last_library_update(pool: &Pool<Sqlite>) {
     .await;
 }
 
+/// A random subset of the downloaded tracks in the selected libraries, disliked tracks left out
+///
 pub async fn get_random_downloaded_tracks(
     pool: &SqlitePool,
     n: usize,
@@ -1272,69 +1635,157 @@ pub async fn get_random_downloaded_tracks(
     only_favorite: bool,
     year_from: Option<u32>,
     year_to: Option<u32>,
-) -> Result<Vec<crate::client::DiscographySong>, Box<dyn std::error::Error>> {
-    use crate::client::DiscographySong;
-
+) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error>> {
     let libs = selected_library_ids(pool).await;
     if libs.is_empty() {
         return Ok(vec![]);
     }
 
-    let placeholders = vec!["?"; libs.len()].join(",");
-    let fetch_limit = (n * 5).max(200) as i64;
-
-    let sql = format!(
+    let records = sqlx::query_as::<_, (String, String, i64)>(
         r#"
         SELECT track, download_status, disliked
         FROM tracks
         WHERE download_status = 'Downloaded'
-          AND library_id IN ({})
+          AND library_id IN (SELECT value FROM json_each(?1))
+          AND disliked = 0
+          AND (?2 = 0 OR played = 1)
+          AND (?3 = 0 OR played = 0)
+          AND (?4 = 0 OR is_favorite = 1)
+          AND (?5 IS NULL OR production_year >= ?5)
+          AND (?6 IS NULL OR production_year <= ?6)
         ORDER BY RANDOM()
-        LIMIT ?
+        LIMIT ?7
         "#,
-        placeholders
-    );
-
-    let mut q = sqlx::query_as::<_, (String, String, i64)>(&sql);
-    for lib in &libs {
-        q = q.bind(lib.clone());
-    }
-    q = q.bind(fetch_limit);
-
-    let records = q.fetch_all(pool).await?;
+    )
+    .bind(json_list(&libs))
+    .bind(only_played)
+    .bind(only_unplayed)
+    .bind(only_favorite)
+    .bind(year_from)
+    .bind(year_to)
+    .bind(n as i64)
+    .fetch_all(pool)
+    .await?;
 
-    let mut tracks: Vec<DiscographySong> = records
+    Ok(records
         .into_iter()
         .filter_map(|(json, ds, disliked)| {
             let mut t: DiscographySong = serde_json::from_str(&json).ok()?;
-            t.download_status = match ds.as_str() {
-                "Downloaded" => DownloadStatus::Downloaded,
-                "Queued" => DownloadStatus::Queued,
-                "Downloading" => DownloadStatus::Downloading,
-                _ => DownloadStatus::NotDownloaded,
-            };
+            t.download_status = DownloadStatus::from_column(&ds);
             t.disliked = disliked != 0;
             Some(t)
         })
-        .collect();
+        .collect())
+}
 
-    if only_played {
-        tracks.retain(|t| t.user_data.played);
-    }
-    if only_unplayed {
-        tracks.retain(|t| !t.user_data.played);
-    }
-    if only_favorite {
-        tracks.retain(|t| t.user_data.is_favorite);
+/// What the offline radio tries to stay close to. Built from one or more seed tracks, or directly
+/// from an artist or genre. Names are compared case-insensitively
+///
+#[derive(Default)]
+pub struct SimilaritySeed {
+    pub genres: HashSet<String>,
+    pub artists: HashSet<String>,
+    pub album_artist_ids: HashSet<String>,
+    pub album_ids: HashSet<String>,
+    pub years: Vec<u64>,
+}
+
+impl SimilaritySeed {
+    pub fn from_tracks(tracks: &[DiscographySong]) -> Self {
+        let mut seed = SimilaritySeed::default();
+        for track in tracks {
+            seed.genres.extend(track.genres.iter().cloned());
+            seed.artists.extend(track.artists.iter().cloned());
+            seed.album_artist_ids.extend(track.album_artists.iter().map(|a| a.id.clone()));
+            seed.album_ids.insert(track.album_id.clone());
+            if track.production_year > 0 {
+                seed.years.push(track.production_year);
+            }
+        }
+        seed
     }
-    if let Some(from) = year_from {
-        tracks.retain(|t| t.production_year >= from as u64);
+}
+
+fn json_list<T: Serialize>(items: impl IntoIterator<Item = T>) -> String {
+    serde_json::to_string(&items.into_iter().collect::<Vec<T>>()).unwrap_or_else(|_| "[]".into())
+}
+
+/// Local recommender over the downloaded tracks, returns at most `limit` of them. The score is computed in SQL:
+/// shared artists weigh the most, then album artists and genres, then era and same album. Tracks sharing
+/// something with the seed come first in a weighted random order (so the best matches are likely, but not
+/// always, picked), random tracks fill up the rest so a radio never runs dry
+///
+pub async fn get_similar_downloaded_tracks(
+    pool: &SqlitePool,
+    seed: &SimilaritySeed,
+    excluded: &HashSet<String>,
+    limit: usize,
+) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error>> {
+    let libs = selected_library_ids(pool).await;
+    if libs.is_empty() {
+        return Ok(vec![]);
     }
-    if let Some(to) = year_to {
-        tracks.retain(|t| t.production_year <= to as u64);
+
+    let records = sqlx::query_as::<_, (String, String, i64, f64)>(
+        r#"
+        SELECT track, download_status, disliked, score
+        FROM (
+            SELECT t.track, t.download_status, t.disliked,
+                4 * (SELECT COUNT(*) FROM track_tags a
+                     WHERE a.kind = 'artist' AND a.track_id = t.id
+                       AND a.value IN (SELECT value FROM json_each(?1)))
+              + 3 * (SELECT COUNT(*) FROM artist_membership m
+                     WHERE m.track_id = t.id AND m.artist_id IN (SELECT value FROM json_each(?2)))
+              + 3 * (SELECT COUNT(*) FROM track_tags g
+                     WHERE g.kind = 'genre' AND g.track_id = t.id
+                       AND g.value IN (SELECT value FROM json_each(?3)))
+              + (t.album_id IN (SELECT value FROM json_each(?4)))
+              + COALESCE((SELECT CASE
+                                     WHEN MIN(ABS(y.value - t.production_year)) <= 2 THEN 2.0
+                                     WHEN MIN(ABS(y.value - t.production_year)) <= 5 THEN 1.0
+                                     WHEN MIN(ABS(y.value - t.production_year)) <= 10 THEN 0.5
+                                 END
+                          FROM json_each(?5) y
+                          WHERE t.production_year > 0), 0.0) AS score
+            FROM tracks t
+            WHERE t.download_status = 'Downloaded'
+              AND t.library_id IN (SELECT value FROM json_each(?6))
+              AND t.id NOT IN (SELECT value FROM json_each(?7))
+        )
+        -- jittered, so which of the weaker matches make it into the limit varies between refills
+        ORDER BY score * (1.0 + ABS(RANDOM() % 1000) / 1000.0) DESC, RANDOM()
+        LIMIT ?8
+        "#,
+    )
+    .bind(json_list(&seed.artists))
+    .bind(json_list(&seed.album_artist_ids))
+    .bind(json_list(&seed.genres))
+    .bind(json_list(&seed.album_ids))
+    .bind(json_list(&seed.years))
+    .bind(json_list(&libs))
+    .bind(json_list(excluded))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let mut similar: Vec<(f64, DiscographySong)> = vec![];
+    let mut rest: Vec<DiscographySong> = vec![];
+    for (json, ds, disliked, score) in records {
+        let Ok(mut track) = serde_json::from_str::<DiscographySong>(&json) else {
+            continue;
+        };
+        track.download_status = DownloadStatus::from_column(&ds);
+        track.disliked = disliked != 0;
+        if score > 0.0 {
+            // weighted random sampling key (Efraimidis-Spirakis), squared to favor close matches
+            let key = rand::random::<f64>().powf(1.0 / (score * score));
+            similar.push((key, track));
+        } else {
+            rest.push(track);
+        }
     }
-    tracks.retain(|t| !t.disliked);
-    tracks.truncate(n);
+    similar.sort_by(|a, b| b.0.total_cmp(&a.0));
+    rest.shuffle(&mut rand::rng());
 
-    Ok(tracks)
+    Ok(similar.into_iter().map(|(_, t)| t).chain(rest).collect())
 }
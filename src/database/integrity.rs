@@ -0,0 +1,637 @@
+/* --------------------------
+Startup self-check of the database
+    - After the migrations the file goes through `PRAGMA integrity_check` and must hold every table and trigger the
+      migrations create. A failed migration counts as a failed check
+    - A file that can't be opened as a database at all is treated the same as one that failed the check
+    - A damaged database is rebuilt from the server once the user agrees: the downloads, dislikes, sync rules, play
+      and playlist history, unsent plays and outbox changes that can still be read are saved, the file is moved aside
+      as .broken and the library is fetched again
+    - The playlist history keeps the membership of its playlists, compared with an empty one the first refresh
+      would record every track as added
+    - Downloads are mapped back by their files in every server's directory, tracks the damaged file had no readable
+      row for are looked up on their server by their album
+-------------------------- */
+
+use super::library_sync::assign_track_libraries;
+use crate::client::{Client, DiscographySong};
+use crate::servers::Servers;
+use crate::themes::dialoguer::DialogTheme;
+use dialoguer::Confirm;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Every table the migrations leave behind
+const EXPECTED_TABLES: [&str; 23] = [
+    "_sqlx_migrations",
+    "album_artist",
+    "albums",
+    "artist_membership",
+    "artists",
+    "libraries",
+    "lyrics",
+    "merged_items",
+    "meta",
+    "missing_counters",
+    "outbox",
+    "pending_plays",
+    "play_log",
+    "playlist_changes",
+    "playlist_membership",
+    "playlist_visits",
+    "playlists",
+    "search_index",
+    "search_items",
+    "sync_rule_tracks",
+    "sync_rules",
+    "track_tags",
+    "tracks",
+];
+
+/// The triggers the updaters rely on, without them the search index goes stale unnoticed
+const EXPECTED_TRIGGERS: [&str; 19] = [
+    "search_albums_delete",
+    "search_albums_insert",
+    "search_albums_update",
+    "search_artists_delete",
+    "search_artists_insert",
+    "search_artists_update",
+    "search_lyrics_delete",
+    "search_lyrics_insert",
+    "search_lyrics_update",
+    "search_playlist_membership_delete",
+    "search_playlist_membership_insert",
+    "search_playlists_update",
+    "search_reindex_insert",
+    "search_tracks_delete",
+    "search_tracks_insert",
+    "search_tracks_update",
+    "track_tags_delete",
+    "track_tags_insert",
+    "track_tags_update",
+];
+
+/// What `integrity_check` reports at most, the first few problems are enough to tell
+const MAX_REPORTED: usize = 5;
+
+/// Returns the problems found, empty if the database is fine
+///
+pub async fn check_database(pool: &SqlitePool) -> Vec<String> {
+    let start = std::time::Instant::now();
+    let mut problems = vec![];
+
+    match sqlx::query_scalar::<_, String>(&format!("PRAGMA integrity_check({})", MAX_REPORTED))
+        .fetch_all(pool)
+        .await
+    {
+        Ok(rows) if rows.len() == 1 && rows[0] == "ok" => {}
+        Ok(rows) => problems.extend(rows),
+        Err(e) => {
+            // not even the check runs, e.g. the file is not a database at all
+            problems.push(e.to_string());
+            return problems;
+        }
+    }
+
+    let objects: Vec<(String, String)> = match sqlx::query_as(
+        "SELECT type, name FROM sqlite_master WHERE type IN ('table', 'trigger')",
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(objects) => objects,
+        Err(e) => {
+            problems.push(e.to_string());
+            return problems;
+        }
+    };
+    let has = |kind: &str, name: &str| objects.iter().any(|(t, n)| t == kind && n == name);
+    for table in EXPECTED_TABLES.iter().filter(|table| !has("table", table)) {
+        problems.push(format!("table {} is missing", table));
+    }
+    for trigger in EXPECTED_TRIGGERS.iter().filter(|trigger| !has("trigger", trigger)) {
+        problems.push(format!("trigger {} is missing", trigger));
+    }
+
+    let failed: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 0")
+            .fetch_all(pool)
+            .await
+            .unwrap_or_default();
+    for version in failed {
+        problems.push(format!("migration {} did not finish", version));
+    }
+
+    log::info!("Database check took {:?}, {} problems", start.elapsed(), problems.len());
+    problems
+}
+
+/// A track row that could still be read from the damaged database
+///
+#[derive(sqlx::FromRow)]
+struct SavedTrack {
+    track: String,
+    disliked: bool,
+}
+
+/// The download details of a saved track, from columns later migrations added
+///
+#[derive(sqlx::FromRow)]
+struct SavedDownload {
+    id: String,
+    download_size_bytes: Option<i64>,
+    downloaded_at: Option<String>,
+    download_container: Option<String>,
+    download_bitrate: Option<i64>,
+    download_checksum: Option<String>,
+    pinned: bool,
+    synced: bool,
+}
+
+/// An offline play that was not sent yet
+///
+#[derive(sqlx::FromRow)]
+struct SavedPlay {
+    track_id: String,
+    position_ticks: i64,
+    played_at: String,
+}
+
+/// A play of the Stats history
+///
+#[derive(sqlx::FromRow)]
+struct SavedPlayLog {
+    track_id: String,
+    played_at: String,
+    listened_ticks: i64,
+    first_play: bool,
+}
+
+/// An entry of a playlist's changelog, the id is kept since the visits point at it
+///
+#[derive(sqlx::FromRow)]
+struct SavedPlaylistChange {
+    id: i64,
+    playlist_id: String,
+    kind: String,
+    track_id: Option<String>,
+    detail: String,
+    by_me: bool,
+    changed_at: i64,
+}
+
+/// A change still waiting in the outbox, conflicts included
+///
+#[derive(sqlx::FromRow)]
+struct SavedChange {
+    mutation: String,
+    created_at: String,
+    conflict: Option<String>,
+    forced: bool,
+}
+
+/// What survives the rebuild
+///
+#[derive(Default)]
+pub struct Salvage {
+    tracks: Vec<SavedTrack>,
+    downloads: HashMap<String, SavedDownload>,
+    plays: Vec<SavedPlay>,
+    play_log: Vec<SavedPlayLog>,
+    playlist_changes: Vec<SavedPlaylistChange>,
+    /// (playlist id, last seen, previous seen)
+    playlist_visits: Vec<(String, i64, i64)>,
+    /// (playlist id, track id, position) of the playlists with a history
+    playlist_membership: Vec<(String, String, i64)>,
+    outbox: Vec<SavedChange>,
+    /// (key, rule), the tracks of a rule are picked again on the next update
+    sync_rules: Vec<(String, String)>,
+}
+
+/// Reports the problems and asks whether to rebuild. On yes, what can be read is saved and the database file is
+/// moved aside, the caller creates a new one in its place and hands the salvage to `restore`
+///
+pub async fn prepare_rebuild(
+    client: &Option<Arc<Client>>,
+    db_path: &str,
+    problems: &[String],
+) -> Result<Salvage, Box<dyn std::error::Error>> {
+    println!(" ! The database {} failed its self-check:", db_path);
+    for problem in problems {
+        println!("   - {}", problem);
+    }
+    if client.is_none() {
+        return Err("The database is damaged and you are offline. Connect to the server and start jellyfin-tui again to rebuild it.".into());
+    }
+
+    let rebuild = Confirm::with_theme(&DialogTheme::default())
+        .with_prompt("Rebuild the local cache from the server? Downloads, dislikes, sync rules, play and playlist history and unsent changes are kept.")
+        .default(true)
+        .wait_for_newline(true)
+        .interact_opt()
+        .unwrap_or(None);
+    if rebuild != Some(true) {
+        return Err("The database is damaged, rebuild declined.".into());
+    }
+
+    let salvage = salvage(db_path).await;
+    println!(" - Saved {} tracks from the damaged database", salvage.tracks.len());
+    if !salvage.play_log.is_empty() {
+        println!(" - Saved {} plays of the listening history", salvage.play_log.len());
+    }
+    if !salvage.plays.is_empty() || !salvage.outbox.is_empty() {
+        println!(
+            " - Saved {} unsent plays and {} unsent changes",
+            salvage.plays.len(),
+            salvage.outbox.len()
+        );
+    }
+
+    // the write-ahead log belongs to the broken file, it goes along
+    for suffix in ["", "-wal", "-shm"] {
+        let from = PathBuf::from(format!("{}{}", db_path, suffix));
+        if from.exists() {
+            std::fs::rename(&from, format!("{}.broken{}", db_path, suffix))?;
+        }
+    }
+    println!(" - The damaged database was kept as {}.broken", db_path);
+
+    Ok(salvage)
+}
+
+/// Best effort, every query that fails leaves its part out and the files on disk fill the gaps
+///
+async fn salvage(db_path: &str) -> Salvage {
+    let Ok(options) = SqliteConnectOptions::from_str(db_path) else {
+        return Salvage::default();
+    };
+    let Ok(pool) =
+        SqlitePoolOptions::new().max_connections(1).connect_with(options.read_only(true)).await
+    else {
+        return Salvage::default();
+    };
+
+    let tracks = match sqlx::query_as::<_, SavedTrack>(
+        r#"
+        SELECT track, disliked != 0 AS disliked
+        FROM tracks
+        WHERE download_status = 'Downloaded' OR disliked != 0
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(tracks) => tracks,
+        // dislikes came with a later migration
+        Err(_) => sqlx::query_as::<_, SavedTrack>(
+            r#"
+            SELECT track, 0 AS disliked
+            FROM tracks
+            WHERE download_status = 'Downloaded'
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap_or_else(|e| {
+            log::warn!("Could not read the tracks of the damaged database: {}", e);
+            vec![]
+        }),
+    };
+
+    let downloads = sqlx::query_as::<_, SavedDownload>(
+        r#"
+        SELECT id, download_size_bytes, downloaded_at, download_container, download_bitrate,
+               download_checksum, pinned != 0 AS pinned, synced != 0 AS synced
+        FROM tracks
+        WHERE download_status = 'Downloaded'
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|download| (download.id.clone(), download))
+    .collect();
+
+    // each of these came with a later migration, an older file simply has none
+    let plays = sqlx::query_as::<_, SavedPlay>(
+        "SELECT track_id, position_ticks, played_at FROM pending_plays ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let play_log = sqlx::query_as::<_, SavedPlayLog>(
+        "SELECT track_id, played_at, listened_ticks, first_play != 0 AS first_play FROM play_log ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let playlist_changes = sqlx::query_as::<_, SavedPlaylistChange>(
+        r#"
+        SELECT id, playlist_id, kind, track_id, detail, by_me != 0 AS by_me, changed_at
+        FROM playlist_changes
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let playlist_visits = sqlx::query_as::<_, (String, i64, i64)>(
+        "SELECT playlist_id, last_seen, previous_seen FROM playlist_visits",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let playlist_membership = sqlx::query_as::<_, (String, String, i64)>(
+        r#"
+        SELECT playlist_id, track_id, position
+        FROM playlist_membership
+        WHERE playlist_id IN (SELECT playlist_id FROM playlist_visits)
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let outbox = sqlx::query_as::<_, SavedChange>(
+        "SELECT mutation, created_at, conflict, forced != 0 AS forced FROM outbox ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let sync_rules =
+        sqlx::query_as::<_, (String, String)>("SELECT key, rule FROM sync_rules ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap_or_default();
+
+    pool.close().await;
+    // without the membership the first refresh would see every track as added, the history starts over instead
+    let playlist_visits = if playlist_membership.is_empty() { vec![] } else { playlist_visits };
+    Salvage {
+        tracks,
+        downloads,
+        plays,
+        play_log,
+        playlist_changes,
+        playlist_visits,
+        playlist_membership,
+        outbox,
+        sync_rules,
+    }
+}
+
+/// The downloaded files of every server, by server and album
+///
+fn downloaded_files() -> BTreeMap<String, BTreeMap<String, HashSet<String>>> {
+    let mut files: BTreeMap<String, BTreeMap<String, HashSet<String>>> = BTreeMap::new();
+    let dir = dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads");
+    let Ok(servers) = std::fs::read_dir(dir) else {
+        return files;
+    };
+    for server in servers.flatten().filter(|s| s.path().is_dir()) {
+        let Some(server_id) = file_name(&server.path()) else {
+            continue;
+        };
+        let Ok(albums) = std::fs::read_dir(server.path()) else {
+            continue;
+        };
+        for album in albums.flatten().filter(|a| a.path().is_dir()) {
+            let Ok(tracks) = std::fs::read_dir(album.path()) else {
+                continue;
+            };
+            for track in tracks.flatten().map(|t| t.path()) {
+                if track.extension().is_some_and(|ext| ext == "part") {
+                    continue;
+                }
+                if let (Some(album_id), Some(track_id)) =
+                    (file_name(&album.path()), file_name(&track))
+                {
+                    files
+                        .entry(server_id.clone())
+                        .or_default()
+                        .entry(album_id)
+                        .or_default()
+                        .insert(track_id);
+                }
+            }
+        }
+    }
+    files
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string())
+}
+
+async fn insert_track(
+    pool: &SqlitePool,
+    track: &DiscographySong,
+    downloaded: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO tracks (id, album_id, download_status, track)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(&track.id)
+    .bind(&track.album_id)
+    .bind(if downloaded { "Downloaded" } else { "NotDownloaded" })
+    .bind(serde_json::to_string(track).unwrap_or_default())
+    .execute(pool)
+    .await?;
+    for artist in &track.album_artists {
+        sqlx::query("INSERT OR IGNORE INTO artist_membership (artist_id, track_id) VALUES (?, ?)")
+            .bind(&artist.id)
+            .bind(&track.id)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Maps the downloads back onto the rebuilt database, applies the saved dislikes and puts back the sync rules and
+/// the plays and changes that were not sent yet
+///
+pub async fn restore(
+    pool: &SqlitePool,
+    servers: &Servers,
+    salvage: Salvage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = downloaded_files();
+    let mut restored = 0;
+    let mut dislikes = 0;
+
+    for saved in &salvage.tracks {
+        let Ok(track) = serde_json::from_str::<DiscographySong>(&saved.track) else {
+            continue;
+        };
+        let on_disk = files
+            .get_mut(&track.server_id)
+            .and_then(|albums| albums.get_mut(&track.album_id))
+            .is_some_and(|album| album.remove(&track.id));
+        insert_track(pool, &track, on_disk).await?;
+        if on_disk {
+            restored += 1;
+        }
+        if saved.disliked {
+            sqlx::query("UPDATE tracks SET disliked = 1 WHERE id = ?")
+                .bind(&track.id)
+                .execute(pool)
+                .await?;
+            dislikes += 1;
+        }
+        if let Some(download) = salvage.downloads.get(&track.id).filter(|_| on_disk) {
+            sqlx::query(
+                r#"
+                UPDATE tracks
+                SET download_size_bytes = ?, downloaded_at = ?, download_container = ?,
+                    download_bitrate = ?, download_checksum = ?, pinned = ?, synced = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(download.download_size_bytes)
+            .bind(&download.downloaded_at)
+            .bind(&download.download_container)
+            .bind(download.download_bitrate)
+            .bind(&download.download_checksum)
+            .bind(download.pinned)
+            .bind(download.synced)
+            .bind(&download.id)
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    // files the damaged database had no readable row for
+    let mut unknown = 0;
+    let albums = files.iter().flat_map(|(server_id, albums)| {
+        albums.iter().map(move |(album_id, track_ids)| (server_id, album_id, track_ids))
+    });
+    for (server_id, album_id, track_ids) in albums.filter(|(_, _, ids)| !ids.is_empty()) {
+        let Some(client) = servers.client_for(server_id) else {
+            log::warn!("Server {} is not connected, can't look up album {}", server_id, album_id);
+            unknown += track_ids.len();
+            continue;
+        };
+        let tracks = match client.album_tracks(album_id).await {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                log::warn!("Could not look up album {} on the server: {}", album_id, e);
+                unknown += track_ids.len();
+                continue;
+            }
+        };
+        for track in tracks.iter().filter(|t| track_ids.contains(&t.id)) {
+            insert_track(pool, track, true).await?;
+            restored += 1;
+        }
+        unknown += track_ids.iter().filter(|id| !tracks.iter().any(|t| &t.id == *id)).count();
+    }
+
+    assign_track_libraries(pool).await?;
+
+    for play in &salvage.plays {
+        sqlx::query(
+            "INSERT INTO pending_plays (track_id, position_ticks, played_at) VALUES (?, ?, ?)",
+        )
+        .bind(&play.track_id)
+        .bind(play.position_ticks)
+        .bind(&play.played_at)
+        .execute(pool)
+        .await?;
+    }
+
+    // the histories can be long, one transaction keeps this quick
+    let mut tx_db = pool.begin().await?;
+    for play in &salvage.play_log {
+        sqlx::query(
+            "INSERT INTO play_log (track_id, played_at, listened_ticks, first_play) VALUES (?, ?, ?, ?)",
+        )
+        .bind(&play.track_id)
+        .bind(&play.played_at)
+        .bind(play.listened_ticks)
+        .bind(play.first_play)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    for change in &salvage.playlist_changes {
+        sqlx::query(
+            r#"
+            INSERT INTO playlist_changes (id, playlist_id, kind, track_id, detail, by_me, changed_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(change.id)
+        .bind(&change.playlist_id)
+        .bind(&change.kind)
+        .bind(&change.track_id)
+        .bind(&change.detail)
+        .bind(change.by_me)
+        .bind(change.changed_at)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    for (playlist_id, last_seen, previous_seen) in &salvage.playlist_visits {
+        sqlx::query(
+            "INSERT OR REPLACE INTO playlist_visits (playlist_id, last_seen, previous_seen) VALUES (?, ?, ?)",
+        )
+        .bind(playlist_id)
+        .bind(last_seen)
+        .bind(previous_seen)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    for (playlist_id, track_id, position) in &salvage.playlist_membership {
+        sqlx::query(
+            "INSERT OR IGNORE INTO playlist_membership (playlist_id, track_id, position) VALUES (?, ?, ?)",
+        )
+        .bind(playlist_id)
+        .bind(track_id)
+        .bind(position)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    tx_db.commit().await?;
+
+    for change in &salvage.outbox {
+        sqlx::query(
+            "INSERT INTO outbox (mutation, created_at, conflict, forced) VALUES (?, ?, ?, ?)",
+        )
+        .bind(&change.mutation)
+        .bind(&change.created_at)
+        .bind(&change.conflict)
+        .bind(change.forced)
+        .execute(pool)
+        .await?;
+    }
+    for (key, rule) in &salvage.sync_rules {
+        sqlx::query("INSERT OR IGNORE INTO sync_rules (key, rule) VALUES (?, ?)")
+            .bind(key)
+            .bind(rule)
+            .execute(pool)
+            .await?;
+    }
+
+    println!(" - Restored {} downloads and {} dislikes", restored, dislikes);
+    if !salvage.sync_rules.is_empty() {
+        println!(" - Restored {} sync rules", salvage.sync_rules.len());
+    }
+    if !salvage.play_log.is_empty() || !salvage.playlist_changes.is_empty() {
+        println!(
+            " - Restored {} plays and {} playlist changes of the history",
+            salvage.play_log.len(),
+            salvage.playlist_changes.len()
+        );
+    }
+    if unknown > 0 {
+        println!(
+            " ! {} downloaded files belong to nothing on the server anymore, they were left on disk",
+            unknown
+        );
+    }
+    Ok(())
+}
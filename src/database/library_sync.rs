@@ -0,0 +1,447 @@
+/* --------------------------
+Incremental library sync
+    - Between full updates only what the server saved since the last sync is fetched (MinDateLastSaved/MinDateLastSavedForUser)
+    - A delta query cannot see removed items, so the album and playlist counts are compared with the server afterwards
+    - A full update still runs once a day, when the counts disagree and while removals are pending in missing_counters
+-------------------------- */
+
+use super::database::Status;
+use super::extension::set_last_library_update;
+use super::playlist_history::record_rename;
+use super::tombstones::ALBUM_MISSING_THRESHOLD;
+use crate::client::{Album, Artist, Client, LibraryView, Playlist};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+
+pub type SyncResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const BATCH_SIZE: usize = 250;
+
+/// How often the full update runs even if nothing points to removals
+pub const FULL_SYNC_INTERVAL_SECS: i64 = 60 * 60 * 24;
+
+/// How many full syncs in a row an artist or a playlist has to be missing for before it is removed
+pub const ARTIST_MISSING_THRESHOLD: i64 = 4;
+pub const PLAYLIST_MISSING_THRESHOLD: i64 = 3;
+
+/// The cursor is moved back by this much, so clock differences and items saved during the last run are not missed
+const CURSOR_OVERLAP_SECS: i64 = 60 * 5;
+
+pub fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+pub async fn get_meta(pool: &SqlitePool, key: &str) -> Option<i64> {
+    sqlx::query_scalar::<_, i64>("SELECT value FROM meta WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+pub async fn set_meta(pool: &SqlitePool, key: &str, value: i64) {
+    let _ = sqlx::query(
+        "INSERT INTO meta (key, value)
+         VALUES (?, ?)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await;
+}
+
+/// The cursor as the server expects it in MinDateLastSaved
+///
+pub fn changes_since(since: i64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(since - CURSOR_OVERLAP_SECS, 0)
+        .map(|since| since.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// The artist list only holds album artists. Changed artists are taken if they are known already or one of their
+/// albums came in with the same changes
+///
+pub async fn album_artists_only(
+    pool: &SqlitePool,
+    artists: Vec<Artist>,
+    albums: &[&Album],
+) -> SyncResult<Vec<Artist>> {
+    let album_artists: HashSet<&str> = albums
+        .iter()
+        .flat_map(|album| album.album_artists.iter())
+        .flat_map(|artist| [artist.id.as_str(), artist.name.as_str()])
+        .collect();
+    let ids = serde_json::to_string(&artists.iter().map(|a| &a.id).collect::<Vec<_>>())?;
+    let known: HashSet<String> = sqlx::query_scalar(
+        "SELECT id FROM artists WHERE id IN (SELECT value FROM json_each(json(?)))",
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+
+    Ok(artists
+        .into_iter()
+        .filter(|a| {
+            known.contains(&a.id)
+                || album_artists.contains(a.id.as_str())
+                || album_artists.contains(a.name.as_str())
+        })
+        .collect())
+}
+
+/// Called after a complete update, `started` is when it began fetching
+///
+pub async fn finish_full_sync(pool: &SqlitePool, started: i64) {
+    set_meta(pool, "last_full_sync", started).await;
+    set_meta(pool, "last_delta_sync", started).await;
+}
+
+/// The time to fetch changes from, or None if a full update is due
+///
+pub async fn delta_cursor(pool: &SqlitePool) -> Option<i64> {
+    let last_full = get_meta(pool, "last_full_sync").await?;
+    if now_secs() - last_full >= FULL_SYNC_INTERVAL_SECS {
+        return None;
+    }
+
+    // removals are only confirmed after several full runs, keep doing them until they are through.
+    // Tombstones, local only albums and artists still in use stay in the table and don't count
+    let pending_removals: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM missing_counters mc
+            WHERE mc.local_only = 0
+              AND mc.missing_seen_count < CASE mc.entity_type
+                  WHEN 'album' THEN ?1
+                  WHEN 'artist' THEN ?2
+                  ELSE ?3
+              END
+              AND NOT (
+                  mc.entity_type = 'album'
+                  AND EXISTS (
+                      SELECT 1 FROM tracks
+                      WHERE album_id = mc.id AND download_status = 'Downloaded'
+                  )
+              )
+        )
+        "#,
+    )
+    .bind(ALBUM_MISSING_THRESHOLD)
+    .bind(ARTIST_MISSING_THRESHOLD)
+    .bind(PLAYLIST_MISSING_THRESHOLD)
+    .fetch_one(pool)
+    .await
+    .unwrap_or(true);
+    if pending_removals {
+        return None;
+    }
+
+    get_meta(pool, "last_delta_sync").await
+}
+
+pub async fn save_libraries(pool: &SqlitePool, libraries: &[LibraryView]) -> sqlx::Result<()> {
+    let mut tx_db = pool.begin().await?;
+    for lib in libraries {
+        sqlx::query(
+            r#"
+            INSERT INTO libraries (id, name, collection_type, last_seen, selected)
+            VALUES (?, ?, ?, CURRENT_TIMESTAMP, 1)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                last_seen = CURRENT_TIMESTAMP;
+            "#,
+        )
+        .bind(&lib.id)
+        .bind(&lib.name)
+        .bind(&lib.collection_type)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    tx_db.commit().await?;
+    Ok(())
+}
+
+/// Returns how many artists were added or changed
+///
+pub async fn upsert_artists(pool: &SqlitePool, artists: &[Artist]) -> SyncResult<u64> {
+    let mut changed = 0;
+    let mut tx_db = pool.begin().await?;
+
+    for (i, artist) in artists.iter().enumerate() {
+        if i != 0 && i % BATCH_SIZE == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let artist_json = serde_json::to_string(&artist)?;
+
+        changed += sqlx::query(
+            r#"
+            INSERT INTO artists (id, artist)
+            VALUES (?, ?)
+            ON CONFLICT(id) DO UPDATE SET artist = excluded.artist
+            WHERE artists.artist != excluded.artist;
+            "#,
+        )
+        .bind(&artist.id)
+        .bind(&artist_json)
+        .execute(&mut *tx_db)
+        .await?
+        .rows_affected();
+    }
+
+    tx_db.commit().await?;
+    Ok(changed)
+}
+
+/// Writes the albums of one library along with their album artists. Returns how many albums were added or changed
+///
+pub async fn upsert_albums(
+    conn: &mut SqliteConnection,
+    library_id: &str,
+    albums: &[Album],
+) -> SyncResult<u64> {
+    let mut changed = 0;
+
+    for (i, album) in albums.iter().enumerate() {
+        if i != 0 && i % BATCH_SIZE == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let album_json = serde_json::to_string(&album)?;
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO albums (id, album, library_id)
+            VALUES (?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                album = excluded.album,
+                library_id = excluded.library_id
+            WHERE albums.album != excluded.album
+               OR albums.library_id IS NULL
+               OR albums.library_id != excluded.library_id;
+            "#,
+        )
+        .bind(&album.id)
+        .bind(&album_json)
+        .bind(library_id)
+        .execute(&mut *conn)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            log::debug!("Album updated: {:?}", album);
+            changed += 1;
+        }
+
+        sqlx::query("DELETE FROM album_artist WHERE album_id = ?")
+            .bind(&album.id)
+            .execute(&mut *conn)
+            .await?;
+
+        for artist in &album.album_artists {
+            let canonical =
+                sqlx::query_scalar::<_, Option<String>>("SELECT id FROM artists WHERE name = ?")
+                    .bind(&artist.name)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .flatten();
+
+            let canonical_id = canonical.unwrap_or_else(|| artist.id.clone());
+
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO album_artist (album_id, artist_id)
+                VALUES (?, ?)
+                "#,
+            )
+            .bind(&album.id)
+            .bind(&canonical_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Returns how many playlists were added or changed
+///
+pub async fn upsert_playlists(pool: &SqlitePool, playlists: &[Playlist]) -> SyncResult<u64> {
+    let mut changed = 0;
+    let mut tx_db = pool.begin().await?;
+
+    for (i, playlist) in playlists.iter().enumerate() {
+        if i != 0 && i % BATCH_SIZE == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let playlist_json = serde_json::to_string(&playlist)?;
+
+        // renames of playlists with a history go into it, a rename made here already updated the cache
+        let old_name: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT p.name
+            FROM playlists p
+            JOIN playlist_visits v ON v.playlist_id = p.id
+            WHERE p.id = ?
+            "#,
+        )
+        .bind(&playlist.id)
+        .fetch_optional(&mut *tx_db)
+        .await?
+        .flatten();
+        if let Some(old_name) = old_name.filter(|old| *old != playlist.name) {
+            record_rename(&mut tx_db, &playlist.id, &old_name, &playlist.name, false).await?;
+        }
+
+        changed += sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO playlists (id, playlist)
+            VALUES (?, ?)
+            ON CONFLICT(id) DO UPDATE SET playlist = excluded.playlist
+            WHERE playlists.playlist != excluded.playlist;
+            "#,
+        )
+        .bind(&playlist.id)
+        .bind(&playlist_json)
+        .execute(&mut *tx_db)
+        .await?
+        .rows_affected();
+    }
+
+    tx_db.commit().await?;
+    Ok(changed)
+}
+
+/// Tracks cached before their album was known get the library of the album
+///
+pub async fn assign_track_libraries(pool: &SqlitePool) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE tracks
+        SET library_id = (
+            SELECT library_id FROM albums WHERE albums.id = tracks.album_id
+        )
+        WHERE library_id IS NULL
+          AND EXISTS (
+              SELECT 1 FROM albums WHERE albums.id = tracks.album_id
+          )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// True if the cache holds more albums or playlists than the server, i.e. something was removed
+///
+async fn removals_pending(
+    pool: &SqlitePool,
+    client: &Client,
+    libraries: &[LibraryView],
+) -> SyncResult<bool> {
+    for lib in libraries {
+        let remote = client.item_count("MusicAlbum", Some(&lib.id)).await?;
+        let local: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM albums WHERE library_id = ?")
+            .bind(&lib.id)
+            .fetch_one(pool)
+            .await?;
+        if local as u64 > remote {
+            log::info!(
+                "Library '{}' has {} albums locally and {} on the server",
+                lib.name,
+                local,
+                remote
+            );
+            return Ok(true);
+        }
+    }
+
+    let remote = client.item_count("Playlist", None).await?;
+    let local: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM playlists").fetch_one(pool).await?;
+    if local as u64 > remote {
+        log::info!("{} playlists locally and {} on the server", local, remote);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Applies what was added or changed on the server since `since` (unix seconds). Returns false if removals were
+/// noticed, in which case the caller has to run the full update to reconcile them
+///
+pub async fn delta_updater(
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    client: &Client,
+    since: i64,
+) -> SyncResult<bool> {
+    let started = now_secs();
+    let start_time = tokio::time::Instant::now();
+
+    let Some(since) = changes_since(since) else {
+        return Ok(false);
+    };
+    log::info!("Fetching library changes since {}", since);
+
+    let music_libs = client.music_libraries().await?;
+    if music_libs.is_empty() {
+        return Err("No music libraries returned".into());
+    }
+    save_libraries(pool, &music_libs).await?;
+
+    // everything is fetched before writing, a failed request leaves the cursor where it was
+    let mut changed_albums = vec![];
+    for lib in &music_libs {
+        changed_albums.push((lib, client.albums_changed_since(&lib.id, &since).await?));
+    }
+    let changed_artists = client.artists_changed_since(&since).await?;
+    let changed_playlists = client.playlists_changed_since(&since).await?;
+
+    let albums = changed_albums.iter().flat_map(|(_, albums)| albums.iter()).collect::<Vec<_>>();
+    let artists = album_artists_only(pool, changed_artists, &albums).await?;
+
+    let artists_changed = upsert_artists(pool, &artists).await?;
+
+    let mut albums_changed = 0;
+    let mut tx_db = pool.begin().await?;
+    for (lib, albums) in &changed_albums {
+        albums_changed += upsert_albums(&mut tx_db, &lib.id, albums).await?;
+    }
+    tx_db.commit().await?;
+    assign_track_libraries(pool).await?;
+
+    let playlists_changed = upsert_playlists(pool, &changed_playlists).await?;
+
+    log::info!(
+        "Delta sync changed {} artists, {} albums and {} playlists in {:.2}s",
+        artists_changed,
+        albums_changed,
+        playlists_changed,
+        start_time.elapsed().as_secs_f32()
+    );
+
+    if artists_changed > 0 {
+        tx.send(Status::ArtistsUpdated).await?;
+    }
+    if albums_changed > 0 {
+        tx.send(Status::AlbumsUpdated).await?;
+    }
+    if playlists_changed > 0 {
+        tx.send(Status::PlaylistsUpdated).await?;
+    }
+
+    if removals_pending(pool, client, &music_libs).await? {
+        return Ok(false);
+    }
+
+    set_meta(pool, "last_delta_sync", started).await;
+    set_last_library_update(pool).await;
+
+    Ok(true)
+}
@@ -0,0 +1,252 @@
+/* --------------------------
+Database maintenance, from the global popup or `--maintenance`
+    - Rows that lost what they belong to are removed: tracks of albums that are gone (unless downloaded or in a
+      playlist), lyrics of removed tracks and memberships pointing at removed tracks, artists or playlists
+    - Cached covers that no library references anymore are deleted. Every server shares the covers directory,
+      so all databases are consulted and nothing is deleted if one of them can't be read
+    - VACUUM hands the freed pages back to the filesystem
+-------------------------- */
+
+use super::database::Status;
+use super::extension::run_migrations;
+use crate::helpers::format_size;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::sync::mpsc::Sender;
+
+pub(super) type MaintenanceResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Default)]
+pub struct MaintenanceReport {
+    pub db_bytes_before: u64,
+    pub db_bytes_after: u64,
+    pub covers_before: (usize, u64),
+    pub covers_after: (usize, u64),
+    pub tracks: u64,
+    pub lyrics: u64,
+    pub artist_memberships: u64,
+    pub playlist_memberships: u64,
+    pub covers: usize,
+}
+
+impl MaintenanceReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Database {} -> {}, covers {} ({}) -> {} ({}). Removed {} tracks, {} lyrics, {} artist and {} playlist memberships, {} covers.",
+            format_size(self.db_bytes_before as i64),
+            format_size(self.db_bytes_after as i64),
+            self.covers_before.0,
+            format_size(self.covers_before.1 as i64),
+            self.covers_after.0,
+            format_size(self.covers_after.1 as i64),
+            self.tracks,
+            self.lyrics,
+            self.artist_memberships,
+            self.playlist_memberships,
+            self.covers,
+        )
+    }
+}
+
+pub(super) fn databases_dir() -> PathBuf {
+    dirs::data_dir().unwrap().join("jellyfin-tui").join("databases")
+}
+
+fn covers_dir() -> PathBuf {
+    dirs::data_dir().unwrap().join("jellyfin-tui").join("covers")
+}
+
+/// The database file along with its write-ahead log
+///
+fn database_size(db_path: &Path) -> u64 {
+    let wal = PathBuf::from(format!("{}-wal", db_path.display()));
+    [db_path, wal.as_path()].iter().filter_map(|p| std::fs::metadata(p).ok()).map(|m| m.len()).sum()
+}
+
+fn covers_size(dir: &Path) -> (usize, u64) {
+    std::fs::read_dir(dir)
+        .map(|files| {
+            files
+                .flatten()
+                .filter_map(|f| f.metadata().ok())
+                .filter(|m| m.is_file())
+                .fold((0, 0), |(count, bytes), m| (count + 1, bytes + m.len()))
+        })
+        .unwrap_or_default()
+}
+
+/// Spawned by the database thread, reports back with `Status::MaintenanceFinished`
+///
+pub async fn t_maintenance(pool: SqlitePool, tx: Sender<Status>, server_id: String) {
+    let db_path = databases_dir().join(format!("{}.db", server_id));
+    let result = async {
+        let mut report = maintain_database(&pool, &db_path).await?;
+        prune_covers(&mut report, Some(&pool)).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(report)
+    }
+    .await;
+    match result {
+        Ok(report) => {
+            log::info!("Maintenance finished: {}", report.summary());
+            let _ = tx.send(Status::MaintenanceFinished { report }).await;
+        }
+        Err(e) => {
+            log::error!("Database maintenance failed: {}", e);
+            let _ = tx.send(Status::Error { error: format!("Maintenance failed: {}", e) }).await;
+        }
+    }
+}
+
+/// `--maintenance`, every server's database and the shared covers
+///
+pub async fn run_from_cli() -> MaintenanceResult<()> {
+    let mut paths = std::fs::read_dir(databases_dir())?
+        .flatten()
+        .map(|f| f.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "db"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut total = MaintenanceReport::default();
+    for path in paths {
+        println!(" - Maintaining {}", path.display());
+        let pool = connect(&path).await?;
+        let report = maintain_database(&pool, &path).await?;
+        pool.close().await;
+        println!(
+            "   {} -> {}, removed {} tracks, {} lyrics, {} artist and {} playlist memberships",
+            format_size(report.db_bytes_before as i64),
+            format_size(report.db_bytes_after as i64),
+            report.tracks,
+            report.lyrics,
+            report.artist_memberships,
+            report.playlist_memberships,
+        );
+        total.db_bytes_before += report.db_bytes_before;
+        total.db_bytes_after += report.db_bytes_after;
+        total.tracks += report.tracks;
+        total.lyrics += report.lyrics;
+        total.artist_memberships += report.artist_memberships;
+        total.playlist_memberships += report.playlist_memberships;
+    }
+
+    prune_covers(&mut total, None).await?;
+    println!(" - {}", total.summary());
+    Ok(())
+}
+
+pub(super) async fn connect(db_path: &Path) -> MaintenanceResult<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(&db_path.to_string_lossy())?
+        .journal_mode(SqliteJournalMode::Wal)
+        .busy_timeout(std::time::Duration::from_secs(5));
+    let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await?;
+    run_migrations(&pool).await?;
+    Ok(pool)
+}
+
+async fn maintain_database(
+    pool: &SqlitePool,
+    db_path: &Path,
+) -> MaintenanceResult<MaintenanceReport> {
+    let mut report =
+        MaintenanceReport { db_bytes_before: database_size(db_path), ..Default::default() };
+
+    let mut tx_db = pool.begin().await?;
+    // the listening history and unsent plays still need the track, e.g. for its length and the stats
+    report.tracks = sqlx::query(
+        "DELETE FROM tracks
+         WHERE album_id NOT IN (SELECT id FROM albums)
+           AND id NOT IN (SELECT track_id FROM playlist_membership)
+           AND id NOT IN (SELECT track_id FROM play_log)
+           AND id NOT IN (SELECT track_id FROM pending_plays)
+           AND download_status = 'NotDownloaded'",
+    )
+    .execute(&mut *tx_db)
+    .await?
+    .rows_affected();
+    report.lyrics = sqlx::query("DELETE FROM lyrics WHERE id NOT IN (SELECT id FROM tracks)")
+        .execute(&mut *tx_db)
+        .await?
+        .rows_affected();
+    report.artist_memberships = sqlx::query(
+        "DELETE FROM artist_membership
+         WHERE track_id NOT IN (SELECT id FROM tracks)
+            OR artist_id NOT IN (SELECT id FROM artists)",
+    )
+    .execute(&mut *tx_db)
+    .await?
+    .rows_affected();
+    report.playlist_memberships = sqlx::query(
+        "DELETE FROM playlist_membership
+         WHERE track_id NOT IN (SELECT id FROM tracks)
+            OR playlist_id NOT IN (SELECT id FROM playlists)",
+    )
+    .execute(&mut *tx_db)
+    .await?
+    .rows_affected();
+    tx_db.commit().await?;
+
+    sqlx::query("VACUUM").execute(pool).await?;
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(pool).await?;
+
+    report.db_bytes_after = database_size(db_path);
+    Ok(report)
+}
+
+/// Every id a cover can be stored under
+///
+async fn referenced_ids(pool: &SqlitePool, ids: &mut HashSet<String>) -> sqlx::Result<()> {
+    let rows: Vec<Option<String>> = sqlx::query_scalar(
+        "SELECT id FROM albums
+         UNION SELECT id FROM artists
+         UNION SELECT id FROM playlists
+         UNION SELECT id FROM tracks
+         UNION SELECT json_extract(track, '$.ParentId') FROM tracks",
+    )
+    .fetch_all(pool)
+    .await?;
+    ids.extend(rows.into_iter().flatten());
+    Ok(())
+}
+
+/// `own` is the database the app has open, the others are only read
+///
+async fn prune_covers(
+    report: &mut MaintenanceReport,
+    own: Option<&SqlitePool>,
+) -> MaintenanceResult<()> {
+    let dir = covers_dir();
+    report.covers_before = covers_size(&dir);
+
+    let mut ids = HashSet::new();
+    if let Some(pool) = own {
+        referenced_ids(pool, &mut ids).await?;
+    }
+    for entry in std::fs::read_dir(databases_dir())?.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        if own.is_some_and(|pool| pool.connect_options().get_filename() == path) {
+            continue;
+        }
+        let options = SqliteConnectOptions::from_str(&path.to_string_lossy())?.read_only(true);
+        let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await?;
+        referenced_ids(&pool, &mut ids).await?;
+        pool.close().await;
+    }
+
+    for file in std::fs::read_dir(&dir)?.flatten() {
+        let name = file.file_name().to_string_lossy().to_string();
+        let id = name.split('.').next().unwrap_or_default();
+        if !ids.contains(id) && std::fs::remove_file(file.path()).is_ok() {
+            report.covers += 1;
+        }
+    }
+
+    report.covers_after = covers_size(&dir);
+    Ok(())
+}
@@ -0,0 +1,6 @@
+PRAGMA foreign_keys = OFF;
+
+-- pinned downloads are never evicted by the storage quota
+ALTER TABLE tracks ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+
+PRAGMA foreign_keys = ON;
@@ -0,0 +1,20 @@
+PRAGMA foreign_keys = OFF;
+
+-- auto-download rules, `rule` is the JSON of a SyncRuleKind and `key` identifies its scope
+CREATE TABLE IF NOT EXISTS sync_rules (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  key TEXT NOT NULL UNIQUE,
+  rule TEXT NOT NULL
+);
+
+-- the tracks each rule covered the last time it was evaluated
+CREATE TABLE IF NOT EXISTS sync_rule_tracks (
+  rule_id INTEGER NOT NULL,
+  track_id TEXT NOT NULL,
+  PRIMARY KEY (rule_id, track_id)
+);
+
+-- downloads queued by a rule, these get removed again once no rule covers them
+ALTER TABLE tracks ADD COLUMN synced INTEGER NOT NULL DEFAULT 0;
+
+PRAGMA foreign_keys = ON;
@@ -0,0 +1,3 @@
+-- quality of the offline copy, NULL when the original file was downloaded
+ALTER TABLE tracks ADD COLUMN download_container TEXT;
+ALTER TABLE tracks ADD COLUMN download_bitrate INTEGER;
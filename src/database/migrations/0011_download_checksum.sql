@@ -0,0 +1,2 @@
+-- sha256 of the offline copy, filled when a download finishes or by the repair pass for older downloads
+ALTER TABLE tracks ADD COLUMN download_checksum TEXT;
@@ -0,0 +1,7 @@
+-- plays that could not be reported to the server, replayed as played/UserData updates once online again
+CREATE TABLE IF NOT EXISTS pending_plays (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  track_id TEXT NOT NULL,
+  position_ticks INTEGER NOT NULL DEFAULT 0,
+  played_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
@@ -0,0 +1,9 @@
+-- changes made while the server was unreachable, applied in order once it is back.
+-- `mutation` is the JSON of a Mutation, `conflict` is set when the server state no longer matches
+CREATE TABLE IF NOT EXISTS outbox (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  mutation TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  conflict TEXT,
+  forced INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,306 @@
+-- full-text search over the local cache. `search_source` is what gets indexed, `search_items` maps
+-- the rows of the index to the items, the triggers below keep it up to date whenever the updaters write.
+-- no OR IGNORE in the triggers, the conflict clause of an outer INSERT OR REPLACE would override it
+CREATE TABLE IF NOT EXISTS search_items (
+  rowid INTEGER PRIMARY KEY,
+  kind TEXT NOT NULL,
+  item_id TEXT NOT NULL,
+  UNIQUE (kind, item_id)
+);
+
+-- the playlist names of a track are looked up by track
+CREATE INDEX IF NOT EXISTS idx_playlist_membership_track ON playlist_membership (track_id);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(
+  name, artists, album, genres, playlists, lyrics,
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIEW IF NOT EXISTS search_source AS
+SELECT
+  'track' AS kind,
+  t.id AS item_id,
+  json_extract(t.track, '$.Name') AS name,
+  trim(
+    COALESCE((SELECT group_concat(a.value, ' ') FROM json_each(t.track, '$.Artists') a), '')
+    || ' ' || COALESCE(json_extract(t.track, '$.AlbumArtist'), '')
+  ) AS artists,
+  json_extract(t.track, '$.Album') AS album,
+  (SELECT group_concat(g.value, ' ') FROM json_each(t.track, '$.Genres') g) AS genres,
+  (
+    SELECT group_concat(json_extract(p.playlist, '$.Name'), ' ')
+    FROM playlist_membership pm
+    JOIN playlists p ON p.id = pm.playlist_id
+    WHERE pm.track_id = t.id
+  ) AS playlists,
+  (
+    SELECT group_concat(json_extract(l.value, '$.Text'), ' ')
+    FROM lyrics, json_each(lyrics.lyric) l
+    WHERE lyrics.id = t.id
+  ) AS lyrics
+FROM tracks t
+UNION ALL
+SELECT
+  'album',
+  al.id,
+  json_extract(al.album, '$.Name'),
+  (SELECT group_concat(json_extract(a.value, '$.Name'), ' ') FROM json_each(al.album, '$.AlbumArtists') a),
+  NULL, NULL, NULL, NULL
+FROM albums al
+UNION ALL
+SELECT 'artist', ar.id, json_extract(ar.artist, '$.Name'), NULL, NULL, NULL, NULL, NULL
+FROM artists ar;
+
+CREATE TRIGGER IF NOT EXISTS search_tracks_insert
+AFTER INSERT ON tracks
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_tracks_update
+AFTER UPDATE OF track ON tracks
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_tracks_delete
+AFTER DELETE ON tracks
+FOR EACH ROW
+BEGIN
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = OLD.id
+  );
+  DELETE FROM search_items WHERE kind = 'track' AND item_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_albums_insert
+AFTER INSERT ON albums
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'album' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'album' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'album' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_albums_update
+AFTER UPDATE OF album ON albums
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'album' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'album' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'album' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_albums_delete
+AFTER DELETE ON albums
+FOR EACH ROW
+BEGIN
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'album' AND item_id = OLD.id
+  );
+  DELETE FROM search_items WHERE kind = 'album' AND item_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_artists_insert
+AFTER INSERT ON artists
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'artist' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'artist' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'artist' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_artists_update
+AFTER UPDATE OF artist ON artists
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'artist' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'artist' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'artist' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_artists_delete
+AFTER DELETE ON artists
+FOR EACH ROW
+BEGIN
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'artist' AND item_id = OLD.id
+  );
+  DELETE FROM search_items WHERE kind = 'artist' AND item_id = OLD.id;
+END;
+
+-- lyrics and playlist names are part of the track row
+CREATE TRIGGER IF NOT EXISTS search_lyrics_insert
+AFTER INSERT ON lyrics
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_lyrics_update
+AFTER UPDATE ON lyrics
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = NEW.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = NEW.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_lyrics_delete
+AFTER DELETE ON lyrics
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = OLD.id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = OLD.id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_playlist_membership_insert
+AFTER INSERT ON playlist_membership
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = NEW.track_id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = NEW.track_id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = NEW.track_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_playlist_membership_delete
+AFTER DELETE ON playlist_membership
+FOR EACH ROW
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id = OLD.track_id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id = OLD.track_id
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id = OLD.track_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_playlists_update
+AFTER UPDATE OF playlist ON playlists
+FOR EACH ROW
+WHEN json_extract(OLD.playlist, '$.Name') IS NOT json_extract(NEW.playlist, '$.Name')
+BEGIN
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = 'track' AND s.item_id IN (SELECT track_id FROM playlist_membership WHERE playlist_id = NEW.id)
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = 'track' AND item_id IN (SELECT track_id FROM playlist_membership WHERE playlist_id = NEW.id)
+  );
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = 'track' AND s.item_id IN (SELECT track_id FROM playlist_membership WHERE playlist_id = NEW.id);
+END;
+
+INSERT INTO search_items (kind, item_id) SELECT kind, item_id FROM search_source;
+INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+  SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+  FROM search_source s
+  JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id;
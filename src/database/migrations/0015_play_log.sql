@@ -0,0 +1,12 @@
+-- every play that counted (half the track or 4 minutes), the source of the Stats tab.
+-- `first_play` is set when the track had never been played before
+CREATE TABLE IF NOT EXISTS play_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  track_id TEXT NOT NULL,
+  played_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+  listened_ticks INTEGER NOT NULL DEFAULT 0,
+  first_play INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_play_log_played_at ON play_log (played_at);
+CREATE INDEX IF NOT EXISTS idx_play_log_track ON play_log (track_id);
@@ -0,0 +1,11 @@
+-- With a unified library, artists and albums of another server that are already in the library (same MusicBrainz id
+-- or name) are not added again. Their ids on the other server are kept here, e.g. to fetch the whole discography.
+CREATE TABLE IF NOT EXISTS merged_items (
+    kind      TEXT NOT NULL, -- 'artist' | 'album'
+    id        TEXT NOT NULL, -- the item shown in the library
+    server_id TEXT NOT NULL,
+    remote_id TEXT NOT NULL, -- its twin on server_id
+    PRIMARY KEY (kind, server_id, remote_id)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS idx_merged_items_id ON merged_items(kind, id);
@@ -0,0 +1,6 @@
+-- Albums that left the server while some of their tracks are downloaded are held back instead of being deleted, the
+-- user decides what happens to them. missing_since is when the item was first missed, local_only keeps it for good.
+ALTER TABLE missing_counters ADD COLUMN missing_since INTEGER;
+ALTER TABLE missing_counters ADD COLUMN local_only INTEGER NOT NULL DEFAULT 0;
+
+UPDATE missing_counters SET missing_since = last_checked_at;
@@ -0,0 +1,20 @@
+-- Membership changes and renames of playlists, as seen by the playlist updater or made from here (by_me).
+-- playlist_visits holds the playlists whose membership has been fetched once, the first fetch is the baseline and
+-- records nothing. last_seen is the newest change seen on the latest visit, previous_seen the one before it.
+CREATE TABLE IF NOT EXISTS playlist_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    playlist_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    track_id TEXT,
+    detail TEXT NOT NULL DEFAULT '',
+    by_me INTEGER NOT NULL DEFAULT 0,
+    changed_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_playlist_changes_playlist ON playlist_changes (playlist_id, id);
+
+CREATE TABLE IF NOT EXISTS playlist_visits (
+    playlist_id TEXT PRIMARY KEY,
+    last_seen INTEGER NOT NULL DEFAULT 0,
+    previous_seen INTEGER NOT NULL DEFAULT 0
+);
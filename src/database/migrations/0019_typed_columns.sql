@@ -0,0 +1,50 @@
+-- The fields used to filter and sort become typed columns. They are generated from the JSON of the row, so every
+-- writer keeps them right without knowing about them, and they are indexed where the queries need it.
+-- The JSON stays the full record. download_status lives in its column only, the readers take it from there, so
+-- the trigger that copied it into the JSON is gone.
+DROP TRIGGER IF EXISTS update_json_download_status;
+
+ALTER TABLE tracks ADD COLUMN name TEXT GENERATED ALWAYS AS (json_extract(track, '$.Name')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN album_name TEXT GENERATED ALWAYS AS (json_extract(track, '$.Album')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN album_artist TEXT GENERATED ALWAYS AS (json_extract(track, '$.AlbumArtist')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN production_year INTEGER GENERATED ALWAYS AS (json_extract(track, '$.ProductionYear')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN index_number INTEGER GENERATED ALWAYS AS (json_extract(track, '$.IndexNumber')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN disc_number INTEGER GENERATED ALWAYS AS (json_extract(track, '$.ParentIndexNumber')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN play_count INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(track, '$.UserData.PlayCount'), 0)) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN played INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(track, '$.UserData.Played'), 0)) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN is_favorite INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(track, '$.UserData.IsFavorite'), 0)) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN genres TEXT GENERATED ALWAYS AS (json_extract(track, '$.Genres')) VIRTUAL;
+ALTER TABLE tracks ADD COLUMN run_time_ticks INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(track, '$.RunTimeTicks'), 0)) VIRTUAL;
+
+CREATE INDEX IF NOT EXISTS idx_tracks_name ON tracks (name COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS idx_tracks_album_order ON tracks (album_id, disc_number, index_number);
+CREATE INDEX IF NOT EXISTS idx_tracks_album_artist ON tracks (album_artist);
+CREATE INDEX IF NOT EXISTS idx_tracks_year ON tracks (production_year);
+CREATE INDEX IF NOT EXISTS idx_tracks_play_count ON tracks (play_count);
+CREATE INDEX IF NOT EXISTS idx_tracks_favorite ON tracks (is_favorite);
+CREATE INDEX IF NOT EXISTS idx_tracks_download_status ON tracks (download_status);
+
+ALTER TABLE albums ADD COLUMN name TEXT GENERATED ALWAYS AS (json_extract(album, '$.Name')) VIRTUAL;
+ALTER TABLE albums ADD COLUMN album_artist TEXT GENERATED ALWAYS AS (json_extract(album, '$.AlbumArtists[0].Name')) VIRTUAL;
+ALTER TABLE albums ADD COLUMN production_year INTEGER GENERATED ALWAYS AS (json_extract(album, '$.ProductionYear')) VIRTUAL;
+ALTER TABLE albums ADD COLUMN play_count INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(album, '$.UserData.PlayCount'), 0)) VIRTUAL;
+ALTER TABLE albums ADD COLUMN is_favorite INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(album, '$.UserData.IsFavorite'), 0)) VIRTUAL;
+ALTER TABLE albums ADD COLUMN server_id TEXT GENERATED ALWAYS AS (COALESCE(json_extract(album, '$.ServerId'), '')) VIRTUAL;
+
+CREATE INDEX IF NOT EXISTS idx_albums_name ON albums (name COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS idx_albums_album_artist ON albums (album_artist);
+CREATE INDEX IF NOT EXISTS idx_albums_year ON albums (production_year);
+CREATE INDEX IF NOT EXISTS idx_albums_server ON albums (server_id);
+
+ALTER TABLE artists ADD COLUMN name TEXT GENERATED ALWAYS AS (json_extract(artist, '$.Name')) VIRTUAL;
+ALTER TABLE artists ADD COLUMN play_count INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(artist, '$.UserData.PlayCount'), 0)) VIRTUAL;
+ALTER TABLE artists ADD COLUMN is_favorite INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(artist, '$.UserData.IsFavorite'), 0)) VIRTUAL;
+ALTER TABLE artists ADD COLUMN server_id TEXT GENERATED ALWAYS AS (COALESCE(json_extract(artist, '$.ServerId'), '')) VIRTUAL;
+
+CREATE INDEX IF NOT EXISTS idx_artists_name ON artists (name COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS idx_artists_server ON artists (server_id);
+
+ALTER TABLE playlists ADD COLUMN name TEXT GENERATED ALWAYS AS (json_extract(playlist, '$.Name')) VIRTUAL;
+ALTER TABLE playlists ADD COLUMN is_favorite INTEGER GENERATED ALWAYS AS (COALESCE(json_extract(playlist, '$.UserData.IsFavorite'), 0)) VIRTUAL;
+
+CREATE INDEX IF NOT EXISTS idx_playlists_name ON playlists (name COLLATE NOCASE);
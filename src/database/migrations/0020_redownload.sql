@@ -0,0 +1,4 @@
+-- a re-download at another quality runs next to the offline copy, which stays Downloaded until it is replaced
+ALTER TABLE tracks ADD COLUMN redownload INTEGER NOT NULL DEFAULT 0;
+-- the quality a part file was started at, a part file of another quality is not resumed
+ALTER TABLE tracks ADD COLUMN part_quality TEXT;
@@ -0,0 +1,2 @@
+-- modification time of the offline copy when its checksum was last verified, unchanged files are not hashed again
+ALTER TABLE tracks ADD COLUMN download_mtime INTEGER;
@@ -0,0 +1,81 @@
+-- the search triggers of 0014 each carried their own copy of the indexing. Inserting into `search_reindex`
+-- now (re)indexes one item, or drops it from the index when its row is gone, and the triggers only say which
+-- item changed. A change to what gets indexed is a change to `search_source` and this one trigger
+CREATE VIEW IF NOT EXISTS search_reindex AS SELECT kind, item_id FROM search_items;
+
+CREATE TRIGGER IF NOT EXISTS search_reindex_insert
+INSTEAD OF INSERT ON search_reindex
+FOR EACH ROW
+BEGIN
+  DELETE FROM search_index WHERE rowid IN (
+    SELECT rowid FROM search_items WHERE kind = NEW.kind AND item_id = NEW.item_id
+  );
+  DELETE FROM search_items
+    WHERE kind = NEW.kind AND item_id = NEW.item_id
+      AND NOT EXISTS (SELECT 1 FROM search_source s WHERE s.kind = NEW.kind AND s.item_id = NEW.item_id);
+  INSERT INTO search_items (kind, item_id)
+    SELECT s.kind, s.item_id FROM search_source s
+    WHERE s.kind = NEW.kind AND s.item_id = NEW.item_id
+      AND NOT EXISTS (SELECT 1 FROM search_items i WHERE i.kind = s.kind AND i.item_id = s.item_id);
+  INSERT INTO search_index (rowid, name, artists, album, genres, playlists, lyrics)
+    SELECT i.rowid, s.name, s.artists, s.album, s.genres, s.playlists, s.lyrics
+    FROM search_source s
+    JOIN search_items i ON i.kind = s.kind AND i.item_id = s.item_id
+    WHERE s.kind = NEW.kind AND s.item_id = NEW.item_id;
+END;
+
+DROP TRIGGER IF EXISTS search_tracks_insert;
+DROP TRIGGER IF EXISTS search_tracks_update;
+DROP TRIGGER IF EXISTS search_tracks_delete;
+DROP TRIGGER IF EXISTS search_albums_insert;
+DROP TRIGGER IF EXISTS search_albums_update;
+DROP TRIGGER IF EXISTS search_albums_delete;
+DROP TRIGGER IF EXISTS search_artists_insert;
+DROP TRIGGER IF EXISTS search_artists_update;
+DROP TRIGGER IF EXISTS search_artists_delete;
+DROP TRIGGER IF EXISTS search_lyrics_insert;
+DROP TRIGGER IF EXISTS search_lyrics_update;
+DROP TRIGGER IF EXISTS search_lyrics_delete;
+DROP TRIGGER IF EXISTS search_playlist_membership_insert;
+DROP TRIGGER IF EXISTS search_playlist_membership_delete;
+DROP TRIGGER IF EXISTS search_playlists_update;
+
+CREATE TRIGGER search_tracks_insert AFTER INSERT ON tracks
+BEGIN INSERT INTO search_reindex VALUES ('track', NEW.id); END;
+CREATE TRIGGER search_tracks_update AFTER UPDATE OF track ON tracks
+BEGIN INSERT INTO search_reindex VALUES ('track', NEW.id); END;
+CREATE TRIGGER search_tracks_delete AFTER DELETE ON tracks
+BEGIN INSERT INTO search_reindex VALUES ('track', OLD.id); END;
+
+CREATE TRIGGER search_albums_insert AFTER INSERT ON albums
+BEGIN INSERT INTO search_reindex VALUES ('album', NEW.id); END;
+CREATE TRIGGER search_albums_update AFTER UPDATE OF album ON albums
+BEGIN INSERT INTO search_reindex VALUES ('album', NEW.id); END;
+CREATE TRIGGER search_albums_delete AFTER DELETE ON albums
+BEGIN INSERT INTO search_reindex VALUES ('album', OLD.id); END;
+
+CREATE TRIGGER search_artists_insert AFTER INSERT ON artists
+BEGIN INSERT INTO search_reindex VALUES ('artist', NEW.id); END;
+CREATE TRIGGER search_artists_update AFTER UPDATE OF artist ON artists
+BEGIN INSERT INTO search_reindex VALUES ('artist', NEW.id); END;
+CREATE TRIGGER search_artists_delete AFTER DELETE ON artists
+BEGIN INSERT INTO search_reindex VALUES ('artist', OLD.id); END;
+
+-- lyrics and playlist names are part of the track row
+CREATE TRIGGER search_lyrics_insert AFTER INSERT ON lyrics
+BEGIN INSERT INTO search_reindex VALUES ('track', NEW.id); END;
+CREATE TRIGGER search_lyrics_update AFTER UPDATE ON lyrics
+BEGIN INSERT INTO search_reindex VALUES ('track', NEW.id); END;
+CREATE TRIGGER search_lyrics_delete AFTER DELETE ON lyrics
+BEGIN INSERT INTO search_reindex VALUES ('track', OLD.id); END;
+
+CREATE TRIGGER search_playlist_membership_insert AFTER INSERT ON playlist_membership
+BEGIN INSERT INTO search_reindex VALUES ('track', NEW.track_id); END;
+CREATE TRIGGER search_playlist_membership_delete AFTER DELETE ON playlist_membership
+BEGIN INSERT INTO search_reindex VALUES ('track', OLD.track_id); END;
+
+CREATE TRIGGER search_playlists_update AFTER UPDATE OF playlist ON playlists
+WHEN json_extract(OLD.playlist, '$.Name') IS NOT json_extract(NEW.playlist, '$.Name')
+BEGIN
+  INSERT INTO search_reindex SELECT 'track', track_id FROM playlist_membership WHERE playlist_id = NEW.id;
+END;
@@ -0,0 +1,37 @@
+-- the genres and artists of every track, one row each. The offline radio compares them with its seed, the JSON
+-- lists in the track can't be indexed
+CREATE TABLE IF NOT EXISTS track_tags (
+  track_id TEXT NOT NULL,
+  kind TEXT NOT NULL, -- 'genre' or 'artist'
+  value TEXT NOT NULL COLLATE NOCASE,
+  PRIMARY KEY (kind, value, track_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_track_tags_track ON track_tags (track_id);
+
+CREATE TRIGGER IF NOT EXISTS track_tags_insert AFTER INSERT ON tracks
+BEGIN
+  INSERT OR IGNORE INTO track_tags (track_id, kind, value)
+    SELECT NEW.id, 'genre', value FROM json_each(NEW.track, '$.Genres')
+    UNION ALL
+    SELECT NEW.id, 'artist', value FROM json_each(NEW.track, '$.Artists');
+END;
+
+CREATE TRIGGER IF NOT EXISTS track_tags_update AFTER UPDATE OF track ON tracks
+BEGIN
+  DELETE FROM track_tags WHERE track_id = OLD.id;
+  INSERT OR IGNORE INTO track_tags (track_id, kind, value)
+    SELECT NEW.id, 'genre', value FROM json_each(NEW.track, '$.Genres')
+    UNION ALL
+    SELECT NEW.id, 'artist', value FROM json_each(NEW.track, '$.Artists');
+END;
+
+CREATE TRIGGER IF NOT EXISTS track_tags_delete AFTER DELETE ON tracks
+BEGIN
+  DELETE FROM track_tags WHERE track_id = OLD.id;
+END;
+
+INSERT OR IGNORE INTO track_tags (track_id, kind, value)
+  SELECT t.id, 'genre', g.value FROM tracks t, json_each(t.track, '$.Genres') g
+  UNION ALL
+  SELECT t.id, 'artist', a.value FROM tracks t, json_each(t.track, '$.Artists') a;
@@ -1,2 +1,15 @@
 pub mod database;
+pub mod export;
 pub mod extension;
+pub mod integrity;
+pub mod library_sync;
+pub mod maintenance;
+pub mod outbox;
+pub mod pending_plays;
+pub mod playlist_history;
+pub mod profile;
+pub mod search;
+pub mod stats;
+pub mod sync_rules;
+pub mod tombstones;
+pub mod unified;
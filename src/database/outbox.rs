@@ -0,0 +1,478 @@
+/* --------------------------
+Outbox of changes for the server
+    - Favorites always go through here, playlist edits when the server can't be reached (e.g. --offline)
+    - The local cache is updated right away, the outbox is flushed in order by the database thread
+    - Before a change is applied it is checked against the server. If the server changed in between,
+      the entry is kept as a conflict and shown to the user, who can apply it anyway or discard it
+-------------------------- */
+
+use super::database::{Command, Status};
+use crate::client::{Client, Playlist};
+use crate::servers::Servers;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+
+/// Only one flush at a time, otherwise a change could be sent twice
+static FLUSH_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Mutation {
+    Favorite { item_id: String, favorite: bool },
+    PlaylistAdd { playlist_id: String, track_ids: Vec<String> },
+    PlaylistRemove { playlist_id: String, track_ids: Vec<String> },
+    PlaylistMove { playlist_id: String, item_id: String, new_index: usize },
+    PlaylistRename { playlist_id: String, old_name: String, new_name: String },
+}
+
+impl Mutation {
+    pub fn describe(&self) -> String {
+        match self {
+            Mutation::Favorite { favorite: true, .. } => "Add favorite".to_string(),
+            Mutation::Favorite { favorite: false, .. } => "Remove favorite".to_string(),
+            Mutation::PlaylistAdd { track_ids, .. } => {
+                format!("Add {} track(s) to a playlist", track_ids.len())
+            }
+            Mutation::PlaylistRemove { track_ids, .. } => {
+                format!("Remove {} track(s) from a playlist", track_ids.len())
+            }
+            Mutation::PlaylistMove { new_index, .. } => {
+                format!("Move a playlist track to position {}", new_index + 1)
+            }
+            Mutation::PlaylistRename { new_name, .. } => format!("Rename playlist to {}", new_name),
+        }
+    }
+
+    /// The item whose server the change goes to
+    pub fn target_id(&self) -> &str {
+        match self {
+            Mutation::Favorite { item_id, .. } => item_id,
+            Mutation::PlaylistAdd { playlist_id, .. }
+            | Mutation::PlaylistRemove { playlist_id, .. }
+            | Mutation::PlaylistMove { playlist_id, .. }
+            | Mutation::PlaylistRename { playlist_id, .. } => playlist_id,
+        }
+    }
+}
+
+impl From<Mutation> for Command {
+    fn from(mutation: Mutation) -> Self {
+        Command::Outbox(OutboxCommand::Push { mutation })
+    }
+}
+
+/// What happened to a change made in the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeOutcome {
+    Sent,
+    /// Saved locally, waiting in the outbox for the server
+    Queued,
+    Failed,
+}
+
+impl ChangeOutcome {
+    /// Appended to the confirmation message so the user knows the server doesn't have it yet
+    pub fn note(&self) -> &'static str {
+        match self {
+            ChangeOutcome::Queued => {
+                " Saved locally, it syncs after the changes still waiting for the server."
+            }
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxConflict {
+    pub id: i64,
+    pub mutation: Mutation,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum OutboxCommand {
+    Push {
+        mutation: Mutation,
+    },
+    /// Applies the conflicting change without checking the server state, all of them if `id` is `None`
+    Force {
+        id: Option<i64>,
+    },
+    /// Drops a conflicting change and keeps the server state, all of them if `id` is `None`
+    Discard {
+        id: Option<i64>,
+    },
+}
+
+enum ApplyError {
+    /// The server can't be reached or can't take changes right now (server errors, expired login, rate limits),
+    /// the change stays queued
+    Unreachable(String),
+    /// The server state doesn't allow the change, it is kept as a conflict
+    Conflict(String),
+}
+
+impl From<reqwest::Error> for ApplyError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status().map(|status| status.as_u16()) {
+            Some(status @ (400 | 404 | 409)) => {
+                ApplyError::Conflict(format!("the server rejected it ({})", status))
+            }
+            _ => ApplyError::Unreachable(e.to_string()),
+        }
+    }
+}
+
+/// Queues a change. Removing tracks that were added offline and never synced just drops them from the queued add
+///
+pub async fn push_mutation(
+    pool: &SqlitePool,
+    mutation: &Mutation,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut mutation = mutation.clone();
+    if let Mutation::PlaylistRemove { playlist_id, track_ids } = &mut mutation {
+        let pending: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, mutation FROM outbox WHERE conflict IS NULL ORDER BY id")
+                .fetch_all(pool)
+                .await?;
+        for (id, pending) in pending {
+            let Ok(Mutation::PlaylistAdd { playlist_id: added_to, track_ids: mut added }) =
+                serde_json::from_str::<Mutation>(&pending)
+            else {
+                continue;
+            };
+            if added_to != *playlist_id || !added.iter().any(|t| track_ids.contains(t)) {
+                continue;
+            }
+            track_ids.retain(|t| !added.contains(t));
+            added.retain(|t| !track_ids.contains(t));
+            if added.is_empty() {
+                sqlx::query("DELETE FROM outbox WHERE id = ?").bind(id).execute(pool).await?;
+            } else {
+                let updated = Mutation::PlaylistAdd { playlist_id: added_to, track_ids: added };
+                sqlx::query("UPDATE outbox SET mutation = ? WHERE id = ?")
+                    .bind(serde_json::to_string(&updated)?)
+                    .bind(id)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        if track_ids.is_empty() {
+            return Ok(());
+        }
+    }
+
+    sqlx::query("INSERT INTO outbox (mutation) VALUES (?)")
+        .bind(serde_json::to_string(&mutation)?)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Mirrors an offline playlist edit in the local cache, tracks are appended at the end
+///
+pub async fn add_to_local_playlist(
+    pool: &SqlitePool,
+    playlist_id: &str,
+    track_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx_db = pool.begin().await?;
+    for track_id in track_ids {
+        sqlx::query(
+            "INSERT OR IGNORE INTO playlist_membership (playlist_id, track_id, position)
+             SELECT ?, ?, COALESCE(MAX(position) + 1, 0) FROM playlist_membership WHERE playlist_id = ?",
+        )
+        .bind(playlist_id)
+        .bind(track_id)
+        .bind(playlist_id)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    tx_db.commit().await
+}
+
+pub async fn remove_from_local_playlist(
+    pool: &SqlitePool,
+    playlist_id: &str,
+    track_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx_db = pool.begin().await?;
+    for track_id in track_ids {
+        sqlx::query("DELETE FROM playlist_membership WHERE playlist_id = ? AND track_id = ?")
+            .bind(playlist_id)
+            .bind(track_id)
+            .execute(&mut *tx_db)
+            .await?;
+    }
+    tx_db.commit().await
+}
+
+/// Stores the order of a playlist after a track was moved
+///
+pub async fn reorder_local_playlist(
+    pool: &SqlitePool,
+    playlist_id: &str,
+    track_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx_db = pool.begin().await?;
+    for (position, track_id) in track_ids.iter().enumerate() {
+        sqlx::query(
+            "UPDATE playlist_membership SET position = ? WHERE playlist_id = ? AND track_id = ?",
+        )
+        .bind(position as i64)
+        .bind(playlist_id)
+        .bind(track_id)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    tx_db.commit().await
+}
+
+pub async fn get_outbox_conflicts(pool: &SqlitePool) -> Result<Vec<OutboxConflict>, sqlx::Error> {
+    let rows: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT id, mutation, conflict FROM outbox WHERE conflict IS NOT NULL ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, mutation, reason)| {
+            serde_json::from_str(&mutation).ok().map(|mutation| OutboxConflict {
+                id,
+                mutation,
+                reason,
+            })
+        })
+        .collect())
+}
+
+/// Whether changes to an item still wait in the outbox, conflicts included. A newer change to it has to wait
+/// behind them, sent on its own it would reach the server first
+///
+pub async fn has_pending_changes(pool: &SqlitePool, item_id: &str) -> Result<bool, sqlx::Error> {
+    let rows: Vec<String> =
+        sqlx::query_scalar("SELECT mutation FROM outbox").fetch_all(pool).await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|mutation| serde_json::from_str::<Mutation>(mutation).ok())
+        .any(|mutation| mutation.target_id() == item_id))
+}
+
+/// Tracks of a playlist with an add or remove still waiting in the outbox. Until it is sent the server
+/// doesn't know about it, comparing them with the server would undo the change locally
+///
+pub async fn pending_playlist_tracks(
+    pool: &SqlitePool,
+    playlist_id: &str,
+) -> Result<HashSet<String>, sqlx::Error> {
+    let rows: Vec<String> =
+        sqlx::query_scalar("SELECT mutation FROM outbox ORDER BY id").fetch_all(pool).await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|mutation| serde_json::from_str::<Mutation>(mutation).ok())
+        .flat_map(|mutation| match mutation {
+            Mutation::PlaylistAdd { playlist_id: id, track_ids }
+            | Mutation::PlaylistRemove { playlist_id: id, track_ids }
+                if id == playlist_id =>
+            {
+                track_ids
+            }
+            _ => vec![],
+        })
+        .collect())
+}
+
+pub async fn resolve_conflicts(
+    pool: &SqlitePool,
+    command: &OutboxCommand,
+) -> Result<(), sqlx::Error> {
+    match command {
+        OutboxCommand::Push { .. } => {}
+        OutboxCommand::Force { id } => {
+            sqlx::query(
+                "UPDATE outbox SET conflict = NULL, forced = 1
+                 WHERE conflict IS NOT NULL AND (? IS NULL OR id = ?)",
+            )
+            .bind(id)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        }
+        OutboxCommand::Discard { id } => {
+            sqlx::query("DELETE FROM outbox WHERE conflict IS NOT NULL AND (? IS NULL OR id = ?)")
+                .bind(id)
+                .bind(id)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The server an item came from, '' for the primary's and unknown items
+///
+pub(super) async fn item_server_id(pool: &SqlitePool, item_id: &str) -> sqlx::Result<String> {
+    sqlx::query_scalar(
+        "SELECT COALESCE(
+            (SELECT json_extract(track, '$.ServerId') FROM tracks WHERE id = ?1),
+            (SELECT server_id FROM albums WHERE id = ?1),
+            (SELECT server_id FROM artists WHERE id = ?1),
+            (SELECT json_extract(playlist, '$.ServerId') FROM playlists WHERE id = ?1),
+            ''
+        )",
+    )
+    .bind(item_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Applies queued changes in order, each on the server of the item it changes. Once a server can't be reached its
+/// remaining changes wait, so their order is kept. Reports the conflicts to the UI if there are any
+///
+pub async fn flush_outbox(
+    pool: &SqlitePool,
+    servers: &Servers,
+    tx: &Sender<Status>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let _guard = FLUSH_LOCK.lock().await;
+
+    let rows: Vec<(i64, String, bool)> = sqlx::query_as(
+        "SELECT id, mutation, forced FROM outbox WHERE conflict IS NULL ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut new_conflicts = false;
+    let mut unreachable = HashSet::new();
+    for (id, mutation, forced) in rows {
+        let Ok(mutation) = serde_json::from_str::<Mutation>(&mutation) else {
+            sqlx::query("DELETE FROM outbox WHERE id = ?").bind(id).execute(pool).await?;
+            continue;
+        };
+        let server_id = item_server_id(pool, mutation.target_id()).await?;
+        // its server isn't connected this session
+        let Some(client) = servers.client_for(&server_id) else {
+            continue;
+        };
+        if unreachable.contains(&client.server_id) {
+            continue;
+        }
+        match apply_mutation(client, &mutation, forced).await {
+            Ok(()) => {
+                sqlx::query("DELETE FROM outbox WHERE id = ?").bind(id).execute(pool).await?;
+            }
+            Err(ApplyError::Conflict(reason)) if forced => {
+                // applied anyway, but there is nothing left to apply it to
+                log::warn!("Dropping forced '{}': {}", mutation.describe(), reason);
+                sqlx::query("DELETE FROM outbox WHERE id = ?").bind(id).execute(pool).await?;
+            }
+            Err(ApplyError::Conflict(reason)) => {
+                log::warn!("Outbox conflict for '{}': {}", mutation.describe(), reason);
+                sqlx::query("UPDATE outbox SET conflict = ? WHERE id = ?")
+                    .bind(reason)
+                    .bind(id)
+                    .execute(pool)
+                    .await?;
+                new_conflicts = true;
+            }
+            Err(ApplyError::Unreachable(e)) => {
+                log::info!("Server unreachable, keeping its outbox entries for later: {}", e);
+                unreachable.insert(client.server_id.clone());
+            }
+        }
+    }
+
+    if new_conflicts {
+        let conflicts = get_outbox_conflicts(pool).await?;
+        let _ = tx.send(Status::OutboxConflicts { conflicts }).await;
+    }
+
+    Ok(())
+}
+
+pub async fn t_flush_outbox(pool: SqlitePool, servers: Servers, tx: Sender<Status>) {
+    if let Err(e) = flush_outbox(&pool, &servers, &tx).await {
+        log::error!("Failed to flush the outbox: {}", e);
+    }
+}
+
+async fn apply_mutation(
+    client: &Client,
+    mutation: &Mutation,
+    forced: bool,
+) -> Result<(), ApplyError> {
+    match mutation {
+        Mutation::Favorite { item_id, favorite } => {
+            client.set_favorite(item_id, *favorite).await?;
+        }
+        Mutation::PlaylistAdd { playlist_id, track_ids } => {
+            playlist_name(client, playlist_id).await?;
+            let existing = client.playlist(playlist_id, None).await?.items;
+            // adding twice would duplicate the track, the change might have arrived before a crash
+            let missing = track_ids
+                .iter()
+                .filter(|id| !existing.iter().any(|t| &&t.id == id))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                client.add_tracks_to_playlist(&missing, playlist_id).await?;
+            }
+        }
+        Mutation::PlaylistRemove { playlist_id, track_ids } => {
+            playlist_name(client, playlist_id).await?;
+            let existing = client.playlist(playlist_id, None).await?.items;
+            // the entry ids are looked up now, tracks already removed on the server are skipped
+            let present = existing
+                .iter()
+                .filter(|t| track_ids.contains(&t.id))
+                .map(|t| t.playlist_item_id.clone())
+                .collect::<Vec<_>>();
+            if !present.is_empty() {
+                client.remove_tracks_from_playlist(&present, playlist_id).await?;
+            }
+        }
+        Mutation::PlaylistMove { playlist_id, item_id, new_index } => {
+            playlist_name(client, playlist_id).await?;
+            let existing = client.playlist(playlist_id, None).await?.items;
+            if !existing.iter().any(|t| &t.id == item_id || &t.playlist_item_id == item_id) {
+                if forced {
+                    // nothing to move anymore, the server state wins
+                    return Ok(());
+                }
+                return Err(ApplyError::Conflict(
+                    "the track is no longer in the playlist".to_string(),
+                ));
+            }
+            let new_index = (*new_index).min(existing.len().saturating_sub(1));
+            client.move_playlist_item(item_id, playlist_id, new_index).await?.error_for_status()?;
+        }
+        Mutation::PlaylistRename { playlist_id, old_name, new_name } => {
+            let current = playlist_name(client, playlist_id).await?;
+            if !forced && current != *old_name && current != *new_name {
+                return Err(ApplyError::Conflict(format!(
+                    "it was renamed to {} on the server",
+                    current
+                )));
+            }
+            let playlist =
+                Playlist { id: playlist_id.clone(), name: new_name.clone(), ..Default::default() };
+            client.update_playlist(&playlist).await?.error_for_status()?;
+        }
+    }
+    Ok(())
+}
+
+async fn playlist_name(client: &Client, playlist_id: &str) -> Result<String, ApplyError> {
+    match client.item_name(playlist_id).await? {
+        Some(name) => Ok(name),
+        None => Err(ApplyError::Conflict("the playlist was deleted on the server".to_string())),
+    }
+}
@@ -0,0 +1,106 @@
+/* --------------------------
+Offline play backlog
+    - Without a connection the playback reports to jellyfin go nowhere, so plays are recorded here instead
+    - Once online again they are replayed as played/UserData updates with the original timestamp
+    - A play counts when half the track or 4 minutes were listened to, the same rule last.fm uses
+-------------------------- */
+
+use crate::servers::Servers;
+use sqlx::{Pool, Sqlite, SqlitePool};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const SCROBBLE_MAX_TICKS: u64 = 4 * 60 * 10_000_000;
+
+/// The rule every play is judged by, tracks of unknown length count as soon as anything was listened to
+///
+pub fn counts_as_play(position_ticks: u64, run_time_ticks: u64) -> bool {
+    position_ticks > 0 && position_ticks >= (run_time_ticks / 2).min(SCROBBLE_MAX_TICKS)
+}
+
+pub async fn record_pending_play(
+    pool: &SqlitePool,
+    track_id: &str,
+    position_ticks: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO pending_plays (track_id, position_ticks) VALUES (?, ?)")
+        .bind(track_id)
+        .bind(position_ticks as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// A 4xx that won't go away by trying again. Auth errors, timeouts and rate limits are not one
+///
+fn is_rejection(status: reqwest::StatusCode) -> bool {
+    status.is_client_error() && !matches!(status.as_u16(), 401 | 403 | 408 | 429)
+}
+
+pub async fn t_flush_pending_plays(pool: Arc<Pool<Sqlite>>, servers: Servers) {
+    if let Err(e) = flush_pending_plays(&pool, &servers).await {
+        log::error!("Failed to replay offline plays: {}", e);
+    }
+}
+
+/// Sends every recorded play to the server of its track, oldest first. Without a connection to that server its
+/// plays wait for the next attempt.
+/// Plays the server rejects (e.g. the track was deleted) and plays too short to count are dropped, plays that
+/// failed for the moment (server errors, expired login, rate limits) stay in the backlog
+///
+async fn flush_pending_plays(
+    pool: &SqlitePool,
+    servers: &Servers,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let rows: Vec<(i64, String, i64, String, Option<i64>, String)> = sqlx::query_as(
+        "SELECT p.id, p.track_id, p.position_ticks, p.played_at, t.run_time_ticks,
+                COALESCE(json_extract(t.track, '$.ServerId'), '')
+         FROM pending_plays p LEFT JOIN tracks t ON t.id = p.track_id
+         ORDER BY p.id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sent = 0;
+    let mut unreachable = HashSet::new();
+    for (id, track_id, position_ticks, played_at, run_time_ticks, server_id) in rows {
+        let run_time_ticks = run_time_ticks.unwrap_or_default().max(0) as u64;
+        let position_ticks = position_ticks.max(0) as u64;
+
+        if counts_as_play(position_ticks, run_time_ticks) {
+            // its server isn't connected this session
+            let Some(client) = servers.client_for(&server_id) else {
+                continue;
+            };
+            if unreachable.contains(&client.server_id) {
+                continue;
+            }
+            match client.mark_played(&track_id, &played_at).await {
+                Ok(()) => sent += 1,
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    log::info!("Server unreachable, keeping its offline plays for later: {}", e);
+                    unreachable.insert(client.server_id.clone());
+                    continue;
+                }
+                Err(e) if e.status().is_some_and(is_rejection) => {
+                    log::warn!(
+                        "The server rejected the offline play of {}, dropping it: {}",
+                        track_id,
+                        e
+                    );
+                }
+                Err(e) => {
+                    log::warn!("Failed to replay offline play of {}, keeping it: {}", track_id, e);
+                    continue;
+                }
+            }
+        }
+        sqlx::query("DELETE FROM pending_plays WHERE id = ?").bind(id).execute(pool).await?;
+    }
+
+    if sent > 0 {
+        log::info!("Replayed {} offline plays to the server", sent);
+    }
+
+    Ok(sent)
+}
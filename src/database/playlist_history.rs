@@ -0,0 +1,242 @@
+/* --------------------------
+Playlist history, what changed in a playlist and whether it was seen yet
+    - The playlist updater compares the server with the cached membership and records added and removed tracks,
+      the library sync records renames. The first fetch of a playlist is only the baseline
+    - Changes made from here are recorded right away with by_me set, the updater skips them when the server catches up
+    - Opening a playlist marks its changes as seen, the playlist list shows how many are new until then
+    - Playlists with changes by others (shared ones, or edits from another device) get a changelog in their popup
+-------------------------- */
+
+use super::library_sync::now_secs;
+use super::outbox::Mutation;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+
+/// How many changes the changelog shows
+const CHANGELOG_LIMIT: i64 = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PlaylistChange {
+    pub id: i64,
+    /// `added`, `removed` or `renamed`
+    pub kind: String,
+    /// The track title, or `old → new` for renames
+    pub detail: String,
+    pub by_me: bool,
+    pub changed_at: i64,
+}
+
+impl PlaylistChange {
+    pub fn describe(&self) -> String {
+        let when = chrono::DateTime::from_timestamp(self.changed_at, 0)
+            .map(|dt| dt.with_timezone(&chrono::Local).format("%-d %b %H:%M").to_string())
+            .unwrap_or_default();
+        let what = match self.kind.as_str() {
+            "added" => format!("+ {}", self.detail),
+            "removed" => format!("- {}", self.detail),
+            _ => format!("Renamed {}", self.detail),
+        };
+        format!("{} · {}{}", what, when, if self.by_me { " (you)" } else { "" })
+    }
+}
+
+/// Per playlist counts of the changes made by others
+///
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PlaylistActivity {
+    /// Made since the playlist was last opened
+    pub unseen: i64,
+    pub by_others: i64,
+}
+
+/// True once the membership of the playlist was fetched, only then do differences mean changes
+///
+pub async fn is_baselined(pool: &SqlitePool, playlist_id: &str) -> sqlx::Result<bool> {
+    let row: Option<i64> =
+        sqlx::query_scalar("SELECT 1 FROM playlist_visits WHERE playlist_id = ?")
+            .bind(playlist_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(row.is_some())
+}
+
+pub async fn set_baseline(conn: &mut SqliteConnection, playlist_id: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO playlist_visits (playlist_id, last_seen, previous_seen)
+        SELECT ?, COALESCE(MAX(id), 0), COALESCE(MAX(id), 0) FROM playlist_changes
+        "#,
+    )
+    .bind(playlist_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Playlists with a baseline, the library update refreshes their membership to notice changes
+///
+pub async fn baselined_playlists(pool: &SqlitePool) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar(
+        "SELECT playlist_id FROM playlist_visits WHERE playlist_id IN (SELECT id FROM playlists)",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Records a track that was added to or removed from a playlist. Nothing is recorded if the latest change of
+/// the track in this playlist already says the same, e.g. when the server catches up with a change made here
+///
+pub async fn record_track_change(
+    conn: &mut SqliteConnection,
+    playlist_id: &str,
+    kind: &str,
+    track_id: &str,
+    by_me: bool,
+) -> sqlx::Result<bool> {
+    let latest: Option<String> = sqlx::query_scalar(
+        "SELECT kind FROM playlist_changes WHERE playlist_id = ? AND track_id = ? ORDER BY id DESC LIMIT 1",
+    )
+    .bind(playlist_id)
+    .bind(track_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if latest.as_deref() == Some(kind) {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO playlist_changes (playlist_id, kind, track_id, detail, by_me, changed_at)
+        VALUES (?, ?, ?,
+                COALESCE((SELECT name FROM tracks WHERE id = ?), ''),
+                ?, ?)
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(kind)
+    .bind(track_id)
+    .bind(track_id)
+    .bind(by_me)
+    .bind(now_secs())
+    .execute(conn)
+    .await?;
+    Ok(true)
+}
+
+pub async fn record_rename(
+    conn: &mut SqliteConnection,
+    playlist_id: &str,
+    old_name: &str,
+    new_name: &str,
+    by_me: bool,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO playlist_changes (playlist_id, kind, detail, by_me, changed_at)
+        VALUES (?, 'renamed', ?, ?, ?)
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(format!("{} → {}", old_name, new_name))
+    .bind(by_me)
+    .bind(now_secs())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Records the tracks a change made from here adds or removes, renames are recorded by the database thread
+///
+pub async fn record_own_change(pool: &SqlitePool, mutation: &Mutation) -> sqlx::Result<()> {
+    let (playlist_id, track_ids, kind) = match mutation {
+        Mutation::PlaylistAdd { playlist_id, track_ids } => (playlist_id, track_ids, "added"),
+        Mutation::PlaylistRemove { playlist_id, track_ids } => (playlist_id, track_ids, "removed"),
+        _ => return Ok(()),
+    };
+    let mut tx_db = pool.begin().await?;
+    for track_id in track_ids {
+        record_track_change(&mut tx_db, playlist_id, kind, track_id, true).await?;
+    }
+    tx_db.commit().await
+}
+
+pub async fn forget_playlist(conn: &mut SqliteConnection, playlist_id: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM playlist_changes WHERE playlist_id = ?")
+        .bind(playlist_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM playlist_visits WHERE playlist_id = ?")
+        .bind(playlist_id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Everything up to the newest change is seen. What was new before stays highlighted in the changelog until
+/// the next change comes in
+///
+pub async fn mark_seen(pool: &SqlitePool, playlist_id: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE playlist_visits
+        SET previous_seen = last_seen,
+            last_seen = (SELECT MAX(id) FROM playlist_changes WHERE playlist_id = ?1)
+        WHERE playlist_id = ?1
+          AND last_seen < (SELECT COALESCE(MAX(id), 0) FROM playlist_changes WHERE playlist_id = ?1)
+        "#,
+    )
+    .bind(playlist_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_playlist_activity(
+    pool: &SqlitePool,
+) -> sqlx::Result<HashMap<String, PlaylistActivity>> {
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT c.playlist_id,
+               SUM(c.id > COALESCE(v.last_seen, 0)),
+               COUNT(*)
+        FROM playlist_changes c
+        LEFT JOIN playlist_visits v ON v.playlist_id = c.playlist_id
+        WHERE c.by_me = 0
+        GROUP BY c.playlist_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, unseen, by_others)| (id, PlaylistActivity { unseen, by_others }))
+        .collect())
+}
+
+/// The newest changes first, and the id up to which they were seen before the latest visit
+///
+pub async fn get_changelog(
+    pool: &SqlitePool,
+    playlist_id: &str,
+) -> sqlx::Result<(Vec<PlaylistChange>, i64)> {
+    let changes = sqlx::query_as(
+        r#"
+        SELECT id, kind, detail, by_me != 0 AS by_me, changed_at
+        FROM playlist_changes
+        WHERE playlist_id = ?
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(CHANGELOG_LIMIT)
+    .fetch_all(pool)
+    .await?;
+    let seen: Option<i64> =
+        sqlx::query_scalar("SELECT previous_seen FROM playlist_visits WHERE playlist_id = ?")
+            .bind(playlist_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok((changes, seen.unwrap_or(0)))
+}
@@ -0,0 +1,250 @@
+/* --------------------------
+Profile backup and restore, `--export-profile` and `--import-profile`
+    - One versioned JSON archive holds what only exists on this machine: dislikes, the per-server preferences and
+      saved states, and the themes and keymap part of the config
+    - Dislikes are matched by track id, or by MusicBrainz track id so they carry over to another server
+    - Server logins are not part of the archive, the other machine keeps its own
+-------------------------- */
+
+use super::maintenance::{connect, databases_dir, MaintenanceResult};
+use crate::config;
+use crate::database::library_sync::now_secs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+pub const PROFILE_VERSION: u32 = 1;
+
+/// The config keys that belong to the profile
+const CONFIG_KEYS: [&str; 3] = ["themes", "keymap", "keymap_inherit"];
+
+#[derive(Serialize, Deserialize)]
+struct Profile {
+    version: u32,
+    exported_at: i64,
+    /// The `CONFIG_KEYS` of the config file as YAML, empty if none are set
+    #[serde(default)]
+    config: String,
+    #[serde(default)]
+    servers: Vec<ServerProfile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ServerProfile {
+    server_id: String,
+    #[serde(default)]
+    preferences: Option<serde_json::Value>,
+    #[serde(default)]
+    state: Option<serde_json::Value>,
+    #[serde(default)]
+    offline_state: Option<serde_json::Value>,
+    #[serde(default)]
+    dislikes: Vec<DislikedTrack>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+struct DislikedTrack {
+    id: String,
+    #[serde(default)]
+    musicbrainz_track: Option<String>,
+    /// Only there to make the archive readable
+    #[serde(default)]
+    name: String,
+}
+
+fn data_dir() -> PathBuf {
+    dirs::data_dir().unwrap().join("jellyfin-tui")
+}
+
+pub fn default_export_path() -> String {
+    dirs::home_dir()
+        .map(|h| h.join("jellyfin-tui-profile.json").to_string_lossy().to_string())
+        .unwrap_or_else(|| "jellyfin-tui-profile.json".to_string())
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    let file = std::fs::File::open(path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+fn write_json(path: &Path, value: &serde_json::Value) -> MaintenanceResult<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec(value)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Every server this machine has data for, by its database, preferences or states
+///
+fn known_servers() -> BTreeSet<String> {
+    let mut ids = BTreeSet::new();
+    for (dir, extension) in [
+        (databases_dir(), "db"),
+        (data_dir().join("preferences"), "json"),
+        (data_dir().join("states"), "json"),
+    ] {
+        let Ok(files) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for path in files.flatten().map(|f| f.path()) {
+            if path.extension().is_none_or(|ext| ext != extension) {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                ids.insert(stem.trim_start_matches("offline_").to_string());
+            }
+        }
+    }
+    ids
+}
+
+fn config_section() -> MaintenanceResult<String> {
+    let Ok((_, config)) = config::get_config() else {
+        return Ok(String::new());
+    };
+    let mut section = serde_yaml::Mapping::new();
+    for key in CONFIG_KEYS {
+        if let Some(value) = config.get(key) {
+            section.insert(key.into(), value.clone());
+        }
+    }
+    if section.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(serde_yaml::to_string(&section)?)
+}
+
+pub async fn export_profile(path: &str) -> MaintenanceResult<()> {
+    let mut profile = Profile {
+        version: PROFILE_VERSION,
+        exported_at: now_secs(),
+        config: config_section()?,
+        servers: vec![],
+    };
+
+    for server_id in known_servers() {
+        let db_path = databases_dir().join(format!("{}.db", server_id));
+        let dislikes = if db_path.exists() {
+            let pool = connect(&db_path).await?;
+            let dislikes = sqlx::query_as::<_, DislikedTrack>(
+                r#"
+                SELECT id,
+                       json_extract(track, '$.ProviderIds.MusicBrainzTrack') AS musicbrainz_track,
+                       COALESCE(name, '') AS name
+                FROM tracks
+                WHERE disliked = 1
+                "#,
+            )
+            .fetch_all(&pool)
+            .await?;
+            pool.close().await;
+            dislikes
+        } else {
+            vec![]
+        };
+
+        let states_dir = data_dir().join("states");
+        let server = ServerProfile {
+            preferences: read_json(
+                &data_dir().join("preferences").join(format!("{}.json", server_id)),
+            ),
+            state: read_json(&states_dir.join(format!("{}.json", server_id))),
+            offline_state: read_json(&states_dir.join(format!("offline_{}.json", server_id))),
+            dislikes,
+            server_id,
+        };
+        println!(" - Server {}: {} dislikes", server.server_id, server.dislikes.len());
+        profile.servers.push(server);
+    }
+
+    std::fs::write(path, serde_json::to_vec_pretty(&profile)?)?;
+    println!(" - Profile written to {}", path);
+    Ok(())
+}
+
+pub async fn import_profile(path: &str) -> MaintenanceResult<()> {
+    let profile: Profile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    if profile.version > PROFILE_VERSION {
+        return Err(format!(
+            "the profile is version {}, this jellyfin-tui reads up to version {}. Please update",
+            profile.version, PROFILE_VERSION
+        )
+        .into());
+    }
+
+    for server in &profile.servers {
+        let states_dir = data_dir().join("states");
+        let files = [
+            (
+                &server.preferences,
+                data_dir().join("preferences").join(format!("{}.json", server.server_id)),
+            ),
+            (&server.state, states_dir.join(format!("{}.json", server.server_id))),
+            (&server.offline_state, states_dir.join(format!("offline_{}.json", server.server_id))),
+        ];
+        for (value, path) in files {
+            if let Some(value) = value {
+                write_json(&path, value)?;
+            }
+        }
+    }
+    println!(" - Restored preferences and states of {} servers", profile.servers.len());
+
+    // dislikes go to every library that has the track, the servers may differ between the machines
+    let dislikes = profile.servers.iter().flat_map(|s| s.dislikes.iter()).collect::<Vec<_>>();
+    let mut matched = BTreeSet::new();
+    let mut paths = std::fs::read_dir(databases_dir())?
+        .flatten()
+        .map(|f| f.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "db"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    for db_path in paths {
+        let pool = connect(&db_path).await?;
+        let mut tx_db = pool.begin().await?;
+        for (i, dislike) in dislikes.iter().enumerate() {
+            let changed = sqlx::query(
+                r#"
+                UPDATE tracks SET disliked = 1
+                WHERE id = ?
+                   OR (? IS NOT NULL AND json_extract(track, '$.ProviderIds.MusicBrainzTrack') = ?)
+                "#,
+            )
+            .bind(&dislike.id)
+            .bind(&dislike.musicbrainz_track)
+            .bind(&dislike.musicbrainz_track)
+            .execute(&mut *tx_db)
+            .await?
+            .rows_affected();
+            if changed > 0 {
+                matched.insert(i);
+            }
+        }
+        tx_db.commit().await?;
+        pool.close().await;
+    }
+    println!(" - Restored {} of {} dislikes", matched.len(), dislikes.len());
+    if matched.len() < dislikes.len() {
+        println!(
+            " - The other tracks are not in a local library yet. Import again once jellyfin-tui has synced"
+        );
+    }
+
+    if !profile.config.is_empty() {
+        match config::get_config() {
+            Ok((config_path, _)) => {
+                config::replace_config_keys(&config_path, &CONFIG_KEYS, &profile.config)
+                    .map_err(|e| e.to_string())?;
+                println!(
+                    " - Themes and keymap written to {} (the previous config is kept next to it as .bak)",
+                    config_path.display()
+                );
+            }
+            Err(_) => println!(
+                " - No config file yet, themes and keymap were not restored. Start jellyfin-tui once and import again"
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+/* --------------------------
+Full-text search over the local cache
+    - The FTS5 index is kept up to date by triggers (0014_search_index.sql), anything the updaters write is searchable
+    - Tracks match on title, artists, album, genres, the playlists they are in and their lyrics
+    - Offline this is the only source of the Search tab, online it is shown right away while the server is asked
+-------------------------- */
+
+use super::database::Status;
+use super::extension::{selected_library_ids, DownloadStatus};
+use crate::client::DiscographySong;
+use crate::servers::Servers;
+use futures_util::future::join_all;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+
+const MAX_RESULTS: usize = 200;
+
+/// Column weights for bm25, in the order of the index columns: name, artists, album, genres, playlists, lyrics
+const RANKING: &str = "bm25(search_index, 10.0, 5.0, 4.0, 2.0, 1.0, 0.5)";
+
+#[derive(Debug, Default)]
+pub struct LocalSearch {
+    pub artist_ids: Vec<String>,
+    pub album_ids: Vec<String>,
+    pub tracks: Vec<DiscographySong>,
+    /// Excerpt of the lyrics for tracks that matched on them
+    pub lyric_matches: HashMap<String, String>,
+}
+
+/// Every word is matched as a prefix and all of them have to match. Quoting keeps FTS5 syntax out of user input
+///
+fn match_expression(term: &str) -> Option<String> {
+    let words = term
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    Some(words.join(" "))
+}
+
+async fn matching_ids(
+    pool: &SqlitePool,
+    expression: &str,
+    kind: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let sql = format!(
+        r#"
+        SELECT i.item_id
+        FROM search_index
+        JOIN search_items i ON i.rowid = search_index.rowid
+        WHERE search_index MATCH ? AND i.kind = ?
+        ORDER BY {}
+        LIMIT ?
+        "#,
+        RANKING
+    );
+    let rows: Vec<(String,)> = sqlx::query_as(&sql)
+        .bind(expression)
+        .bind(kind)
+        .bind(MAX_RESULTS as i64)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// Searches the cache. Offline only downloaded tracks are returned, since nothing else can be played
+///
+pub async fn search_local(
+    pool: &SqlitePool,
+    term: &str,
+    downloaded_only: bool,
+) -> Result<LocalSearch, Box<dyn std::error::Error>> {
+    let Some(expression) = match_expression(term) else {
+        return Ok(LocalSearch::default());
+    };
+
+    let artist_ids = matching_ids(pool, &expression, "artist").await?;
+    let album_ids = matching_ids(pool, &expression, "album").await?;
+
+    let libs = selected_library_ids(pool).await;
+    if libs.is_empty() {
+        return Ok(LocalSearch { artist_ids, album_ids, ..Default::default() });
+    }
+    let placeholders = vec!["?"; libs.len()].join(",");
+
+    let sql = format!(
+        r#"
+        SELECT t.track, t.download_status
+        FROM search_index
+        JOIN search_items i ON i.rowid = search_index.rowid
+        JOIN tracks t ON t.id = i.item_id
+        WHERE search_index MATCH ?
+          AND i.kind = 'track'
+          AND t.library_id IN ({})
+          {}
+        ORDER BY {}
+        LIMIT ?
+        "#,
+        placeholders,
+        if downloaded_only { "AND t.download_status = 'Downloaded'" } else { "" },
+        RANKING
+    );
+    let mut query = sqlx::query_as::<_, (String, String)>(&sql).bind(&expression);
+    for lib in libs {
+        query = query.bind(lib);
+    }
+    let rows = query.bind(MAX_RESULTS as i64).fetch_all(pool).await?;
+    let tracks = rows
+        .into_iter()
+        .filter_map(|(json, download_status)| {
+            let mut track = serde_json::from_str::<DiscographySong>(&json).ok()?;
+            track.download_status = DownloadStatus::from_column(&download_status);
+            Some(track)
+        })
+        .collect::<Vec<_>>();
+
+    let snippets: Vec<(String, String)> = sqlx::query_as(&format!(
+        r#"
+        SELECT i.item_id, snippet(search_index, 5, '', '', '…', 8)
+        FROM search_index
+        JOIN search_items i ON i.rowid = search_index.rowid
+        WHERE search_index MATCH ? AND i.kind = 'track'
+        ORDER BY {}
+        LIMIT ?
+        "#,
+        RANKING
+    ))
+    .bind(format!("lyrics : ({})", expression))
+    .bind(MAX_RESULTS as i64)
+    .fetch_all(pool)
+    .await?;
+    let lyric_matches = snippets
+        .into_iter()
+        .filter(|(id, _)| tracks.iter().any(|t| &t.id == id))
+        .collect::<HashMap<_, _>>();
+
+    Ok(LocalSearch { artist_ids, album_ids, tracks, lyric_matches })
+}
+
+/// Asks every server for tracks while the local results are already shown. Servers that fail are left out
+///
+pub async fn t_search_server(servers: Servers, tx: Sender<Status>, term: String) {
+    let results = join_all(servers.all().map(|client| client.search_tracks(term.clone()))).await;
+    let mut tracks = Vec::new();
+    let mut found = false;
+    for result in results {
+        match result {
+            Ok(server_tracks) => {
+                found = true;
+                tracks.extend(server_tracks);
+            }
+            Err(e) => {
+                log::error!("Server search for '{}' failed: {}", term, e);
+            }
+        }
+    }
+    if found {
+        let _ = tx.send(Status::SearchResults { term, tracks }).await;
+    }
+}
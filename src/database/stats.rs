@@ -0,0 +1,433 @@
+/* --------------------------
+Listening statistics
+    - Every play that counts is written to the play log, which covers the selectable periods
+    - All time numbers come from the UserData play counts, they include plays from before the log and other clients
+    - Nothing leaves the machine, the report can be exported as JSON and the play log as CSV
+-------------------------- */
+
+use super::pending_plays::counts_as_play;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+const TOP_ENTRIES: usize = 10;
+const TICKS_PER_SECOND: i64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum StatsPeriod {
+    Week,
+    #[default]
+    Month,
+    Year,
+    AllTime,
+}
+
+impl StatsPeriod {
+    pub const ALL: [StatsPeriod; 4] =
+        [StatsPeriod::Week, StatsPeriod::Month, StatsPeriod::Year, StatsPeriod::AllTime];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatsPeriod::Week => "Last 7 days",
+            StatsPeriod::Month => "Last 30 days",
+            StatsPeriod::Year => "Last 365 days",
+            StatsPeriod::AllTime => "All time",
+        }
+    }
+
+    fn days(&self) -> Option<u32> {
+        match self {
+            StatsPeriod::Week => Some(7),
+            StatsPeriod::Month => Some(30),
+            StatsPeriod::Year => Some(365),
+            StatsPeriod::AllTime => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(self) -> Self {
+        let index = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+
+    /// SQL condition on a play log timestamp column
+    fn since(&self, column: &str) -> String {
+        match self.days() {
+            Some(days) => {
+                format!("{} >= strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '-{} days')", column, days)
+            }
+            None => "1 = 1".to_string(),
+        }
+    }
+
+    /// Every row of the `plays` CTE is a track with its number of plays and the time listened
+    fn plays_source(&self) -> String {
+        match self {
+            StatsPeriod::AllTime => r#"
+                WITH plays AS (
+                    SELECT
+                        t.id AS track_id,
+                        t.play_count AS plays,
+                        t.play_count * t.run_time_ticks AS ticks
+                    FROM tracks t
+                    WHERE t.play_count > 0
+                )
+                "#
+            .to_string(),
+            _ => format!(
+                r#"
+                WITH plays AS (
+                    SELECT p.track_id, 1 AS plays, p.listened_ticks AS ticks
+                    FROM play_log p
+                    WHERE {}
+                )
+                "#,
+                self.since("p.played_at")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TopEntry {
+    pub name: String,
+    /// Artist of a track or album, empty otherwise
+    pub detail: String,
+    pub plays: u64,
+    pub listened_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListeningTime {
+    /// First day of the day or week, YYYY-MM-DD in local time
+    pub date: String,
+    pub plays: u64,
+    pub listened_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsReport {
+    pub period: StatsPeriod,
+    pub plays: u64,
+    pub listened_secs: u64,
+    pub top_tracks: Vec<TopEntry>,
+    pub top_artists: Vec<TopEntry>,
+    pub top_albums: Vec<TopEntry>,
+    pub top_genres: Vec<TopEntry>,
+    pub per_day: Vec<ListeningTime>,
+    pub per_week: Vec<ListeningTime>,
+    /// Days in a row with at least one play, ending today or yesterday
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    /// Distinct tracks in the play log for the period, and how many of them were played for the first time
+    pub tracks_played: u64,
+    pub first_plays: u64,
+}
+
+impl StatsReport {
+    pub fn discovery_rate(&self) -> f64 {
+        if self.tracks_played == 0 {
+            return 0.0;
+        }
+        self.first_plays as f64 / self.tracks_played as f64
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PlayRow {
+    played_at: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    listened_ticks: i64,
+    first_play: bool,
+    track_id: String,
+}
+
+/// Writes a finished play to the log if it counts as one
+///
+pub async fn log_play(
+    pool: &SqlitePool,
+    track_id: &str,
+    position_ticks: u64,
+) -> Result<(), sqlx::Error> {
+    let track: Option<(i64, i64)> =
+        sqlx::query_as("SELECT run_time_ticks, play_count FROM tracks WHERE id = ?")
+            .bind(track_id)
+            .fetch_optional(pool)
+            .await?;
+    let run_time_ticks = track.map(|(ticks, _)| ticks.max(0) as u64).unwrap_or_default();
+    if !counts_as_play(position_ticks, run_time_ticks) {
+        return Ok(());
+    }
+    let played_before = track.is_some_and(|(_, play_count)| play_count > 0);
+
+    sqlx::query(
+        "INSERT INTO play_log (track_id, listened_ticks, first_play)
+         SELECT ?, ?, ? AND NOT EXISTS (SELECT 1 FROM play_log WHERE track_id = ?)",
+    )
+    .bind(track_id)
+    .bind(position_ticks as i64)
+    .bind(!played_before)
+    .bind(track_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn top_entries(
+    pool: &SqlitePool,
+    period: StatsPeriod,
+    select: &str,
+) -> Result<Vec<TopEntry>, sqlx::Error> {
+    let sql = format!(
+        "{} {} ORDER BY 3 DESC, 4 DESC LIMIT {}",
+        period.plays_source(),
+        select,
+        TOP_ENTRIES
+    );
+    let rows: Vec<(Option<String>, Option<String>, i64, i64)> =
+        sqlx::query_as(&sql).fetch_all(pool).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(name, detail, plays, ticks)| TopEntry {
+            name: name.unwrap_or_else(|| "Unknown".to_string()),
+            detail: detail.unwrap_or_default(),
+            plays: plays.max(0) as u64,
+            listened_secs: (ticks / TICKS_PER_SECOND).max(0) as u64,
+        })
+        .collect())
+}
+
+/// Listening time for the last `count` days or weeks, including the ones without plays
+///
+async fn listening_time(
+    pool: &SqlitePool,
+    count: u32,
+    weekly: bool,
+) -> Result<Vec<ListeningTime>, sqlx::Error> {
+    let (start, step) = if weekly {
+        // monday of this week, `weekday 1` moves forward so start 6 days back
+        (
+            format!(
+                "date('now', 'localtime', '-6 days', 'weekday 1', '-{} days')",
+                (count - 1) * 7
+            ),
+            7,
+        )
+    } else {
+        (format!("date('now', 'localtime', '-{} days')", count - 1), 1)
+    };
+    let sql = format!(
+        r#"
+        WITH RECURSIVE periods(start) AS (
+            SELECT {start}
+            UNION ALL
+            SELECT date(start, '+{step} days') FROM periods
+            WHERE date(start, '+{step} days') <= date('now', 'localtime')
+        )
+        SELECT periods.start, COUNT(p.id), COALESCE(SUM(p.listened_ticks), 0)
+        FROM periods
+        LEFT JOIN play_log p
+            ON date(p.played_at, 'localtime') >= periods.start
+           AND date(p.played_at, 'localtime') < date(periods.start, '+{step} days')
+        GROUP BY periods.start
+        ORDER BY periods.start
+        "#,
+    );
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(&sql).fetch_all(pool).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(date, plays, ticks)| ListeningTime {
+            date,
+            plays: plays.max(0) as u64,
+            listened_secs: (ticks / TICKS_PER_SECOND).max(0) as u64,
+        })
+        .collect())
+}
+
+/// Current and longest run of days with at least one play
+///
+async fn streaks(pool: &SqlitePool) -> Result<(u32, u32), sqlx::Error> {
+    let days: Vec<(i64,)> = sqlx::query_as(
+        "SELECT DISTINCT CAST(julianday(date(played_at, 'localtime')) AS INTEGER)
+         FROM play_log ORDER BY 1",
+    )
+    .fetch_all(pool)
+    .await?;
+    let (today,): (i64,) =
+        sqlx::query_as("SELECT CAST(julianday(date('now', 'localtime')) AS INTEGER)")
+            .fetch_one(pool)
+            .await?;
+
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for (day,) in &days {
+        run = if previous == Some(day - 1) { run + 1 } else { 1 };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+    // a streak is still alive until a whole day passes without plays
+    let current = match previous {
+        Some(last) if last >= today - 1 => run,
+        _ => 0,
+    };
+
+    Ok((current, longest))
+}
+
+pub async fn compute_stats(
+    pool: &SqlitePool,
+    period: StatsPeriod,
+) -> Result<StatsReport, sqlx::Error> {
+    let (plays, ticks): (i64, i64) = sqlx::query_as(&format!(
+        "{} SELECT COALESCE(SUM(plays), 0), COALESCE(SUM(ticks), 0) FROM plays",
+        period.plays_source()
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    let top_tracks = top_entries(
+        pool,
+        period,
+        "SELECT t.name, t.album_artist,
+                SUM(pl.plays), SUM(pl.ticks)
+         FROM plays pl JOIN tracks t ON t.id = pl.track_id
+         GROUP BY pl.track_id",
+    )
+    .await?;
+    let top_artists = top_entries(
+        pool,
+        period,
+        "SELECT a.value, NULL, SUM(pl.plays), SUM(pl.ticks)
+         FROM plays pl JOIN tracks t ON t.id = pl.track_id, json_each(t.track, '$.Artists') a
+         GROUP BY a.value",
+    )
+    .await?;
+    let top_albums = top_entries(
+        pool,
+        period,
+        "SELECT t.album_name, t.album_artist,
+                SUM(pl.plays), SUM(pl.ticks)
+         FROM plays pl JOIN tracks t ON t.id = pl.track_id
+         GROUP BY t.album_id",
+    )
+    .await?;
+    let top_genres = top_entries(
+        pool,
+        period,
+        "SELECT g.value, NULL, SUM(pl.plays), SUM(pl.ticks)
+         FROM plays pl JOIN tracks t ON t.id = pl.track_id, json_each(t.track, '$.Genres') g
+         GROUP BY g.value",
+    )
+    .await?;
+
+    let days = period.days().unwrap_or(30).min(30);
+    let weeks = period.days().map(|d| d.div_ceil(7)).unwrap_or(52).clamp(1, 52);
+    let per_day = listening_time(pool, days, false).await?;
+    let per_week = listening_time(pool, weeks, true).await?;
+
+    let (current_streak, longest_streak) = streaks(pool).await?;
+
+    let (tracks_played, first_plays): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT COUNT(DISTINCT track_id), COUNT(DISTINCT CASE WHEN first_play = 1 THEN track_id END)
+         FROM play_log WHERE {}",
+        period.since("played_at")
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    Ok(StatsReport {
+        period,
+        plays: plays.max(0) as u64,
+        listened_secs: (ticks / TICKS_PER_SECOND).max(0) as u64,
+        top_tracks,
+        top_artists,
+        top_albums,
+        top_genres,
+        per_day,
+        per_week,
+        current_streak,
+        longest_streak,
+        tracks_played: tracks_played.max(0) as u64,
+        first_plays: first_plays.max(0) as u64,
+    })
+}
+
+pub fn default_export_path() -> String {
+    dirs::home_dir()
+        .map(|h| h.join("jellyfin-tui-stats.json").to_string_lossy().to_string())
+        .unwrap_or_else(|| "jellyfin-tui-stats.json".to_string())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the report as JSON, or the plays of its period as CSV when the path ends in .csv.
+/// Returns the number of plays covered
+///
+pub async fn export_stats(
+    pool: &SqlitePool,
+    report: &StatsReport,
+    path: &std::path::Path,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let is_csv = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    let (content, count) = if is_csv {
+        let rows: Vec<PlayRow> = sqlx::query_as(&format!(
+            r#"
+            SELECT
+                p.played_at,
+                t.name AS title,
+                t.album_artist AS artist,
+                t.album_name AS album,
+                p.listened_ticks,
+                p.first_play,
+                p.track_id
+            FROM play_log p
+            LEFT JOIN tracks t ON t.id = p.track_id
+            WHERE {}
+            ORDER BY p.played_at
+            "#,
+            report.period.since("p.played_at")
+        ))
+        .fetch_all(pool)
+        .await?;
+
+        let mut out =
+            String::from("played_at,title,artist,album,listened_seconds,first_play,track_id\n");
+        for row in &rows {
+            out += &[
+                csv_field(&row.played_at),
+                csv_field(row.title.as_deref().unwrap_or_default()),
+                csv_field(row.artist.as_deref().unwrap_or_default()),
+                csv_field(row.album.as_deref().unwrap_or_default()),
+                (row.listened_ticks / TICKS_PER_SECOND).to_string(),
+                row.first_play.to_string(),
+                csv_field(&row.track_id),
+            ]
+            .join(",");
+            out += "\n";
+        }
+        (out, rows.len() as u64)
+    } else {
+        (serde_json::to_string_pretty(report)?, report.plays)
+    };
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(count)
+}
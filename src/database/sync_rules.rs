@@ -0,0 +1,367 @@
+/* --------------------------
+Auto-download rules (smart sync)
+    - Rules are evaluated by the background updater after every library sync
+    - Tracks queued by a rule are flagged as `synced` and removed again once no rule covers them
+    - Manual and pinned downloads are never touched. Downloading or removing a rule's track by hand makes it a
+      manual one
+    - Pins are rules too: a pinned artist, album or playlist is kept downloaded in full, with its image, and its
+      tracks are never evicted by the download quota. New releases of a pinned artist are queued as soon as the
+      discography updater sees them
+-------------------------- */
+
+use super::database::Status;
+use super::extension::{query_download_tracks, remove_track_download, DownloadStatus};
+use super::outbox::item_server_id;
+use super::unified::merged_discography;
+use crate::client::{Client, DiscographySong};
+use crate::servers::Servers;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio::sync::mpsc::Sender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PinKind {
+    Artist,
+    Album,
+    Playlist,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SyncRuleKind {
+    Favorites,
+    Playlist { id: String, name: String },
+    RecentlyAdded { count: usize },
+    Pin { kind: PinKind, id: String, name: String },
+}
+
+impl SyncRuleKind {
+    /// Rules with the same key replace each other, there is one favorites rule, one per playlist
+    /// and one for recently added tracks
+    ///
+    fn key(&self) -> String {
+        match self {
+            SyncRuleKind::Favorites => "favorites".to_string(),
+            SyncRuleKind::Playlist { id, .. } => format!("playlist:{}", id),
+            SyncRuleKind::RecentlyAdded { .. } => "recently_added".to_string(),
+            SyncRuleKind::Pin { id, .. } => pin_key(id),
+        }
+    }
+
+    /// The artist, album or playlist this rule pins
+    ///
+    pub fn pinned_id(&self) -> Option<&str> {
+        match self {
+            SyncRuleKind::Pin { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The playlist or pinned item the rule follows, `None` for rules over the whole library
+    ///
+    pub fn item_id(&self) -> Option<&str> {
+        match self {
+            SyncRuleKind::Playlist { id, .. } | SyncRuleKind::Pin { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SyncRuleKind::Favorites => "Keep all favorites downloaded".to_string(),
+            SyncRuleKind::Playlist { name, .. } => format!("Keep playlist {} in sync", name),
+            SyncRuleKind::RecentlyAdded { count } => {
+                format!("Keep the {} most recently added tracks", count)
+            }
+            SyncRuleKind::Pin { kind: PinKind::Artist, name, .. } => {
+                format!("Pinned artist {}", name)
+            }
+            SyncRuleKind::Pin { kind: PinKind::Album, name, .. } => {
+                format!("Pinned album {}", name)
+            }
+            SyncRuleKind::Pin { kind: PinKind::Playlist, name, .. } => {
+                format!("Pinned playlist {}", name)
+            }
+        }
+    }
+}
+
+fn pin_key(id: &str) -> String {
+    format!("pin:{}", id)
+}
+
+fn has_cached_image(id: &str) -> bool {
+    let cover_dir = dirs::data_dir().unwrap().join("jellyfin-tui").join("covers");
+    std::fs::read_dir(cover_dir).is_ok_and(|files| {
+        files.flatten().any(|f| {
+            let name = f.file_name().to_string_lossy().to_string();
+            name.starts_with(&format!("{}.", id)) && !name.ends_with(".part")
+        })
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncRule {
+    pub id: i64,
+    pub kind: SyncRuleKind,
+}
+
+#[derive(Debug)]
+pub enum SyncRuleCommand {
+    Add { kind: SyncRuleKind },
+    Remove { id: i64 },
+}
+
+pub async fn get_sync_rules(pool: &SqlitePool) -> Result<Vec<SyncRule>, sqlx::Error> {
+    let rows: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, rule FROM sync_rules ORDER BY id").fetch_all(pool).await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, rule)| serde_json::from_str(&rule).ok().map(|kind| SyncRule { id, kind }))
+        .collect())
+}
+
+/// All tracks covered by at least one rule, used for the indicator next to the download status
+///
+pub async fn get_synced_track_ids(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let rows: Vec<(String,)> =
+        sqlx::query_as("SELECT DISTINCT track_id FROM sync_rule_tracks").fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+pub async fn add_sync_rule(
+    pool: &SqlitePool,
+    kind: &SyncRuleKind,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    sqlx::query(
+        "INSERT INTO sync_rules (key, rule) VALUES (?, ?)
+         ON CONFLICT(key) DO UPDATE SET rule = excluded.rule",
+    )
+    .bind(kind.key())
+    .bind(serde_json::to_string(kind)?)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn remove_sync_rule(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut tx_db = pool.begin().await?;
+    sqlx::query("DELETE FROM sync_rule_tracks WHERE rule_id = ?")
+        .bind(id)
+        .execute(&mut *tx_db)
+        .await?;
+    sqlx::query("DELETE FROM sync_rules WHERE id = ?").bind(id).execute(&mut *tx_db).await?;
+    tx_db.commit().await?;
+
+    Ok(())
+}
+
+async fn rule_tracks(
+    pool: &SqlitePool,
+    servers: &Servers,
+    client: &Client,
+    kind: &SyncRuleKind,
+) -> Result<Vec<DiscographySong>, Box<dyn std::error::Error + Send + Sync>> {
+    match kind {
+        SyncRuleKind::Favorites => client.sync_rule_tracks(true, None).await,
+        SyncRuleKind::RecentlyAdded { count } => client.sync_rule_tracks(false, Some(*count)).await,
+        SyncRuleKind::Playlist { id, .. }
+        | SyncRuleKind::Pin { kind: PinKind::Playlist, id, .. } => {
+            Ok(client.playlist(id, None).await?.items)
+        }
+        SyncRuleKind::Pin { kind: PinKind::Album, id, .. } => Ok(client.album_tracks(id).await?),
+        SyncRuleKind::Pin { kind: PinKind::Artist, id, .. } => {
+            let mut tracks = client.discography(id).await?;
+            let (merged, complete) = merged_discography(pool, servers, id).await?;
+            if !complete {
+                return Err("a server of the unified library could not be reached".into());
+            }
+            tracks.extend(merged);
+            Ok(tracks)
+        }
+    }
+}
+
+/// Queues the tracks that are not downloaded yet, flagged as `synced`. `queued` skips tracks another rule
+/// already queued in this run
+///
+async fn queue_missing(
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    tracks: Vec<DiscographySong>,
+    queued: &mut HashSet<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut missing = vec![];
+    for track in tracks {
+        if queued.contains(&track.id) {
+            continue;
+        }
+        let status =
+            sqlx::query_as::<_, DownloadStatus>("SELECT download_status FROM tracks WHERE id = ?")
+                .bind(&track.id)
+                .fetch_optional(pool)
+                .await?;
+        if matches!(status, None | Some(DownloadStatus::NotDownloaded)) {
+            queued.insert(track.id.clone());
+            missing.push(track);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if let Err(e) = query_download_tracks(pool, &mut missing).await {
+        log::error!("Failed to queue tracks for sync rule: {}", e);
+        return Ok(());
+    }
+    for track in missing {
+        sqlx::query("UPDATE tracks SET synced = 1 WHERE id = ?")
+            .bind(&track.id)
+            .execute(pool)
+            .await?;
+        let _ = tx.send(Status::TrackQueued { id: track.id }).await;
+    }
+    Ok(())
+}
+
+/// Called by the discography updater, queues what a pinned artist released since the rules last ran
+///
+pub async fn queue_pinned_artist_tracks(
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    artist_id: &str,
+    tracks: &[DiscographySong],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(rule_id) = sqlx::query_scalar::<_, i64>("SELECT id FROM sync_rules WHERE key = ?")
+        .bind(pin_key(artist_id))
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let mut tx_db = pool.begin().await?;
+    for track in tracks {
+        sqlx::query("INSERT OR IGNORE INTO sync_rule_tracks (rule_id, track_id) VALUES (?, ?)")
+            .bind(rule_id)
+            .bind(&track.id)
+            .execute(&mut *tx_db)
+            .await?;
+    }
+    tx_db.commit().await?;
+
+    queue_missing(pool, tx, tracks.to_vec(), &mut HashSet::new()).await?;
+    let _ = tx.send(Status::SyncRulesUpdated).await;
+    Ok(())
+}
+
+/// Evaluates every rule against the server, queues what is missing and cleans up what fell out of scope.
+/// A rule that fails to fetch keeps its previous tracks, so a flaky connection never removes downloads.
+///
+pub async fn apply_sync_rules(
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    servers: &Servers,
+    data_dir: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if servers.primary().is_none() {
+        return Ok(());
+    }
+    let rules = get_sync_rules(pool).await?;
+
+    let mut queued = HashSet::new();
+    for rule in rules {
+        // a playlist or pinned item is fetched from its own server, the other rules ask the primary
+        let client = match rule.kind.item_id() {
+            Some(id) => servers.client_for(&item_server_id(pool, id).await?),
+            None => servers.primary(),
+        };
+        let Some(client) = client else {
+            // its server isn't connected this session, the tracks from the last run stay
+            log::warn!(
+                "Skipping sync rule '{}', its server is not connected",
+                rule.kind.describe()
+            );
+            continue;
+        };
+        let tracks = match rule_tracks(pool, servers, client, &rule.kind).await {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                log::error!("Failed to evaluate sync rule '{}': {}", rule.kind.describe(), e);
+                continue;
+            }
+        };
+
+        let mut tx_db = pool.begin().await?;
+        sqlx::query("DELETE FROM sync_rule_tracks WHERE rule_id = ?")
+            .bind(rule.id)
+            .execute(&mut *tx_db)
+            .await?;
+        for track in &tracks {
+            sqlx::query("INSERT OR IGNORE INTO sync_rule_tracks (rule_id, track_id) VALUES (?, ?)")
+                .bind(rule.id)
+                .bind(&track.id)
+                .execute(&mut *tx_db)
+                .await?;
+        }
+        tx_db.commit().await?;
+
+        // the artist image or playlist cover, album covers come with the tracks
+        if let Some(id) = rule.kind.pinned_id().filter(|id| !has_cached_image(id)) {
+            if let Err(e) = client.download_cover_art(&id.to_string()).await {
+                log::warn!("Failed to download the image of pinned item {}: {}", id, e);
+            }
+        }
+
+        queue_missing(pool, tx, tracks, &mut queued).await?;
+    }
+
+    cleanup_synced_downloads(pool, tx, data_dir).await
+}
+
+/// Removes downloads that a rule queued but no rule covers anymore. Pinned tracks stay
+///
+pub async fn cleanup_synced_downloads(
+    pool: &SqlitePool,
+    tx: &Sender<Status>,
+    data_dir: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let stale = sqlx::query_as::<_, (String, String)>(
+        "
+        SELECT track, download_status FROM tracks
+        WHERE synced = 1
+          AND pinned = 0
+          AND id NOT IN (SELECT track_id FROM sync_rule_tracks)
+        ",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for (track_str, download_status) in stale {
+        let Ok(mut track) = serde_json::from_str::<DiscographySong>(&track_str) else {
+            continue;
+        };
+        track.download_status = DownloadStatus::from_column(&download_status);
+        let removed = remove_track_download(pool, &track, data_dir).await.is_ok();
+        if !removed {
+            log::error!("Failed to remove synced download {}", track.id);
+            continue;
+        }
+        sqlx::query("UPDATE tracks SET synced = 0 WHERE id = ?")
+            .bind(&track.id)
+            .execute(pool)
+            .await?;
+        let _ = tx.send(Status::TrackDeleted { id: track.id }).await;
+    }
+
+    let _ = tx.send(Status::SyncRulesUpdated).await;
+
+    Ok(())
+}
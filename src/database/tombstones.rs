@@ -0,0 +1,324 @@
+/* --------------------------
+Tombstones, albums removed from the server that still have downloads
+    - mark_missing holds these back instead of deleting them, they show up in "Removed from server" in the global popup
+    - Keep: the album stays as a local-only album for good
+    - Re-match: the server often brings an album back under a new id after a rescan. The downloads are moved over to
+      the album matching by MusicBrainz id or name and album artist, track by track. Albums that both have a
+      MusicBrainz id only match if it is the same, and the tracks keep their playlists
+    - Delete: removes the album and its downloads like a regular removal would have
+-------------------------- */
+
+use super::database::{delete_album, Status};
+use super::extension::DownloadStatus;
+use super::unified::MatchKey;
+use crate::client::{Album, Client, DiscographySong};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+/// How many full syncs in a row an album has to be missing for before it is removed, or held as a tombstone
+pub const ALBUM_MISSING_THRESHOLD: i64 = 3;
+
+type TombstoneResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Tombstone {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+    pub downloaded: i64,
+    /// Unix seconds of the first sync that did not see the album
+    pub missing_since: i64,
+    pub local_only: bool,
+}
+
+impl Tombstone {
+    pub fn describe(&self) -> String {
+        let last_seen = chrono::DateTime::from_timestamp(self.missing_since, 0)
+            .map(|dt| dt.format("%-d %b %Y").to_string())
+            .unwrap_or_default();
+        format!(
+            "{} by {}, {} downloaded, last seen before {}{}",
+            self.name,
+            self.artist,
+            self.downloaded,
+            last_seen,
+            if self.local_only { " (kept)" } else { "" }
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TombstoneCommand {
+    Keep { id: String },
+    Rematch { id: String },
+    Delete { id: String },
+}
+
+pub async fn get_tombstones(pool: &SqlitePool) -> sqlx::Result<Vec<Tombstone>> {
+    sqlx::query_as(
+        r#"
+        SELECT * FROM (
+            SELECT mc.id,
+                   COALESCE(a.name, '') AS name,
+                   COALESCE(a.album_artist, '') AS artist,
+                   (SELECT COUNT(*) FROM tracks t
+                    WHERE t.album_id = mc.id AND t.download_status = 'Downloaded') AS downloaded,
+                   COALESCE(mc.missing_since, mc.last_checked_at) AS missing_since,
+                   mc.local_only != 0 AS local_only
+            FROM missing_counters mc
+            JOIN albums a ON a.id = mc.id
+            WHERE mc.entity_type = 'album'
+              AND (mc.missing_seen_count >= ? OR mc.local_only != 0)
+        )
+        WHERE downloaded > 0 OR local_only
+        ORDER BY missing_since DESC
+        "#,
+    )
+    .bind(ALBUM_MISSING_THRESHOLD)
+    .fetch_all(pool)
+    .await
+}
+
+fn downloads_dir() -> PathBuf {
+    dirs::data_dir().unwrap().join("jellyfin-tui").join("downloads")
+}
+
+async fn album_tracks(
+    pool: &SqlitePool,
+    album_id: &str,
+    downloaded: bool,
+) -> TombstoneResult<Vec<DiscographySong>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT track, download_status FROM tracks
+         WHERE album_id = ? AND (? = 0 OR download_status = 'Downloaded')",
+    )
+    .bind(album_id)
+    .bind(downloaded)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(json, download_status)| {
+            let mut track = serde_json::from_str::<DiscographySong>(&json).ok()?;
+            track.download_status = DownloadStatus::from_column(&download_status);
+            Some(track)
+        })
+        .collect())
+}
+
+async fn load_album(pool: &SqlitePool, album_id: &str) -> TombstoneResult<Option<Album>> {
+    let json: Option<String> = sqlx::query_scalar("SELECT album FROM albums WHERE id = ?")
+        .bind(album_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(json.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+fn match_key(album: &Album) -> MatchKey {
+    let artist = album.album_artists.first().map(|a| a.name.as_str()).unwrap_or("");
+    let musicbrainz_id = album.provider_ids.get("MusicBrainzAlbum").and_then(|v| v.as_str());
+    MatchKey::album(&album.name, artist, musicbrainz_id)
+}
+
+/// The MusicBrainz track id decides, then disc, track number and title. The title alone only counts for tracks
+/// without disc and track numbers, an album can have the same title twice. Tracks in `used` already got a download
+///
+fn matching_track<'a>(
+    track: &DiscographySong,
+    candidates: &'a [DiscographySong],
+    used: &HashSet<String>,
+) -> Option<&'a DiscographySong> {
+    let name = track.name.trim().to_lowercase();
+    let mut free = candidates.iter().filter(|c| !used.contains(&c.id));
+    track
+        .provider_ids
+        .musicbrainz_track
+        .as_ref()
+        .and_then(|mbid| {
+            free.clone().find(|c| c.provider_ids.musicbrainz_track.as_ref() == Some(mbid))
+        })
+        .or_else(|| {
+            free.clone().find(|c| {
+                c.parent_index_number == track.parent_index_number
+                    && c.index_number == track.index_number
+                    && c.name.trim().to_lowercase() == name
+            })
+        })
+        .or_else(|| {
+            if track.parent_index_number != 0 || track.index_number != 0 {
+                return None;
+            }
+            free.find(|c| c.name.trim().to_lowercase() == name)
+        })
+}
+
+/// Spawned by the database thread, the outcome is shown with `Status::TombstoneResolved`
+///
+pub async fn t_tombstone(
+    pool: SqlitePool,
+    tx: Sender<Status>,
+    client: Option<Arc<Client>>,
+    cmd: TombstoneCommand,
+) {
+    let result = match cmd {
+        TombstoneCommand::Keep { id } => keep(&pool, &id).await,
+        TombstoneCommand::Rematch { id } => rematch(&pool, client.as_deref(), &id).await,
+        TombstoneCommand::Delete { id } => delete(&pool, &id).await,
+    };
+    match result {
+        Ok(message) => {
+            let _ = tx.send(Status::TombstoneResolved { message }).await;
+        }
+        Err(e) => {
+            log::error!("Failed to resolve a removed album: {}", e);
+            let _ = tx.send(Status::Error { error: e.to_string() }).await;
+        }
+    }
+    let _ = tx.send(Status::AlbumsUpdated).await;
+    let _ = tx.send(Status::TombstonesUpdated).await;
+}
+
+async fn keep(pool: &SqlitePool, id: &str) -> TombstoneResult<String> {
+    sqlx::query(
+        "UPDATE missing_counters SET local_only = 1 WHERE entity_type = 'album' AND id = ?",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok("The album stays in your library as a local-only album.".to_string())
+}
+
+async fn delete(pool: &SqlitePool, id: &str) -> TombstoneResult<String> {
+    let tracks = album_tracks(pool, id, false).await?;
+    let downloaded = album_tracks(pool, id, true).await?.len();
+    let server_ids = tracks.iter().map(|t| t.server_id.clone()).collect::<HashSet<_>>();
+
+    let mut tx_db = pool.begin().await?;
+    delete_album(&mut tx_db, id).await?;
+    sqlx::query("DELETE FROM missing_counters WHERE entity_type = 'album' AND id = ?")
+        .bind(id)
+        .execute(&mut *tx_db)
+        .await?;
+    tx_db.commit().await?;
+
+    for server_id in server_ids {
+        let _ = tokio::fs::remove_dir_all(downloads_dir().join(server_id).join(id)).await;
+    }
+    Ok(format!("The album and its {} downloaded tracks were deleted.", downloaded))
+}
+
+async fn rematch(pool: &SqlitePool, client: Option<&Client>, id: &str) -> TombstoneResult<String> {
+    let Some(album) = load_album(pool, id).await? else {
+        return Err("the album is not in the library anymore".into());
+    };
+    let wanted = match_key(&album);
+
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "SELECT album FROM albums
+         WHERE id != ? AND id NOT IN (SELECT id FROM missing_counters WHERE entity_type = 'album')",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?;
+    let Some(replacement) = rows
+        .into_iter()
+        .filter_map(|(json,)| serde_json::from_str::<Album>(&json).ok())
+        .find(|candidate| match_key(candidate).matches(&wanted))
+    else {
+        return Ok(format!("No album on the server matches {}, nothing was changed.", album.name));
+    };
+
+    let mut candidates = album_tracks(pool, &replacement.id, false).await?;
+    if candidates.is_empty() {
+        let Some(client) = client else {
+            return Ok(format!(
+                "{} matches, but its tracks are not cached. Try again while online.",
+                replacement.name
+            ));
+        };
+        candidates = client.album_tracks(&replacement.id).await?;
+    }
+
+    let downloaded = album_tracks(pool, id, true).await?;
+    let mut moved = 0;
+    let mut used = HashSet::new();
+    for track in &downloaded {
+        let Some(target) = matching_track(track, &candidates, &used) else {
+            continue;
+        };
+        let from = downloads_dir().join(&track.server_id).join(&track.album_id).join(&track.id);
+        let to = downloads_dir().join(&target.server_id).join(&target.album_id).join(&target.id);
+        if let Some(parent) = to.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if let Err(e) = tokio::fs::rename(&from, &to).await {
+            log::warn!("Failed to move {} to {}: {}", from.display(), to.display(), e);
+            continue;
+        }
+
+        let mut tx_db = pool.begin().await?;
+        sqlx::query(
+            "INSERT OR IGNORE INTO tracks (id, album_id, download_status, track, library_id)
+             VALUES (?, ?, 'NotDownloaded', ?, (SELECT library_id FROM albums WHERE id = ?))",
+        )
+        .bind(&target.id)
+        .bind(&target.album_id)
+        .bind(serde_json::to_string(target)?)
+        .bind(&target.album_id)
+        .execute(&mut *tx_db)
+        .await?;
+        // the download and everything known about it moves along
+        sqlx::query(
+            "UPDATE tracks SET (download_status, download_size_bytes, downloaded_at, last_played,
+                                download_container, download_bitrate, download_checksum, pinned, synced, disliked) =
+                (SELECT 'Downloaded', download_size_bytes, downloaded_at, last_played,
+                        download_container, download_bitrate, download_checksum, pinned, synced, disliked
+                 FROM tracks WHERE id = ?)
+             WHERE id = ?",
+        )
+        .bind(&track.id)
+        .bind(&target.id)
+        .execute(&mut *tx_db)
+        .await?;
+        sqlx::query("UPDATE tracks SET download_status = 'NotDownloaded' WHERE id = ?")
+            .bind(&track.id)
+            .execute(&mut *tx_db)
+            .await?;
+        // deleting the old album drops its playlist memberships
+        sqlx::query(
+            "INSERT OR IGNORE INTO playlist_membership (playlist_id, track_id, position)
+             SELECT playlist_id, ?, position FROM playlist_membership WHERE track_id = ?",
+        )
+        .bind(&target.id)
+        .bind(&track.id)
+        .execute(&mut *tx_db)
+        .await?;
+        for artist in &target.album_artists {
+            sqlx::query(
+                "INSERT OR IGNORE INTO artist_membership (artist_id, track_id) VALUES (?, ?)",
+            )
+            .bind(&artist.id)
+            .bind(&target.id)
+            .execute(&mut *tx_db)
+            .await?;
+        }
+        tx_db.commit().await?;
+        used.insert(target.id.clone());
+        moved += 1;
+    }
+
+    if moved < downloaded.len() {
+        return Ok(format!(
+            "Moved {} of {} downloads to {}. The others did not match a track and stay where they are.",
+            moved,
+            downloaded.len(),
+            replacement.name
+        ));
+    }
+    delete(pool, id).await?;
+    Ok(format!("Moved {} downloads to {} and removed the old album.", moved, replacement.name))
+}
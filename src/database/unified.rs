@@ -0,0 +1,381 @@
+/* --------------------------
+Unified library, the database side
+    - The primary server is synced by the data_updater, the other servers are merged in after it by the peer_updater
+    - An artist or album another server already brought in (same MusicBrainz id, or same name) is recorded in
+      merged_items instead of being added twice. The first server to have an item owns it
+    - Every server reconciles removals of its own items only, told apart by the ServerId in their JSON
+-------------------------- */
+
+use super::database::{mark_missing, Status};
+use super::library_sync::{
+    album_artists_only, assign_track_libraries, changes_since, get_meta, now_secs, save_libraries,
+    set_meta, upsert_albums, upsert_artists, SyncResult, ARTIST_MISSING_THRESHOLD,
+    FULL_SYNC_INTERVAL_SECS,
+};
+use super::tombstones::ALBUM_MISSING_THRESHOLD;
+use crate::client::{Album, Artist, Client, DiscographySong};
+use crate::servers::Servers;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc::Sender;
+
+#[derive(sqlx::FromRow)]
+struct MergeCandidate {
+    id: String,
+    name: String,
+    musicbrainz_id: Option<String>,
+    /// The first album artist, albums only
+    artist: Option<String>,
+}
+
+/// What artists and albums are matched by. When both sides have a MusicBrainz id it decides, so a deluxe edition
+/// or a re-release with its own id stays a separate album. Otherwise the name (and album artist) has to be the same
+///
+#[derive(Debug, Clone)]
+pub(super) struct MatchKey {
+    name: String,
+    musicbrainz_id: Option<String>,
+}
+
+impl MatchKey {
+    fn artist(name: &str, musicbrainz_id: Option<&str>) -> Self {
+        Self {
+            name: name.trim().to_lowercase(),
+            musicbrainz_id: musicbrainz_id.filter(|id| !id.is_empty()).map(String::from),
+        }
+    }
+
+    pub(super) fn album(name: &str, artist: &str, musicbrainz_id: Option<&str>) -> Self {
+        Self {
+            name: format!("{}\u{1f}{}", name.trim().to_lowercase(), artist.trim().to_lowercase()),
+            musicbrainz_id: musicbrainz_id.filter(|id| !id.is_empty()).map(String::from),
+        }
+    }
+
+    pub(super) fn matches(&self, other: &MatchKey) -> bool {
+        match (&self.musicbrainz_id, &other.musicbrainz_id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            _ => self.name == other.name,
+        }
+    }
+}
+
+/// Items by MusicBrainz id and by name, to find the one a `MatchKey` matches
+///
+#[derive(Default)]
+struct KeyIndex {
+    by_musicbrainz_id: HashMap<String, String>,
+    by_name: HashMap<String, Vec<(MatchKey, String)>>,
+}
+
+impl KeyIndex {
+    fn insert(&mut self, key: MatchKey, id: String) {
+        if let Some(musicbrainz_id) = &key.musicbrainz_id {
+            self.by_musicbrainz_id.insert(musicbrainz_id.clone(), id.clone());
+        }
+        self.by_name.entry(key.name.clone()).or_default().push((key, id));
+    }
+
+    fn find(&self, key: &MatchKey) -> Option<&String> {
+        key.musicbrainz_id.as_ref().and_then(|id| self.by_musicbrainz_id.get(id)).or_else(|| {
+            self.by_name
+                .get(&key.name)?
+                .iter()
+                .find(|(other, _)| key.matches(other))
+                .map(|(_, id)| id)
+        })
+    }
+}
+
+fn provider_id<'a>(provider_ids: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    provider_ids.get(key).and_then(|v| v.as_str())
+}
+
+/// Artists and albums of the other servers
+///
+struct Twins {
+    artists: KeyIndex,
+    albums: KeyIndex,
+}
+
+impl Twins {
+    async fn load(pool: &SqlitePool, server_id: &str) -> sqlx::Result<Self> {
+        let artists: Vec<MergeCandidate> = sqlx::query_as(
+            r#"
+            SELECT id,
+                   COALESCE(name, '') AS name,
+                   json_extract(artist, '$.ProviderIds.MusicBrainzArtist') AS musicbrainz_id,
+                   NULL AS artist
+            FROM artists
+            WHERE server_id != ?
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+        let albums: Vec<MergeCandidate> = sqlx::query_as(
+            r#"
+            SELECT id,
+                   COALESCE(name, '') AS name,
+                   json_extract(album, '$.ProviderIds.MusicBrainzAlbum') AS musicbrainz_id,
+                   album_artist AS artist
+            FROM albums
+            WHERE server_id != ?
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+        let mut twins = Self { artists: KeyIndex::default(), albums: KeyIndex::default() };
+        for a in artists {
+            twins.artists.insert(MatchKey::artist(&a.name, a.musicbrainz_id.as_deref()), a.id);
+        }
+        for a in albums {
+            let key = MatchKey::album(
+                &a.name,
+                a.artist.as_deref().unwrap_or(""),
+                a.musicbrainz_id.as_deref(),
+            );
+            twins.albums.insert(key, a.id);
+        }
+        Ok(twins)
+    }
+
+    fn artist(&self, artist: &Artist) -> Option<&String> {
+        self.artists.find(&MatchKey::artist(
+            &artist.name,
+            provider_id(&artist.provider_ids, "MusicBrainzArtist"),
+        ))
+    }
+
+    fn album(&self, album: &Album) -> Option<&String> {
+        let artist = album.album_artists.first().map(|a| a.name.as_str()).unwrap_or("");
+        self.albums.find(&MatchKey::album(
+            &album.name,
+            artist,
+            provider_id(&album.provider_ids, "MusicBrainzAlbum"),
+        ))
+    }
+}
+
+async fn record_merged(
+    pool: &SqlitePool,
+    kind: &str,
+    server_id: &str,
+    merged: &[(String, String)],
+) -> sqlx::Result<()> {
+    let mut tx_db = pool.begin().await?;
+    for (id, remote_id) in merged {
+        sqlx::query(
+            r#"
+            INSERT INTO merged_items (kind, id, server_id, remote_id)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(kind, server_id, remote_id) DO UPDATE SET id = excluded.id
+            "#,
+        )
+        .bind(kind)
+        .bind(id)
+        .bind(server_id)
+        .bind(remote_id)
+        .execute(&mut *tx_db)
+        .await?;
+    }
+    tx_db.commit().await?;
+    Ok(())
+}
+
+/// Items written before unified libraries existed carry no ServerId, they are the primary's.
+/// Stamping them keeps the peers' removal passes away from them
+///
+pub async fn claim_unowned_items(pool: &SqlitePool, server_id: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE artists SET artist = json_set(artist, '$.ServerId', ?)
+         WHERE server_id = ''",
+    )
+    .bind(server_id)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "UPDATE albums SET album = json_set(album, '$.ServerId', ?)
+         WHERE server_id = ''",
+    )
+    .bind(server_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Merges the artists and albums of another server into the library. Like the data updater this only fetches the
+/// changes between the daily full runs, and removals are reconciled on the full runs
+///
+pub async fn peer_updater(pool: &SqlitePool, tx: &Sender<Status>, peer: &Client) -> SyncResult<()> {
+    let started = now_secs();
+    let full_key = format!("last_full_sync:{}", peer.server_id);
+    let delta_key = format!("last_delta_sync:{}", peer.server_id);
+
+    let since = match get_meta(pool, &full_key).await {
+        Some(last_full) if started - last_full < FULL_SYNC_INTERVAL_SECS => {
+            get_meta(pool, &delta_key).await.and_then(changes_since)
+        }
+        _ => None,
+    };
+    log::info!(
+        "Unified library: syncing server {} ({})",
+        peer.server_id,
+        since.as_deref().unwrap_or("full")
+    );
+
+    let music_libs = peer.music_libraries().await?;
+    save_libraries(pool, &music_libs).await?;
+
+    let mut albums_complete = true;
+    let mut remote_albums = vec![];
+    for lib in &music_libs {
+        let albums = match &since {
+            Some(since) => peer.albums_changed_since(&lib.id, since).await?,
+            None => match peer.albums(Some(&lib.id)).await {
+                Ok(albums) => albums,
+                Err(e) => {
+                    albums_complete = false;
+                    log::warn!("Failed to fetch albums for library {}: {}", lib.id, e);
+                    continue;
+                }
+            },
+        };
+        remote_albums.push((lib, albums));
+    }
+    let remote_artists = match &since {
+        Some(since) => {
+            let artists = peer.artists_changed_since(since).await?;
+            let albums =
+                remote_albums.iter().flat_map(|(_, albums)| albums.iter()).collect::<Vec<_>>();
+            album_artists_only(pool, artists, &albums).await?
+        }
+        None => peer.artists(String::new()).await?,
+    };
+
+    let twins = Twins::load(pool, &peer.server_id).await?;
+    if since.is_none() {
+        // a full run sees every item, the merges are recorded again from scratch
+        sqlx::query("DELETE FROM merged_items WHERE server_id = ?")
+            .bind(&peer.server_id)
+            .execute(pool)
+            .await?;
+    }
+
+    let mut merged = vec![];
+    let mut artists = vec![];
+    for artist in &remote_artists {
+        match twins.artist(artist) {
+            Some(id) => merged.push((id.clone(), artist.id.clone())),
+            None => artists.push(artist.clone()),
+        }
+    }
+    record_merged(pool, "artist", &peer.server_id, &merged).await?;
+    let artists_changed = upsert_artists(pool, &artists).await?;
+
+    let mut albums_changed = 0;
+    let mut merged = vec![];
+    let mut tx_db = pool.begin().await?;
+    for (lib, remote) in &remote_albums {
+        let mut albums = vec![];
+        for album in remote {
+            match twins.album(album) {
+                Some(id) => merged.push((id.clone(), album.id.clone())),
+                None => albums.push(album.clone()),
+            }
+        }
+        albums_changed += upsert_albums(&mut tx_db, &lib.id, &albums).await?;
+    }
+    tx_db.commit().await?;
+    record_merged(pool, "album", &peer.server_id, &merged).await?;
+    assign_track_libraries(pool).await?;
+
+    if since.is_none() {
+        let tx = Some(tx.clone());
+        let artist_ids = remote_artists.iter().map(|a| a.id.clone()).collect::<Vec<_>>();
+        mark_missing(pool, &tx, "artist", &artist_ids, &peer.server_id, ARTIST_MISSING_THRESHOLD)
+            .await?;
+        if albums_complete {
+            let album_ids = remote_albums
+                .iter()
+                .flat_map(|(_, albums)| albums.iter().map(|a| a.id.clone()))
+                .collect::<Vec<_>>();
+            mark_missing(pool, &tx, "album", &album_ids, &peer.server_id, ALBUM_MISSING_THRESHOLD)
+                .await?;
+        }
+    }
+
+    if artists_changed > 0 {
+        tx.send(Status::ArtistsUpdated).await?;
+    }
+    if albums_changed > 0 {
+        tx.send(Status::AlbumsUpdated).await?;
+    }
+
+    if since.is_none() && albums_complete {
+        set_meta(pool, &full_key, started).await;
+    }
+    set_meta(pool, &delta_key, started).await;
+
+    Ok(())
+}
+
+/// The ServerId of an artist, empty for the primary's older rows
+///
+pub async fn artist_server(pool: &SqlitePool, artist_id: &str) -> String {
+    sqlx::query_scalar::<_, Option<String>>("SELECT server_id FROM artists WHERE id = ?")
+        .bind(artist_id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Tracks of the other servers' twins of an artist. Tracks of albums merged into another are left out, the
+/// album is there already. The flag is false if a server could not be asked
+///
+pub async fn merged_discography(
+    pool: &SqlitePool,
+    servers: &Servers,
+    artist_id: &str,
+) -> SyncResult<(Vec<DiscographySong>, bool)> {
+    let twins: Vec<(String, String)> = sqlx::query_as(
+        "SELECT server_id, remote_id FROM merged_items WHERE kind = 'artist' AND id = ?",
+    )
+    .bind(artist_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut tracks = vec![];
+    let mut complete = true;
+    for (server_id, remote_id) in twins {
+        let Some(client) = servers.client_for(&server_id) else {
+            complete = false;
+            continue;
+        };
+        let discography = match client.discography(&remote_id).await {
+            Ok(discography) => discography,
+            Err(e) => {
+                log::warn!("Failed to fetch discography {} from {}: {}", remote_id, server_id, e);
+                complete = false;
+                continue;
+            }
+        };
+        let merged_albums: HashSet<String> = sqlx::query_scalar(
+            "SELECT remote_id FROM merged_items WHERE kind = 'album' AND server_id = ?",
+        )
+        .bind(&server_id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+        tracks.extend(discography.into_iter().filter(|t| !merged_albums.contains(&t.album_id)));
+    }
+
+    Ok((tracks, complete))
+}
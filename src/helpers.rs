@@ -1,6 +1,6 @@
 use crate::client::{DiscographySong, ProgressReportInternal};
 use crate::themes::theme::Theme;
-use crate::tui::RadioMode;
+use crate::tui::{RadioMode, RadioProfile, StopMarker};
 use crate::{
     client::{Album, Artist, Playlist},
     helpers,
@@ -160,6 +160,18 @@ pub fn format_release_date(s: &str) -> Option<String> {
     DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.format(" (%-d %b %Y)").to_string())
 }
 
+pub fn format_size(bytes: i64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.2} KB", bytes as f64 / 1024.0)
+    } else if bytes < 1024 * 1024 * 1024 {
+        format!("{:.2} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.2} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    }
+}
+
 pub fn centered_rect_percent(width_percent: u16, height_percent: u16, area: Rect) -> Rect {
     let vertical = Layout::default()
         .direction(Direction::Vertical)
@@ -314,6 +326,9 @@ pub struct State {
     #[serde(default)]
     pub current_playback_state: MpvPlaybackState,
 
+    #[serde(default)]
+    pub stop_marker: Option<StopMarker>,
+
     #[serde(skip)]
     pub last_reported: Option<ProgressReportInternal>,
 }
@@ -378,6 +393,7 @@ impl State {
                 seek_active: false,
                 idle_active: false,
             },
+            stop_marker: None,
             last_reported: None,
         }
     }
@@ -440,11 +456,15 @@ pub struct Symbols {
     pub pause: String,
     pub sleep: String,
     pub downloaded: String,
+    pub synced: String,
+    pub pinned: String,
+    pub changed: String,
     pub queued: String,
     pub lyrics: String,
     pub spinner: String,
     pub separator: String,
     pub disc: String,
+    pub stop: String,
 }
 
 impl Default for Symbols {
@@ -456,11 +476,15 @@ impl Default for Symbols {
             pause: "⏸︎".into(),
             sleep: "⏾".into(),
             downloaded: "⇊".into(),
+            synced: "⟳".into(),
+            pinned: "◈".into(),
+            changed: "✦".into(),
             queued: "◴".into(),
             lyrics: "♪".into(),
             spinner: "◰◳◲◱".into(),
             separator: "›".into(),
             disc: "○".into(),
+            stop: "⏹︎".into(),
         }
     }
 }
@@ -523,6 +547,10 @@ pub struct Preferences {
     pub instant_playlist_size: usize,
     #[serde(default)]
     pub radio_mode: RadioMode,
+    #[serde(default)]
+    pub radio: RadioProfile,
+    #[serde(default)]
+    pub radio_profiles: Vec<RadioProfile>,
 
     // runtime assigned server_id
     #[serde(skip)]
@@ -568,6 +596,8 @@ impl Preferences {
 
             instant_playlist_size: 100,
             radio_mode: RadioMode::default(),
+            radio: RadioProfile::default(),
+            radio_profiles: vec![],
 
             server_id,
 
@@ -14,11 +14,15 @@ use crate::{
     tui::App,
 };
 use std::borrow::Cow;
+use std::collections::HashSet;
 
 use crate::database::extension::{
-    get_album_tracks, get_discography, get_playlist_tracks, get_tracks, set_favorite_album,
+    get_album_tracks, get_discography, get_playlist_tracks, set_favorite_album,
     set_favorite_artist, set_favorite_playlist, set_favorite_track,
 };
+use crate::database::outbox::Mutation;
+use crate::database::search::{search_local, LocalSearch};
+use crate::database::stats::compute_stats;
 pub(crate) use crate::helpers::{search_ranked_indices, search_ranked_refs};
 use crate::mpv::SeekFlag;
 
@@ -69,6 +73,10 @@ pub enum Action {
     Download,
     /// Remove downloaded item
     RemoveDownload,
+    /// Mark / unmark the selected row for bulk actions
+    ToggleMark,
+    /// Start / stop marking a range of rows (vim visual mode)
+    VisualMode,
 
     /// Move current item up (relocate)
     MoveItemUp,
@@ -82,6 +90,8 @@ pub enum Action {
     QueueAppend,
     /// Clear the temporary queue
     ClearTempQueue,
+    /// Pause after the selected queue row (or the playing track). Pressing it again removes the marker
+    ToggleStopMarker,
     /// Play all tracks from the current list (discography / album / playlist), respecting shuffle
     PlayAll,
 
@@ -165,6 +175,8 @@ impl Action {
             Action::ToggleFavorite => Cow::Borrowed("Toggle favorite"),
             Action::Download => Cow::Borrowed("Download"),
             Action::RemoveDownload => Cow::Borrowed("Remove download"),
+            Action::ToggleMark => Cow::Borrowed("Mark / unmark row for bulk actions"),
+            Action::VisualMode => Cow::Borrowed("Visual mode (mark a range of rows)"),
             // Queue
             Action::MoveItemUp => Cow::Borrowed("Move item up"),
             Action::MoveItemDown => Cow::Borrowed("Move item down"),
@@ -172,6 +184,7 @@ impl Action {
             Action::QueueTempBack => Cow::Borrowed("Queue at end of temporary queue"),
             Action::QueueAppend => Cow::Borrowed("Queue at end of main queue"),
             Action::ClearTempQueue => Cow::Borrowed("Clear temporary queue"),
+            Action::ToggleStopMarker => Cow::Borrowed("Toggle stop marker after this track"),
             Action::PlayAll => Cow::Borrowed("Play all (discography / album / playlist)"),
             // Playback
             Action::PlayPause => Cow::Borrowed("Play / Pause"),
@@ -233,9 +246,11 @@ impl Action {
             | Action::NextPane
             | Action::PreviousPane => ActionCategory::Navigation,
 
-            Action::ToggleFavorite | Action::Download | Action::RemoveDownload => {
-                ActionCategory::Library
-            }
+            Action::ToggleFavorite
+            | Action::Download
+            | Action::RemoveDownload
+            | Action::ToggleMark
+            | Action::VisualMode => ActionCategory::Library,
 
             Action::MoveItemUp
             | Action::MoveItemDown
@@ -243,6 +258,7 @@ impl Action {
             | Action::QueueTempBack
             | Action::QueueAppend
             | Action::ClearTempQueue
+            | Action::ToggleStopMarker
             | Action::PlayAll => ActionCategory::Queue,
 
             Action::PlayPause
@@ -291,6 +307,7 @@ const DEFAULT_BINDINGS: &[(KeyCombination, Action)] = &[
     (key!(2), Action::Tab(2)),
     (key!(3), Action::Tab(3)),
     (key!(4), Action::Tab(4)),
+    (key!(5), Action::Tab(5)),
     // up
     (key!(k), Action::Up),
     (key!(up), Action::Up),
@@ -353,10 +370,13 @@ const DEFAULT_BINDINGS: &[(KeyCombination, Action)] = &[
     (key!(ctrl - e), Action::QueueTempFront),
     (key!('e'), Action::QueueTempBack),
     (key!(shift - e), Action::ClearTempQueue),
+    (key!(shift - x), Action::ToggleStopMarker),
     // row commands
     (key!('f'), Action::ToggleFavorite),
     (key!(shift - d), Action::RemoveDownload),
     (key!('d'), Action::Download),
+    (key!('m'), Action::ToggleMark),
+    (key!('v'), Action::VisualMode),
     // global commands
     (key!(r), Action::Repeat),
     (key!(shift - r), Action::CycleRadio),
@@ -514,6 +534,20 @@ impl App {
             self.process_search_tab_action(&action).await;
             return;
         }
+        if self.state.active_tab == ActiveTab::Stats && self.process_stats_tab_action(action).await
+        {
+            return;
+        }
+
+        if self.has_marks() {
+            if let Action::Cancel = action {
+                self.clear_marks();
+                return;
+            }
+            if self.apply_marked_action(action).await {
+                return;
+            }
+        }
 
         match action {
             Action::Quit => self.exit().await,
@@ -552,7 +586,7 @@ impl App {
             }
             Action::Next => self.next().await,
             Action::Previous => self.previous().await,
-            Action::Tab(index) => self.set_tab(std::cmp::min(*index, 4)).await,
+            Action::Tab(index) => self.set_tab(std::cmp::min(*index, 5)).await,
             Action::PlayPause => match self.paused {
                 true => self.play().await,
                 false => self.pause().await,
@@ -578,6 +612,7 @@ impl App {
             Action::QueueTempBack => self.emplace_temp(false).await,
             Action::QueueAppend => self.emplace_main().await,
             Action::ClearTempQueue => self.clear_temporary_queue().await,
+            Action::ToggleStopMarker => self.toggle_stop_marker(),
             Action::PlayAll => self.execute_play_all().await,
             Action::ToggleFavorite => self.toggle_favorite().await,
             Action::Download => self.download(false).await,
@@ -589,6 +624,8 @@ impl App {
             Action::Popup => self.request_popup(false).await,
             Action::GlobalPopup => self.request_popup(true).await,
             Action::CycleRadio => self.cycle_radio().await,
+            Action::ToggleMark => self.toggle_mark(),
+            Action::VisualMode => self.toggle_visual_mode(),
             // noops
             Action::DeleteBack => {}
             Action::Type(_) => {}
@@ -596,6 +633,10 @@ impl App {
                 crate::helpers::run_shell_command(&cmd).await;
             }
         }
+
+        if self.marks.visual() {
+            self.update_visual_marks();
+        }
     }
 
     async fn set_tab(&mut self, index: u8) {
@@ -622,6 +663,10 @@ impl App {
                 self.state.active_tab = ActiveTab::Search;
                 self.searching = true;
             }
+            5 => {
+                self.state.active_tab = ActiveTab::Stats;
+                self.refresh_stats().await;
+            }
             _ => {}
         }
     }
@@ -909,6 +954,10 @@ impl App {
                 self.searching = true;
             }
 
+            Action::Tab(5) => {
+                self.set_tab(5).await;
+            }
+
             Action::CyclePrimaryPanes => {
                 self.toggle_search_section(true);
             }
@@ -997,6 +1046,44 @@ impl App {
         }
     }
 
+    /// Returns true if the Stats tab handled the action, everything else (playback etc.) works as usual
+    async fn process_stats_tab_action(&mut self, action: &Action) -> bool {
+        match action {
+            Action::Cancel => {
+                self.state.active_tab = ActiveTab::Library;
+            }
+            Action::CyclePrimaryPanes | Action::NextPane => {
+                self.stats_period = self.stats_period.next();
+                self.refresh_stats().await;
+            }
+            Action::CycleSecondaryPanes | Action::PreviousPane => {
+                self.stats_period = self.stats_period.previous();
+                self.refresh_stats().await;
+            }
+            Action::Enter => {
+                self.refresh_stats().await;
+            }
+            Action::Popup => {
+                self.open_stats_export().await;
+            }
+            Action::Up
+            | Action::Down
+            | Action::PageUp
+            | Action::PageDown
+            | Action::JumpFirst
+            | Action::JumpLast => {}
+            _ => return false,
+        }
+        true
+    }
+
+    pub async fn refresh_stats(&mut self) {
+        match compute_stats(&self.db.pool, self.stats_period).await {
+            Ok(report) => self.stats = Some(report),
+            Err(e) => log::error!("Failed to compute listening stats: {}", e),
+        }
+    }
+
     fn process_mouse_event(&mut self, _mouse_event: crossterm::event::MouseEvent) {
         // println!("Mouse event: {:?}", _mouse_event);
         // self.dirty = true;
@@ -1382,7 +1469,7 @@ impl App {
                         let prev = move_up(self.state.selected_playlist.selected());
                         self.playlist_select_by_index(prev);
                     }
-                    ActiveTab::Search => {
+                    ActiveTab::Search | ActiveTab::Stats => {
                         // handle_search_tab_events()
                     }
                 }
@@ -1483,7 +1570,7 @@ impl App {
                         self.playlist_select_by_index(next);
                         return;
                     }
-                    ActiveTab::Search => {
+                    ActiveTab::Search | ActiveTab::Stats => {
                         // handle_search_tab_events()
                     }
                 }
@@ -2353,7 +2440,8 @@ impl App {
                     {
                         Ok(t) if !t.is_empty() => t,
                         _ => {
-                            if let Some(client) = self.client.as_ref() {
+                            let server_id = artists[selected].server_id.clone();
+                            if let Some(client) = self.servers.client_for(&server_id) {
                                 match client.discography(&id).await {
                                     Ok(t) => t,
                                     Err(_) => return,
@@ -2384,7 +2472,8 @@ impl App {
                     match get_album_tracks(&self.db.pool, &id, self.client.as_ref()).await {
                         Ok(t) if !t.is_empty() => t,
                         _ => {
-                            if let Some(client) = self.client.as_ref() {
+                            let server_id = albums[selected].server_id.clone();
+                            if let Some(client) = self.servers.client_for(&server_id) {
                                 match client.album_tracks(&id).await {
                                     Ok(t) => t,
                                     Err(_) => return,
@@ -2481,6 +2570,9 @@ impl App {
                 self.search_term = String::from("");
                 self.state.active_tab = ActiveTab::Library;
             }
+            ActiveTab::Stats => {
+                self.state.active_tab = ActiveTab::Library;
+            }
         }
     }
 
@@ -2549,177 +2641,208 @@ impl App {
 
     async fn toggle_favorite(&mut self) {
         match self.state.active_section {
-            ActiveSection::List => {
-                if let Some(client) = &self.client {
-                    match self.state.active_tab {
-                        ActiveTab::Library => {
-                            let id = self.get_id_of_selected(&self.artists, Selectable::Artist);
-                            if let Some(artist) =
-                                self.original_artists.iter_mut().find(|a| a.id == id)
-                            {
-                                let _ = client
-                                    .set_favorite(&artist.id, !artist.user_data.is_favorite)
-                                    .await;
-                                let _ = set_favorite_artist(
-                                    &self.db.pool,
-                                    &artist.id,
-                                    !artist.user_data.is_favorite,
-                                )
-                                .await;
-                                artist.user_data.is_favorite = !artist.user_data.is_favorite;
-                                self.reorder_lists();
-                                self.reposition_cursor(&id, Selectable::Artist);
-                            }
+            ActiveSection::List => match self.state.active_tab {
+                ActiveTab::Library => {
+                    let id = self.get_id_of_selected(&self.artists, Selectable::Artist);
+                    if let Some(artist) = self.original_artists.iter_mut().find(|a| a.id == id) {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: artist.id.clone(),
+                                    favorite: !artist.user_data.is_favorite,
+                                }
+                                .into(),
+                            )
+                            .await;
+                        let _ = set_favorite_artist(
+                            &self.db.pool,
+                            &artist.id,
+                            !artist.user_data.is_favorite,
+                        )
+                        .await;
+                        artist.user_data.is_favorite = !artist.user_data.is_favorite;
+                        self.reorder_lists();
+                        self.reposition_cursor(&id, Selectable::Artist);
+                    }
+                }
+                ActiveTab::Albums => {
+                    let id = self.get_id_of_selected(&self.albums, Selectable::Album);
+                    if let Some(album) = self.original_albums.iter_mut().find(|a| a.id == id) {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: album.id.clone(),
+                                    favorite: !album.user_data.is_favorite,
+                                }
+                                .into(),
+                            )
+                            .await;
+
+                        let _ = set_favorite_album(
+                            &self.db.pool,
+                            &album.id,
+                            !album.user_data.is_favorite,
+                        )
+                        .await;
+                        album.user_data.is_favorite = !album.user_data.is_favorite;
+                        self.reorder_lists();
+                        self.reposition_cursor(&id, Selectable::Album);
+                    }
+                    if let Some(album) =
+                        self.tracks.iter_mut().find(|a| a.id == format!("_album_{}", id))
+                    {
+                        album.user_data.is_favorite = !album.user_data.is_favorite;
+                    }
+                }
+                ActiveTab::Playlists => {
+                    let id = self.get_id_of_selected(&self.playlists, Selectable::Playlist);
+                    if let Some(playlist) = self.original_playlists.iter_mut().find(|a| a.id == id)
+                    {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: playlist.id.clone(),
+                                    favorite: !playlist.user_data.is_favorite,
+                                }
+                                .into(),
+                            )
+                            .await;
+                        let _ = set_favorite_playlist(
+                            &self.db.pool,
+                            &playlist.id,
+                            !playlist.user_data.is_favorite,
+                        )
+                        .await;
+                        playlist.user_data.is_favorite = !playlist.user_data.is_favorite;
+                        self.reorder_lists();
+                        self.reposition_cursor(&id, Selectable::Playlist);
+                    }
+                }
+                _ => {}
+            },
+            ActiveSection::Tracks => match self.state.active_tab {
+                ActiveTab::Library => {
+                    let id = self.get_id_of_selected(&self.tracks, Selectable::Track);
+                    if let Some(track) = self.tracks.iter_mut().find(|t| t.id == id) {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: track.id.clone(),
+                                    favorite: !track.user_data.is_favorite,
+                                }
+                                .into(),
+                            )
+                            .await;
+                        let _ = set_favorite_track(
+                            &self.db.pool,
+                            &track.id,
+                            !track.user_data.is_favorite,
+                        )
+                        .await;
+                        track.user_data.is_favorite = !track.user_data.is_favorite;
+                        if let Some(tr) = self.state.queue.iter_mut().find(|t| t.id == track.id) {
+                            tr.is_favorite = !tr.is_favorite;
                         }
-                        ActiveTab::Albums => {
-                            let id = self.get_id_of_selected(&self.albums, Selectable::Album);
-                            if let Some(album) =
-                                self.original_albums.iter_mut().find(|a| a.id == id)
-                            {
-                                let _ = client
-                                    .set_favorite(&album.id, !album.user_data.is_favorite)
-                                    .await;
-
-                                let _ = set_favorite_album(
-                                    &self.db.pool,
-                                    &album.id,
-                                    !album.user_data.is_favorite,
-                                )
-                                .await;
+                        if track.id.starts_with("_album_") {
+                            let id = track.id.replace("_album_", "");
+                            if let Some(album) = self.albums.iter_mut().find(|a| a.id == id) {
                                 album.user_data.is_favorite = !album.user_data.is_favorite;
-                                self.reorder_lists();
-                                self.reposition_cursor(&id, Selectable::Album);
                             }
+                            let _ = set_favorite_album(
+                                &self.db.pool,
+                                &id,
+                                !track.user_data.is_favorite,
+                            )
+                            .await;
                             if let Some(album) =
-                                self.tracks.iter_mut().find(|a| a.id == format!("_album_{}", id))
+                                self.original_albums.iter_mut().find(|a| a.id == id)
                             {
                                 album.user_data.is_favorite = !album.user_data.is_favorite;
                             }
+                            self.reorder_lists();
                         }
-                        ActiveTab::Playlists => {
-                            let id = self.get_id_of_selected(&self.playlists, Selectable::Playlist);
-                            if let Some(playlist) =
-                                self.original_playlists.iter_mut().find(|a| a.id == id)
-                            {
-                                let _ = client
-                                    .set_favorite(&playlist.id, !playlist.user_data.is_favorite)
-                                    .await;
-                                let _ = set_favorite_playlist(
-                                    &self.db.pool,
-                                    &playlist.id,
-                                    !playlist.user_data.is_favorite,
-                                )
-                                .await;
-                                playlist.user_data.is_favorite = !playlist.user_data.is_favorite;
-                                self.reorder_lists();
-                                self.reposition_cursor(&id, Selectable::Playlist);
-                            }
-                        }
-                        _ => {}
                     }
                 }
-            }
-            ActiveSection::Tracks => {
-                if let Some(client) = &self.client {
-                    match self.state.active_tab {
-                        ActiveTab::Library => {
-                            let id = self.get_id_of_selected(&self.tracks, Selectable::Track);
-                            if let Some(track) = self.tracks.iter_mut().find(|t| t.id == id) {
-                                let _ = client
-                                    .set_favorite(&track.id, !track.user_data.is_favorite)
-                                    .await;
-                                let _ = set_favorite_track(
-                                    &self.db.pool,
-                                    &track.id,
-                                    !track.user_data.is_favorite,
-                                )
-                                .await;
-                                track.user_data.is_favorite = !track.user_data.is_favorite;
-                                if let Some(tr) =
-                                    self.state.queue.iter_mut().find(|t| t.id == track.id)
-                                {
-                                    tr.is_favorite = !tr.is_favorite;
-                                }
-                                if track.id.starts_with("_album_") {
-                                    let id = track.id.replace("_album_", "");
-                                    if let Some(album) = self.albums.iter_mut().find(|a| a.id == id)
-                                    {
-                                        album.user_data.is_favorite = !album.user_data.is_favorite;
-                                    }
-                                    let _ = set_favorite_album(
-                                        &self.db.pool,
-                                        &id,
-                                        !track.user_data.is_favorite,
-                                    )
-                                    .await;
-                                    if let Some(album) =
-                                        self.original_albums.iter_mut().find(|a| a.id == id)
-                                    {
-                                        album.user_data.is_favorite = !album.user_data.is_favorite;
-                                    }
-                                    self.reorder_lists();
-                                }
-                            }
-                        }
-                        ActiveTab::Albums => {
-                            let id =
-                                self.get_id_of_selected(&self.album_tracks, Selectable::AlbumTrack);
-                            if let Some(track) = self.album_tracks.iter_mut().find(|t| t.id == id) {
-                                let _ = client
-                                    .set_favorite(&track.id, !track.user_data.is_favorite)
-                                    .await;
-                                let _ = set_favorite_track(
-                                    &self.db.pool,
-                                    &track.id,
-                                    !track.user_data.is_favorite,
-                                )
-                                .await;
-                                track.user_data.is_favorite = !track.user_data.is_favorite;
-                                if let Some(tr) =
-                                    self.state.queue.iter_mut().find(|t| t.id == track.id)
-                                {
-                                    tr.is_favorite = !tr.is_favorite;
+                ActiveTab::Albums => {
+                    let id = self.get_id_of_selected(&self.album_tracks, Selectable::AlbumTrack);
+                    if let Some(track) = self.album_tracks.iter_mut().find(|t| t.id == id) {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: track.id.clone(),
+                                    favorite: !track.user_data.is_favorite,
                                 }
-                            }
+                                .into(),
+                            )
+                            .await;
+                        let _ = set_favorite_track(
+                            &self.db.pool,
+                            &track.id,
+                            !track.user_data.is_favorite,
+                        )
+                        .await;
+                        track.user_data.is_favorite = !track.user_data.is_favorite;
+                        if let Some(tr) = self.state.queue.iter_mut().find(|t| t.id == track.id) {
+                            tr.is_favorite = !tr.is_favorite;
                         }
-                        ActiveTab::Playlists => {
-                            let id = self.get_id_of_selected(
-                                &self.playlist_tracks,
-                                Selectable::PlaylistTrack,
-                            );
-                            if let Some(track) =
-                                self.playlist_tracks.iter_mut().find(|t| t.id == id)
-                            {
-                                let _ = client
-                                    .set_favorite(&track.id, !track.user_data.is_favorite)
-                                    .await;
-                                let _ = set_favorite_track(
-                                    &self.db.pool,
-                                    &track.id,
-                                    !track.user_data.is_favorite,
-                                )
-                                .await;
-                                track.user_data.is_favorite = !track.user_data.is_favorite;
-                                if let Some(tr) =
-                                    self.state.queue.iter_mut().find(|t| t.id == track.id)
-                                {
-                                    tr.is_favorite = !tr.is_favorite;
+                    }
+                }
+                ActiveTab::Playlists => {
+                    let id =
+                        self.get_id_of_selected(&self.playlist_tracks, Selectable::PlaylistTrack);
+                    if let Some(track) = self.playlist_tracks.iter_mut().find(|t| t.id == id) {
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(
+                                Mutation::Favorite {
+                                    item_id: track.id.clone(),
+                                    favorite: !track.user_data.is_favorite,
                                 }
-                            }
+                                .into(),
+                            )
+                            .await;
+                        let _ = set_favorite_track(
+                            &self.db.pool,
+                            &track.id,
+                            !track.user_data.is_favorite,
+                        )
+                        .await;
+                        track.user_data.is_favorite = !track.user_data.is_favorite;
+                        if let Some(tr) = self.state.queue.iter_mut().find(|t| t.id == track.id) {
+                            tr.is_favorite = !tr.is_favorite;
                         }
-                        _ => {}
                     }
                 }
-            }
+                _ => {}
+            },
             ActiveSection::Queue => {
-                if let Some(client) = &self.client {
-                    let selected = self.state.selected_queue_item.selected().unwrap_or(0);
-                    let track = &self.state.queue[selected].clone();
-                    let _ = client.set_favorite(&track.id, !track.is_favorite).await;
-                    self.state.queue[selected].is_favorite = !track.is_favorite;
-                    if let Some(tr) = self.tracks.iter_mut().find(|t| t.id == track.id) {
-                        tr.user_data.is_favorite = !track.is_favorite;
-                    }
+                let selected = self.state.selected_queue_item.selected().unwrap_or(0);
+                let track = &self.state.queue[selected].clone();
+                let _ = self
+                    .db
+                    .cmd_tx
+                    .send(
+                        Mutation::Favorite {
+                            item_id: track.id.clone(),
+                            favorite: !track.is_favorite,
+                        }
+                        .into(),
+                    )
+                    .await;
+                self.state.queue[selected].is_favorite = !track.is_favorite;
+                if let Some(tr) = self.tracks.iter_mut().find(|t| t.id == track.id) {
+                    tr.user_data.is_favorite = !track.is_favorite;
                 }
             }
             _ => {}
@@ -2948,14 +3071,12 @@ impl App {
             return;
         }
 
-        let Some(client) = self.client.as_ref() else { return };
-
         let playlist_id = self.get_id_of_selected(&self.playlists, Selectable::Playlist);
         let item_id = self.playlist_edit_item_id.clone().unwrap();
 
         let new_index = self.playlist_tracks.iter().position(|t| t.id == item_id).unwrap();
 
-        client.move_playlist_item(&item_id, &playlist_id, new_index).await.ok();
+        self.send_playlist_change(Mutation::PlaylistMove { playlist_id, item_id, new_index }).await;
 
         self.playlist_editing = false;
         self.playlist_edit_item_id = None;
@@ -3024,7 +3145,8 @@ impl App {
                             {
                                 Ok(tracks) if !tracks.is_empty() => Some(tracks),
                                 _ => {
-                                    if let Some(client) = self.client.as_ref() {
+                                    if let Some(client) = self.servers.client_for(&artist.server_id)
+                                    {
                                         if let Ok(tracks) = client.discography(&artist.id).await {
                                             Some(tracks)
                                         } else {
@@ -3101,7 +3223,8 @@ impl App {
                             {
                                 Ok(tracks) if !tracks.is_empty() => Some(tracks),
                                 _ => {
-                                    if let Some(client) = self.client.as_ref() {
+                                    if let Some(client) = self.servers.client_for(&artist.server_id)
+                                    {
                                         if let Ok(tracks) = client.discography(&artist.id).await {
                                             Some(tracks)
                                         } else {
@@ -3153,12 +3276,22 @@ impl App {
     }
 
     async fn global_search_perform(&mut self) {
+        let local =
+            match search_local(&self.db.pool, &self.search_term, self.client.is_none()).await {
+                Ok(local) => local,
+                Err(e) => {
+                    log::error!("Local search failed: {}", e);
+                    LocalSearch::default()
+                }
+            };
+        let term = normalize_for_search(&self.search_term);
+
+        // the index matches whole words and album artists, the substring match keeps partial words working
+        let artist_ids = local.artist_ids.into_iter().collect::<HashSet<String>>();
         let artists = self
             .original_artists
             .iter()
-            .filter(|a| {
-                normalize_for_search(&a.name).contains(&normalize_for_search(&self.search_term))
-            })
+            .filter(|a| artist_ids.contains(&a.id) || normalize_for_search(&a.name).contains(&term))
             .cloned()
             .collect::<Vec<Artist>>();
         self.search_result_artists = artists;
@@ -3169,12 +3302,11 @@ impl App {
         self.state.search_artist_scroll_state =
             self.state.search_artist_scroll_state.content_length(self.search_result_artists.len());
 
+        let album_ids = local.album_ids.into_iter().collect::<HashSet<String>>();
         let albums = self
             .original_albums
             .iter()
-            .filter(|a| {
-                normalize_for_search(&a.name).contains(&normalize_for_search(&self.search_term))
-            })
+            .filter(|a| album_ids.contains(&a.id) || normalize_for_search(&a.name).contains(&term))
             .cloned()
             .collect::<Vec<Album>>();
         self.search_result_albums = albums;
@@ -3184,17 +3316,15 @@ impl App {
         self.state.search_album_scroll_state =
             self.state.search_album_scroll_state.content_length(self.search_result_albums.len());
 
-        let tracks = match &self.client {
-            Some(client) => client.search_tracks(self.search_term.clone()).await,
-            None => Ok(get_tracks(&self.db.pool, &self.search_term).await.unwrap_or_default()),
-        };
-        if let Ok(tracks) = tracks {
-            self.search_result_tracks = tracks;
-            self.state.selected_search_track.select(Some(0));
-            self.state.search_track_scroll_state = self
-                .state
-                .search_track_scroll_state
-                .content_length(self.search_result_tracks.len());
+        // the server results are added to these once they arrive
+        self.search_result_tracks = local.tracks;
+        self.search_lyric_matches = local.lyric_matches;
+        self.state.selected_search_track.select(Some(0));
+        self.state.search_track_scroll_state =
+            self.state.search_track_scroll_state.content_length(self.search_result_tracks.len());
+        if self.client.is_some() {
+            let _ =
+                self.db.cmd_tx.send(Command::SearchServer { term: self.search_term.clone() }).await;
         }
 
         self.state.search_section = SearchSection::Artists;
@@ -3345,6 +3475,7 @@ pub enum ActiveTab {
     Albums,
     Playlists,
     Search,
+    Stats,
 }
 
 // Music - active "section"
@@ -16,7 +16,7 @@ use crate::tui::App;
 use crate::{helpers, keyboard::*};
 
 use crate::config::LyricsVisibility;
-use crate::helpers::{find_all_subsequences, format_release_date};
+use crate::helpers::{find_all_subsequences, format_release_date, format_size};
 use layout::Flex;
 use ratatui::{
     prelude::*,
@@ -69,20 +69,20 @@ impl App {
                 vec![
                     Constraint::Percentage(68),
                     Constraint::Percentage(32),
-                    Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                    Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
                 ]
             } else {
                 vec![
                     Constraint::Min(3),
                     Constraint::Percentage(100),
-                    Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                    Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
                 ]
             }
         } else {
             vec![
                 Constraint::Min(0),
                 Constraint::Percentage(100),
-                Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
             ]
         };
 
@@ -134,7 +134,7 @@ impl App {
     /// preferred (a, b, c) percentages.
     pub(crate) fn build_vertical_chunks(&self, app_container: Rect) -> std::rc::Rc<[Rect]> {
         let player_height = 8;
-        let download_height = if self.download_item.is_some() { 3 } else { 0 };
+        let download_height = if !self.downloads.is_empty() { 3 } else { 0 };
         let has_lyrics = self.lyrics.as_ref().is_some_and(|(_, l, _)| !l.is_empty());
         let show_lyrics_panel = match self.lyrics_visibility {
             LyricsVisibility::Auto => has_lyrics,
@@ -158,24 +158,33 @@ impl App {
     }
 
     fn render_download_bar(&self, frame: &mut Frame, area: Rect) {
-        let Some(download_item) = &self.download_item else {
+        if self.downloads.is_empty() {
             return;
+        }
+        let progress_text = self
+            .downloads
+            .iter()
+            .map(|d| format!("{:.1}% - {}", (d.progress * 100.0).round() / 100.0, d.name))
+            .collect::<Vec<String>>()
+            .join(&format!(" {} ", self.symbols.separator));
+        let title = match self.download_settings.quota {
+            Some(quota) => format!(
+                "Downloading ({} / {})",
+                format_size(self.download_usage),
+                format_size(quota)
+            ),
+            None => format!("Downloading ({})", format_size(self.download_usage)),
         };
-        let progress = (download_item.progress * 100.0).round() / 100.0;
-        let progress_text = format!("{:.1}%", progress);
-
-        let p = Paragraph::new(format!(
-            "{} {} - {}",
-            &self.spinner_stages[self.spinner], progress_text, &download_item.name,
-        ))
-        .style(Style::default().fg(self.theme.resolve(&self.theme.foreground)))
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(Line::from("Downloading").fg(self.theme.resolve(&self.theme.section_title)))
-                .border_type(self.border_type)
-                .fg(self.theme.resolve(&self.theme.border)),
-        );
+
+        let p = Paragraph::new(format!("{} {}", &self.spinner_stages[self.spinner], progress_text))
+            .style(Style::default().fg(self.theme.resolve(&self.theme.foreground)))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(Line::from(title).fg(self.theme.resolve(&self.theme.section_title)))
+                    .border_type(self.border_type)
+                    .fg(self.theme.resolve(&self.theme.border)),
+            );
 
         frame.render_widget(p, area);
     }
@@ -284,6 +293,7 @@ impl App {
         let playlist_block_inner_h = artist_block.inner(left[0]).height as usize;
         self.left_list_height = playlist_block_inner_h.max(1);
 
+        let pinned = self.pinned_items();
         // render all artists as a list here in left[0]
         let items = artists
             .iter()
@@ -317,6 +327,12 @@ impl App {
                         Style::default().fg(self.theme.primary_color),
                     ));
                 }
+                if pinned.contains(&artist.id) {
+                    item.push_span(Span::styled(
+                        format!("{} ", &self.symbols.pinned),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
 
                 let all_subsequences = find_all_subsequences(
                     &self.state.artists_search_term.to_lowercase(),
@@ -345,6 +361,13 @@ impl App {
                     ));
                 }
 
+                if let Some(tag) = self.servers.tag(&artist.server_id) {
+                    item.push_span(Span::styled(
+                        format!(" [{}]", tag),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
+
                 ListItem::new(item)
             })
             .collect::<Vec<ListItem>>();
@@ -445,6 +468,7 @@ impl App {
         let playlist_block_inner_h = album_block.inner(left[0]).height as usize;
         self.left_list_height = playlist_block_inner_h.max(1);
 
+        let pinned = self.pinned_items();
         let items = albums
             .iter()
             .enumerate()
@@ -476,6 +500,12 @@ impl App {
                         Style::default().fg(self.theme.primary_color),
                     ));
                 }
+                if pinned.contains(&album.id) {
+                    item.push_span(Span::styled(
+                        format!("{} ", &self.symbols.pinned),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
 
                 let all_subsequences = find_all_subsequences(
                     &self.state.albums_search_term.to_lowercase(),
@@ -518,7 +548,18 @@ impl App {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
                 ));
 
-                ListItem::new(item)
+                if let Some(tag) = self.servers.tag(&album.server_id) {
+                    item.push_span(Span::styled(
+                        format!(" [{}]", tag),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
+
+                if self.is_marked(ActiveSection::List, &album.id) {
+                    ListItem::new(item).style(self.marked_style())
+                } else {
+                    ListItem::new(item)
+                }
             })
             .collect::<Vec<ListItem>>();
 
@@ -707,6 +748,9 @@ impl App {
             current.saturating_sub(1).min(total.saturating_sub(height))
         };
 
+        let stop_row = self.stop_marker_row();
+        let marked_rows = self.marked_queue_rows();
+
         let items = self
             .state
             .queue
@@ -759,7 +803,25 @@ impl App {
                     Style::default().fg(artist_fg),
                 ));
 
-                ListItem::new(text)
+                if let Some(tag) = self.servers.tag(&song.server_id) {
+                    text.push_span(Span::styled(
+                        format!(" [{}]", tag),
+                        Style::default().fg(artist_fg),
+                    ));
+                }
+
+                if stop_row == Some(index) {
+                    text.push_span(Span::styled(
+                        format!(" {}", self.symbols.stop),
+                        Style::default().fg(self.theme.primary_color).bold(),
+                    ));
+                }
+
+                if marked_rows.contains(&index) {
+                    ListItem::new(text).style(self.marked_style())
+                } else {
+                    ListItem::new(text)
+                }
             })
             .collect::<Vec<ListItem>>();
 
@@ -955,6 +1017,9 @@ impl App {
         // this sets the current maximum time duration to later use as Column width.
         let mut max_duration_len = "Duration".len();
 
+        let marked = self.marked_ids(ActiveSection::Tracks);
+        let marked_style = self.marked_style();
+
         let items = tracks
             .iter()
             .enumerate()
@@ -1047,6 +1112,9 @@ impl App {
                     {
                         row = row.bg(album_header_background);
                     }
+                    if marked.contains(&track.id) {
+                        row = row.style(marked_style);
+                    }
                     return row;
                 }
 
@@ -1115,6 +1183,9 @@ impl App {
                 // ⇊ (download)
                 if self.client.is_some() {
                     cells.push(Cell::from(match track.download_status {
+                        DownloadStatus::Downloaded if self.synced_ids.contains(&track.id) => {
+                            Line::from(self.symbols.synced.as_str())
+                        }
                         DownloadStatus::Downloaded => Line::from(self.symbols.downloaded.as_str()),
                         DownloadStatus::Queued => Line::from(self.symbols.queued.as_str()),
                         DownloadStatus::Downloading => {
@@ -1159,7 +1230,11 @@ impl App {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground))
                 };
 
-                Row::new(cells).style(style)
+                Row::new(cells).style(if marked.contains(&track.id) {
+                    style.patch(marked_style)
+                } else {
+                    style
+                })
             })
             .collect::<Vec<Row>>();
 
@@ -1316,6 +1391,9 @@ impl App {
         let terminal_height = frame.area().height as usize;
         let selection = self.state.selected_album_track.selected().unwrap_or(0);
 
+        let marked = self.marked_ids(ActiveSection::Tracks);
+        let marked_style = self.marked_style();
+
         let items = tracks
             .iter()
             .enumerate()
@@ -1390,6 +1468,9 @@ impl App {
                 // ⇊
                 if self.client.is_some() {
                     cells.push(Cell::from(match track.download_status {
+                        DownloadStatus::Downloaded if self.synced_ids.contains(&track.id) => {
+                            Line::from(self.symbols.synced.as_str())
+                        }
                         DownloadStatus::Downloaded => Line::from(self.symbols.downloaded.as_str()),
                         DownloadStatus::Queued => Line::from(self.symbols.queued.as_str()),
                         DownloadStatus::Downloading => {
@@ -1427,12 +1508,18 @@ impl App {
                         .alignment(Alignment::Right),
                 ));
 
-                Row::new(cells).style(if track.id == self.active_song_id {
+                let style = if track.id == self.active_song_id {
                     Style::default().fg(self.theme.primary_color).italic()
                 } else if track.disliked {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground_dim))
                 } else {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground))
+                };
+
+                Row::new(cells).style(if marked.contains(&track.id) {
+                    style.patch(marked_style)
+                } else {
+                    style
                 })
             })
             .collect::<Vec<Row>>();
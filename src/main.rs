@@ -8,14 +8,18 @@ mod helpers;
 mod keyboard;
 mod library;
 mod macos;
+mod marks;
 mod mpris;
 mod mpv;
 mod player;
 mod playlists;
 mod popup;
 mod queue;
+mod queue_io;
 mod search;
+mod servers;
 mod sort;
+mod stats;
 mod themes;
 mod tui;
 
@@ -128,49 +132,110 @@ async fn main() {
 
     log::info!("jellyfin-tui {} started", version);
 
-    config::initialize_config();
-
-    let mut app = tui::App::new(offline, force_server_select).await;
-    if let Err(e) = app.load_state().await {
-        println!(" ! Error loading state: {}", e);
+    if args.contains(&String::from("--maintenance")) {
+        if let Err(e) = database::maintenance::run_from_cli().await {
+            println!(" ! Maintenance failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    enable_raw_mode().unwrap();
-    execute!(stdout(), EnterAlternateScreen).unwrap();
+    if let Some(at) = args.iter().position(|a| a == "--export-profile") {
+        let path = args
+            .get(at + 1)
+            .filter(|a| !a.starts_with("--"))
+            .cloned()
+            .unwrap_or_else(database::profile::default_export_path);
+        if let Err(e) = database::profile::export_profile(&path).await {
+            println!(" ! Exporting the profile failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let _ = execute!(
-        stdout(),
-        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
-    );
-    app.combiner.enable_combining().ok();
+    if let Some(at) = args.iter().position(|a| a == "--import-profile") {
+        let Some(path) = args.get(at + 1).filter(|a| !a.starts_with("--")) else {
+            println!(" ! --import-profile needs the path of an exported profile");
+            std::process::exit(1);
+        };
+        if let Err(e) = database::profile::import_profile(path).await {
+            println!(" ! Importing the profile failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let mut terminal = Terminal::new(CrosstermBackend::new(stdout())).unwrap();
+    config::initialize_config();
 
-    terminal.clear().unwrap();
+    let mut app = tui::App::new(offline, force_server_select, None).await;
 
+    // the app is built again when switching servers, the login output is shown outside the TUI like at startup
     loop {
-        // Pump the macOS runloop to allow Now Playing events to be processed
-        #[cfg(target_os = "macos")]
-        macos::pump_runloop();
-
-        // main event loop
-        // run() polls events and updates the app state
-        if let Err(e) = app.run().await {
-            log::error!("Runtime error: {}", e);
+        if let Err(e) = app.load_state().await {
+            println!(" ! Error loading state: {}", e);
         }
-        if app.exit || panicked.load(Ordering::SeqCst) {
-            let _ = disable_raw_mode();
-            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
-            let _ = execute!(stdout(), LeaveAlternateScreen);
-            break;
+
+        enable_raw_mode().unwrap();
+        execute!(stdout(), EnterAlternateScreen).unwrap();
+
+        let _ = execute!(
+            stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        );
+        app.combiner.enable_combining().ok();
+
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout())).unwrap();
+
+        terminal.clear().unwrap();
+
+        loop {
+            // Pump the macOS runloop to allow Now Playing events to be processed
+            #[cfg(target_os = "macos")]
+            macos::pump_runloop();
+
+            // main event loop
+            // run() polls events and updates the app state
+            if let Err(e) = app.run().await {
+                log::error!("Runtime error: {}", e);
+            }
+            if app.exit || panicked.load(Ordering::SeqCst) {
+                let _ = disable_raw_mode();
+                let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+                let _ = execute!(stdout(), LeaveAlternateScreen);
+                break;
+            }
+            // draw() renders the app state to the terminal
+            if let Err(e) = app.draw(&mut terminal).await {
+                log::error!("Draw error: {}", e);
+            }
         }
-        // draw() renders the app state to the terminal
-        if let Err(e) = app.draw(&mut terminal).await {
-            log::error!("Draw error: {}", e);
+        if panicked.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let Some(switch) = app.switch_to.take() else {
+            break;
+        };
+        println!(" - Switching to {}...", switch.server.url);
+        log::info!("Switching to server {}", switch.server.url);
+        app.shutdown().await;
+        // mpv, media controls and discord are released before the new app claims them
+        drop(app);
+        app = tui::App::new(false, false, Some(switch.server.clone())).await;
+
+        if let Some(name) = switch.add_as {
+            if app.client.is_none() {
+                println!(" ! Could not log into {}, it was not added to the config.", name);
+                continue;
+            }
+            match config::get_config() {
+                Ok((path, _)) => match config::add_server(&path, &name, &switch.server) {
+                    Ok(_) => println!(" - Added {} to {}", name, path.display()),
+                    Err(e) => println!(" ! Failed to add {} to the config: {}", name, e),
+                },
+                Err(e) => println!(" ! Failed to load config: {}", e),
+            }
         }
-    }
-    if panicked.load(Ordering::SeqCst) {
-        return;
     }
     println!(" - Exiting...");
 }
@@ -220,6 +285,11 @@ fn print_help() {
     println!("  --no-splash\t\tDo not show jellyfish splash screen");
     println!("  --select-server\tForce server selection on startup");
     println!("  --offline\t\tStart in offline mode");
+    println!(
+        "  --maintenance\t\tPrune orphaned rows and unused covers, vacuum the databases and exit"
+    );
+    println!("  --export-profile [PATH]\tSave dislikes, preferences, states, themes and keymap to one file and exit");
+    println!("  --import-profile PATH\tRestore a profile saved with --export-profile and exit");
 
     println!("\nControls:");
     println!("  For a list of controls, press '?' in the application.");
@@ -0,0 +1,470 @@
+/// Visual / mark mode for bulk actions
+///
+/// Rows can be marked one by one (`m`) or as a range in visual mode (`v`). While anything is marked
+/// in the active pane, the row commands (queue, download, favorite, delete) act on the marked set
+/// instead of the selected row, and the popup offers bulk versions of its actions.
+use crate::client::DiscographySong;
+use crate::database::database::{Command, DownloadCommand, RemoveCommand};
+use crate::database::extension::{
+    get_album_tracks, get_tracks_by_ids, set_favorite_album, set_favorite_track, DownloadStatus,
+};
+use crate::database::outbox::Mutation;
+use crate::keyboard::{search_ranked_refs, Action, ActiveSection, ActiveTab};
+use crate::tui::App;
+use ratatui::style::{Modifier, Style};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub struct Marks {
+    tab: ActiveTab,
+    section: ActiveSection,
+    /// ids of marked tracks / albums
+    ids: HashSet<String>,
+    /// the queue can contain duplicates, so a row there is the song id and which occurrence of it it is. That
+    /// way marks move along with their songs when the queue is reordered
+    queue: HashSet<(String, usize)>,
+    queue_len: usize,
+    /// row where visual mode started, and what was marked before that
+    anchor: Option<usize>,
+    base_ids: HashSet<String>,
+    base_queue: HashSet<(String, usize)>,
+}
+
+impl Marks {
+    pub fn visual(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    fn clear(&mut self) {
+        *self = Marks::default();
+    }
+}
+
+impl App {
+    /// Whether the marks belong to the pane the user is looking at
+    ///
+    fn marks_active_here(&self, section: ActiveSection) -> bool {
+        self.marks.tab == self.state.active_tab
+            && self.marks.section == section
+            && (section != ActiveSection::Queue || self.marks.queue_len == self.state.queue.len())
+    }
+
+    pub fn marked_count(&self, section: ActiveSection) -> usize {
+        if !self.marks_active_here(section) {
+            return 0;
+        }
+        match section {
+            ActiveSection::Queue => self.marks.queue.len(),
+            _ => self.marks.ids.len(),
+        }
+    }
+
+    pub fn has_marks(&self) -> bool {
+        self.marked_count(self.state.active_section) > 0 || self.marks.visual()
+    }
+
+    pub fn is_marked(&self, section: ActiveSection, id: &str) -> bool {
+        self.marks_active_here(section) && self.marks.ids.contains(id)
+    }
+
+    /// Owned copy for render closures that can't hold on to `self`
+    ///
+    pub fn marked_ids(&self, section: ActiveSection) -> HashSet<String> {
+        if self.marks_active_here(section) {
+            self.marks.ids.clone()
+        } else {
+            HashSet::new()
+        }
+    }
+
+    /// Mark keys of the queue rows, in queue order
+    ///
+    fn queue_keys(&self) -> Vec<(String, usize)> {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        self.state
+            .queue
+            .iter()
+            .map(|song| {
+                let occurrence = seen.entry(song.id.as_str()).or_default();
+                *occurrence += 1;
+                (song.id.clone(), *occurrence - 1)
+            })
+            .collect()
+    }
+
+    /// Indices of the marked queue rows
+    ///
+    pub fn marked_queue_rows(&self) -> HashSet<usize> {
+        if !self.marks_active_here(ActiveSection::Queue) || self.marks.queue.is_empty() {
+            return HashSet::new();
+        }
+        self.queue_keys()
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| self.marks.queue.contains(key))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn marked_style(&self) -> Style {
+        Style::default()
+            .bg(self.theme.resolve(&self.theme.selected_inactive_background))
+            .fg(self.theme.resolve(&self.theme.selected_inactive_foreground))
+            .add_modifier(Modifier::BOLD)
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marks.clear();
+    }
+
+    /// Ids of the rows in the active pane in display order, together with the selected row
+    ///
+    fn markable_rows(&self) -> Option<(Vec<String>, usize)> {
+        match (self.state.active_tab, self.state.active_section) {
+            (ActiveTab::Library, ActiveSection::Tracks) => Some((
+                search_ranked_refs(&self.tracks, &self.state.tracks_search_term, true)
+                    .iter()
+                    .map(|t| t.id.clone())
+                    .collect(),
+                self.state.selected_track.selected().unwrap_or(0),
+            )),
+            (ActiveTab::Albums, ActiveSection::Tracks) => Some((
+                search_ranked_refs(&self.album_tracks, &self.state.album_tracks_search_term, true)
+                    .iter()
+                    .map(|t| t.id.clone())
+                    .collect(),
+                self.state.selected_album_track.selected().unwrap_or(0),
+            )),
+            (ActiveTab::Playlists, ActiveSection::Tracks) => Some((
+                search_ranked_refs(
+                    &self.playlist_tracks,
+                    &self.state.playlist_tracks_search_term,
+                    true,
+                )
+                .iter()
+                .map(|t| t.id.clone())
+                .collect(),
+                self.state.selected_playlist_track.selected().unwrap_or(0),
+            )),
+            (ActiveTab::Albums, ActiveSection::List) => Some((
+                search_ranked_refs(&self.albums, &self.state.albums_search_term, true)
+                    .iter()
+                    .map(|a| a.id.clone())
+                    .collect(),
+                self.state.selected_album.selected().unwrap_or(0),
+            )),
+            (_, ActiveSection::Queue) => Some((
+                (0..self.state.queue.len()).map(|i| i.to_string()).collect(),
+                self.state.selected_queue_item.selected().unwrap_or(0),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Marks start over when the user moves to a different pane
+    ///
+    fn ensure_marks_for_active_pane(&mut self) {
+        if !self.marks_active_here(self.state.active_section) {
+            self.marks.clear();
+            self.marks.tab = self.state.active_tab;
+            self.marks.section = self.state.active_section;
+            self.marks.queue_len = self.state.queue.len();
+        }
+    }
+
+    pub fn toggle_mark(&mut self) {
+        let Some((rows, selected)) = self.markable_rows() else {
+            return;
+        };
+        let Some(id) = rows.get(selected).cloned() else {
+            return;
+        };
+        self.ensure_marks_for_active_pane();
+        if self.state.active_section == ActiveSection::Queue {
+            let Some(key) = self.queue_keys().into_iter().nth(selected) else {
+                return;
+            };
+            if !self.marks.queue.remove(&key) {
+                self.marks.queue.insert(key);
+            }
+        } else if !self.marks.ids.remove(&id) {
+            self.marks.ids.insert(id);
+        }
+        // like most file managers, marking moves on to the next row
+        self.route_mark_step();
+    }
+
+    fn route_mark_step(&mut self) {
+        let Some((rows, selected)) = self.markable_rows() else {
+            return;
+        };
+        let next = (selected + 1).min(rows.len().saturating_sub(1));
+        match (self.state.active_tab, self.state.active_section) {
+            (ActiveTab::Library, ActiveSection::Tracks) => self.track_select_by_index(next),
+            (ActiveTab::Albums, ActiveSection::Tracks) => self.album_track_select_by_index(next),
+            (ActiveTab::Playlists, ActiveSection::Tracks) => {
+                self.playlist_track_select_by_index(next)
+            }
+            (ActiveTab::Albums, ActiveSection::List) => self.album_select_by_index(next),
+            (_, ActiveSection::Queue) => {
+                self.state.selected_queue_item_manual_override = true;
+                self.state.selected_queue_item.select(Some(next));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn toggle_visual_mode(&mut self) {
+        if self.marks.visual() {
+            self.marks.anchor = None;
+            return;
+        }
+        let Some((_, selected)) = self.markable_rows() else {
+            return;
+        };
+        self.ensure_marks_for_active_pane();
+        self.marks.anchor = Some(selected);
+        self.marks.base_ids = self.marks.ids.clone();
+        self.marks.base_queue = self.marks.queue.clone();
+        self.update_visual_marks();
+    }
+
+    /// Called after every cursor movement, marks everything between the anchor and the cursor
+    ///
+    pub fn update_visual_marks(&mut self) {
+        let Some(anchor) = self.marks.anchor else {
+            return;
+        };
+        if !self.marks_active_here(self.state.active_section) {
+            self.marks.clear();
+            return;
+        }
+        let Some((rows, selected)) = self.markable_rows() else {
+            return;
+        };
+        let (from, to) = (anchor.min(selected), anchor.max(selected));
+        if self.state.active_section == ActiveSection::Queue {
+            self.marks.queue = self.marks.base_queue.clone();
+            self.marks.queue.extend(self.queue_keys().into_iter().skip(from).take(to - from + 1));
+        } else {
+            self.marks.ids = self.marks.base_ids.clone();
+            self.marks.ids.extend(rows.iter().skip(from).take(to - from + 1).cloned());
+        }
+    }
+
+    /// Tracks behind the marked rows, albums and album headers are expanded into their tracks
+    ///
+    pub async fn marked_tracks(&self, section: ActiveSection) -> Vec<DiscographySong> {
+        if self.marked_count(section) == 0 {
+            return vec![];
+        }
+        let mut tracks: Vec<DiscographySong> = vec![];
+        match (self.state.active_tab, section) {
+            (ActiveTab::Library, ActiveSection::Tracks) => {
+                for row in search_ranked_refs(&self.tracks, &self.state.tracks_search_term, true) {
+                    if !self.marks.ids.contains(&row.id) {
+                        continue;
+                    }
+                    if let Some(album_id) = row.id.strip_prefix("_album_") {
+                        tracks.extend(
+                            self.tracks
+                                .iter()
+                                .filter(|t| t.album_id == album_id && !t.id.starts_with("_album_"))
+                                .cloned(),
+                        );
+                    } else {
+                        tracks.push(row.clone());
+                    }
+                }
+            }
+            (ActiveTab::Albums, ActiveSection::Tracks) => {
+                tracks = self
+                    .album_tracks
+                    .iter()
+                    .filter(|t| self.marks.ids.contains(&t.id))
+                    .cloned()
+                    .collect();
+            }
+            (ActiveTab::Playlists, ActiveSection::Tracks) => {
+                tracks = self
+                    .playlist_tracks
+                    .iter()
+                    .filter(|t| self.marks.ids.contains(&t.id))
+                    .cloned()
+                    .collect();
+            }
+            (ActiveTab::Albums, ActiveSection::List) => {
+                for album in search_ranked_refs(&self.albums, &self.state.albums_search_term, true)
+                {
+                    if !self.marks.ids.contains(&album.id) {
+                        continue;
+                    }
+                    match get_album_tracks(&self.db.pool, &album.id, self.client.as_ref()).await {
+                        Ok(album_tracks) if !album_tracks.is_empty() => tracks.extend(album_tracks),
+                        _ => {
+                            if let Some(client) = self.servers.client_for(&album.server_id) {
+                                tracks.extend(
+                                    client.album_tracks(&album.id).await.unwrap_or_default(),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            (_, ActiveSection::Queue) => {
+                let ids = self
+                    .queue_keys()
+                    .into_iter()
+                    .filter(|key| self.marks.queue.contains(key))
+                    .map(|(id, _)| id)
+                    .collect::<Vec<String>>();
+                let mut found = get_tracks_by_ids(&self.db.pool, &ids).await.unwrap_or_default();
+                let missing = ids
+                    .iter()
+                    .filter(|id| !found.iter().any(|t| &t.id == *id))
+                    .cloned()
+                    .collect::<Vec<String>>();
+                if let Some(client) = self.client.as_ref() {
+                    if !missing.is_empty() {
+                        found.extend(client.tracks_by_ids(&missing).await.unwrap_or_default());
+                    }
+                }
+                // keep queue order
+                tracks = ids
+                    .iter()
+                    .filter_map(|id| found.iter().find(|t| &t.id == id))
+                    .cloned()
+                    .collect();
+            }
+            _ => {}
+        }
+        tracks
+    }
+
+    /// Runs a row command against the marked set. Returns false if the action has no bulk version
+    ///
+    pub async fn apply_marked_action(&mut self, action: &Action) -> bool {
+        match action {
+            Action::QueueAppend => {
+                let tracks = self.marked_tracks(self.state.active_section).await;
+                self.append_to_main_queue(&tracks, 0).await;
+            }
+            Action::QueueTempBack => {
+                let tracks = self.marked_tracks(self.state.active_section).await;
+                self.push_to_temporary_queue(&tracks, 0, tracks.len()).await;
+            }
+            Action::QueueTempFront => {
+                let tracks = self.marked_tracks(self.state.active_section).await;
+                // nothing to put them in front of, the selection simply becomes the queue
+                if self.state.queue.is_empty() {
+                    self.initiate_main_queue(&tracks, 0).await;
+                } else {
+                    // each push lands right after the current song, so go backwards to keep the order
+                    for i in (0..tracks.len()).rev() {
+                        self.push_next_to_temporary_queue(&tracks, i).await;
+                    }
+                }
+            }
+            Action::Download => {
+                let tracks = self
+                    .marked_tracks(self.state.active_section)
+                    .await
+                    .into_iter()
+                    .filter(|t| !matches!(t.download_status, DownloadStatus::Downloaded))
+                    .collect::<Vec<DiscographySong>>();
+                let _ = self
+                    .db
+                    .cmd_tx
+                    .send(Command::Download(DownloadCommand::Tracks { tracks }))
+                    .await;
+            }
+            Action::RemoveDownload => {
+                let tracks = self.marked_tracks(self.state.active_section).await;
+                let ids = tracks.iter().map(|t| t.id.clone()).collect::<HashSet<String>>();
+                let _ =
+                    self.db.cmd_tx.send(Command::Remove(RemoveCommand::Tracks { tracks })).await;
+                for t in self
+                    .tracks
+                    .iter_mut()
+                    .chain(self.album_tracks.iter_mut())
+                    .chain(self.playlist_tracks.iter_mut())
+                    .filter(|t| ids.contains(&t.id))
+                {
+                    t.download_status = DownloadStatus::NotDownloaded;
+                }
+            }
+            Action::ToggleFavorite => self.toggle_favorite_marked().await,
+            Action::Delete => {
+                if self.state.active_section != ActiveSection::Queue {
+                    return false;
+                }
+                self.remove_marked_from_queue().await;
+            }
+            _ => return false,
+        }
+        self.clear_marks();
+        true
+    }
+
+    /// If anything is not a favorite yet everything becomes one, otherwise everything is unfavorited
+    ///
+    async fn toggle_favorite_marked(&mut self) {
+        if let (ActiveTab::Albums, ActiveSection::List) =
+            (self.state.active_tab, self.state.active_section)
+        {
+            let ids = self.marks.ids.clone();
+            let favorite = self
+                .original_albums
+                .iter()
+                .filter(|a| ids.contains(&a.id))
+                .any(|a| !a.user_data.is_favorite);
+            for album in self.original_albums.iter_mut().filter(|a| ids.contains(&a.id)) {
+                let _ = self
+                    .db
+                    .cmd_tx
+                    .send(Mutation::Favorite { item_id: album.id.clone(), favorite }.into())
+                    .await;
+                let _ = set_favorite_album(&self.db.pool, &album.id, favorite).await;
+                album.user_data.is_favorite = favorite;
+            }
+            self.reorder_lists();
+            return;
+        }
+
+        let tracks = self.marked_tracks(self.state.active_section).await;
+        let favorite = tracks.iter().any(|t| !t.user_data.is_favorite);
+        let ids = tracks.iter().map(|t| t.id.clone()).collect::<HashSet<String>>();
+        for id in &ids {
+            let _ = self
+                .db
+                .cmd_tx
+                .send(Mutation::Favorite { item_id: id.clone(), favorite }.into())
+                .await;
+            let _ = set_favorite_track(&self.db.pool, id, favorite).await;
+        }
+        for t in self
+            .tracks
+            .iter_mut()
+            .chain(self.album_tracks.iter_mut())
+            .chain(self.playlist_tracks.iter_mut())
+            .filter(|t| ids.contains(&t.id))
+        {
+            t.user_data.is_favorite = favorite;
+        }
+        for song in self.state.queue.iter_mut().filter(|s| ids.contains(&s.id)) {
+            song.is_favorite = favorite;
+        }
+    }
+
+    pub async fn remove_marked_from_queue(&mut self) {
+        let mut indices = self.marked_queue_rows().into_iter().collect::<Vec<usize>>();
+        indices.sort_unstable();
+        for i in indices.into_iter().rev() {
+            if i < self.state.queue.len() {
+                self.mpv_handle.playlist_remove(i).await;
+                self.state.queue.remove(i);
+            }
+        }
+        self.clear_marks();
+    }
+}
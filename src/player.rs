@@ -4,7 +4,7 @@ use crate::database::extension::get_tracks_by_ids;
 use crate::keyboard::ActiveSection;
 use crate::mpv::SeekFlag;
 use crate::popup::{PopupMenu, ShuffleConfig};
-use crate::tui::{App, RadioMode, Repeat, SleepTimer};
+use crate::tui::{App, RadioMode, RadioSeed, Repeat, SleepTimer, Song, StopMarker};
 use std::time::Duration;
 use tokio::time::Instant;
 
@@ -134,6 +134,7 @@ impl App {
     }
 
     pub async fn stop(&mut self) {
+        let server_id = self.song_server_id(&self.active_song_id);
         self.stopped = true;
         self.paused = true;
         self.mpv_handle.stop().await;
@@ -147,6 +148,7 @@ impl App {
                 .db
                 .cmd_tx
                 .send(Command::Jellyfin(JellyfinCommand::Stopped {
+                    server_id,
                     id: Some(self.active_song_id.clone()),
                     position_ticks: Some(
                         self.state.current_playback_state.position as u64 * 10_000_000,
@@ -200,6 +202,7 @@ impl App {
                 .db
                 .cmd_tx
                 .send(Command::Jellyfin(JellyfinCommand::Stopped {
+                    server_id: self.song_server_id(&self.active_song_id),
                     id: Some(self.active_song_id.clone()),
                     position_ticks: Some(
                         self.state.current_playback_state.position as u64 * 10_000_000,
@@ -227,12 +230,8 @@ impl App {
                 self.preferences.repeat = Repeat::One;
             }
             Repeat::One => {
-                // radio is online-only, if ever added just keep the is_some block
-                if self.client.is_some() {
-                    self.preferences.repeat = Repeat::Radio;
-                } else {
-                    self.preferences.repeat = Repeat::None;
-                }
+                // offline the radio recommends from downloaded tracks
+                self.preferences.repeat = Repeat::Radio;
             }
             Repeat::Radio => {
                 self.preferences.repeat = Repeat::None;
@@ -243,14 +242,17 @@ impl App {
     }
 
     pub async fn cycle_radio(&mut self) {
-        if self.client.is_none() {
-            return;
-        }
         if self.preferences.repeat != Repeat::Radio {
             self.preferences.repeat = Repeat::Radio;
             let _ = self.preferences.save();
             return;
         }
+        // a radio started from an artist, album, genre or playlist goes back to the queue first
+        if self.preferences.radio.seed != RadioSeed::Queue {
+            self.preferences.radio.seed = RadioSeed::Queue;
+            let _ = self.preferences.save();
+            return;
+        }
         match self.preferences.radio_mode {
             RadioMode::Random => {
                 self.preferences.radio_mode = RadioMode::Similar;
@@ -326,6 +328,71 @@ impl App {
         self.sleep_timer = Some(SleepTimer::EndOfTrack);
     }
 
+    /// Queue row the stop marker currently points at, if it is still ahead of us
+    ///
+    pub fn stop_marker_row(&self) -> Option<usize> {
+        let current = self.state.current_playback_state.current_index;
+        match self.state.stop_marker.as_ref()? {
+            StopMarker::Track { id } => {
+                self.state.queue.iter().skip(current).position(|s| &s.id == id).map(|i| i + current)
+            }
+            StopMarker::Album { album_id } => {
+                let mut row = None;
+                for (i, song) in self.state.queue.iter().enumerate().skip(current) {
+                    if &song.album_id == album_id {
+                        row = Some(i);
+                    } else if row.is_some() {
+                        break;
+                    }
+                }
+                row
+            }
+            StopMarker::Tracks { remaining } => {
+                Some(current + remaining.saturating_sub(1)).filter(|i| *i < self.state.queue.len())
+            }
+        }
+    }
+
+    /// Places a stop marker on the selected queue row (or the playing track), or removes it from there
+    ///
+    pub fn toggle_stop_marker(&mut self) {
+        let index = if self.state.active_section == ActiveSection::Queue {
+            self.state.selected_queue_item.selected()
+        } else {
+            Some(self.state.current_playback_state.current_index)
+        };
+        let Some(song) = index.and_then(|i| self.state.queue.get(i)) else {
+            return;
+        };
+        let marker = StopMarker::Track { id: song.id.clone() };
+        if self.state.stop_marker.as_ref() == Some(&marker) {
+            self.state.stop_marker = None;
+        } else {
+            self.state.stop_marker = Some(marker);
+        }
+    }
+
+    /// Called when playback moves on to another queue entry or repeats the current one, pauses if the marker has
+    /// been reached
+    ///
+    pub async fn handle_stop_marker(&mut self, previous: &Song, song: &Song) {
+        let stop = match &mut self.state.stop_marker {
+            Some(StopMarker::Track { id }) => *id == previous.id,
+            Some(StopMarker::Album { album_id }) => {
+                song.album_id != *album_id && previous.album_id == *album_id
+            }
+            Some(StopMarker::Tracks { remaining }) => {
+                *remaining = remaining.saturating_sub(1);
+                *remaining == 0
+            }
+            None => false,
+        };
+        if stop {
+            self.pause().await;
+            self.state.stop_marker = None;
+        }
+    }
+
     pub async fn clear_sleep_timer(&mut self) {
         if let Some(vol) = self.sleep_timer_original_volume.take() {
             self.mpv_handle.set_volume(vol).await;
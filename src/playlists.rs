@@ -2,9 +2,11 @@
 The playlists tab is rendered here.
 -------------------------- */
 
+use crate::database::sync_rules::SyncRuleKind;
 use crate::keyboard::*;
 use crate::tui::App;
 use crate::{database::extension::DownloadStatus, helpers};
+use std::collections::HashSet;
 
 use crate::config::LyricsVisibility;
 use ratatui::{
@@ -70,6 +72,15 @@ impl App {
         let playlist_block_inner_h = playlist_block.inner(left[0]).height as usize;
         self.left_list_height = playlist_block_inner_h.max(1);
 
+        let synced_playlists = self
+            .sync_rules
+            .iter()
+            .filter_map(|rule| match &rule.kind {
+                SyncRuleKind::Playlist { id, .. } => Some(id.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<&str>>();
+        let pinned = self.pinned_items();
         let items = playlists
             .iter()
             .enumerate()
@@ -94,6 +105,18 @@ impl App {
                         Style::default().fg(self.theme.primary_color),
                     ));
                 }
+                if synced_playlists.contains(playlist.id.as_str()) {
+                    item.push_span(Span::styled(
+                        format!("{} ", &self.symbols.synced),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
+                if pinned.contains(&playlist.id) {
+                    item.push_span(Span::styled(
+                        format!("{} ", &self.symbols.pinned),
+                        Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    ));
+                }
 
                 let all_subsequences = crate::helpers::find_all_subsequences(
                     &self.state.playlists_search_term.to_lowercase(),
@@ -121,6 +144,15 @@ impl App {
                         Style::default().fg(color),
                     ));
                 }
+                // changes by others since the playlist was last opened
+                if let Some(activity) =
+                    self.playlist_activity.get(&playlist.id).filter(|a| a.unseen > 0)
+                {
+                    item.push_span(Span::styled(
+                        format!(" {} {}", &self.symbols.changed, activity.unseen),
+                        Style::default().fg(self.theme.primary_color),
+                    ));
+                }
                 ListItem::new(item)
             })
             .collect::<Vec<ListItem>>();
@@ -216,6 +248,9 @@ impl App {
         let table_body_h = table_block_inner.height.saturating_sub(header_h) as usize;
         self.track_list_height = table_body_h.max(1);
 
+        let marked = self.marked_ids(ActiveSection::Tracks);
+        let marked_style = self.marked_style();
+
         let items = playlist_tracks
             .iter()
             .enumerate()
@@ -287,6 +322,9 @@ impl App {
                 // ⇊
                 if self.client.is_some() {
                     cells.push(Cell::from(match track.download_status {
+                        DownloadStatus::Downloaded if self.synced_ids.contains(&track.id) => {
+                            Line::from(self.symbols.synced.as_str())
+                        }
                         DownloadStatus::Downloaded => Line::from(self.symbols.downloaded.as_str()),
                         DownloadStatus::Queued => Line::from(self.symbols.queued.as_str()),
                         DownloadStatus::Downloading => {
@@ -318,12 +356,18 @@ impl App {
                         .alignment(Alignment::Right),
                 ));
 
-                Row::new(cells).style(if track.id == self.active_song_id {
+                let style = if track.id == self.active_song_id {
                     Style::default().fg(self.theme.primary_color).italic()
                 } else if track.disliked {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground_dim))
                 } else {
                     Style::default().fg(self.theme.resolve(&self.theme.foreground))
+                };
+
+                Row::new(cells).style(if marked.contains(&track.id) {
+                    style.patch(marked_style)
+                } else {
+                    style
                 })
             })
             .collect::<Vec<Row>>();
@@ -609,20 +653,20 @@ impl App {
                 vec![
                     Constraint::Percentage(68),
                     Constraint::Percentage(32),
-                    Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                    Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
                 ]
             } else {
                 vec![
                     Constraint::Min(3),
                     Constraint::Percentage(100),
-                    Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                    Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
                 ]
             }
         } else {
             vec![
                 Constraint::Min(0),
                 Constraint::Percentage(100),
-                Constraint::Min(if self.download_item.is_some() { 3 } else { 0 }),
+                Constraint::Min(if !self.downloads.is_empty() { 3 } else { 0 }),
             ]
         };
 
@@ -6,18 +6,27 @@ This file can look very daunting, but it actually just defines a sort of structu
 - The `create_popup` function is responsible for creating and rendering the popup on the screen.
 */
 use crate::client::{Album, DiscographySong, LibraryView};
+use crate::config::ExportSettings;
 use crate::database::database::{
     t_discography_updater, Command, DeleteCommand, DownloadCommand, RemoveCommand, RenameCommand,
     UpdateCommand,
 };
 use crate::database::extension::{get_album_tracks, set_selected_libraries, DownloadStatus};
+use crate::database::outbox::{ChangeOutcome, Mutation, OutboxCommand, OutboxConflict};
+use crate::database::playlist_history::{
+    get_changelog, mark_seen, PlaylistActivity, PlaylistChange,
+};
+use crate::database::stats::export_stats;
+use crate::database::sync_rules::{PinKind, SyncRule, SyncRuleKind};
+use crate::database::tombstones::{Tombstone, TombstoneCommand};
 use crate::helpers::{find_all_subsequences, Searchable, Selectable};
 use crate::keyboard::{search_ranked_indices, search_ranked_refs, Action};
+use crate::servers::{server_choices, NewServer, ServerChoice, ServerField, ServerSwitch};
 use crate::themes::theme::Theme;
 use crate::{
     client::{Artist, Playlist, ScheduledTask},
     keyboard::{ActiveSection, ActiveTab},
-    tui::{Filter, Sort},
+    tui::{Filter, RadioProfile, RadioSeed, Sort, StopMarker},
 };
 use arboard::Clipboard;
 use chrono::Datelike;
@@ -81,11 +90,40 @@ pub enum PopupMenu {
         track_based_art: bool,
         downloading: bool,
         sleep_timer_enabled: bool,
+        offline_quality: String,
+        sync_conflicts: usize,
+        removed_albums: usize,
     },
     GlobalRunScheduledTask {
         tasks: Vec<ScheduledTask>,
     },
     GlobalShuffle(ShuffleConfig),
+    GlobalRadio {
+        radio: RadioProfile,
+        profiles: Vec<RadioProfile>,
+    },
+    GlobalRadioSaveProfile {
+        profile: RadioProfile,
+    },
+    GlobalRadioDeleteProfile {
+        profiles: Vec<RadioProfile>,
+    },
+    GlobalSyncRules {
+        rules: Vec<SyncRule>,
+        recent_count: usize,
+    },
+    GlobalSyncRulePlaylist {
+        playlists: Vec<Playlist>,
+    },
+    GlobalOutboxConflicts {
+        conflicts: Vec<OutboxConflict>,
+    },
+    GlobalTombstones {
+        tombstones: Vec<Tombstone>,
+    },
+    GlobalTombstone {
+        tombstone: Tombstone,
+    },
     GlobalPickTheme {},
     GlobalSetThemes {
         themes: Vec<crate::themes::theme::Theme>,
@@ -97,11 +135,43 @@ pub enum PopupMenu {
         minutes: u64,
         sleep_timer_enabled: bool,
     },
+    StatsExport {
+        path: String,
+    },
+    GlobalExportQueue {
+        path: String,
+    },
+    GlobalExportDownloads {
+        path: String,
+    },
+    GlobalImportPlaylist {
+        path: String,
+    },
+    GlobalSaveQueueAsPlaylist {
+        name: String,
+        public: bool,
+    },
+    GlobalSwitchServer {
+        servers: Vec<ServerChoice>,
+    },
+    GlobalAddServer {
+        server: NewServer,
+        field: ServerField,
+    },
     /**
      * Playlist related popups
      */
     PlaylistRoot {
         playlist_name: String,
+        sync_rule: Option<i64>,
+        pin: Option<i64>,
+        pinnable: bool,
+        activity: PlaylistActivity,
+    },
+    PlaylistChangelog {
+        changes: Vec<PlaylistChange>,
+        /// Changes after this one were new on the latest visit
+        seen: i64,
     },
     PlaylistSetName {
         playlist_name: String,
@@ -126,6 +196,8 @@ pub enum PopupMenu {
         track: DiscographySong,
         transcoding: bool,
         now_playing_name: Option<String>,
+        pinned: bool,
+        offline_copy: Option<String>,
     },
     TrackAddToPlaylist {
         track_name: String,
@@ -139,6 +211,8 @@ pub enum PopupMenu {
     PlaylistTracksRoot {
         track: DiscographySong,
         transcoding: bool,
+        pinned: bool,
+        offline_copy: Option<String>,
     },
     PlaylistTrackAddToPlaylist {
         track_name: String,
@@ -151,12 +225,42 @@ pub enum PopupMenu {
         playlist_name: String,
         playlist_id: String,
     },
+    /**
+     * Queue related popups
+     */
+    QueueRoot {
+        track_name: String,
+        track_id: String,
+        album_id: String,
+        album_name: String,
+        pause_after: usize,
+        has_stop_marker: bool,
+    },
+    /**
+     * Bulk actions on marked rows
+     */
+    MarkedRoot {
+        count: usize,
+        in_playlist: bool,
+        in_queue: bool,
+    },
+    MarkedAddToPlaylist {
+        track_ids: Vec<String>,
+        playlists: Vec<Playlist>,
+    },
+    MarkedPlaylistRemove {
+        track_ids: Vec<String>,
+        playlist_name: String,
+        playlist_id: String,
+    },
     /**
      * Artist related popups
      */
     ArtistRoot {
         artist: Artist,
         playing_artists: Option<Vec<Artist>>,
+        pin: Option<i64>,
+        pinnable: bool,
     },
     ArtistJumpToCurrent {
         // this one is for if there are multiple artists for a track
@@ -169,6 +273,8 @@ pub enum PopupMenu {
      */
     AlbumsRoot {
         album: Album,
+        pin: Option<i64>,
+        pinnable: bool,
     },
     AlbumsChangeFilter {},
     AlbumsChangeSort {},
@@ -181,6 +287,8 @@ pub enum PopupMenu {
         disliked: bool,
         transcoding: bool,
         now_playing_name: Option<String>,
+        pinned: bool,
+        offline_copy: Option<String>,
     },
 }
 
@@ -245,6 +353,41 @@ pub enum PopupCommand {
     SleepTimer,
     SleepEndTrack,
     SleepOff,
+    ExportQueue,
+    ExportDownloads,
+    ImportPlaylist,
+    SaveQueueAsPlaylist,
+    RadioSettings,
+    StartRadio,
+    RadioSeedQueue,
+    LoadRadioProfile { index: usize },
+    DeleteRadioProfile { index: usize },
+    StopAfterTrack,
+    StopAfterAlbum,
+    StopAfterTracks,
+    ClearStopMarker,
+    SetPinned { pinned: bool },
+    Redownload,
+    RedownloadMismatched,
+    SyncRules,
+    AddSyncRule { kind: SyncRuleKind },
+    RemoveSyncRule { id: i64 },
+    PickSyncPlaylist,
+    OutboxConflicts,
+    Tombstones,
+    OpenTombstone { index: usize },
+    Changelog,
+    KeepLocal,
+    Rematch,
+    ForceChanges { id: Option<i64> },
+    DiscardChanges { id: Option<i64> },
+    ToggleSyncRule,
+    TogglePin,
+    SwitchServer,
+    UseServer { name: String },
+    AddServer,
+    EditServerField { field: ServerField },
+    Maintenance,
 }
 
 #[derive(Clone, Debug)]
@@ -282,11 +425,29 @@ impl PopupMenu {
             PopupMenu::GlobalRunScheduledTask { .. } => "Run a Jellyfin task".to_string(),
             PopupMenu::GlobalSleepTimer { .. } => "Sleep Timer".to_string(),
             PopupMenu::GlobalShuffle(_) => "Global Shuffle".to_string(),
+            PopupMenu::GlobalRadio { .. } => "Radio".to_string(),
+            PopupMenu::GlobalRadioSaveProfile { .. } => "Save radio profile".to_string(),
+            PopupMenu::GlobalRadioDeleteProfile { .. } => "Delete radio profile".to_string(),
+            PopupMenu::GlobalSyncRules { .. } => "Auto-download rules".to_string(),
+            PopupMenu::GlobalSyncRulePlaylist { .. } => "Keep a playlist in sync".to_string(),
+            PopupMenu::GlobalOutboxConflicts { conflicts } => {
+                format!("{} offline changes conflict with the server", conflicts.len())
+            }
+            PopupMenu::GlobalTombstones { .. } => "Removed from server".to_string(),
+            PopupMenu::GlobalTombstone { tombstone } => tombstone.name.clone(),
             PopupMenu::GlobalSetThemes { .. } => "Set Theme".to_string(),
             PopupMenu::GlobalPickTheme { .. } => "Pick variant".to_string(),
             PopupMenu::GlobalSelectLibraries { .. } => "Select Libraries".to_string(),
+            PopupMenu::GlobalExportQueue { .. } => "Export queue (.m3u8 or .xspf)".to_string(),
+            PopupMenu::StatsExport { .. } => "Export listening stats (.json or .csv)".to_string(),
+            PopupMenu::GlobalExportDownloads { .. } => "Export downloads to a folder".to_string(),
+            PopupMenu::GlobalImportPlaylist { .. } => "Import playlist file".to_string(),
+            PopupMenu::GlobalSaveQueueAsPlaylist { .. } => "Save queue as playlist".to_string(),
+            PopupMenu::GlobalSwitchServer { .. } => "Switch server or user".to_string(),
+            PopupMenu::GlobalAddServer { .. } => "Add a server".to_string(),
             // ---------- Playlists ---------- //
             PopupMenu::PlaylistRoot { playlist_name, .. } => playlist_name.to_string(),
+            PopupMenu::PlaylistChangelog { .. } => "Changelog".to_string(),
             PopupMenu::PlaylistSetName { .. } => "Type to change name".to_string(),
             PopupMenu::PlaylistConfirmRename { .. } => "Confirm Rename".to_string(),
             PopupMenu::PlaylistConfirmDelete { .. } => "Confirm Delete".to_string(),
@@ -301,6 +462,14 @@ impl PopupMenu {
             PopupMenu::PlaylistTracksRoot { track, .. } => track.name.to_string(),
             PopupMenu::PlaylistTrackAddToPlaylist { track_name, .. } => track_name.to_string(),
             PopupMenu::PlaylistTracksRemove { track_name, .. } => track_name.to_string(),
+            // ---------- Queue ---------- //
+            PopupMenu::QueueRoot { track_name, .. } => track_name.to_string(),
+            // ---------- Marked ---------- //
+            PopupMenu::MarkedRoot { count, .. } => format!("{} marked", count),
+            PopupMenu::MarkedAddToPlaylist { track_ids, .. } => {
+                format!("Add {} tracks to playlist", track_ids.len())
+            }
+            PopupMenu::MarkedPlaylistRemove { .. } => "Confirm removal".to_string(),
             // ---------- Artists ---------- //
             PopupMenu::ArtistRoot { artist, .. } => artist.name.to_string(),
             PopupMenu::ArtistJumpToCurrent { artists, .. } => {
@@ -309,7 +478,7 @@ impl PopupMenu {
             PopupMenu::ArtistsChangeFilter {} => "Change filter".to_string(),
             PopupMenu::ArtistsChangeSort {} => "Change sort".to_string(),
             // ---------- Albums ---------- //
-            PopupMenu::AlbumsRoot { album } => album.name.to_string(),
+            PopupMenu::AlbumsRoot { album, .. } => album.name.to_string(),
             PopupMenu::AlbumsChangeFilter {} => "Change filter".to_string(),
             PopupMenu::AlbumsChangeSort {} => "Change sort".to_string(),
             // ---------- Album tracks ---------- //
@@ -325,7 +494,15 @@ impl PopupMenu {
                 PopupAction::new("Ok".to_string(), PopupCommand::Ok, Style::default(), false),
             ],
             // ---------- Global commands ---------- //
-            PopupMenu::GlobalRoot { large_art, track_based_art, downloading, .. } => vec![
+            PopupMenu::GlobalRoot {
+                large_art,
+                track_based_art,
+                downloading,
+                offline_quality,
+                sync_conflicts,
+                removed_albums,
+                ..
+            } => vec![
                 PopupAction::new(
                     "Synchronize with Jellyfin (runs every 10 minutes)".to_string(),
                     PopupCommand::Refresh,
@@ -344,6 +521,48 @@ impl PopupMenu {
                     Style::default(),
                     false,
                 ),
+                PopupAction::new(
+                    "Radio settings and profiles".to_string(),
+                    PopupCommand::RadioSettings,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Auto-download rules".to_string(),
+                    PopupCommand::SyncRules,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    format!("Review sync conflicts ({})", sync_conflicts),
+                    if *sync_conflicts > 0 {
+                        PopupCommand::OutboxConflicts
+                    } else {
+                        PopupCommand::None
+                    },
+                    if *sync_conflicts > 0 {
+                        Style::default()
+                    } else {
+                        Style::default().fg(style::Color::DarkGray)
+                    },
+                    false,
+                ),
+                PopupAction::new(
+                    format!("Removed from server, still downloaded ({})", removed_albums),
+                    if *removed_albums > 0 { PopupCommand::Tombstones } else { PopupCommand::None },
+                    if *removed_albums > 0 {
+                        Style::default()
+                    } else {
+                        Style::default().fg(style::Color::DarkGray)
+                    },
+                    false,
+                ),
+                PopupAction::new(
+                    format!("Re-download offline copies at {}", offline_quality),
+                    PopupCommand::RedownloadMismatched,
+                    Style::default(),
+                    true,
+                ),
                 PopupAction::new(
                     if *large_art {
                         "Switch to small artwork".to_string()
@@ -364,6 +583,36 @@ impl PopupMenu {
                     Style::default(),
                     false,
                 ),
+                PopupAction::new(
+                    "Export queue to file".to_string(),
+                    PopupCommand::ExportQueue,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Export downloads to a music folder".to_string(),
+                    PopupCommand::ExportDownloads,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Import playlist file (.m3u8, .xspf)".to_string(),
+                    PopupCommand::ImportPlaylist,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Save queue as new playlist".to_string(),
+                    PopupCommand::SaveQueueAsPlaylist,
+                    Style::default(),
+                    true,
+                ),
+                PopupAction::new(
+                    "Switch server or user".to_string(),
+                    PopupCommand::SwitchServer,
+                    Style::default(),
+                    false,
+                ),
                 PopupAction::new(
                     "Theme".to_string(),
                     PopupCommand::GlobalSetTheme,
@@ -377,11 +626,17 @@ impl PopupMenu {
                     false,
                 ),
                 PopupAction::new(
-                    "Repair offline downloads (could take a minute)".to_string(),
+                    "Verify and repair offline downloads (could take a minute)".to_string(),
                     PopupCommand::OfflineRepair,
                     Style::default(),
                     false,
                 ),
+                PopupAction::new(
+                    "Database maintenance: prune orphans and unused covers, vacuum".to_string(),
+                    PopupCommand::Maintenance,
+                    Style::default(),
+                    false,
+                ),
                 PopupAction::new(
                     "Stop downloading and abort queued".to_string(),
                     PopupCommand::CancelDownloads,
@@ -494,262 +749,459 @@ impl PopupMenu {
                     ),
                 ]
             }
-            PopupMenu::GlobalPickTheme {} => {
-                let mut actions: Vec<PopupAction> = Theme::builtin_themes()
-                    .into_iter()
-                    .map(|t| {
-                        PopupAction::new(
-                            t.name.clone(),
-                            PopupCommand::SetTheme { theme: t },
-                            Style::default(),
-                            false,
-                        )
-                    })
-                    .collect();
-
-                actions.push(PopupAction::new(
-                    "Custom Themes".to_string(),
-                    PopupCommand::Custom,
-                    Style::default(),
-                    false,
-                ));
-
-                actions
-            }
-            PopupMenu::GlobalSetThemes { themes } => {
-                let mut actions = vec![];
-                for theme in themes {
-                    actions.push(PopupAction::new(
-                        theme.name.clone(),
-                        PopupCommand::SetCustomTheme { theme: theme.clone() },
+            PopupMenu::GlobalRadio { radio: r, profiles } => {
+                let check = |b| if b { "✓" } else { " " };
+                let year = |y: Option<u32>| y.map_or("Any".to_string(), |v| v.to_string());
+                let mut actions = vec![
+                    PopupAction::new(
+                        format!("Seed: {} (Enter to seed from the queue)", r.seed.describe()),
+                        PopupCommand::RadioSeedQueue,
                         Style::default(),
                         false,
-                    ));
-                }
-                actions.push(PopupAction::new(
-                    "Back".to_string(),
-                    PopupCommand::None,
-                    Style::default(),
-                    false,
-                ));
-                actions
-            }
-            PopupMenu::GlobalSleepTimer { minutes, sleep_timer_enabled } => {
-                vec![
+                    ),
                     PopupAction::new(
-                        format!("Start ({} min), +/- to change.", minutes),
-                        PopupCommand::Confirm,
+                        format!("  {} tracks per refill (+/- to change)", r.tracks_per_refill),
+                        PopupCommand::None,
                         Style::default(),
                         false,
                     ),
                     PopupAction::new(
-                        "Start (after current track)".to_string(),
-                        PopupCommand::SleepEndTrack,
+                        format!(
+                            "  Skip tracks played in the last {} min (+/- to change)",
+                            r.dedup_minutes
+                        ),
+                        PopupCommand::None,
                         Style::default(),
                         false,
                     ),
                     PopupAction::new(
-                        "Turn off".to_string(),
-                        if *sleep_timer_enabled {
-                            PopupCommand::SleepOff
-                        } else {
-                            PopupCommand::None
-                        },
-                        if *sleep_timer_enabled {
-                            Style::default()
-                        } else {
-                            Style::default().fg(style::Color::DarkGray)
-                        },
+                        format!(
+                            "  Max tracks per artist: {} (+/- to change)",
+                            if r.max_per_artist == 0 {
+                                "Any".to_string()
+                            } else {
+                                r.max_per_artist.to_string()
+                            }
+                        ),
+                        PopupCommand::None,
+                        Style::default(),
                         false,
                     ),
-                ]
+                    PopupAction::new(
+                        format!("  From year: {} (+/- to change)", year(r.year_from)),
+                        PopupCommand::None,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        format!("  To year:   {} (+/- to change)", year(r.year_to)),
+                        PopupCommand::None,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        format!("{} Only favorite tracks", check(r.only_favorite)),
+                        PopupCommand::OnlyFavorite,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        format!("{} Exclude disliked tracks", check(r.exclude_disliked)),
+                        PopupCommand::Dislike,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Start radio".to_string(),
+                        PopupCommand::StartRadio,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Save as profile".to_string(),
+                        PopupCommand::Create,
+                        Style::default(),
+                        false,
+                    ),
+                ];
+                for (index, profile) in profiles.iter().enumerate() {
+                    actions.push(PopupAction::new(
+                        format!("Load profile: {} ({})", profile.name, profile.seed.describe()),
+                        PopupCommand::LoadRadioProfile { index },
+                        Style::default(),
+                        false,
+                    ));
+                }
+                if !profiles.is_empty() {
+                    actions.push(PopupAction::new(
+                        "Delete a profile".to_string(),
+                        PopupCommand::Delete,
+                        Style::default().fg(style::Color::Red),
+                        false,
+                    ));
+                }
+                actions
             }
-            // ---------- Playlists ----------
-            PopupMenu::PlaylistRoot { .. } => vec![
-                PopupAction::new("Play".to_string(), PopupCommand::Play, Style::default(), false),
+            PopupMenu::GlobalRadioSaveProfile { profile } => vec![
                 PopupAction::new(
-                    "Append to main queue".to_string(),
-                    PopupCommand::Append,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Append to temporary queue".to_string(),
-                    PopupCommand::AppendTemporary,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Rename".to_string(),
-                    PopupCommand::Rename,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new(
-                    "Download all tracks".to_string(),
-                    PopupCommand::Download,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new(
-                    "Remove downloaded tracks".to_string(),
-                    PopupCommand::RemoveDownload,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new(
-                    "Create new playlist".to_string(),
-                    PopupCommand::Create,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new(
-                    "Change filter".to_string(),
-                    PopupCommand::ChangeFilter,
+                    format!("Name: {}", profile.name),
+                    PopupCommand::Type,
                     Style::default(),
                     false,
                 ),
+                PopupAction::new("Save".to_string(), PopupCommand::Confirm, Style::default(), true),
                 PopupAction::new(
-                    "Change sort order".to_string(),
-                    PopupCommand::ChangeOrder,
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
                     Style::default(),
                     false,
                 ),
-                PopupAction::new(
-                    "Delete".to_string(),
-                    PopupCommand::Delete,
-                    Style::default().fg(style::Color::Red),
-                    true,
-                ),
             ],
-            PopupMenu::PlaylistSetName { new_name, .. } => {
-                vec![
+            PopupMenu::GlobalSyncRules { rules, recent_count } => {
+                let mut actions = vec![
                     PopupAction::new(
-                        // if new_name is empty, then the user has not typed anything yet. Otherwise show the new name
-                        if new_name.is_empty() {
-                            "Type in the new name".to_string()
-                        } else {
-                            format!("Name: {}", new_name)
-                        },
-                        PopupCommand::Type,
+                        "Add: keep all favorites downloaded".to_string(),
+                        PopupCommand::AddSyncRule { kind: SyncRuleKind::Favorites },
                         Style::default(),
                         true,
                     ),
                     PopupAction::new(
-                        "Confirm".to_string(),
-                        PopupCommand::Confirm,
+                        format!(
+                            "Add: keep the {} most recently added tracks (+/- to change)",
+                            recent_count
+                        ),
+                        PopupCommand::AddSyncRule {
+                            kind: SyncRuleKind::RecentlyAdded { count: *recent_count },
+                        },
                         Style::default(),
                         true,
                     ),
                     PopupAction::new(
-                        "Cancel".to_string(),
-                        PopupCommand::Cancel,
+                        "Add: keep a playlist in sync".to_string(),
+                        PopupCommand::PickSyncPlaylist,
                         Style::default(),
                         true,
                     ),
-                ]
+                ];
+                for rule in rules {
+                    actions.push(PopupAction::new(
+                        format!("Remove: {}", rule.kind.describe()),
+                        PopupCommand::RemoveSyncRule { id: rule.id },
+                        Style::default().fg(style::Color::Red),
+                        false,
+                    ));
+                }
+                actions
             }
-            PopupMenu::PlaylistConfirmRename { new_name, .. } => vec![
-                PopupAction::new(
-                    format!("Rename to: {}", new_name),
-                    PopupCommand::Rename,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new("Yes".to_string(), PopupCommand::Yes, Style::default(), true),
-                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), true),
-            ],
-            PopupMenu::PlaylistConfirmDelete { playlist_name } => vec![
-                PopupAction::new(
-                    format!("Delete playlist: {}", playlist_name),
-                    PopupCommand::Delete,
+            PopupMenu::GlobalOutboxConflicts { conflicts } => {
+                let mut actions = conflicts
+                    .iter()
+                    .map(|conflict| {
+                        PopupAction::new(
+                            format!(
+                                "Discard: {}, {}",
+                                conflict.mutation.describe(),
+                                conflict.reason
+                            ),
+                            PopupCommand::DiscardChanges { id: Some(conflict.id) },
+                            Style::default(),
+                            false,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                actions.push(PopupAction::new(
+                    "Apply all anyway".to_string(),
+                    PopupCommand::ForceChanges { id: None },
                     Style::default(),
-                    true,
-                ),
-                PopupAction::new("Yes".to_string(), PopupCommand::Yes, Style::default(), true),
-                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), true),
-            ],
-            PopupMenu::PlaylistCreate { name, public } => vec![
+                    false,
+                ));
+                actions.push(PopupAction::new(
+                    "Discard all, keep the server state".to_string(),
+                    PopupCommand::DiscardChanges { id: None },
+                    Style::default().fg(style::Color::Red),
+                    false,
+                ));
+                actions
+            }
+            PopupMenu::GlobalTombstones { tombstones } => tombstones
+                .iter()
+                .enumerate()
+                .map(|(index, tombstone)| {
+                    PopupAction::new(
+                        tombstone.describe(),
+                        PopupCommand::OpenTombstone { index },
+                        Style::default(),
+                        false,
+                    )
+                })
+                .collect(),
+            PopupMenu::GlobalTombstone { tombstone } => vec![
                 PopupAction::new(
-                    if name.is_empty() {
-                        "Type in the new playlist name".into()
+                    if tombstone.local_only {
+                        "Kept as a local-only album".to_string()
                     } else {
-                        format!("Name: {}", name)
+                        "Keep as a local-only album".to_string()
                     },
-                    PopupCommand::Type,
+                    PopupCommand::KeepLocal,
                     Style::default(),
-                    true,
+                    false,
                 ),
                 PopupAction::new(
-                    format!("Public: {}", public),
-                    PopupCommand::Toggle,
+                    "Re-match to the same album on the server (new id)".to_string(),
+                    PopupCommand::Rematch,
                     Style::default(),
-                    true,
+                    false,
                 ),
                 PopupAction::new(
-                    "Create".to_string(),
+                    "Delete the album and its downloads".to_string(),
+                    PopupCommand::Delete,
+                    Style::default().fg(style::Color::Red),
+                    false,
+                ),
+            ],
+            PopupMenu::GlobalSyncRulePlaylist { playlists } => playlists
+                .iter()
+                .map(|playlist| {
+                    PopupAction::new(
+                        playlist.name.clone(),
+                        PopupCommand::AddSyncRule {
+                            kind: SyncRuleKind::Playlist {
+                                id: playlist.id.clone(),
+                                name: playlist.name.clone(),
+                            },
+                        },
+                        Style::default(),
+                        true,
+                    )
+                })
+                .collect(),
+            PopupMenu::GlobalSwitchServer { servers } => {
+                let mut actions = servers
+                    .iter()
+                    .map(|server| {
+                        let label = match &server.user {
+                            Some(user) => format!("{} ({}) as {}", server.name, server.url, user),
+                            None => format!("{} ({}) with Quick Connect", server.name, server.url),
+                        };
+                        PopupAction::new(
+                            if server.current { format!("{} (current)", label) } else { label },
+                            PopupCommand::UseServer { name: server.name.clone() },
+                            if server.current {
+                                Style::default().fg(style::Color::DarkGray)
+                            } else {
+                                Style::default()
+                            },
+                            false,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                actions.push(PopupAction::new(
+                    "Add a server".to_string(),
+                    PopupCommand::AddServer,
+                    Style::default(),
+                    false,
+                ));
+                actions
+            }
+            PopupMenu::GlobalAddServer { server, .. } => {
+                let field = |label: &str, value: &str, field: ServerField| {
+                    PopupAction::new(
+                        format!("{}: {}", label, value),
+                        PopupCommand::EditServerField { field },
+                        Style::default(),
+                        false,
+                    )
+                };
+                let mut actions = vec![
+                    field("Name", &server.name, ServerField::Name),
+                    field("URL", &server.url, ServerField::Url),
+                    PopupAction::new(
+                        if server.quick_connect {
+                            "Authentication: Quick Connect".to_string()
+                        } else {
+                            "Authentication: Username & password".to_string()
+                        },
+                        PopupCommand::Toggle,
+                        Style::default(),
+                        false,
+                    ),
+                ];
+                if !server.quick_connect {
+                    actions.push(field("Username", &server.username, ServerField::Username));
+                    actions.push(field(
+                        "Password",
+                        &"*".repeat(server.password.chars().count()),
+                        ServerField::Password,
+                    ));
+                }
+                actions.push(PopupAction::new(
+                    "Add and switch".to_string(),
                     PopupCommand::Create,
                     Style::default(),
-                    true,
+                    false,
+                ));
+                actions.push(PopupAction::new(
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
+                    Style::default(),
+                    false,
+                ));
+                actions
+            }
+            PopupMenu::GlobalRadioDeleteProfile { profiles } => profiles
+                .iter()
+                .enumerate()
+                .map(|(index, profile)| {
+                    PopupAction::new(
+                        format!("Delete {} ({})", profile.name, profile.seed.describe()),
+                        PopupCommand::DeleteRadioProfile { index },
+                        Style::default().fg(style::Color::Red),
+                        false,
+                    )
+                })
+                .collect(),
+            PopupMenu::GlobalPickTheme {} => {
+                let mut actions: Vec<PopupAction> = Theme::builtin_themes()
+                    .into_iter()
+                    .map(|t| {
+                        PopupAction::new(
+                            t.name.clone(),
+                            PopupCommand::SetTheme { theme: t },
+                            Style::default(),
+                            false,
+                        )
+                    })
+                    .collect();
+
+                actions.push(PopupAction::new(
+                    "Custom Themes".to_string(),
+                    PopupCommand::Custom,
+                    Style::default(),
+                    false,
+                ));
+
+                actions
+            }
+            PopupMenu::GlobalSetThemes { themes } => {
+                let mut actions = vec![];
+                for theme in themes {
+                    actions.push(PopupAction::new(
+                        theme.name.clone(),
+                        PopupCommand::SetCustomTheme { theme: theme.clone() },
+                        Style::default(),
+                        false,
+                    ));
+                }
+                actions.push(PopupAction::new(
+                    "Back".to_string(),
+                    PopupCommand::None,
+                    Style::default(),
+                    false,
+                ));
+                actions
+            }
+            PopupMenu::GlobalSleepTimer { minutes, sleep_timer_enabled } => {
+                vec![
+                    PopupAction::new(
+                        format!("Start ({} min), +/- to change.", minutes),
+                        PopupCommand::Confirm,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Start (after current track)".to_string(),
+                        PopupCommand::SleepEndTrack,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Turn off".to_string(),
+                        if *sleep_timer_enabled {
+                            PopupCommand::SleepOff
+                        } else {
+                            PopupCommand::None
+                        },
+                        if *sleep_timer_enabled {
+                            Style::default()
+                        } else {
+                            Style::default().fg(style::Color::DarkGray)
+                        },
+                        false,
+                    ),
+                ]
+            }
+            PopupMenu::GlobalExportQueue { path } => vec![
+                PopupAction::new(
+                    format!("Path: {}", path),
+                    PopupCommand::Type,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Export".to_string(),
+                    PopupCommand::Confirm,
+                    Style::default(),
+                    false,
                 ),
                 PopupAction::new(
                     "Cancel".to_string(),
                     PopupCommand::Cancel,
                     Style::default(),
-                    true,
+                    false,
                 ),
             ],
-            PopupMenu::PlaylistsChangeSort {} => vec![
+            PopupMenu::StatsExport { path } => vec![
                 PopupAction::new(
-                    "Ascending".to_string(),
-                    PopupCommand::Ascending,
+                    format!("Path: {}", path),
+                    PopupCommand::Type,
                     Style::default(),
                     false,
                 ),
                 PopupAction::new(
-                    "Descending".to_string(),
-                    PopupCommand::Descending,
+                    "Export".to_string(),
+                    PopupCommand::Confirm,
                     Style::default(),
                     false,
                 ),
                 PopupAction::new(
-                    "Date created".to_string(),
-                    PopupCommand::DateCreated,
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
                     Style::default(),
                     false,
                 ),
+            ],
+            PopupMenu::GlobalExportDownloads { path } => vec![
                 PopupAction::new(
-                    "Random".to_string(),
-                    PopupCommand::Random,
+                    format!("Path: {}", path),
+                    PopupCommand::Type,
                     Style::default(),
                     false,
                 ),
-            ],
-            PopupMenu::PlaylistsChangeFilter {} => vec![
                 PopupAction::new(
-                    "Normal".to_string(),
-                    PopupCommand::Normal,
+                    "Export".to_string(),
+                    PopupCommand::Confirm,
                     Style::default(),
                     false,
                 ),
                 PopupAction::new(
-                    "Show favorites first".to_string(),
-                    PopupCommand::ShowFavoritesFirst,
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
                     Style::default(),
                     false,
                 ),
             ],
-            // ---------- Tracks ---------- //
-            PopupMenu::TrackRoot { track, transcoding, now_playing_name } => vec![
+            PopupMenu::GlobalImportPlaylist { path } => vec![
                 PopupAction::new(
-                    match now_playing_name {
-                        Some(name) => format!("Locate now-playing ({})", name),
-                        None => "Locate now-playing track".to_string(),
+                    if path.is_empty() {
+                        "Type in the path to a playlist file".to_string()
+                    } else {
+                        format!("Path: {}", path)
                     },
-                    PopupCommand::JumpToCurrent,
+                    PopupCommand::Type,
                     Style::default(),
                     false,
                 ),
+                PopupAction::new("Play".to_string(), PopupCommand::Play, Style::default(), false),
                 PopupAction::new(
                     "Append to main queue".to_string(),
                     PopupCommand::Append,
@@ -757,62 +1209,407 @@ impl PopupMenu {
                     false,
                 ),
                 PopupAction::new(
-                    "Append to temporary queue".to_string(),
-                    PopupCommand::AppendTemporary,
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
                     Style::default(),
                     false,
                 ),
+            ],
+            PopupMenu::GlobalSaveQueueAsPlaylist { name, public } => vec![
                 PopupAction::new(
-                    "Add to playlist".to_string(),
-                    PopupCommand::AddToPlaylist { playlist_id: String::new() },
+                    if name.is_empty() {
+                        "Type in the new playlist name".into()
+                    } else {
+                        format!("Name: {}", name)
+                    },
+                    PopupCommand::Type,
                     Style::default(),
                     true,
                 ),
                 PopupAction::new(
-                    "Instant Mix".to_string(),
-                    PopupCommand::InstantMix,
+                    format!("Public: {}", public),
+                    PopupCommand::Toggle,
                     Style::default(),
                     true,
                 ),
                 PopupAction::new(
-                    if track.disliked {
-                        "Remove dislike".to_string()
-                    } else {
-                        "Dislike track".to_string()
-                    },
-                    PopupCommand::Dislike,
+                    "Create".to_string(),
+                    PopupCommand::Create,
+                    Style::default(),
+                    true,
+                ),
+                PopupAction::new(
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
+                    Style::default(),
+                    true,
+                ),
+            ],
+            // ---------- Playlists ----------
+            PopupMenu::PlaylistRoot { sync_rule, pin, pinnable, activity, .. } => {
+                let mut actions = vec![
+                    PopupAction::new(
+                        "Play".to_string(),
+                        PopupCommand::Play,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to main queue".to_string(),
+                        PopupCommand::Append,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to temporary queue".to_string(),
+                        PopupCommand::AppendTemporary,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Start radio from this playlist".to_string(),
+                        PopupCommand::StartRadio,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Rename".to_string(),
+                        PopupCommand::Rename,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Download all tracks".to_string(),
+                        PopupCommand::Download,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Remove downloaded tracks".to_string(),
+                        PopupCommand::RemoveDownload,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        if sync_rule.is_some() {
+                            "Stop keeping this playlist in sync".to_string()
+                        } else {
+                            "Keep this playlist in sync (auto-download)".to_string()
+                        },
+                        PopupCommand::ToggleSyncRule,
+                        Style::default(),
+                        sync_rule.is_none(),
+                    ),
+                    PopupAction::new(
+                        if pin.is_some() {
+                            "Unpin playlist".to_string()
+                        } else {
+                            "Pin playlist (keep it offline in full)".to_string()
+                        },
+                        PopupCommand::TogglePin,
+                        Style::default(),
+                        pin.is_none(),
+                    ),
+                    PopupAction::new(
+                        "Create new playlist".to_string(),
+                        PopupCommand::Create,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Change filter".to_string(),
+                        PopupCommand::ChangeFilter,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Change sort order".to_string(),
+                        PopupCommand::ChangeOrder,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Delete".to_string(),
+                        PopupCommand::Delete,
+                        Style::default().fg(style::Color::Red),
+                        true,
+                    ),
+                ];
+                // edited by others, e.g. a shared playlist
+                if activity.by_others > 0 {
+                    let at = actions
+                        .iter()
+                        .position(|a| matches!(a.action, PopupCommand::TogglePin))
+                        .map_or(actions.len(), |i| i + 1);
+                    actions.insert(
+                        at,
+                        PopupAction::new(
+                            format!("Changelog ({} new)", activity.unseen),
+                            PopupCommand::Changelog,
+                            Style::default(),
+                            false,
+                        ),
+                    );
+                }
+                if pin.is_none() && !pinnable {
+                    actions.retain(|a| !matches!(a.action, PopupCommand::TogglePin));
+                }
+                actions
+            }
+            PopupMenu::PlaylistChangelog { changes, seen } => changes
+                .iter()
+                .map(|change| {
+                    PopupAction::new(
+                        change.describe(),
+                        PopupCommand::None,
+                        if change.id > *seen && !change.by_me {
+                            Style::default().bold()
+                        } else {
+                            Style::default()
+                        },
+                        false,
+                    )
+                })
+                .collect(),
+            PopupMenu::PlaylistSetName { new_name, .. } => {
+                vec![
+                    PopupAction::new(
+                        // if new_name is empty, then the user has not typed anything yet. Otherwise show the new name
+                        if new_name.is_empty() {
+                            "Type in the new name".to_string()
+                        } else {
+                            format!("Name: {}", new_name)
+                        },
+                        PopupCommand::Type,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Confirm".to_string(),
+                        PopupCommand::Confirm,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Cancel".to_string(),
+                        PopupCommand::Cancel,
+                        Style::default(),
+                        false,
+                    ),
+                ]
+            }
+            PopupMenu::PlaylistConfirmRename { new_name, .. } => vec![
+                PopupAction::new(
+                    format!("Rename to: {}", new_name),
+                    PopupCommand::Rename,
                     Style::default(),
                     false,
                 ),
+                PopupAction::new("Yes".to_string(), PopupCommand::Yes, Style::default(), false),
+                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), false),
+            ],
+            PopupMenu::PlaylistConfirmDelete { playlist_name } => vec![
                 PopupAction::new(
-                    if *transcoding {
-                        "Copy URL to clipboard (transcoded)".to_string()
+                    format!("Delete playlist: {}", playlist_name),
+                    PopupCommand::Delete,
+                    Style::default(),
+                    true,
+                ),
+                PopupAction::new("Yes".to_string(), PopupCommand::Yes, Style::default(), true),
+                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), true),
+            ],
+            PopupMenu::PlaylistCreate { name, public } => vec![
+                PopupAction::new(
+                    if name.is_empty() {
+                        "Type in the new playlist name".into()
                     } else {
-                        "Copy URL to clipboard".to_string()
+                        format!("Name: {}", name)
                     },
-                    PopupCommand::CopyUrl,
+                    PopupCommand::Type,
                     Style::default(),
                     true,
                 ),
                 PopupAction::new(
-                    "Copy Last.fm album URL to clipboard".to_string(),
-                    PopupCommand::CopyLastfmUrl,
+                    format!("Public: {}", public),
+                    PopupCommand::Toggle,
                     Style::default(),
                     true,
                 ),
                 PopupAction::new(
-                    "Change album order".to_string(),
-                    PopupCommand::ChangeOrder,
+                    "Create".to_string(),
+                    PopupCommand::Create,
                     Style::default(),
-                    false,
+                    true,
                 ),
                 PopupAction::new(
-                    "Re-fetch artwork".to_string(),
-                    PopupCommand::FetchArt,
+                    "Cancel".to_string(),
+                    PopupCommand::Cancel,
                     Style::default(),
                     true,
                 ),
             ],
+            PopupMenu::PlaylistsChangeSort {} => vec![
+                PopupAction::new(
+                    "Ascending".to_string(),
+                    PopupCommand::Ascending,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Descending".to_string(),
+                    PopupCommand::Descending,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Date created".to_string(),
+                    PopupCommand::DateCreated,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Random".to_string(),
+                    PopupCommand::Random,
+                    Style::default(),
+                    false,
+                ),
+            ],
+            PopupMenu::PlaylistsChangeFilter {} => vec![
+                PopupAction::new(
+                    "Normal".to_string(),
+                    PopupCommand::Normal,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Show favorites first".to_string(),
+                    PopupCommand::ShowFavoritesFirst,
+                    Style::default(),
+                    false,
+                ),
+            ],
+            // ---------- Tracks ---------- //
+            PopupMenu::TrackRoot { track, transcoding, now_playing_name, pinned, offline_copy } => {
+                vec![
+                    PopupAction::new(
+                        match now_playing_name {
+                            Some(name) => format!("Locate now-playing ({})", name),
+                            None => "Locate now-playing track".to_string(),
+                        },
+                        PopupCommand::JumpToCurrent,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to main queue".to_string(),
+                        PopupCommand::Append,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to temporary queue".to_string(),
+                        PopupCommand::AppendTemporary,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Add to playlist".to_string(),
+                        PopupCommand::AddToPlaylist { playlist_id: String::new() },
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Instant Mix".to_string(),
+                        PopupCommand::InstantMix,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        match track.genres.first() {
+                            Some(genre) => format!("Start {} radio", genre),
+                            None => "Start genre radio (no genres)".to_string(),
+                        },
+                        if track.genres.is_empty() {
+                            PopupCommand::None
+                        } else {
+                            PopupCommand::StartRadio
+                        },
+                        if track.genres.is_empty() {
+                            Style::default().fg(style::Color::DarkGray)
+                        } else {
+                            Style::default()
+                        },
+                        false,
+                    ),
+                    PopupAction::new(
+                        if track.disliked {
+                            "Remove dislike".to_string()
+                        } else {
+                            "Dislike track".to_string()
+                        },
+                        PopupCommand::Dislike,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        if *pinned {
+                            "Unpin download".to_string()
+                        } else {
+                            "Pin download (never evicted by the quota)".to_string()
+                        },
+                        PopupCommand::SetPinned { pinned: !*pinned },
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        match offline_copy {
+                            Some(quality) => {
+                                format!("Re-download offline copy (currently {})", quality)
+                            }
+                            None => "Re-download offline copy (not downloaded)".to_string(),
+                        },
+                        if offline_copy.is_some() {
+                            PopupCommand::Redownload
+                        } else {
+                            PopupCommand::None
+                        },
+                        if offline_copy.is_some() {
+                            Style::default()
+                        } else {
+                            Style::default().fg(style::Color::DarkGray)
+                        },
+                        true,
+                    ),
+                    PopupAction::new(
+                        if *transcoding {
+                            "Copy URL to clipboard (transcoded)".to_string()
+                        } else {
+                            "Copy URL to clipboard".to_string()
+                        },
+                        PopupCommand::CopyUrl,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Copy Last.fm album URL to clipboard".to_string(),
+                        PopupCommand::CopyLastfmUrl,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Change album order".to_string(),
+                        PopupCommand::ChangeOrder,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Re-fetch artwork".to_string(),
+                        PopupCommand::FetchArt,
+                        Style::default(),
+                        true,
+                    ),
+                ]
+            }
             PopupMenu::TrackAddToPlaylist { playlists, .. } => {
                 let mut actions = vec![];
                 for playlist in playlists {
@@ -825,7 +1622,7 @@ impl PopupMenu {
                         ),
                         PopupCommand::AddToPlaylist { playlist_id: playlist.id.clone() },
                         Style::default(),
-                        true,
+                        false,
                     ));
                 }
                 actions
@@ -887,7 +1684,7 @@ impl PopupMenu {
                 ),
             ],
             // ---------- Playlist tracks ---------- //
-            PopupMenu::PlaylistTracksRoot { track, transcoding } => vec![
+            PopupMenu::PlaylistTracksRoot { track, transcoding, pinned, offline_copy } => vec![
                 PopupAction::new(
                     "Jump to album".to_string(),
                     PopupCommand::GoAlbum,
@@ -898,7 +1695,7 @@ impl PopupMenu {
                     "Add to playlist".to_string(),
                     PopupCommand::AddToPlaylist { playlist_id: String::new() },
                     Style::default(),
-                    true,
+                    false,
                 ),
                 PopupAction::new(
                     if track.disliked {
@@ -910,6 +1707,35 @@ impl PopupMenu {
                     Style::default(),
                     false,
                 ),
+                PopupAction::new(
+                    if *pinned {
+                        "Unpin download".to_string()
+                    } else {
+                        "Pin download (never evicted by the quota)".to_string()
+                    },
+                    PopupCommand::SetPinned { pinned: !*pinned },
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    match offline_copy {
+                        Some(quality) => {
+                            format!("Re-download offline copy (currently {})", quality)
+                        }
+                        None => "Re-download offline copy (not downloaded)".to_string(),
+                    },
+                    if offline_copy.is_some() {
+                        PopupCommand::Redownload
+                    } else {
+                        PopupCommand::None
+                    },
+                    if offline_copy.is_some() {
+                        Style::default()
+                    } else {
+                        Style::default().fg(style::Color::DarkGray)
+                    },
+                    true,
+                ),
                 PopupAction::new(
                     if *transcoding {
                         "Copy URL to clipboard (transcoded)".to_string()
@@ -930,7 +1756,7 @@ impl PopupMenu {
                     "Remove from this playlist".to_string(),
                     PopupCommand::Delete,
                     Style::default().fg(style::Color::Red),
-                    true,
+                    false,
                 ),
             ],
             PopupMenu::PlaylistTrackAddToPlaylist { playlists, .. } => {
@@ -945,7 +1771,7 @@ impl PopupMenu {
                         ),
                         PopupCommand::AddToPlaylist { playlist_id: playlist.id.clone() },
                         Style::default(),
-                        true,
+                        false,
                     ));
                 }
                 actions
@@ -955,18 +1781,167 @@ impl PopupMenu {
                     format!("Remove {} from playlist?", track_name),
                     PopupCommand::None,
                     Style::default().fg(style::Color::Red),
-                    true,
+                    false,
                 ),
                 PopupAction::new(
                     "Yes".to_string(),
                     PopupCommand::Yes,
                     Style::default().fg(style::Color::Red),
-                    true,
+                    false,
                 ),
-                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), true),
+                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), false),
+            ],
+            // ---------- Queue ---------- //
+            PopupMenu::QueueRoot { album_name, pause_after, has_stop_marker, .. } => vec![
+                PopupAction::new(
+                    "Stop after this track".to_string(),
+                    PopupCommand::StopAfterTrack,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    format!("Stop after this album ({})", album_name),
+                    PopupCommand::StopAfterAlbum,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    format!("Pause after {} tracks (+/- to change)", pause_after),
+                    PopupCommand::StopAfterTracks,
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    "Clear stop marker".to_string(),
+                    if *has_stop_marker {
+                        PopupCommand::ClearStopMarker
+                    } else {
+                        PopupCommand::None
+                    },
+                    if *has_stop_marker {
+                        Style::default()
+                    } else {
+                        Style::default().fg(style::Color::DarkGray)
+                    },
+                    false,
+                ),
+            ],
+            // ---------- Marked ---------- //
+            PopupMenu::MarkedRoot { in_playlist, in_queue, .. } => {
+                let mut actions = vec![
+                    PopupAction::new(
+                        "Append to main queue".to_string(),
+                        PopupCommand::Append,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to temporary queue".to_string(),
+                        PopupCommand::AppendTemporary,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Download".to_string(),
+                        PopupCommand::Download,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Remove downloads".to_string(),
+                        PopupCommand::RemoveDownload,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Pin downloads".to_string(),
+                        PopupCommand::SetPinned { pinned: true },
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Unpin downloads".to_string(),
+                        PopupCommand::SetPinned { pinned: false },
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Re-download at the offline quality".to_string(),
+                        PopupCommand::Redownload,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Toggle favorite".to_string(),
+                        PopupCommand::Toggle,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        "Add to playlist".to_string(),
+                        PopupCommand::AddToPlaylist { playlist_id: String::new() },
+                        Style::default(),
+                        false,
+                    ),
+                ];
+                if *in_playlist {
+                    actions.push(PopupAction::new(
+                        "Remove from this playlist".to_string(),
+                        PopupCommand::Delete,
+                        Style::default().fg(style::Color::Red),
+                        false,
+                    ));
+                }
+                if *in_queue {
+                    actions.push(PopupAction::new(
+                        "Remove from queue".to_string(),
+                        PopupCommand::Delete,
+                        Style::default().fg(style::Color::Red),
+                        false,
+                    ));
+                }
+                actions.push(PopupAction::new(
+                    "Clear marks".to_string(),
+                    PopupCommand::Cancel,
+                    Style::default(),
+                    false,
+                ));
+                actions
+            }
+            PopupMenu::MarkedAddToPlaylist { playlists, .. } => {
+                let mut actions = vec![];
+                for playlist in playlists {
+                    actions.push(PopupAction::new(
+                        format!(
+                            "{}{} ({})",
+                            if playlist.user_data.is_favorite { "♥ " } else { "" },
+                            playlist.name,
+                            playlist.child_count
+                        ),
+                        PopupCommand::AddToPlaylist { playlist_id: playlist.id.clone() },
+                        Style::default(),
+                        false,
+                    ));
+                }
+                actions
+            }
+            PopupMenu::MarkedPlaylistRemove { track_ids, playlist_name, .. } => vec![
+                PopupAction::new(
+                    format!("Remove {} tracks from {}?", track_ids.len(), playlist_name),
+                    PopupCommand::None,
+                    Style::default().fg(style::Color::Red),
+                    false,
+                ),
+                PopupAction::new(
+                    "Yes".to_string(),
+                    PopupCommand::Yes,
+                    Style::default().fg(style::Color::Red),
+                    false,
+                ),
+                PopupAction::new("No".to_string(), PopupCommand::No, Style::default(), false),
             ],
             // ---------- Artists ---------- //
-            PopupMenu::ArtistRoot { playing_artists, .. } => {
+            PopupMenu::ArtistRoot { artist, playing_artists, pin, pinnable } => {
                 let mut actions = vec![];
                 if let Some(artists) = playing_artists {
                     actions.push(PopupAction::new(
@@ -983,6 +1958,25 @@ impl PopupMenu {
                         false,
                     ));
                 }
+                actions.push(PopupAction::new(
+                    format!("Start {} radio", artist.name),
+                    PopupCommand::StartRadio,
+                    Style::default(),
+                    false,
+                ));
+                if pin.is_some() || *pinnable {
+                    actions.push(PopupAction::new(
+                        if pin.is_some() {
+                            "Unpin artist".to_string()
+                        } else {
+                            "Pin artist (keep it offline in full, new releases included)"
+                                .to_string()
+                        },
+                        PopupCommand::TogglePin,
+                        Style::default(),
+                        pin.is_none(),
+                    ));
+                }
                 actions.push(PopupAction::new(
                     "Change filter".to_string(),
                     PopupCommand::ChangeFilter,
@@ -1056,44 +2050,66 @@ impl PopupMenu {
                 ),
             ],
             // ---------- Albums ---------- //
-            PopupMenu::AlbumsRoot { .. } => vec![
-                PopupAction::new(
-                    "Jump to current album".to_string(),
-                    PopupCommand::JumpToCurrent,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Download album".to_string(),
-                    PopupCommand::Download,
-                    Style::default(),
-                    true,
-                ),
-                PopupAction::new(
-                    "Append to main queue".to_string(),
-                    PopupCommand::Append,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Append to temporary queue".to_string(),
-                    PopupCommand::AppendTemporary,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Change filter".to_string(),
-                    PopupCommand::ChangeFilter,
-                    Style::default(),
-                    false,
-                ),
-                PopupAction::new(
-                    "Change sort order".to_string(),
-                    PopupCommand::ChangeOrder,
-                    Style::default(),
-                    false,
-                ),
-            ],
+            PopupMenu::AlbumsRoot { pin, pinnable, .. } => {
+                let mut actions = vec![
+                    PopupAction::new(
+                        "Jump to current album".to_string(),
+                        PopupCommand::JumpToCurrent,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Download album".to_string(),
+                        PopupCommand::Download,
+                        Style::default(),
+                        true,
+                    ),
+                    PopupAction::new(
+                        if pin.is_some() {
+                            "Unpin album".to_string()
+                        } else {
+                            "Pin album (keep it offline in full)".to_string()
+                        },
+                        PopupCommand::TogglePin,
+                        Style::default(),
+                        pin.is_none(),
+                    ),
+                    PopupAction::new(
+                        "Append to main queue".to_string(),
+                        PopupCommand::Append,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Append to temporary queue".to_string(),
+                        PopupCommand::AppendTemporary,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Start radio from this album".to_string(),
+                        PopupCommand::StartRadio,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Change filter".to_string(),
+                        PopupCommand::ChangeFilter,
+                        Style::default(),
+                        false,
+                    ),
+                    PopupAction::new(
+                        "Change sort order".to_string(),
+                        PopupCommand::ChangeOrder,
+                        Style::default(),
+                        false,
+                    ),
+                ];
+                if pin.is_none() && !pinnable {
+                    actions.retain(|a| !matches!(a.action, PopupCommand::TogglePin));
+                }
+                actions
+            }
             PopupMenu::AlbumsChangeFilter {} => vec![
                 PopupAction::new(
                     "Normal".to_string(),
@@ -1147,7 +2163,14 @@ impl PopupMenu {
                 ),
             ],
             // ---------- Album tracks ---------- //
-            PopupMenu::AlbumTrackRoot { disliked, transcoding, now_playing_name, .. } => vec![
+            PopupMenu::AlbumTrackRoot {
+                disliked,
+                transcoding,
+                now_playing_name,
+                pinned,
+                offline_copy,
+                ..
+            } => vec![
                 PopupAction::new(
                     match now_playing_name {
                         Some(name) => format!("Locate now-playing ({})", name),
@@ -1161,7 +2184,7 @@ impl PopupMenu {
                     "Add to playlist".to_string(),
                     PopupCommand::AddToPlaylist { playlist_id: String::new() },
                     Style::default(),
-                    true,
+                    false,
                 ),
                 PopupAction::new(
                     if *disliked {
@@ -1173,6 +2196,35 @@ impl PopupMenu {
                     Style::default(),
                     false,
                 ),
+                PopupAction::new(
+                    if *pinned {
+                        "Unpin download".to_string()
+                    } else {
+                        "Pin download (never evicted by the quota)".to_string()
+                    },
+                    PopupCommand::SetPinned { pinned: !*pinned },
+                    Style::default(),
+                    false,
+                ),
+                PopupAction::new(
+                    match offline_copy {
+                        Some(quality) => {
+                            format!("Re-download offline copy (currently {})", quality)
+                        }
+                        None => "Re-download offline copy (not downloaded)".to_string(),
+                    },
+                    if offline_copy.is_some() {
+                        PopupCommand::Redownload
+                    } else {
+                        PopupCommand::None
+                    },
+                    if offline_copy.is_some() {
+                        Style::default()
+                    } else {
+                        Style::default().fg(style::Color::DarkGray)
+                    },
+                    true,
+                ),
                 PopupAction::new(
                     if *transcoding {
                         "Copy URL to clipboard (transcoded)".to_string()
@@ -1217,6 +2269,21 @@ impl crate::tui::App {
                 Some(PopupMenu::PlaylistCreate { name, .. }) => {
                     *name = self.popup.editing_new.clone();
                 }
+                Some(PopupMenu::GlobalSaveQueueAsPlaylist { name, .. }) => {
+                    *name = self.popup.editing_new.clone();
+                }
+                Some(PopupMenu::GlobalRadioSaveProfile { profile }) => {
+                    profile.name = self.popup.editing_new.clone();
+                }
+                Some(PopupMenu::GlobalAddServer { server, field }) => {
+                    *server.field_mut(*field) = self.popup.editing_new.clone();
+                }
+                Some(PopupMenu::GlobalExportQueue { path })
+                | Some(PopupMenu::GlobalExportDownloads { path })
+                | Some(PopupMenu::StatsExport { path })
+                | Some(PopupMenu::GlobalImportPlaylist { path }) => {
+                    *path = self.popup.editing_new.clone();
+                }
                 _ => {}
             }
             return;
@@ -1314,6 +2381,61 @@ impl crate::tui::App {
                     };
                     self.popup.current_menu = Some(PopupMenu::GlobalShuffle(updated));
                 }
+                if let Some(PopupMenu::GlobalSyncRules { recent_count, .. }) =
+                    &mut self.popup.current_menu
+                {
+                    // the add rows are hidden offline
+                    if self.client.is_some() && self.popup.selected.selected() == Some(1) {
+                        *recent_count = if *delta > 0 {
+                            *recent_count + 50
+                        } else {
+                            recent_count.saturating_sub(50).max(50)
+                        };
+                    }
+                }
+                if let Some(PopupMenu::QueueRoot { pause_after, .. }) = &mut self.popup.current_menu
+                {
+                    if self.popup.selected.selected() == Some(2) {
+                        *pause_after = if *delta > 0 {
+                            *pause_after + 1
+                        } else {
+                            pause_after.saturating_sub(1).max(1)
+                        };
+                    }
+                }
+                if let Some(PopupMenu::GlobalRadio { radio, .. }) = &mut self.popup.current_menu {
+                    let cur_yr = chrono::Utc::now().year() as u32;
+                    let up = *delta > 0;
+                    let bump = |val: Option<u32>, floor: u32, cap: u32| match val {
+                        None if up => Some(floor),
+                        None => None,
+                        Some(y) if up => Some((y + 1).min(cap)),
+                        Some(y) if y > floor => Some(y - 1),
+                        Some(_) => None,
+                    };
+                    match self.popup.selected.selected().unwrap_or(0) {
+                        1 if up => radio.tracks_per_refill = (radio.tracks_per_refill + 1).min(100),
+                        1 => {
+                            radio.tracks_per_refill =
+                                radio.tracks_per_refill.saturating_sub(1).max(1)
+                        }
+                        2 if up => radio.dedup_minutes += 15,
+                        2 => radio.dedup_minutes = (radio.dedup_minutes - 15).max(0),
+                        3 if up => radio.max_per_artist += 1,
+                        3 => radio.max_per_artist = radio.max_per_artist.saturating_sub(1),
+                        4 => {
+                            radio.year_from =
+                                bump(radio.year_from, 1900, radio.year_to.unwrap_or(cur_yr))
+                        }
+                        5 => {
+                            radio.year_to =
+                                bump(radio.year_to, radio.year_from.unwrap_or(1900), cur_yr)
+                        }
+                        _ => {}
+                    }
+                    self.preferences.radio = radio.clone();
+                    let _ = self.preferences.save();
+                }
                 if let Some(PopupMenu::GlobalSleepTimer { minutes, sleep_timer_enabled }) =
                     &self.popup.current_menu
                 {
@@ -1450,13 +2572,49 @@ impl crate::tui::App {
             return;
         }
 
+        if let PopupCommand::SetPinned { .. } | PopupCommand::Redownload = action {
+            let track_ids = match menu {
+                PopupMenu::TrackRoot { track, .. }
+                | PopupMenu::PlaylistTracksRoot { track, .. } => {
+                    vec![track.id.clone()]
+                }
+                PopupMenu::AlbumTrackRoot { track_id, .. } => vec![track_id.clone()],
+                _ => self
+                    .marked_tracks(self.state.last_section)
+                    .await
+                    .into_iter()
+                    .map(|t| t.id)
+                    .collect(),
+            };
+            self.close_popup();
+            self.clear_marks();
+            match action {
+                PopupCommand::SetPinned { pinned } => self.set_pinned(track_ids, pinned).await,
+                _ => self.redownload(track_ids).await,
+            }
+            return;
+        }
+
+        if let PopupMenu::MarkedRoot { .. }
+        | PopupMenu::MarkedAddToPlaylist { .. }
+        | PopupMenu::MarkedPlaylistRemove { .. } = menu
+        {
+            self.apply_marked_popup_action(&action, menu.clone()).await;
+            return;
+        }
+
+        if let PopupMenu::QueueRoot { .. } = menu {
+            self.apply_queue_action(&action, menu.clone());
+            return;
+        }
+
         match self.state.active_tab {
             ActiveTab::Library => match self.state.last_section {
                 ActiveSection::Tracks => {
                     self.apply_track_action(&action, menu.clone()).await;
                 }
                 ActiveSection::List => {
-                    self.apply_artist_action(&action, menu.clone());
+                    self.apply_artist_action(&action, menu.clone()).await;
                 }
                 _ => {}
             },
@@ -1490,7 +2648,11 @@ impl crate::tui::App {
         match menu {
             PopupMenu::GlobalRoot { downloading, sleep_timer_enabled, .. } => match action {
                 PopupCommand::Refresh => {
-                    let _ = self.db.cmd_tx.send(Command::Update(UpdateCommand::Library)).await;
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::Library { full: true }))
+                        .await;
                     self.close_popup();
                 }
                 PopupCommand::ChangeCoverArtLayout => {
@@ -1543,8 +2705,11 @@ impl crate::tui::App {
                     self.popup.selected.select_first();
                 }
                 PopupCommand::OfflineRepair => {
-                    if let Ok(_) =
-                        self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await
+                    if let Ok(_) = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::OfflineRepair { requested: true }))
+                        .await
                     {
                         self.db_updating = true;
                         self.close_popup();
@@ -1556,6 +2721,13 @@ impl crate::tui::App {
                         );
                     }
                 }
+                PopupCommand::Maintenance => {
+                    let _ = self.db.cmd_tx.send(Command::Maintenance).await;
+                    self.set_generic_message(
+                        "Running maintenance",
+                        "This runs in the background, you will see the numbers when it finishes.",
+                    );
+                }
                 PopupCommand::CancelDownloads => {
                     if !downloading {
                         return None;
@@ -1568,6 +2740,47 @@ impl crate::tui::App {
                         ),
                     }
                 }
+                PopupCommand::RedownloadMismatched => {
+                    self.close_popup();
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Download(DownloadCommand::RedownloadMismatched))
+                        .await;
+                }
+                PopupCommand::OutboxConflicts => {
+                    self.popup.current_menu = Some(PopupMenu::GlobalOutboxConflicts {
+                        conflicts: self.outbox_conflicts.clone(),
+                    });
+                    self.popup.selected.select_first();
+                }
+                PopupCommand::Tombstones => {
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalTombstones { tombstones: self.tombstones.clone() });
+                    self.popup.selected.select_first();
+                }
+                PopupCommand::SyncRules => {
+                    let recent_count = self
+                        .sync_rules
+                        .iter()
+                        .find_map(|r| match r.kind {
+                            SyncRuleKind::RecentlyAdded { count } => Some(count),
+                            _ => None,
+                        })
+                        .unwrap_or(200);
+                    self.popup.current_menu = Some(PopupMenu::GlobalSyncRules {
+                        rules: self.sync_rules.clone(),
+                        recent_count,
+                    });
+                    self.popup.selected.select_first();
+                }
+                PopupCommand::RadioSettings => {
+                    self.popup.current_menu = Some(PopupMenu::GlobalRadio {
+                        radio: self.preferences.radio.clone(),
+                        profiles: self.preferences.radio_profiles.clone(),
+                    });
+                    self.popup.selected.select_first();
+                }
                 PopupCommand::SleepTimer => {
                     self.popup.current_menu = Some(PopupMenu::GlobalSleepTimer {
                         minutes: self.preferences.preferred_sleep_timer_minutes,
@@ -1575,8 +2788,274 @@ impl crate::tui::App {
                     });
                     self.popup.selected.select_first();
                 }
+                PopupCommand::ExportQueue => {
+                    let path = crate::queue_io::default_export_path();
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalExportQueue { path: path.clone() });
+                    self.popup.editing_original = path.clone();
+                    self.popup.editing_new = path;
+                    self.popup.selected.select_first();
+                    self.popup.editing = true;
+                }
+                PopupCommand::ExportDownloads => {
+                    let path = ExportSettings::from_config(&self.config).path;
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalExportDownloads { path: path.clone() });
+                    self.popup.editing_original = path.clone();
+                    self.popup.editing_new = path;
+                    self.popup.selected.select_first();
+                    self.popup.editing = true;
+                }
+                PopupCommand::ImportPlaylist => {
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalImportPlaylist { path: String::new() });
+                    self.popup.editing_original = String::new();
+                    self.popup.editing_new = String::new();
+                    self.popup.selected.select_first();
+                    self.popup.editing = true;
+                }
+                PopupCommand::SwitchServer => {
+                    let config = match crate::config::get_config() {
+                        Ok((_, config)) => config,
+                        Err(e) => {
+                            self.set_generic_message("Failed to load config", &e.to_string());
+                            return None;
+                        }
+                    };
+                    self.popup.current_menu = Some(PopupMenu::GlobalSwitchServer {
+                        servers: server_choices(&config, self.client.as_deref()),
+                    });
+                    self.popup.selected.select_first();
+                }
+                PopupCommand::SaveQueueAsPlaylist => {
+                    self.popup.current_menu = Some(PopupMenu::GlobalSaveQueueAsPlaylist {
+                        name: String::new(),
+                        public: false,
+                    });
+                    self.popup.editing_original = String::new();
+                    self.popup.editing_new = String::new();
+                    self.popup.selected.select_first();
+                    self.popup.editing = true;
+                }
                 _ => {}
             },
+            PopupMenu::GlobalExportQueue { path } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing = true;
+                }
+                PopupCommand::Confirm => match self.export_queue(&path) {
+                    Ok(n) => {
+                        self.set_generic_message(
+                            "Queue exported",
+                            &format!("{} tracks written to {}.", n, path),
+                        );
+                    }
+                    Err(e) => {
+                        log::error!("Failed to export queue: {}", e);
+                        self.set_generic_message("Error exporting queue", &e.to_string());
+                    }
+                },
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::StatsExport { path } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing = true;
+                }
+                PopupCommand::Confirm => {
+                    let Some(report) = self.stats.as_ref() else {
+                        self.close_popup();
+                        return Some(());
+                    };
+                    let target = crate::queue_io::expand_path(&path);
+                    match export_stats(&self.db.pool, report, &target).await {
+                        Ok(plays) => {
+                            self.set_generic_message(
+                                "Stats exported",
+                                &format!(
+                                    "{} ({} plays) written to {}.",
+                                    report.period.label(),
+                                    plays,
+                                    path
+                                ),
+                            );
+                        }
+                        Err(e) => {
+                            log::error!("Failed to export stats: {}", e);
+                            self.set_generic_message("Error exporting stats", &e.to_string());
+                        }
+                    }
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalExportDownloads { path } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing = true;
+                }
+                PopupCommand::Confirm => {
+                    let settings =
+                        ExportSettings { path, ..ExportSettings::from_config(&self.config) };
+                    let _ = self.db.cmd_tx.send(Command::ExportDownloads { settings }).await;
+                    self.set_generic_message(
+                        "Exporting downloads",
+                        "This runs in the background, you will see a summary when it finishes.",
+                    );
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalImportPlaylist { path } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing = true;
+                }
+                PopupCommand::Play | PopupCommand::Append => {
+                    if path.trim().is_empty() {
+                        self.popup.editing = true;
+                        self.popup.selected.select_first();
+                        return None;
+                    }
+                    let (tracks, unmatched) = match self.import_playlist_file(&path).await {
+                        Ok(result) => result,
+                        Err(e) => {
+                            log::error!("Failed to import playlist file {}: {}", path, e);
+                            self.set_generic_message("Error importing playlist", &e.to_string());
+                            return None;
+                        }
+                    };
+                    if tracks.is_empty() {
+                        self.set_generic_message(
+                            "Nothing to import",
+                            &format!("None of the {} entries matched your library.", unmatched),
+                        );
+                        return None;
+                    }
+                    if let PopupCommand::Play = action {
+                        self.initiate_main_queue(&tracks, 0).await;
+                    } else {
+                        self.append_to_main_queue(&tracks, 0).await;
+                    }
+                    if unmatched > 0 {
+                        self.set_generic_message(
+                            "Playlist imported",
+                            &format!(
+                                "{} tracks imported, {} entries could not be matched.",
+                                tracks.len(),
+                                unmatched
+                            ),
+                        );
+                    } else {
+                        self.close_popup();
+                    }
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalSaveQueueAsPlaylist { name, mut public } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing = true;
+                }
+                PopupCommand::Toggle => {
+                    public = !public;
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalSaveQueueAsPlaylist { name: name.clone(), public });
+                }
+                PopupCommand::Create => {
+                    if name.trim().is_empty() {
+                        self.popup.editing = true;
+                        self.popup.selected.select_first();
+                        return None;
+                    }
+                    match self.save_queue_as_playlist(&name, public).await {
+                        Ok(_) => {
+                            let _ = self
+                                .db
+                                .cmd_tx
+                                .send(Command::Update(UpdateCommand::Library { full: false }))
+                                .await;
+                            self.set_generic_message(
+                                "Playlist created",
+                                &format!(
+                                    "Playlist {} created with {} tracks.",
+                                    name,
+                                    self.state.queue.len()
+                                ),
+                            );
+                        }
+                        Err(e) => {
+                            log::error!("Failed to save queue as playlist: {}", e);
+                            self.set_generic_message(
+                                "Error creating playlist",
+                                &format!("Failed to create playlist {}.", name),
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalSwitchServer { servers } => match action {
+                PopupCommand::UseServer { name } => {
+                    if servers.iter().any(|s| s.current && s.name == *name) {
+                        self.close_popup();
+                        return None;
+                    }
+                    let config = crate::config::get_config().map(|(_, c)| c).unwrap_or_default();
+                    match crate::config::find_server(&config, name) {
+                        Ok(server) => {
+                            self.close_popup();
+                            self.switch_server(ServerSwitch { server, add_as: None }).await;
+                        }
+                        Err(e) => self.set_generic_message("Cannot switch server", &e),
+                    }
+                }
+                PopupCommand::AddServer => {
+                    self.popup.current_menu = Some(PopupMenu::GlobalAddServer {
+                        server: NewServer { url: "https://".to_string(), ..Default::default() },
+                        field: ServerField::Name,
+                    });
+                    self.popup.editing_original = String::new();
+                    self.popup.editing_new = String::new();
+                    self.popup.selected.select_first();
+                    self.popup.editing = true;
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalAddServer { mut server, .. } => match action {
+                PopupCommand::EditServerField { field } => {
+                    let value = server.field_mut(*field).clone();
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalAddServer { server, field: *field });
+                    self.popup.editing_original = value.clone();
+                    self.popup.editing_new = value;
+                    self.popup.editing = true;
+                }
+                PopupCommand::Toggle => {
+                    server.quick_connect = !server.quick_connect;
+                    self.popup.current_menu =
+                        Some(PopupMenu::GlobalAddServer { server, field: ServerField::Name });
+                }
+                PopupCommand::Create => {
+                    let config = crate::config::get_config().map(|(_, c)| c).unwrap_or_default();
+                    match server.validate(&config) {
+                        Ok((name, server)) => {
+                            self.close_popup();
+                            self.switch_server(ServerSwitch { server, add_as: Some(name) }).await;
+                        }
+                        Err(e) => self.set_generic_message("Cannot add server", &e),
+                    }
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
             PopupMenu::GlobalSleepTimer { minutes, .. } => match action {
                 PopupCommand::None => {
                     self.popup.selected.select_next();
@@ -1769,15 +3248,177 @@ impl crate::tui::App {
                         };
                         self.initiate_main_queue(&tracks, 0).await;
                         self.close_popup();
-                        self.preferences.preferred_global_shuffle =
-                            Some(PopupMenu::GlobalShuffle(s.clone()));
-                        let _ = self.preferences.save();
+                        self.preferences.preferred_global_shuffle =
+                            Some(PopupMenu::GlobalShuffle(s.clone()));
+                        let _ = self.preferences.save();
+                    }
+                    _ => {
+                        self.close_popup();
+                    }
+                }
+            }
+            PopupMenu::GlobalRadio { mut radio, profiles } => {
+                match action {
+                    PopupCommand::None => {
+                        self.popup.selected.select_next();
+                        return Some(());
+                    }
+                    PopupCommand::RadioSeedQueue => radio.seed = RadioSeed::Queue,
+                    PopupCommand::OnlyFavorite => radio.only_favorite = !radio.only_favorite,
+                    PopupCommand::Dislike => radio.exclude_disliked = !radio.exclude_disliked,
+                    PopupCommand::StartRadio => {
+                        self.preferences.radio = radio.clone();
+                        self.close_popup();
+                        self.start_radio(radio.seed).await;
+                        return Some(());
+                    }
+                    PopupCommand::Create => {
+                        self.popup.editing_original = radio.name.clone();
+                        self.popup.editing_new = radio.name.clone();
+                        self.popup.current_menu =
+                            Some(PopupMenu::GlobalRadioSaveProfile { profile: radio });
+                        self.popup.selected.select_first();
+                        self.popup.editing = true;
+                        return Some(());
+                    }
+                    PopupCommand::LoadRadioProfile { index } => {
+                        radio = profiles.get(*index)?.clone();
+                    }
+                    PopupCommand::Delete => {
+                        self.popup.current_menu =
+                            Some(PopupMenu::GlobalRadioDeleteProfile { profiles });
+                        self.popup.selected.select_first();
+                        return Some(());
+                    }
+                    _ => {
+                        self.close_popup();
+                        return Some(());
+                    }
+                }
+                self.preferences.radio = radio.clone();
+                let _ = self.preferences.save();
+                self.popup.current_menu = Some(PopupMenu::GlobalRadio { radio, profiles });
+            }
+            PopupMenu::GlobalRadioSaveProfile { profile } => match action {
+                PopupCommand::Type => {
+                    self.popup.editing_original = profile.name.clone();
+                    self.popup.editing_new = profile.name.clone();
+                    self.popup.editing = true;
+                }
+                PopupCommand::Confirm => {
+                    if profile.name.trim().is_empty() {
+                        self.set_generic_message("Radio", "The profile needs a name.");
+                        return Some(());
+                    }
+                    // saving under an existing name overwrites that profile
+                    let profiles = &mut self.preferences.radio_profiles;
+                    match profiles.iter_mut().find(|p| p.name == profile.name) {
+                        Some(existing) => *existing = profile.clone(),
+                        None => profiles.push(profile.clone()),
+                    }
+                    self.preferences.radio = profile.clone();
+                    let _ = self.preferences.save();
+                    self.popup.current_menu = Some(PopupMenu::GlobalRadio {
+                        radio: profile,
+                        profiles: self.preferences.radio_profiles.clone(),
+                    });
+                    self.popup.selected.select_first();
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalSyncRules { .. } | PopupMenu::GlobalSyncRulePlaylist { .. } => {
+                match action {
+                    PopupCommand::AddSyncRule { kind } => {
+                        self.close_popup();
+                        self.add_sync_rule(kind.clone()).await;
+                    }
+                    PopupCommand::RemoveSyncRule { id } => {
+                        self.close_popup();
+                        self.remove_sync_rule(*id).await;
+                    }
+                    PopupCommand::PickSyncPlaylist => {
+                        self.popup.current_menu = Some(PopupMenu::GlobalSyncRulePlaylist {
+                            playlists: self.playlists.clone(),
+                        });
+                        self.popup.selected.select_first();
                     }
                     _ => {
                         self.close_popup();
                     }
                 }
             }
+            PopupMenu::GlobalTombstones { tombstones } => match action {
+                PopupCommand::OpenTombstone { index } => {
+                    let tombstone = tombstones.get(*index)?.clone();
+                    self.popup.current_menu = Some(PopupMenu::GlobalTombstone { tombstone });
+                    self.popup.selected.select_first();
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalTombstone { tombstone } => {
+                let id = tombstone.id.clone();
+                let cmd = match action {
+                    PopupCommand::KeepLocal => TombstoneCommand::Keep { id },
+                    PopupCommand::Rematch => TombstoneCommand::Rematch { id },
+                    PopupCommand::Delete => TombstoneCommand::Delete { id },
+                    _ => {
+                        self.close_popup();
+                        return Some(());
+                    }
+                };
+                let _ = self.db.cmd_tx.send(Command::Tombstone(cmd)).await;
+                self.set_generic_message("Removed from server", "Working on it…");
+            }
+            PopupMenu::GlobalOutboxConflicts { .. } => match action {
+                PopupCommand::DiscardChanges { id } => {
+                    let id = *id;
+                    let _ =
+                        self.db.cmd_tx.send(Command::Outbox(OutboxCommand::Discard { id })).await;
+                    self.outbox_conflicts.retain(|c| id.is_some_and(|id| c.id != id));
+                    if self.outbox_conflicts.is_empty() {
+                        self.close_popup();
+                    } else {
+                        self.popup.current_menu = Some(PopupMenu::GlobalOutboxConflicts {
+                            conflicts: self.outbox_conflicts.clone(),
+                        });
+                        self.popup.selected.select_first();
+                    }
+                }
+                PopupCommand::ForceChanges { id } => {
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Outbox(OutboxCommand::Force { id: *id }))
+                        .await;
+                    self.outbox_conflicts.clear();
+                    self.set_generic_message(
+                        "Applying changes",
+                        "The changes are sent to the server as soon as it is reachable.",
+                    );
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
+            PopupMenu::GlobalRadioDeleteProfile { profiles } => match action {
+                PopupCommand::DeleteRadioProfile { index } => {
+                    let name = &profiles.get(*index)?.name;
+                    self.preferences.radio_profiles.retain(|p| &p.name != name);
+                    let _ = self.preferences.save();
+                    self.popup.current_menu = Some(PopupMenu::GlobalRadio {
+                        radio: self.preferences.radio.clone(),
+                        profiles: self.preferences.radio_profiles.clone(),
+                    });
+                    self.popup.selected.select_first();
+                }
+                _ => {
+                    self.close_popup();
+                }
+            },
             PopupMenu::GlobalPickTheme { .. } => match action {
                 PopupCommand::SetTheme { theme } => {
                     self.preferences.theme = theme.name.clone();
@@ -1817,6 +3458,11 @@ impl crate::tui::App {
     async fn apply_track_action(&mut self, action: &PopupCommand, menu: PopupMenu) -> Option<()> {
         match menu {
             PopupMenu::TrackRoot { track, .. } => match action {
+                PopupCommand::StartRadio => {
+                    let name = track.genres.first()?.clone();
+                    self.close_popup();
+                    self.start_radio(RadioSeed::Genre { name }).await;
+                }
                 PopupCommand::AddToPlaylist { .. } => {
                     self.popup.current_menu = Some(PopupMenu::TrackAddToPlaylist {
                         track_name: track.name,
@@ -1984,9 +3630,13 @@ impl crate::tui::App {
             PopupMenu::TrackAddToPlaylist { track_name, track_id, playlists } => match action {
                 PopupCommand::AddToPlaylist { playlist_id } => {
                     let playlist = playlists.iter().find(|p| p.id == *playlist_id)?;
-                    if let Err(_) =
-                        self.client.as_ref()?.add_to_playlist(&track_id, playlist_id).await
-                    {
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistAdd {
+                            playlist_id: playlist_id.clone(),
+                            track_ids: vec![track_id.clone()],
+                        })
+                        .await;
+                    if outcome == ChangeOutcome::Failed {
                         self.set_generic_message(
                             "Error adding track",
                             &format!(
@@ -1994,6 +3644,7 @@ impl crate::tui::App {
                                 track_name, playlist.name
                             ),
                         );
+                        return Some(());
                     }
                     self.playlists
                         .iter_mut()
@@ -2003,8 +3654,10 @@ impl crate::tui::App {
                     self.set_generic_message(
                         "Track added",
                         &format!(
-                            "Track {} successfully added to playlist {}.",
-                            track_name, playlist.name
+                            "Track {} added to playlist {}.{}",
+                            track_name,
+                            playlist.name,
+                            outcome.note()
                         ),
                     );
                 }
@@ -2053,8 +3706,26 @@ impl crate::tui::App {
 
     async fn apply_album_action(&mut self, action: &PopupCommand, menu: PopupMenu) -> Option<()> {
         match menu {
-            PopupMenu::AlbumsRoot { album } => {
+            PopupMenu::AlbumsRoot { album, pin, .. } => {
                 match action {
+                    PopupCommand::TogglePin => {
+                        self.close_popup();
+                        match pin {
+                            Some(id) => self.remove_sync_rule(id).await,
+                            None => {
+                                self.add_sync_rule(SyncRuleKind::Pin {
+                                    kind: PinKind::Album,
+                                    id: album.id,
+                                    name: album.name,
+                                })
+                                .await
+                            }
+                        }
+                    }
+                    PopupCommand::StartRadio => {
+                        self.close_popup();
+                        self.start_radio(RadioSeed::Album { id: album.id, name: album.name }).await;
+                    }
                     PopupCommand::JumpToCurrent => {
                         let current_track = self
                             .state
@@ -2094,7 +3765,7 @@ impl crate::tui::App {
                             Arc::clone(&self.db.pool),
                             parent.clone(),
                             self.db.status_tx.clone(),
-                            self.client.clone().unwrap(), /* this fn is online guarded */
+                            self.servers.clone(), /* this fn is online guarded */
                         )
                         .await
                         {
@@ -2329,9 +4000,13 @@ impl crate::tui::App {
             PopupMenu::TrackAddToPlaylist { track_name, track_id, playlists } => match action {
                 PopupCommand::AddToPlaylist { playlist_id } => {
                     let playlist = playlists.iter().find(|p| p.id == *playlist_id)?;
-                    if let Err(_) =
-                        self.client.as_ref()?.add_to_playlist(&track_id, playlist_id).await
-                    {
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistAdd {
+                            playlist_id: playlist_id.clone(),
+                            track_ids: vec![track_id.clone()],
+                        })
+                        .await;
+                    if outcome == ChangeOutcome::Failed {
                         self.set_generic_message(
                             "Error adding track",
                             &format!(
@@ -2339,6 +4014,7 @@ impl crate::tui::App {
                                 track_name, playlist.name
                             ),
                         );
+                        return Some(());
                     }
                     self.playlists
                         .iter_mut()
@@ -2348,8 +4024,10 @@ impl crate::tui::App {
                     self.set_generic_message(
                         "Track added",
                         &format!(
-                            "Track {} successfully added to playlist {}.",
-                            track_name, playlist.name
+                            "Track {} added to playlist {}.{}",
+                            track_name,
+                            playlist.name,
+                            outcome.note()
                         ),
                     );
                 }
@@ -2441,9 +4119,13 @@ impl crate::tui::App {
             PopupMenu::PlaylistTrackAddToPlaylist { track_name, track_id, playlists } => {
                 if let PopupCommand::AddToPlaylist { playlist_id } = action {
                     let playlist = playlists.iter().find(|p| p.id == *playlist_id)?;
-                    if let Err(_) =
-                        self.client.as_ref()?.add_to_playlist(&track_id, playlist_id).await
-                    {
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistAdd {
+                            playlist_id: playlist_id.clone(),
+                            track_ids: vec![track_id.clone()],
+                        })
+                        .await;
+                    if outcome == ChangeOutcome::Failed {
                         self.set_generic_message(
                             "Error adding track",
                             &format!(
@@ -2451,6 +4133,7 @@ impl crate::tui::App {
                                 track_name, playlist.name
                             ),
                         );
+                        return Some(());
                     }
                     self.playlists
                         .iter_mut()
@@ -2460,8 +4143,10 @@ impl crate::tui::App {
                     self.set_generic_message(
                         "Track added",
                         &format!(
-                            "Track {} successfully added to playlist {}.",
-                            track_name, playlist.name
+                            "Track {} added to playlist {}.{}",
+                            track_name,
+                            playlist.name,
+                            outcome.note()
                         ),
                     );
                 } else {
@@ -2478,17 +4163,20 @@ impl crate::tui::App {
                     self.popup.selected.select_next();
                 }
                 PopupCommand::Yes => {
-                    if self
-                        .client
-                        .as_ref()?
-                        .remove_from_playlist(&track_id, &playlist_id)
-                        .await
-                        .is_ok()
-                    {
-                        self.playlist_tracks.retain(|t| t.playlist_item_id != track_id);
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistRemove {
+                            playlist_id: playlist_id.clone(),
+                            track_ids: vec![track_id.clone()],
+                        })
+                        .await;
+                    if outcome != ChangeOutcome::Failed {
+                        self.playlist_tracks.retain(|t| t.id != track_id);
+                        if let Some(p) = self.playlists.iter_mut().find(|p| p.id == playlist_id) {
+                            p.child_count = p.child_count.saturating_sub(1);
+                        }
                         self.set_generic_message(
                             &format!("{} removed", track_name),
-                            &format!("Successfully removed from {}.", playlist_name),
+                            &format!("Removed from {}.{}", playlist_name, outcome.note()),
                         );
                     } else {
                         self.set_generic_message(
@@ -2509,6 +4197,151 @@ impl crate::tui::App {
         Some(())
     }
 
+    fn apply_queue_action(&mut self, action: &PopupCommand, menu: PopupMenu) {
+        let PopupMenu::QueueRoot { track_id, album_id, pause_after, .. } = menu else {
+            return;
+        };
+        self.state.stop_marker = match action {
+            PopupCommand::StopAfterTrack => Some(StopMarker::Track { id: track_id }),
+            PopupCommand::StopAfterAlbum => Some(StopMarker::Album { album_id }),
+            PopupCommand::StopAfterTracks => Some(StopMarker::Tracks { remaining: pause_after }),
+            PopupCommand::ClearStopMarker => None,
+            _ => return,
+        };
+        self.close_popup();
+    }
+
+    /// The row commands go through the same path as their keybindings, once the popup is closed
+    /// the marks belong to the active section again
+    ///
+    async fn apply_marked_popup_action(
+        &mut self,
+        action: &PopupCommand,
+        menu: PopupMenu,
+    ) -> Option<()> {
+        match menu {
+            PopupMenu::MarkedRoot { in_playlist, .. } => {
+                let bulk = match action {
+                    PopupCommand::Append => Some(Action::QueueAppend),
+                    PopupCommand::AppendTemporary => Some(Action::QueueTempBack),
+                    PopupCommand::Download => Some(Action::Download),
+                    PopupCommand::RemoveDownload => Some(Action::RemoveDownload),
+                    PopupCommand::Toggle => Some(Action::ToggleFavorite),
+                    PopupCommand::Delete if !in_playlist => Some(Action::Delete),
+                    _ => None,
+                };
+                if let Some(bulk) = bulk {
+                    self.close_popup();
+                    self.apply_marked_action(&bulk).await;
+                    return Some(());
+                }
+                match action {
+                    PopupCommand::AddToPlaylist { .. } => {
+                        let tracks = self.marked_tracks(self.state.last_section).await;
+                        self.popup.current_menu = Some(PopupMenu::MarkedAddToPlaylist {
+                            track_ids: tracks.into_iter().map(|t| t.id).collect(),
+                            playlists: self.playlists.clone(),
+                        });
+                        self.popup.selected.select_first();
+                    }
+                    PopupCommand::Delete => {
+                        let ids = self.marked_ids(self.state.last_section);
+                        self.popup.current_menu = Some(PopupMenu::MarkedPlaylistRemove {
+                            track_ids: self
+                                .playlist_tracks
+                                .iter()
+                                .filter(|t| ids.contains(&t.id))
+                                .map(|t| t.id.clone())
+                                .collect(),
+                            playlist_name: self.state.current_playlist.name.clone(),
+                            playlist_id: self.state.current_playlist.id.clone(),
+                        });
+                        self.popup.selected.select(Some(1));
+                    }
+                    PopupCommand::Cancel => {
+                        self.clear_marks();
+                        self.close_popup();
+                    }
+                    _ => {}
+                }
+            }
+            PopupMenu::MarkedAddToPlaylist { track_ids, playlists } => {
+                if let PopupCommand::AddToPlaylist { playlist_id } = action {
+                    let playlist = playlists.iter().find(|p| p.id == *playlist_id)?;
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistAdd {
+                            playlist_id: playlist_id.clone(),
+                            track_ids: track_ids.clone(),
+                        })
+                        .await;
+                    if outcome == ChangeOutcome::Failed {
+                        self.set_generic_message(
+                            "Error adding tracks",
+                            &format!("Failed to add tracks to playlist {}.", playlist.name),
+                        );
+                        return Some(());
+                    }
+                    if let Some(p) = self.playlists.iter_mut().find(|p| p.id == playlist.id) {
+                        p.child_count += track_ids.len() as u64;
+                    }
+                    self.clear_marks();
+                    self.set_generic_message(
+                        "Tracks added",
+                        &format!(
+                            "{} tracks added to playlist {}.{}",
+                            track_ids.len(),
+                            playlist.name,
+                            outcome.note()
+                        ),
+                    );
+                } else {
+                    self.close_popup();
+                }
+            }
+            PopupMenu::MarkedPlaylistRemove { track_ids, playlist_name, playlist_id } => {
+                match action {
+                    PopupCommand::None => {
+                        self.popup.selected.select_next();
+                    }
+                    PopupCommand::Yes => {
+                        let outcome = self
+                            .send_playlist_change(Mutation::PlaylistRemove {
+                                playlist_id: playlist_id.clone(),
+                                track_ids: track_ids.clone(),
+                            })
+                            .await;
+                        if outcome != ChangeOutcome::Failed {
+                            self.playlist_tracks.retain(|t| !track_ids.contains(&t.id));
+                            if let Some(p) = self.playlists.iter_mut().find(|p| p.id == playlist_id)
+                            {
+                                p.child_count =
+                                    p.child_count.saturating_sub(track_ids.len() as u64);
+                            }
+                            self.clear_marks();
+                            self.set_generic_message(
+                                &format!("{} tracks removed", track_ids.len()),
+                                &format!("Removed from {}.{}", playlist_name, outcome.note()),
+                            );
+                        } else {
+                            self.set_generic_message(
+                                "Error removing tracks",
+                                &format!(
+                                    "Failed to remove tracks from playlist {}.",
+                                    playlist_name
+                                ),
+                            );
+                        }
+                    }
+                    _ => {
+                        self.close_popup();
+                    }
+                }
+            }
+            _ => {}
+        }
+        Some(())
+    }
+
     async fn apply_playlist_action(
         &mut self,
         action: &PopupCommand,
@@ -2518,8 +4351,55 @@ impl crate::tui::App {
         let mut selected_playlist = self.playlists.iter().find(|p| p.id == id)?.clone();
 
         match menu {
-            PopupMenu::PlaylistRoot { .. } => {
+            PopupMenu::PlaylistRoot { sync_rule, pin, .. } => {
                 match action {
+                    PopupCommand::TogglePin => {
+                        self.close_popup();
+                        match pin {
+                            Some(id) => self.remove_sync_rule(id).await,
+                            None => {
+                                self.add_sync_rule(SyncRuleKind::Pin {
+                                    kind: PinKind::Playlist,
+                                    id: selected_playlist.id.clone(),
+                                    name: selected_playlist.name.clone(),
+                                })
+                                .await
+                            }
+                        }
+                    }
+                    PopupCommand::Changelog => {
+                        if let Err(e) = mark_seen(&self.db.pool, &selected_playlist.id).await {
+                            log::error!("Failed to mark playlist changes as seen: {}", e);
+                        }
+                        self.reload_playlist_activity().await;
+                        let (changes, seen) = get_changelog(&self.db.pool, &selected_playlist.id)
+                            .await
+                            .unwrap_or_default();
+                        self.popup.current_menu =
+                            Some(PopupMenu::PlaylistChangelog { changes, seen });
+                        self.popup.selected.select_first();
+                    }
+                    PopupCommand::ToggleSyncRule => {
+                        self.close_popup();
+                        match sync_rule {
+                            Some(id) => self.remove_sync_rule(id).await,
+                            None => {
+                                self.add_sync_rule(SyncRuleKind::Playlist {
+                                    id: selected_playlist.id.clone(),
+                                    name: selected_playlist.name.clone(),
+                                })
+                                .await
+                            }
+                        }
+                    }
+                    PopupCommand::StartRadio => {
+                        self.close_popup();
+                        self.start_radio(RadioSeed::Playlist {
+                            id: selected_playlist.id.clone(),
+                            name: selected_playlist.name.clone(),
+                        })
+                        .await;
+                    }
                     PopupCommand::Play => {
                         self.open_playlist(None).await;
                         self.initiate_main_queue(&self.playlist_tracks.clone(), 0).await;
@@ -2636,8 +4516,17 @@ impl crate::tui::App {
                     self.popup.selected.select(Some(1));
                 }
                 PopupCommand::Cancel => {
-                    self.popup.current_menu =
-                        Some(PopupMenu::PlaylistRoot { playlist_name: playlist_name.clone() });
+                    self.popup.current_menu = Some(PopupMenu::PlaylistRoot {
+                        playlist_name: playlist_name.clone(),
+                        sync_rule: self.playlist_sync_rule(&selected_playlist.id),
+                        pin: self.pin_rule(&selected_playlist.id),
+                        pinnable: self.can_pin(&selected_playlist.server_id),
+                        activity: self
+                            .playlist_activity
+                            .get(&selected_playlist.id)
+                            .copied()
+                            .unwrap_or_default(),
+                    });
                     self.popup.selected.select(Some(3));
                 }
                 _ => {}
@@ -2654,7 +4543,14 @@ impl crate::tui::App {
                     self.original_playlists.iter_mut().find(|p| p.id == id)?.name =
                         new_name.clone();
 
-                    if self.client.as_ref()?.update_playlist(&selected_playlist).await.is_ok() {
+                    let outcome = self
+                        .send_playlist_change(Mutation::PlaylistRename {
+                            playlist_id: id.clone(),
+                            old_name: old_name.clone(),
+                            new_name: new_name.clone(),
+                        })
+                        .await;
+                    if outcome != ChangeOutcome::Failed {
                         let _ = self
                             .db
                             .cmd_tx
@@ -2666,7 +4562,7 @@ impl crate::tui::App {
                         self.reorder_lists();
                         self.set_generic_message(
                             "Playlist renamed",
-                            &format!("Playlist successfully renamed to {}.", new_name),
+                            &format!("Playlist renamed to {}.{}", new_name, outcome.note()),
                         );
                     } else {
                         self.set_generic_message(
@@ -2740,7 +4636,11 @@ impl crate::tui::App {
                         return None;
                     }
                     if let Ok(id) = self.client.as_ref()?.create_playlist(&name, public).await {
-                        let _ = self.db.cmd_tx.send(Command::Update(UpdateCommand::Library)).await;
+                        let _ = self
+                            .db
+                            .cmd_tx
+                            .send(Command::Update(UpdateCommand::Library { full: false }))
+                            .await;
 
                         let index = self.playlists.iter().position(|p| p.id == id).unwrap_or(0);
                         self.state.selected_playlist.select(Some(index));
@@ -2803,9 +4703,27 @@ impl crate::tui::App {
         Some(())
     }
 
-    fn apply_artist_action(&mut self, action: &PopupCommand, menu: PopupMenu) {
+    async fn apply_artist_action(&mut self, action: &PopupCommand, menu: PopupMenu) {
         match menu {
-            PopupMenu::ArtistRoot { .. } => match action {
+            PopupMenu::ArtistRoot { artist, pin, .. } => match action {
+                PopupCommand::TogglePin => {
+                    self.close_popup();
+                    match pin {
+                        Some(id) => self.remove_sync_rule(id).await,
+                        None => {
+                            self.add_sync_rule(SyncRuleKind::Pin {
+                                kind: PinKind::Artist,
+                                id: artist.id,
+                                name: artist.name,
+                            })
+                            .await
+                        }
+                    }
+                }
+                PopupCommand::StartRadio => {
+                    self.close_popup();
+                    self.start_radio(RadioSeed::Artist { id: artist.id, name: artist.name }).await;
+                }
                 PopupCommand::JumpToCurrent => {
                     let artists =
                         match self.state.queue.get(self.state.current_playback_state.current_index)
@@ -2900,7 +4818,8 @@ impl crate::tui::App {
     }
 
     fn copy_url(&mut self, track: &DiscographySong) -> Option<()> {
-        let url = self.client.as_ref()?.song_url_sync(&track.id, Some(&self.transcoding));
+        let client = self.servers.client_for(&track.server_id)?;
+        let url = client.song_url_sync(&track.id, Some(&self.transcoding));
         if let Err(e) = Clipboard::new().and_then(|mut c| c.set_text(url)) {
             log::error!("Failed to copy URL for track {}: {}", track.name, e);
             self.set_generic_message(
@@ -2983,6 +4902,18 @@ impl crate::tui::App {
         }
     }
 
+    /// The Stats tab has a single popup, exporting the report
+    ///
+    pub async fn open_stats_export(&mut self) {
+        let path = crate::database::stats::default_export_path();
+        self.request_popup(true).await;
+        self.popup.current_menu = Some(PopupMenu::StatsExport { path: path.clone() });
+        self.popup.editing_original = path.clone();
+        self.popup.editing_new = path;
+        self.popup.selected.select_first();
+        self.popup.editing = true;
+    }
+
     /// Create popup based on the current selected tab and section
     ///
     pub fn create_popup(&mut self, frame: &mut Frame) -> Option<()> {
@@ -2995,8 +4926,46 @@ impl crate::tui::App {
                 self.popup.current_menu = Some(PopupMenu::GlobalRoot {
                     large_art: self.preferences.large_art,
                     track_based_art: self.preferences.track_based_art,
-                    downloading: self.download_item.is_some(),
+                    downloading: !self.downloads.is_empty(),
                     sleep_timer_enabled: self.sleep_timer.is_some(),
+                    offline_quality: self.download_settings.target_label(),
+                    sync_conflicts: self.outbox_conflicts.len(),
+                    removed_albums: self.tombstones.len(),
+                });
+                self.popup.selected.select_first();
+            }
+            self.render_popup(frame);
+            return Some(());
+        }
+
+        let marked = self.marked_count(self.state.last_section);
+        if marked > 0 {
+            if self.popup.current_menu.is_none() {
+                self.popup.current_menu = Some(PopupMenu::MarkedRoot {
+                    count: marked,
+                    in_playlist: self.state.active_tab == ActiveTab::Playlists
+                        && self.state.last_section == ActiveSection::Tracks,
+                    in_queue: self.state.last_section == ActiveSection::Queue,
+                });
+                self.popup.selected.select_first();
+            }
+            self.render_popup(frame);
+            return Some(());
+        }
+
+        if self.state.last_section == ActiveSection::Queue {
+            if self.popup.current_menu.is_none() {
+                let index = self.state.selected_queue_item.selected()?;
+                let song = self.state.queue.get(index)?;
+                // by default pause after the selected row
+                let current = self.state.current_playback_state.current_index;
+                self.popup.current_menu = Some(PopupMenu::QueueRoot {
+                    track_name: song.name.clone(),
+                    track_id: song.id.clone(),
+                    album_id: song.album_id.clone(),
+                    album_name: song.album.clone(),
+                    pause_after: (index + 1).saturating_sub(current).max(1),
+                    has_stop_marker: self.state.stop_marker.is_some(),
                 });
                 self.popup.selected.select_first();
             }
@@ -3011,6 +4980,8 @@ impl crate::tui::App {
                     let track = self.tracks.iter().find(|t| t.id == id)?.clone();
                     if self.popup.current_menu.is_none() {
                         self.popup.current_menu = Some(PopupMenu::TrackRoot {
+                            pinned: self.pinned_ids.contains(&track.id),
+                            offline_copy: self.offline_copy(&track),
                             track,
                             transcoding: self.transcoding.enabled,
                             now_playing_name: self
@@ -3027,6 +4998,8 @@ impl crate::tui::App {
                         let artists = self.get_id_of_selected(&self.artists, Selectable::Artist);
                         let artist = self.artists.iter().find(|a| a.id == artists)?.clone();
                         self.popup.current_menu = Some(PopupMenu::ArtistRoot {
+                            pin: self.pin_rule(&artist.id),
+                            pinnable: self.can_pin(&artist.server_id),
                             artist: artist.clone(),
                             playing_artists: self
                                 .state
@@ -3046,7 +5019,11 @@ impl crate::tui::App {
                     if self.popup.current_menu.is_none() {
                         let id = self.get_id_of_selected(&self.albums, Selectable::Album);
                         let album = self.albums.iter().find(|a| a.id == id)?.clone();
-                        self.popup.current_menu = Some(PopupMenu::AlbumsRoot { album });
+                        self.popup.current_menu = Some(PopupMenu::AlbumsRoot {
+                            pin: self.pin_rule(&album.id),
+                            pinnable: self.can_pin(&album.server_id),
+                            album,
+                        });
                         self.popup.selected.select_first();
                     }
                 }
@@ -3058,6 +5035,8 @@ impl crate::tui::App {
                             track_id: id.clone(),
                             track_name: track.name.clone(),
                             disliked: track.disliked,
+                            pinned: self.pinned_ids.contains(&track.id),
+                            offline_copy: self.offline_copy(track),
                             transcoding: self.transcoding.enabled,
                             now_playing_name: self
                                 .state
@@ -3077,8 +5056,17 @@ impl crate::tui::App {
                     if self.popup.current_menu.is_none() {
                         let id = self.get_id_of_selected(&self.playlists, Selectable::Playlist);
                         let playlist = self.playlists.iter().find(|p| p.id == id)?.clone();
-                        self.popup.current_menu =
-                            Some(PopupMenu::PlaylistRoot { playlist_name: playlist.name });
+                        self.popup.current_menu = Some(PopupMenu::PlaylistRoot {
+                            sync_rule: self.playlist_sync_rule(&playlist.id),
+                            pin: self.pin_rule(&playlist.id),
+                            pinnable: self.can_pin(&playlist.server_id),
+                            activity: self
+                                .playlist_activity
+                                .get(&playlist.id)
+                                .copied()
+                                .unwrap_or_default(),
+                            playlist_name: playlist.name,
+                        });
                         self.popup.selected.select_first();
                     }
                 }
@@ -3090,6 +5078,8 @@ impl crate::tui::App {
                         self.popup.current_menu = Some(PopupMenu::PlaylistTracksRoot {
                             track: track.clone(),
                             transcoding: self.transcoding.enabled,
+                            pinned: self.pinned_ids.contains(&track.id),
+                            offline_copy: self.offline_copy(track),
                         });
                         self.popup.selected.select_first();
                     }
@@ -1,9 +1,12 @@
 use crate::client::{Client, Transcoding};
 use crate::database::database::{Command, UpdateCommand};
-use crate::database::extension::get_recent_track_ids;
+use crate::database::extension::{
+    get_album_tracks, get_disliked_track_ids, get_playlist_tracks, get_recent_track_ids,
+    get_similar_downloaded_tracks, get_tracks_by_ids, SimilaritySeed,
+};
 use crate::keyboard::{search_ranked_refs, ActiveSection};
 use crate::mpv::LoadFileFlag;
-use crate::tui::RadioMode;
+use crate::tui::{RadioMode, RadioProfile, RadioSeed, Repeat};
 use crate::{
     client::DiscographySong,
     database::extension::DownloadStatus,
@@ -11,9 +14,9 @@ use crate::{
     tui::{App, Song},
 };
 use rand::seq::IndexedRandom;
-use rand::seq::IteratorRandom;
 use rand::seq::SliceRandom;
-use std::collections::HashMap;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
 /// This file has all the queue control functions
 /// the basic idea is keeping our queue in sync with mpv and doing some basic operations
 ///
@@ -50,7 +53,7 @@ fn make_track(
         album_artists: track.album_artists.clone(),
         album: track.album.clone(),
         album_id: track.album_id.clone(),
-        musicbrainz_album_id: track.musicbrainz_album_id.clone(),
+        musicbrainz_album_id: track.provider_ids.musicbrainz_album.clone(),
         // parent_id: track.parent_id.clone(),
         production_year: track.production_year,
         is_in_queue,
@@ -60,35 +63,195 @@ fn make_track(
         original_index: 0,
         run_time_ticks: track.run_time_ticks,
         disliked: track.disliked,
+        server_id: track.server_id.clone(),
+    }
+}
+
+/// What a radio batch is based on. Online, everything but a genre is a Jellyfin instant mix of the item
+///
+pub enum RadioSource {
+    Track(String),
+    Artist { id: String, name: String },
+    Album(String),
+    Playlist(String),
+    Genre(String),
+}
+
+impl RadioSource {
+    fn from_seed(seed: &RadioSeed) -> Option<Self> {
+        match seed {
+            RadioSeed::Queue => None,
+            RadioSeed::Artist { id, name } => {
+                Some(RadioSource::Artist { id: id.clone(), name: name.clone() })
+            }
+            RadioSeed::Album { id, .. } => Some(RadioSource::Album(id.clone())),
+            RadioSeed::Playlist { id, .. } => Some(RadioSource::Playlist(id.clone())),
+            RadioSeed::Genre { name } => Some(RadioSource::Genre(name.clone())),
+        }
+    }
+
+    /// Builds the seed for the offline recommender from whatever the cache knows about the source
+    ///
+    async fn similarity_seed(&self, pool: &SqlitePool) -> SimilaritySeed {
+        match self {
+            RadioSource::Track(id) => SimilaritySeed::from_tracks(
+                &get_tracks_by_ids(pool, &vec![id.clone()]).await.unwrap_or_default(),
+            ),
+            RadioSource::Artist { id, name } => SimilaritySeed {
+                artists: HashSet::from([name.clone()]),
+                album_artist_ids: HashSet::from([id.clone()]),
+                ..Default::default()
+            },
+            RadioSource::Album(id) => SimilaritySeed::from_tracks(
+                &get_album_tracks(pool, id, None).await.unwrap_or_default(),
+            ),
+            RadioSource::Playlist(id) => SimilaritySeed::from_tracks(
+                &get_playlist_tracks(pool, id, None).await.unwrap_or_default(),
+            ),
+            RadioSource::Genre(name) => {
+                SimilaritySeed { genres: HashSet::from([name.clone()]), ..Default::default() }
+            }
+        }
+    }
+}
+
+/// Decides which radio candidates make it into the queue, based on the active `RadioProfile`
+///
+pub struct RadioFilter {
+    profile: RadioProfile,
+    excluded: HashSet<String>,
+    per_artist: HashMap<String, usize>,
+}
+
+impl RadioFilter {
+    fn accepts(&self, song: &DiscographySong) -> bool {
+        if self.excluded.contains(&song.id) || (self.profile.exclude_disliked && song.disliked) {
+            return false;
+        }
+        if self.profile.only_favorite && !song.user_data.is_favorite {
+            return false;
+        }
+        if self.profile.year_from.is_some() || self.profile.year_to.is_some() {
+            let year = song.production_year as u32;
+            if year == 0
+                || year < self.profile.year_from.unwrap_or(0)
+                || year > self.profile.year_to.unwrap_or(u32::MAX)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn artist_key(song: &DiscographySong) -> String {
+        song.artists.first().cloned().unwrap_or_else(|| song.album_artist.clone())
+    }
+
+    /// Picks the first `n` acceptable candidates. Only the picks count towards the per-artist limit
+    ///
+    fn pick(&mut self, candidates: Vec<DiscographySong>, n: usize) -> Vec<DiscographySong> {
+        let mut per_artist = self.per_artist.clone();
+        let mut picked: Vec<DiscographySong> = vec![];
+        for song in candidates {
+            if picked.len() == n {
+                break;
+            }
+            if !self.accepts(&song) || picked.iter().any(|p| p.id == song.id) {
+                continue;
+            }
+            let count = per_artist.entry(Self::artist_key(&song)).or_insert(0);
+            if self.profile.max_per_artist > 0 && *count >= self.profile.max_per_artist {
+                continue;
+            }
+            *count += 1;
+            picked.push(song);
+        }
+        self.per_artist = per_artist;
+        self.excluded.extend(picked.iter().map(|s| s.id.clone()));
+        picked
     }
 }
 
 pub async fn get_similar_tracks(
     client: &Arc<Client>,
-    seed: String,
+    source: &RadioSource,
     number_of_tracks: usize,
-    recent_ids: &[String],
+    filter: &mut RadioFilter,
     max_attempts: usize,
 ) -> Vec<DiscographySong> {
+    let mut best: Vec<DiscographySong> = vec![];
     let mut attempt: usize = 0;
     while attempt < max_attempts {
         let n_tracks = number_of_tracks + 10 + 2 * attempt + ((attempt as f32).powf(2.75) as usize);
-        let tracks = match client.instant_playlist(&seed, Some(n_tracks)).await {
-            Ok(tracks) => tracks
-                .into_iter()
-                .filter(|song| !recent_ids.contains(&song.id))
-                .sample(&mut rand::rng(), number_of_tracks),
-            Err(_) => vec![],
+        let mut candidates = match source {
+            RadioSource::Track(id)
+            | RadioSource::Artist { id, .. }
+            | RadioSource::Album(id)
+            | RadioSource::Playlist(id) => client.instant_playlist(id, Some(n_tracks)).await,
+            RadioSource::Genre(genre) => client.genre_tracks(genre, n_tracks).await,
+        }
+        .unwrap_or_default();
+        candidates.shuffle(&mut rand::rng());
+
+        // picking commits to the per-artist counts, so try on a copy until we have enough
+        let mut attempt_filter = RadioFilter {
+            profile: filter.profile.clone(),
+            excluded: filter.excluded.clone(),
+            per_artist: filter.per_artist.clone(),
         };
+        let tracks = attempt_filter.pick(candidates, number_of_tracks);
 
         if tracks.len() == number_of_tracks {
+            *filter = attempt_filter;
             return tracks;
         }
+        if tracks.len() > best.len() {
+            best = tracks;
+        }
 
         attempt += 1;
     }
 
-    vec![]
+    // strict filters may never fill a whole refill, a partial one still beats stopping the radio
+    filter.excluded.extend(best.iter().map(|s| s.id.clone()));
+    for song in &best {
+        *filter.per_artist.entry(RadioFilter::artist_key(song)).or_insert(0) += 1;
+    }
+    best
+}
+
+/// How many ranked candidates the offline radio looks at per track it needs
+const LOCAL_CANDIDATES_PER_TRACK: usize = 20;
+
+/// The offline counterpart of `get_similar_tracks`, recommends from the downloaded tracks. Only a limited
+/// number of the best candidates is loaded, more are asked for while the filters leave the refill short
+///
+pub async fn get_similar_local_tracks(
+    pool: &SqlitePool,
+    source: &RadioSource,
+    number_of_tracks: usize,
+    filter: &mut RadioFilter,
+) -> Vec<DiscographySong> {
+    let seed = source.similarity_seed(pool).await;
+    let mut limit = number_of_tracks * LOCAL_CANDIDATES_PER_TRACK;
+    loop {
+        let candidates = get_similar_downloaded_tracks(pool, &seed, &filter.excluded, limit)
+            .await
+            .unwrap_or_default();
+        let exhausted = candidates.len() < limit;
+
+        let mut attempt_filter = RadioFilter {
+            profile: filter.profile.clone(),
+            excluded: filter.excluded.clone(),
+            per_artist: filter.per_artist.clone(),
+        };
+        let tracks = attempt_filter.pick(candidates, number_of_tracks);
+        if tracks.len() == number_of_tracks || exhausted {
+            *filter = attempt_filter;
+            return tracks;
+        }
+        limit *= 4;
+    }
 }
 
 impl App {
@@ -114,7 +277,7 @@ impl App {
             .filter(|(_, track)| !track.id.starts_with("_album_")) // and then we filter out the album itself
             .map(|(_, track)| {
                 make_track(
-                    self.client.as_ref(),
+                    self.servers.client_for(&track.server_id),
                     &self.downloads_dir,
                     track,
                     false,
@@ -164,7 +327,9 @@ impl App {
                         let _ = self
                             .db
                             .cmd_tx
-                            .send(Command::Update(UpdateCommand::OfflineRepair))
+                            .send(Command::Update(UpdateCommand::OfflineRepair {
+                                requested: false,
+                            }))
                             .await;
                     }
                 }
@@ -197,7 +362,7 @@ impl App {
                 continue;
             }
             new_queue.push(make_track(
-                self.client.as_ref(),
+                self.servers.client_for(&track.server_id),
                 &self.downloads_dir,
                 track,
                 false,
@@ -222,7 +387,9 @@ impl App {
                         let _ = self
                             .db
                             .cmd_tx
-                            .send(Command::Update(UpdateCommand::OfflineRepair))
+                            .send(Command::Update(UpdateCommand::OfflineRepair {
+                                requested: false,
+                            }))
                             .await;
                     }
                 }
@@ -257,7 +424,7 @@ impl App {
                 return;
             }
             let song = make_track(
-                self.client.as_ref(),
+                self.servers.client_for(&track.server_id),
                 &self.downloads_dir,
                 track,
                 true,
@@ -296,7 +463,9 @@ impl App {
                         let _ = self
                             .db
                             .cmd_tx
-                            .send(Command::Update(UpdateCommand::OfflineRepair))
+                            .send(Command::Update(UpdateCommand::OfflineRepair {
+                                requested: false,
+                            }))
                             .await;
                     }
                 }
@@ -319,8 +488,13 @@ impl App {
             return;
         }
 
-        let song =
-            make_track(self.client.as_ref(), &self.downloads_dir, track, true, &self.transcoding);
+        let song = make_track(
+            self.servers.client_for(&track.server_id),
+            &self.downloads_dir,
+            track,
+            true,
+            &self.transcoding,
+        );
 
         match helpers::normalize_mpvsafe_url(&song.url) {
             Ok(safe_url) => {
@@ -330,8 +504,11 @@ impl App {
             Err(e) => {
                 log::error!("Failed to normalize URL '{}': {:?}", song.url, e);
                 if e.to_string().contains("No such file or directory") {
-                    let _ =
-                        self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await;
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::OfflineRepair { requested: false }))
+                        .await;
                 }
             }
         }
@@ -375,7 +552,7 @@ impl App {
 
         for track in tracks.iter().rev() {
             let song = make_track(
-                self.client.as_ref(),
+                self.servers.client_for(&track.server_id),
                 &self.downloads_dir,
                 track,
                 true,
@@ -784,29 +961,61 @@ impl App {
         self.mpv_handle.await_reply().await;
     }
 
-    pub async fn append_radio_tracks(&mut self, number_of_tracks: usize) {
-        if self.state.queue.is_empty() {
-            return;
+    async fn radio_filter(&self, profile: &RadioProfile) -> RadioFilter {
+        let mut excluded = get_recent_track_ids(&self.db.pool, profile.dedup_minutes)
+            .await
+            .unwrap_or_else(|_| vec![])
+            .into_iter()
+            .collect::<HashSet<String>>();
+        if profile.exclude_disliked {
+            excluded.extend(get_disliked_track_ids(&self.db.pool).await.unwrap_or_default());
         }
+        // whatever is still coming up in the queue shouldn't be queued again
+        excluded.extend(
+            self.state
+                .queue
+                .iter()
+                .skip(self.state.current_playback_state.current_index)
+                .map(|s| s.id.clone()),
+        );
+
+        RadioFilter { profile: profile.clone(), excluded, per_artist: HashMap::new() }
+    }
 
-        let client = match self.client.clone() {
-            Some(c) => c,
-            None => return,
-        };
+    /// One refill worth of radio tracks, from Jellyfin or from the local cache when offline
+    ///
+    async fn radio_batch(
+        &self,
+        source: &RadioSource,
+        number_of_tracks: usize,
+        filter: &mut RadioFilter,
+    ) -> Vec<DiscographySong> {
+        match self.client.as_ref() {
+            Some(client) => get_similar_tracks(client, source, number_of_tracks, filter, 10).await,
+            None => get_similar_local_tracks(&self.db.pool, source, number_of_tracks, filter).await,
+        }
+    }
 
-        let recent_ids = get_recent_track_ids(&self.db.pool, 90).await.unwrap_or_else(|_| vec![]);
+    pub async fn append_radio_tracks(&mut self) {
+        let profile = self.preferences.radio.clone();
+        let number_of_tracks = profile.tracks_per_refill.max(1);
+        let mut filter = self.radio_filter(&profile).await;
+
+        if let Some(source) = RadioSource::from_seed(&profile.seed) {
+            let tracks = self.radio_batch(&source, number_of_tracks, &mut filter).await;
+            self.append_to_main_queue(&tracks, 0).await;
+            return;
+        }
+
+        if self.state.queue.is_empty() {
+            return;
+        }
 
         match self.preferences.radio_mode {
             RadioMode::Random => {
                 if let Some(seed) = self.state.queue.choose(&mut rand::rng()) {
-                    let tracks = get_similar_tracks(
-                        &client,
-                        seed.id.clone(),
-                        number_of_tracks,
-                        &recent_ids,
-                        10,
-                    )
-                    .await;
+                    let source = RadioSource::Track(seed.id.clone());
+                    let tracks = self.radio_batch(&source, number_of_tracks, &mut filter).await;
 
                     self.append_to_main_queue(&tracks, 0).await;
                 }
@@ -814,14 +1023,8 @@ impl App {
 
             RadioMode::Similar => {
                 if let Some(seed) = self.state.queue.first() {
-                    let tracks = get_similar_tracks(
-                        &client,
-                        seed.id.clone(),
-                        number_of_tracks,
-                        &recent_ids,
-                        10,
-                    )
-                    .await;
+                    let source = RadioSource::Track(seed.id.clone());
+                    let tracks = self.radio_batch(&source, number_of_tracks, &mut filter).await;
 
                     self.append_to_main_queue(&tracks, 0).await;
                 }
@@ -830,8 +1033,8 @@ impl App {
             RadioMode::Continues => {
                 for _ in 0..number_of_tracks {
                     if let Some(seed) = self.state.queue.last() {
-                        let tracks =
-                            get_similar_tracks(&client, seed.id.clone(), 1, &recent_ids, 10).await;
+                        let source = RadioSource::Track(seed.id.clone());
+                        let tracks = self.radio_batch(&source, 1, &mut filter).await;
 
                         self.append_to_main_queue(&tracks, 0).await;
                     }
@@ -839,4 +1042,33 @@ impl App {
             }
         }
     }
+
+    /// Starts a radio from the given seed, replacing the queue unless the seed is the queue itself
+    ///
+    pub async fn start_radio(&mut self, seed: RadioSeed) {
+        self.preferences.radio.seed = seed.clone();
+        self.preferences.repeat = Repeat::Radio;
+        self.mpv_handle.set_repeat(self.preferences.repeat).await;
+        let _ = self.preferences.save();
+
+        let Some(source) = RadioSource::from_seed(&seed) else {
+            if self.state.queue.is_empty() {
+                return;
+            }
+            self.append_radio_tracks().await;
+            return;
+        };
+
+        let profile = self.preferences.radio.clone();
+        let mut filter = self.radio_filter(&profile).await;
+        let tracks = self.radio_batch(&source, profile.tracks_per_refill.max(1), &mut filter).await;
+        if tracks.is_empty() {
+            self.set_generic_message(
+                "Radio",
+                &format!("No tracks for {} match the radio filters.", seed.describe()),
+            );
+            return;
+        }
+        self.initiate_main_queue(&tracks, 0).await;
+    }
 }
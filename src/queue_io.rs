@@ -0,0 +1,361 @@
+/// Import and export of the queue as M3U8 / XSPF playlist files
+///
+/// Exported entries point at the local file if a track is downloaded, otherwise at the
+/// stream endpoint *without* the access token, so the files are safe to share. Importing
+/// tries to match every entry against the library by path (jellyfin id), MusicBrainz album
+/// id + title and finally title + artist.
+use crate::client::DiscographySong;
+use crate::database::extension::{find_track_by_metadata, get_tracks_by_ids};
+use crate::helpers::normalize_for_search;
+use crate::tui::{App, Song};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()) {
+            Some(ext) if ext == "xspf" => PlaylistFormat::Xspf,
+            _ => PlaylistFormat::M3u8,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PlaylistEntry {
+    pub location: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub duration_secs: u64,
+    pub musicbrainz_album_id: Option<String>,
+    pub jellyfin_id: Option<String>,
+}
+
+impl PlaylistEntry {
+    fn from_song(song: &Song) -> Self {
+        // never write the ApiKey into a file
+        let location = if song.url.starts_with("http") {
+            song.url.split('?').next().unwrap_or_default().to_string()
+        } else {
+            song.url.clone()
+        };
+        Self {
+            location,
+            title: song.name.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            duration_secs: song.run_time_ticks / 10_000_000,
+            musicbrainz_album_id: song.musicbrainz_album_id.clone(),
+            jellyfin_id: Some(song.id.clone()),
+        }
+    }
+
+    /// The jellyfin id is either stored explicitly or can be recovered from the location.
+    /// Downloads are stored as `<server_id>/<album_id>/<track_id>` and stream urls as `/Audio/<id>/universal`
+    fn id(&self) -> Option<String> {
+        if let Some(id) = &self.jellyfin_id {
+            return Some(id.clone());
+        }
+        let location = self.location.split('?').next().unwrap_or_default();
+        if let Some(rest) = location.split("/Audio/").nth(1) {
+            return rest.split('/').next().map(|s| s.to_string());
+        }
+        let name = Path::new(location).file_name()?.to_str()?;
+        if name.len() == 32 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Some(name.to_string());
+        }
+        None
+    }
+}
+
+/// Expands a leading `~` so users can type paths the way they do in a shell
+pub fn expand_path(path: &str) -> PathBuf {
+    let path = path.trim();
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    PathBuf::from(path)
+}
+
+pub fn default_export_path() -> String {
+    dirs::home_dir()
+        .map(|h| h.join("jellyfin-tui-queue.m3u8").to_string_lossy().to_string())
+        .unwrap_or_else(|| "jellyfin-tui-queue.m3u8".to_string())
+}
+
+pub fn to_m3u8(entries: &[PlaylistEntry]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for e in entries {
+        out += &format!("#EXTINF:{},{} - {}\n", e.duration_secs, e.artist, e.title);
+        if !e.album.is_empty() {
+            out += &format!("#EXTALB:{}\n", e.album);
+        }
+        if let Some(mbid) = &e.musicbrainz_album_id {
+            out += &format!("#EXTMBALBUM:{}\n", mbid);
+        }
+        if let Some(id) = &e.jellyfin_id {
+            out += &format!("#EXTJELLYFIN:{}\n", id);
+        }
+        out += &e.location;
+        out += "\n";
+    }
+    out
+}
+
+pub fn parse_m3u8(content: &str) -> Vec<PlaylistEntry> {
+    let mut entries = vec![];
+    let mut current = PlaylistEntry::default();
+    for line in content.lines().map(|l| l.trim()) {
+        if line.is_empty() || line == "#EXTM3U" {
+            continue;
+        }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (duration, rest) = info.split_once(',').unwrap_or((info, ""));
+            current.duration_secs = duration.trim().parse::<i64>().unwrap_or(0).max(0) as u64;
+            match rest.split_once(" - ") {
+                Some((artist, title)) => {
+                    current.artist = artist.trim().to_string();
+                    current.title = title.trim().to_string();
+                }
+                None => current.title = rest.trim().to_string(),
+            }
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            current.album = album.trim().to_string();
+        } else if let Some(mbid) = line.strip_prefix("#EXTMBALBUM:") {
+            current.musicbrainz_album_id = Some(mbid.trim().to_string());
+        } else if let Some(id) = line.strip_prefix("#EXTJELLYFIN:") {
+            current.jellyfin_id = Some(id.trim().to_string());
+        } else if line.starts_with('#') {
+            continue;
+        } else {
+            current.location = line.to_string();
+            if current.title.is_empty() {
+                // bare m3u, the best we can do is the file name
+                current.title = Path::new(line)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default();
+            }
+            entries.push(std::mem::take(&mut current));
+        }
+    }
+    entries
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn to_xspf(entries: &[PlaylistEntry]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for e in entries {
+        out += "    <track>\n";
+        out += &format!("      <location>{}</location>\n", xml_escape(&e.location));
+        out += &format!("      <title>{}</title>\n", xml_escape(&e.title));
+        out += &format!("      <creator>{}</creator>\n", xml_escape(&e.artist));
+        if !e.album.is_empty() {
+            out += &format!("      <album>{}</album>\n", xml_escape(&e.album));
+        }
+        out += &format!("      <duration>{}</duration>\n", e.duration_secs * 1000);
+        if let Some(mbid) = &e.musicbrainz_album_id {
+            out += &format!(
+                "      <identifier>https://musicbrainz.org/release/{}</identifier>\n",
+                xml_escape(mbid)
+            );
+        }
+        if let Some(id) = &e.jellyfin_id {
+            out += &format!("      <meta rel=\"jellyfin-id\">{}</meta>\n", xml_escape(id));
+        }
+        out += "    </track>\n";
+    }
+    out += "  </trackList>\n</playlist>\n";
+    out
+}
+
+/// Returns the text content of the first `<tag ...>content</tag>` in `s`
+fn xml_tag(s: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}", tag);
+    let mut start = 0;
+    while let Some(pos) = s[start..].find(&open) {
+        let after = start + pos + open.len();
+        // make sure we matched the whole tag name, <title> and not <titles>
+        if s[after..].starts_with('>') || s[after..].starts_with(' ') {
+            let content_start = after + s[after..].find('>')? + 1;
+            let close = format!("</{}>", tag);
+            let content_end = content_start + s[content_start..].find(&close)?;
+            return Some(xml_unescape(s[content_start..content_end].trim()));
+        }
+        start = after;
+    }
+    None
+}
+
+pub fn parse_xspf(content: &str) -> Vec<PlaylistEntry> {
+    content
+        .split("<track>")
+        .skip(1)
+        .filter_map(|chunk| {
+            let chunk = chunk.split("</track>").next()?;
+            let jellyfin_id = chunk
+                .split("<meta rel=\"jellyfin-id\">")
+                .nth(1)
+                .and_then(|rest| rest.split("</meta>").next())
+                .map(|id| xml_unescape(id.trim()));
+            let musicbrainz_album_id = xml_tag(chunk, "identifier").and_then(|i| {
+                i.strip_prefix("https://musicbrainz.org/release/").map(|s| s.to_string())
+            });
+            Some(PlaylistEntry {
+                location: xml_tag(chunk, "location").unwrap_or_default(),
+                title: xml_tag(chunk, "title").unwrap_or_default(),
+                artist: xml_tag(chunk, "creator").unwrap_or_default(),
+                album: xml_tag(chunk, "album").unwrap_or_default(),
+                duration_secs: xml_tag(chunk, "duration")
+                    .and_then(|d| d.parse::<u64>().ok())
+                    .unwrap_or(0)
+                    / 1000,
+                musicbrainz_album_id,
+                jellyfin_id,
+            })
+        })
+        .collect()
+}
+
+impl App {
+    /// Writes the current queue to `path`, the format is picked by the file extension
+    ///
+    pub fn export_queue(&self, path: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        if self.state.queue.is_empty() {
+            return Err("The queue is empty".into());
+        }
+        let path = expand_path(path);
+        let entries = self.state.queue.iter().map(PlaylistEntry::from_song).collect::<Vec<_>>();
+        let content = match PlaylistFormat::from_path(&path) {
+            PlaylistFormat::M3u8 => to_m3u8(&entries),
+            PlaylistFormat::Xspf => to_xspf(&entries),
+        };
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)?;
+        Ok(entries.len())
+    }
+
+    /// Reads a playlist file and matches its entries against the library.
+    /// Returns the matched tracks in file order and the number of entries we could not match
+    ///
+    pub async fn import_playlist_file(
+        &self,
+        path: &str,
+    ) -> Result<(Vec<DiscographySong>, usize), Box<dyn std::error::Error>> {
+        let path = expand_path(path);
+        let content = std::fs::read_to_string(&path)?;
+        let entries = match PlaylistFormat::from_path(&path) {
+            PlaylistFormat::M3u8 => parse_m3u8(&content),
+            PlaylistFormat::Xspf => parse_xspf(&content),
+        };
+        if entries.is_empty() {
+            return Err("No playlist entries found in file".into());
+        }
+
+        let mut tracks = vec![];
+        let mut unmatched = 0;
+        for entry in &entries {
+            match self.match_playlist_entry(entry).await {
+                Some(track) => tracks.push(track),
+                None => {
+                    log::warn!(
+                        "Could not match playlist entry: {} - {}",
+                        entry.artist,
+                        entry.title
+                    );
+                    unmatched += 1;
+                }
+            }
+        }
+        Ok((tracks, unmatched))
+    }
+
+    async fn match_playlist_entry(&self, entry: &PlaylistEntry) -> Option<DiscographySong> {
+        if let Some(id) = entry.id() {
+            if let Ok(found) = get_tracks_by_ids(&self.db.pool, &vec![id.clone()]).await {
+                if let Some(track) = found.into_iter().next() {
+                    return Some(track);
+                }
+            }
+            if let Some(client) = &self.client {
+                if let Ok(found) = client.tracks_by_ids(&[id]).await {
+                    if let Some(track) = found.into_iter().next() {
+                        return Some(track);
+                    }
+                }
+            }
+        }
+
+        if entry.title.is_empty() {
+            return None;
+        }
+
+        // the local database covers both mbid and title/artist matching
+        if let Ok(Some(track)) = find_track_by_metadata(
+            &self.db.pool,
+            &entry.title,
+            &entry.artist,
+            entry.musicbrainz_album_id.as_deref(),
+        )
+        .await
+        {
+            return Some(track);
+        }
+
+        let client = self.client.as_ref()?;
+        let artist = normalize_for_search(&entry.artist);
+        let title = normalize_for_search(&entry.title);
+        client.search_tracks(entry.title.clone()).await.ok()?.into_iter().find(|t| {
+            normalize_for_search(&t.name) == title
+                && (artist.is_empty()
+                    || normalize_for_search(&t.album_artist) == artist
+                    || t.artists.iter().any(|a| normalize_for_search(a) == artist))
+        })
+    }
+
+    /// Creates a playlist on the server and fills it with the current queue
+    ///
+    pub async fn save_queue_as_playlist(
+        &mut self,
+        name: &String,
+        public: bool,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let client = self.client.as_ref().ok_or("Not connected to a server")?;
+        if self.state.queue.is_empty() {
+            return Err("The queue is empty".into());
+        }
+        let playlist_id = client.create_playlist(name, public).await?;
+        if playlist_id.is_empty() {
+            return Err("Server did not return a playlist id".into());
+        }
+        let ids = self.state.queue.iter().map(|s| s.id.clone()).collect::<Vec<String>>();
+        client.add_tracks_to_playlist(&ids, &playlist_id).await?;
+        Ok(playlist_id)
+    }
+}
@@ -121,6 +121,9 @@ impl App {
                 if track.has_lyrics {
                     time_span_text.push_str(" ♪");
                 }
+                if let Some(excerpt) = self.search_lyric_matches.get(&track.id) {
+                    time_span_text.push_str(&format!("  \"{}\"", excerpt));
+                }
 
                 if track.id == self.active_song_id {
                     let mut time: Text = Text::from(Span::styled(
@@ -0,0 +1,248 @@
+/* --------------------------
+Unified library
+    - With `unified_library: true` every configured server is logged into, the one selected at startup is the primary
+    - The other servers' artists and albums are merged into the primary's database, see database/unified.rs
+    - Items keep the ServerId they came from, tracks are streamed, downloaded and reported to their own server
+    - Each server keeps its own entry in the auth cache
+-------------------------- */
+
+use crate::client::{AuthMethod, Client, NetworkQuality, RemoteCommand, SelectedServer};
+use crate::config::configured_servers;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+/// A server to reload the app for, chosen in the server switcher
+///
+pub struct ServerSwitch {
+    pub server: SelectedServer,
+    /// A server added in the switcher, written to the config once it could be logged into
+    pub add_as: Option<String>,
+}
+
+/// A configured server as listed in the server switcher
+///
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ServerChoice {
+    pub name: String,
+    pub url: String,
+    pub user: Option<String>,
+    pub current: bool,
+}
+
+/// A server being added in the server switcher
+///
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct NewServer {
+    pub name: String,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub quick_connect: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ServerField {
+    Name,
+    Url,
+    Username,
+    Password,
+}
+
+impl NewServer {
+    pub fn field_mut(&mut self, field: ServerField) -> &mut String {
+        match field {
+            ServerField::Name => &mut self.name,
+            ServerField::Url => &mut self.url,
+            ServerField::Username => &mut self.username,
+            ServerField::Password => &mut self.password,
+        }
+    }
+
+    /// Checks the input the same way the first-run setup does
+    ///
+    pub fn validate(&self, config: &serde_yaml::Value) -> Result<(String, SelectedServer), String> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err("Please enter a name for the server.".to_string());
+        }
+        if configured_servers(config).iter().any(|(n, _)| n == name) {
+            return Err(format!("A server named {} already exists.", name));
+        }
+        let url = self.url.trim().trim_end_matches('/');
+        if !(url.starts_with("http://") || url.starts_with("https://"))
+            || url == "http:"
+            || url == "https:"
+        {
+            return Err("Please enter a valid URL including http or https.".to_string());
+        }
+        let auth = if self.quick_connect {
+            AuthMethod::QuickConnect
+        } else if self.username.trim().is_empty() {
+            return Err("Please enter a username.".to_string());
+        } else {
+            AuthMethod::UserPass {
+                username: self.username.trim().to_string(),
+                password: self.password.clone(),
+            }
+        };
+        Ok((name.to_string(), SelectedServer { url: url.to_string(), auth }))
+    }
+}
+
+/// The configured servers for the switcher, `current` marks the one (and user) this session is logged into
+///
+pub fn server_choices(config: &serde_yaml::Value, current: Option<&Client>) -> Vec<ServerChoice> {
+    let auth_cache = crate::config::load_auth_cache().unwrap_or_default();
+    configured_servers(config)
+        .into_iter()
+        .map(|(name, server)| {
+            let user = match &server.auth {
+                AuthMethod::UserPass { username, .. } => Some(username.clone()),
+                AuthMethod::QuickConnect => None,
+            };
+            let current = current.is_some_and(|client| {
+                crate::config::find_cached_auth_by_url(&auth_cache, &server.url)
+                    .is_some_and(|(server_id, _)| *server_id == client.server_id)
+                    && user.as_ref().is_none_or(|u| u.eq_ignore_ascii_case(&client.user_name))
+            });
+            ServerChoice { name, url: server.url, user, current }
+        })
+        .collect()
+}
+
+/// Every server the app is logged into. Without a unified library that is only the primary
+///
+#[derive(Clone, Default)]
+pub struct Servers {
+    primary: Option<Arc<Client>>,
+    peers: HashMap<String, Arc<Client>>,
+    /// ServerId -> name from the config
+    names: HashMap<String, String>,
+}
+
+impl Servers {
+    pub fn new(primary: Option<Arc<Client>>) -> Self {
+        Self { primary, ..Default::default() }
+    }
+
+    pub fn primary(&self) -> Option<&Arc<Client>> {
+        self.primary.as_ref()
+    }
+
+    pub fn is_unified(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Arc<Client>> {
+        self.peers.values()
+    }
+
+    /// The primary followed by the peers
+    ///
+    pub fn all(&self) -> impl Iterator<Item = &Arc<Client>> {
+        self.primary.iter().chain(self.peers.values())
+    }
+
+    /// The client items of `server_id` are served from. Items without a ServerId belong to the primary,
+    /// None if their server could not be reached this session
+    ///
+    pub fn client_for(&self, server_id: &str) -> Option<&Arc<Client>> {
+        let primary = self.primary.as_ref()?;
+        if server_id.is_empty() || server_id == primary.server_id {
+            return Some(primary);
+        }
+        self.peers.get(server_id)
+    }
+
+    /// The name of the server an item came from, only for items that are not the primary's
+    ///
+    pub fn tag(&self, server_id: &str) -> Option<&str> {
+        if !self.is_unified() || !self.peers.contains_key(server_id) {
+            return None;
+        }
+        self.names.get(server_id).map(|s| s.as_str())
+    }
+
+    /// Logs into the other configured servers. Ones that can't be reached are left out for this session
+    ///
+    pub async fn connect_peers(&mut self, config: &serde_yaml::Value) {
+        let Some(primary) = self.primary.clone() else {
+            return;
+        };
+        let auth_cache = crate::config::load_auth_cache().unwrap_or_default();
+
+        for (name, server) in configured_servers(config) {
+            let known = crate::config::find_cached_auth_by_url(&auth_cache, &server.url);
+            if known.is_some_and(|(server_id, _)| *server_id == primary.server_id) {
+                self.names.insert(primary.server_id.clone(), name);
+                continue;
+            }
+
+            println!(" - Unified library: connecting to {}...", name);
+            // only the primary listens for remote control, the peers' commands go nowhere
+            let (ws_tx, _) = tokio::sync::mpsc::channel(1);
+            match connect(&server, ws_tx).await {
+                Some((client, _)) if client.server_id == primary.server_id => {
+                    self.names.insert(primary.server_id.clone(), name);
+                }
+                Some((client, _)) => {
+                    self.names.insert(client.server_id.clone(), name);
+                    self.peers.insert(client.server_id.clone(), client);
+                }
+                None => {
+                    println!(
+                        " ! Could not connect to {}, its music is unavailable this session.",
+                        name
+                    );
+                    log::warn!("Unified library: failed to connect to {} ({})", name, server.url);
+                }
+            }
+        }
+    }
+}
+
+/// Logs into a server, reusing the cached token when it is still valid
+///
+pub async fn connect(
+    server: &SelectedServer,
+    ws_tx: Sender<RemoteCommand>,
+) -> Option<(Arc<Client>, NetworkQuality)> {
+    let mut auth_cache = crate::config::load_auth_cache().unwrap_or_default();
+    let maybe_cached = crate::config::find_cached_auth_by_url(&auth_cache, &server.url);
+
+    let (base_url, network_quality) =
+        Client::probe_server(&reqwest::Client::new(), &server.url).await;
+
+    // the cache holds one user per server, another user of the same server logs in again
+    let maybe_cached = maybe_cached.filter(|(_, entry)| match &server.auth {
+        AuthMethod::UserPass { username, .. } => entry.username.eq_ignore_ascii_case(username),
+        AuthMethod::QuickConnect => true,
+    });
+    if let Some((server_id, cached_entry)) = maybe_cached {
+        let client = Client::from_cache(&server.url, server_id, cached_entry, ws_tx.clone()).await;
+        if client.validate_token().await {
+            return Some((client, network_quality));
+        }
+        println!(" - Expired auth token, re-authenticating...");
+    }
+    let client = match &server.auth {
+        AuthMethod::UserPass { username, password } => {
+            Client::new(&base_url, username, password, ws_tx).await?
+        }
+        AuthMethod::QuickConnect => Client::quick_connect(&base_url, ws_tx).await,
+    };
+    if client.access_token.is_empty() {
+        println!(" ! Failed to authenticate. Please check your credentials and try again.");
+        return None;
+    }
+
+    println!(" - Authenticated as {}.", client.user_name);
+
+    auth_cache = crate::config::update_cache_with_new_auth(auth_cache, server, &client);
+    if let Err(e) = crate::config::save_auth_cache(&auth_cache) {
+        println!(" ! Failed to update auth cache: {}", e);
+    }
+
+    Some((client, network_quality))
+}
@@ -0,0 +1,236 @@
+/* --------------------------
+Stats tab rendering
+    - The entry point is the render_stats function, it runs at each frame and renders the stats tab.
+    - The report is computed from the local database when the tab is opened or the period changes.
+    - Summary up top, the top tracks/artists/albums/genres in the middle and listening time at the bottom.
+-------------------------- */
+
+use crate::database::stats::{ListeningTime, StatsPeriod, TopEntry};
+use crate::tui::App;
+
+use ratatui::{
+    prelude::*,
+    widgets::*,
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+fn format_listened(secs: u64) -> String {
+    let hours = secs / 3600;
+    let minutes = (secs % 3600) / 60;
+    match hours {
+        0 => format!("{}m", minutes),
+        _ => format!("{}h {:02}m", hours, minutes),
+    }
+}
+
+impl App {
+    pub fn render_stats(&mut self, app_container: Rect, frame: &mut Frame) {
+        let is_vertical = app_container.width < crate::library::VERTICAL_LAYOUT_THRESHOLD;
+        let stats_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(4),
+                Constraint::Min(8),
+                Constraint::Length(if is_vertical { 8 } else { 12 }),
+            ])
+            .split(app_container);
+
+        self.render_stats_summary(stats_layout[0], frame);
+
+        let top_layout = if is_vertical {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(stats_layout[1]);
+            let top = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(rows[0]);
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(rows[1]);
+            vec![top[0], top[1], bottom[0], bottom[1]]
+        } else {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(20),
+                ])
+                .split(stats_layout[1])
+                .to_vec()
+        };
+
+        let report = self.stats.clone().unwrap_or_default();
+        let lists = [
+            ("Top tracks", &report.top_tracks),
+            ("Top artists", &report.top_artists),
+            ("Top albums", &report.top_albums),
+            ("Top genres", &report.top_genres),
+        ];
+        for ((title, entries), area) in lists.into_iter().zip(top_layout) {
+            self.render_top_list(title, entries, area, frame);
+        }
+
+        // days read better for short periods, weeks for the long ones
+        let (title, buckets) = match report.period {
+            StatsPeriod::Week | StatsPeriod::Month => ("Listening time per day", &report.per_day),
+            StatsPeriod::Year | StatsPeriod::AllTime => {
+                ("Listening time per week", &report.per_week)
+            }
+        };
+        self.render_listening_time(title, buckets, stats_layout[2], frame);
+
+        self.create_popup(frame);
+    }
+
+    fn render_stats_summary(&self, area: Rect, frame: &mut Frame) {
+        let mut periods: Vec<Span> = vec![];
+        for period in StatsPeriod::ALL {
+            let span = Span::raw(format!(" {} ", period.label()));
+            periods.push(if period == self.stats_period {
+                span.fg(self.theme.primary_color).bold()
+            } else {
+                span.fg(self.theme.resolve(&self.theme.foreground_dim))
+            });
+        }
+
+        let instructions = Line::from(vec![
+            " Period ".fg(self.theme.resolve(&self.theme.foreground)),
+            "<Tab>".fg(self.theme.primary_color).bold(),
+            " Refresh ".fg(self.theme.resolve(&self.theme.foreground)),
+            "<Enter>".fg(self.theme.primary_color).bold(),
+            " Export ".fg(self.theme.resolve(&self.theme.foreground)),
+            "<p> ".fg(self.theme.primary_color).bold(),
+        ]);
+
+        let text = match &self.stats {
+            Some(report) => {
+                let mut parts = vec![
+                    format!("{} plays", report.plays),
+                    format!("{} listened", format_listened(report.listened_secs)),
+                    format!(
+                        "streak {} days (longest {})",
+                        report.current_streak, report.longest_streak
+                    ),
+                ];
+                if report.tracks_played > 0 {
+                    parts.push(format!(
+                        "discovery {:.0}% ({} of {} tracks new)",
+                        report.discovery_rate() * 100.0,
+                        report.first_plays,
+                        report.tracks_played
+                    ));
+                }
+                parts.join("  ·  ")
+            }
+            None => "No stats yet".to_string(),
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(Line::from(periods))
+            .title_bottom(instructions.alignment(Alignment::Center))
+            .border_type(self.border_type)
+            .border_style(Style::default().fg(self.theme.resolve(&self.theme.border)));
+
+        let summary = Paragraph::new(text)
+            .block(block)
+            .fg(self.theme.resolve(&self.theme.foreground))
+            .wrap(Wrap { trim: true });
+        frame.render_widget(summary, area);
+    }
+
+    fn render_top_list(&self, title: &str, entries: &[TopEntry], area: Rect, frame: &mut Frame) {
+        let items = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let mut line = Line::from(vec![
+                    Span::raw(format!("{:>2}. ", i + 1))
+                        .fg(self.theme.resolve(&self.theme.foreground_dim)),
+                    Span::raw(entry.name.clone()).fg(self.theme.resolve(&self.theme.foreground)),
+                ]);
+                if !entry.detail.is_empty() {
+                    line.push_span(
+                        Span::raw(format!(" - {}", entry.detail))
+                            .fg(self.theme.resolve(&self.theme.foreground_secondary)),
+                    );
+                }
+                line.push_span(
+                    Span::raw(format!("  {} plays", entry.plays))
+                        .fg(self.theme.resolve(&self.theme.foreground_dim))
+                        .add_modifier(Modifier::ITALIC),
+                );
+                ListItem::new(line)
+            })
+            .collect::<Vec<ListItem>>();
+
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(self.border_type)
+                .border_style(Style::default().fg(self.theme.resolve(&self.theme.border)))
+                .title(Line::from(title).fg(self.theme.resolve(&self.theme.section_title))),
+        );
+        frame.render_widget(list, area);
+    }
+
+    fn render_listening_time(
+        &self,
+        title: &str,
+        buckets: &[ListeningTime],
+        area: Rect,
+        frame: &mut Frame,
+    ) {
+        let inner_width = area.width.saturating_sub(2) as usize;
+        let bar_width = (inner_width / buckets.len().max(1)).saturating_sub(1).clamp(1, 6) as u16;
+
+        let bars = buckets
+            .iter()
+            .map(|bucket| {
+                // MM-DD for weeks, the day of the month for days
+                let label = if bar_width >= 5 {
+                    bucket.date.get(5..).unwrap_or_default()
+                } else {
+                    bucket.date.get(8..).unwrap_or_default()
+                };
+                Bar::default()
+                    .value(bucket.listened_secs / 60)
+                    .text_value(if bucket.listened_secs > 0 {
+                        format!("{}", bucket.listened_secs / 60)
+                    } else {
+                        String::new()
+                    })
+                    .label(Line::from(label.to_string()))
+            })
+            .collect::<Vec<Bar>>();
+
+        let chart = BarChart::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(self.border_type)
+                    .border_style(Style::default().fg(self.theme.resolve(&self.theme.border)))
+                    .title(
+                        Line::from(format!("{} (minutes)", title))
+                            .fg(self.theme.resolve(&self.theme.section_title)),
+                    ),
+            )
+            .data(BarGroup::default().bars(&bars))
+            .bar_width(bar_width)
+            .bar_gap(1)
+            .bar_style(Style::default().fg(self.theme.primary_color))
+            .value_style(
+                Style::default()
+                    .fg(self.theme.resolve(&self.theme.foreground))
+                    .bg(self.theme.primary_color),
+            )
+            .label_style(Style::default().fg(self.theme.resolve(&self.theme.foreground_dim)));
+        frame.render_widget(chart, area);
+    }
+}
@@ -8,8 +8,8 @@ Notable fields:
     - controls = MPRIS controls. We use MPRIS for media controls.
 -------------------------- */
 use crate::client::{
-    Album, Artist, AuthMethod, Client, DiscographySong, LibraryView, Lyric, NetworkQuality,
-    Playlist, ProgressReport, ProgressReportInternal, QueueItem, RemoteCommand,
+    Album, Artist, Client, DiscographySong, LibraryView, Lyric, NetworkQuality, Playlist,
+    ProgressReport, ProgressReportInternal, QueueItem, RemoteCommand, SelectedServer,
     TempDiscographyAlbum, Transcoding,
 };
 use crate::config::LyricsVisibility;
@@ -19,14 +19,21 @@ use crate::database::database::{
 };
 use crate::database::extension::{
     get_album_tracks, get_albums_with_tracks, get_all_albums, get_all_artists, get_all_playlists,
-    get_artists_with_tracks, get_discography, get_libraries, get_lyrics, get_playlist_tracks,
+    get_artists_with_tracks, get_discography, get_download_qualities, get_download_usage,
+    get_libraries, get_lyrics, get_pinned_track_ids, get_playlist_tracks,
     get_playlists_with_tracks, insert_lyrics,
 };
+use crate::database::outbox::{get_outbox_conflicts, OutboxConflict};
+use crate::database::playlist_history::{get_playlist_activity, mark_seen, PlaylistActivity};
+use crate::database::stats::{StatsPeriod, StatsReport};
+use crate::database::sync_rules::{get_sync_rules, get_synced_track_ids, SyncRule};
+use crate::database::tombstones::{get_tombstones, Tombstone};
 use crate::help::{build_tab_labels, render_help_modal};
 use crate::helpers::{Preferences, State, Symbols};
 use crate::keyboard::{try_load_keymap, ActiveSection, ActiveTab, Selectable};
 use crate::mpv::MpvHandle;
 use crate::popup::PopupState;
+use crate::servers::{ServerSwitch, Servers};
 use crate::themes::dialoguer::DialogTheme;
 use crate::themes::theme::Theme;
 use crate::{helpers, mpris, sort};
@@ -39,7 +46,7 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite};
 use tokio::sync::mpsc;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Stdout, Write};
 
 use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, MediaPosition};
@@ -131,6 +138,9 @@ pub struct Song {
     pub run_time_ticks: u64,
     #[serde(default)]
     pub disliked: bool,
+    /// The server the track is streamed from, empty for the primary
+    #[serde(default)]
+    pub server_id: String,
 }
 #[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub enum Repeat {
@@ -149,6 +159,92 @@ pub enum RadioMode {
     Continues,
 }
 
+/// Where the radio gets its tracks from. `Queue` uses the `RadioMode` to pick seeds from the queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum RadioSeed {
+    #[default]
+    Queue,
+    Artist {
+        id: String,
+        name: String,
+    },
+    Album {
+        id: String,
+        name: String,
+    },
+    Genre {
+        name: String,
+    },
+    Playlist {
+        id: String,
+        name: String,
+    },
+}
+
+impl RadioSeed {
+    pub fn describe(&self) -> String {
+        match self {
+            RadioSeed::Queue => "queue".to_string(),
+            RadioSeed::Artist { name, .. } => format!("artist {}", name),
+            RadioSeed::Album { name, .. } => format!("album {}", name),
+            RadioSeed::Genre { name } => format!("genre {}", name),
+            RadioSeed::Playlist { name, .. } => format!("playlist {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RadioProfile {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub seed: RadioSeed,
+    #[serde(default)]
+    pub year_from: Option<u32>,
+    #[serde(default)]
+    pub year_to: Option<u32>,
+    #[serde(default)]
+    pub only_favorite: bool,
+    #[serde(default = "RadioProfile::default_exclude_disliked")]
+    pub exclude_disliked: bool,
+    /// 0 means no limit
+    #[serde(default)]
+    pub max_per_artist: usize,
+    #[serde(default = "RadioProfile::default_tracks_per_refill")]
+    pub tracks_per_refill: usize,
+    /// tracks played within this many minutes are skipped
+    #[serde(default = "RadioProfile::default_dedup_minutes")]
+    pub dedup_minutes: i64,
+}
+
+impl Default for RadioProfile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            seed: RadioSeed::default(),
+            year_from: None,
+            year_to: None,
+            only_favorite: false,
+            exclude_disliked: Self::default_exclude_disliked(),
+            max_per_artist: 0,
+            tracks_per_refill: Self::default_tracks_per_refill(),
+            dedup_minutes: Self::default_dedup_minutes(),
+        }
+    }
+}
+
+impl RadioProfile {
+    fn default_exclude_disliked() -> bool {
+        true
+    }
+    fn default_tracks_per_refill() -> usize {
+        10
+    }
+    fn default_dedup_minutes() -> i64 {
+        90
+    }
+}
+
 #[derive(PartialEq, Serialize, Deserialize, Default)]
 pub enum Filter {
     Normal,
@@ -183,6 +279,17 @@ pub enum SleepTimer {
     EndOfTrack,
 }
 
+/// A point in the queue after which playback pauses. Unlike the sleep timer it is saved with the `State`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StopMarker {
+    /// after the queue row playing this track
+    Track { id: String },
+    /// once playback leaves this album
+    Album { album_id: String },
+    /// after this many more tracks, counting the current one
+    Tracks { remaining: usize },
+}
+
 pub struct DatabaseWrapper {
     pub pool: Arc<Pool<Sqlite>>,
     pub cmd_tx: mpsc::Sender<database::database::Command>,
@@ -192,9 +299,10 @@ pub struct DatabaseWrapper {
 
 pub struct App {
     pub exit: bool,
-    pub dirty: bool,       // dirty flag for rendering
-    pub dirty_clear: bool, // dirty flag for clearing the screen
-    pub db_updating: bool, // flag to show if db is processing data
+    pub switch_to: Option<ServerSwitch>, // set when exiting to reload the app for another server
+    pub dirty: bool,                     // dirty flag for rendering
+    pub dirty_clear: bool,               // dirty flag for clearing the screen
+    pub db_updating: bool,               // flag to show if db is processing data
     pub transcoding: Transcoding,
 
     pub state: State,             // main persistent state
@@ -212,7 +320,7 @@ pub struct App {
     pub keymap: IndexMap<KeyCombination, crate::keyboard::Action>,
     pub keymap_error: Option<String>,
     pub combiner: Combiner,
-    tab_labels: [String; 4],
+    tab_labels: [String; 5],
     config_watcher: crate::themes::theme::ConfigWatcher,
     pub auto_color: bool, // grab color from cover art (coolest feature ever omg)
     pub border_type: BorderType,
@@ -244,7 +352,16 @@ pub struct App {
     pub stopped: bool,
     pub hard_seek_target: Option<f64>, // pending seek position
     pub buffering: bool,               // buffering state (spinner)
-    pub download_item: Option<DownloadItem>,
+    pub downloads: Vec<DownloadItem>,  // downloads in progress
+    pub download_settings: crate::config::DownloadSettings, // quota, concurrency, offline quality
+    pub download_qualities: HashMap<String, String>, // track id -> quality of transcoded offline copies
+    pub download_usage: i64,
+    pub pinned_ids: HashSet<String>, // pinned downloads are never evicted
+    pub sync_rules: Vec<SyncRule>,   // auto-download rules
+    pub outbox_conflicts: Vec<OutboxConflict>, // offline changes the server state no longer allows
+    pub tombstones: Vec<Tombstone>,  // albums removed from the server that still have downloads
+    pub playlist_activity: HashMap<String, PlaylistActivity>, // changes to playlists made by others
+    pub synced_ids: HashSet<String>, // tracks covered by any of the rules
 
     pub symbols: Symbols,
     pub spinner: usize, // spinner for buffering
@@ -273,13 +390,18 @@ pub struct App {
     pub search_result_artists: Vec<Artist>,
     pub search_result_albums: Vec<Album>,
     pub search_result_tracks: Vec<DiscographySong>,
+    pub search_lyric_matches: HashMap<String, String>, // track id -> lyrics excerpt
+    pub stats_period: StatsPeriod,
+    pub stats: Option<StatsReport>,
 
     pub popup: PopupState,
     pub popup_search_term: String, // this is here because popup isn't persisted
 
     pub client: Option<Arc<Client>>, // jellyfin http client
+    pub servers: Servers, // every server logged into, more than one with a unified library
     pub client_ws_rx: Option<tokio::sync::mpsc::Receiver<RemoteCommand>>,
     pub network_quality: NetworkQuality,
+    background_tasks: Vec<tokio::task::JoinHandle<()>>, // database thread and websocket, stopped on a server switch
     pub discord: Option<(
         mpsc::Sender<crate::discord::DiscordCommand>,
         Instant,
@@ -308,6 +430,7 @@ pub struct App {
     last_state_saved: Instant,
     scrobble_this: (String, u64), // an id of the previous song we want to scrobble when it ends, and the position in jellyfin ticks
     should_scrobble: bool,        // flag to track if we should scrobble the current song
+    left_entry: Option<Song>, // the queue entry playback just moved on from, the same track when it repeats
     pub controls: Option<MediaControls>,
     pub db: DatabaseWrapper,
 
@@ -315,10 +438,18 @@ pub struct App {
 
     pub sleep_timer: Option<SleepTimer>,
     pub sleep_timer_original_volume: Option<i64>,
+
+    pub marks: crate::marks::Marks, // rows marked for bulk actions
 }
 
 impl App {
-    pub async fn new(offline: bool, force_server_select: bool) -> Self {
+    /// `server` skips the server selection, it is set when switching servers from inside the app
+    ///
+    pub async fn new(
+        offline: bool,
+        force_server_select: bool,
+        server: Option<SelectedServer>,
+    ) -> Self {
         let (config_path, config) = crate::config::get_config().unwrap_or_else(|e| {
             println!(" ! Failed to load config: {}", e);
             log::error!("Failed to load config: {}", e);
@@ -350,17 +481,18 @@ impl App {
         let (mpris_tx, mpris_rx) = channel::<MediaControlEvent>();
 
         // try to go online, construct the http client
+        let mut background_tasks = vec![];
         let (client, network_quality, client_ws_rx, successfully_online) = if !offline {
             // websocket init
             let (ws_tx, ws_rx) = tokio::sync::mpsc::channel(64);
 
-            match App::init_online(&config, force_server_select, ws_tx).await {
+            match App::init_online(&config, force_server_select, server, ws_tx).await {
                 Some((client, network_quality)) => {
                     let ws_client = Arc::clone(&client);
 
-                    tokio::spawn(async move {
+                    background_tasks.push(tokio::spawn(async move {
                         ws_client.run_remote_socket().await;
-                    });
+                    }));
 
                     (Some(client), network_quality, Some(ws_rx), true)
                 }
@@ -370,9 +502,15 @@ impl App {
             (None, NetworkQuality::Normal, None, false)
         };
 
+        // a unified library logs into the other configured servers as well
+        let mut servers = Servers::new(client.clone());
+        if client.is_some() && crate::config::unified_library(&config) {
+            servers.connect_peers(&config).await;
+        }
+
         // db init
         let (db_path, server_id) = Self::get_database_file(&config, &client);
-        let pool = Self::init_db(&client, &db_path).await.unwrap_or_else(|e| {
+        let pool = Self::init_db(&servers, &db_path).await.unwrap_or_else(|e| {
             println!(" ! Failed to connect to database {}. Error: {}", db_path, e);
             log::error!("Failed to connect to database {}. Error: {}", db_path, e);
             std::process::exit(1);
@@ -380,6 +518,15 @@ impl App {
         let db = DatabaseWrapper { pool, cmd_tx, status_tx: status_tx.clone(), status_rx };
 
         let music_libraries = get_libraries(&db.pool).await;
+        let download_settings = crate::config::DownloadSettings::from_config(&config);
+        let download_qualities = get_download_qualities(&db.pool).await.unwrap_or_default();
+        let download_usage = get_download_usage(&db.pool).await;
+        let pinned_ids = get_pinned_track_ids(&db.pool).await.unwrap_or_default();
+        let sync_rules = get_sync_rules(&db.pool).await.unwrap_or_default();
+        let outbox_conflicts = get_outbox_conflicts(&db.pool).await.unwrap_or_default();
+        let tombstones = get_tombstones(&db.pool).await.unwrap_or_default();
+        let playlist_activity = get_playlist_activity(&db.pool).await.unwrap_or_default();
+        let synced_ids = get_synced_track_ids(&db.pool).await.unwrap_or_default();
 
         let (
             // load initial data
@@ -389,15 +536,14 @@ impl App {
         ) = Self::init_library(&db.pool, successfully_online).await;
 
         // this is the main background thread
-        tokio::spawn(database::database::t_database(
+        background_tasks.push(tokio::spawn(database::database::t_database(
             Arc::clone(&db.pool),
             cmd_rx,
             status_tx,
-            successfully_online,
-            client.clone(),
+            servers.clone(),
             server_id.clone(),
             network_quality,
-        ));
+        )));
 
         // connect to mpv, set options and default properties
         let mpv_handle = MpvHandle::new(&config, sender);
@@ -477,6 +623,7 @@ impl App {
 
         Self {
             exit: false,
+            switch_to: None,
             dirty: true,
             dirty_clear: false,
             db_updating: false,
@@ -555,7 +702,16 @@ impl App {
 
             hard_seek_target: None,
             buffering: false,
-            download_item: None,
+            downloads: vec![],
+            download_settings,
+            download_qualities,
+            download_usage,
+            pinned_ids: pinned_ids.into_iter().collect(),
+            sync_rules,
+            outbox_conflicts,
+            tombstones,
+            playlist_activity,
+            synced_ids: synced_ids.into_iter().collect(),
 
             spinner_stages: symbols.spinner_stages(),
             symbols,
@@ -583,13 +739,18 @@ impl App {
             search_result_artists: vec![],
             search_result_albums: vec![],
             search_result_tracks: vec![],
+            search_lyric_matches: HashMap::new(),
+            stats_period: StatsPeriod::default(),
+            stats: None,
 
             popup: PopupState::default(),
             popup_search_term: String::from(""),
 
             client,
+            servers,
             client_ws_rx,
             network_quality,
+            background_tasks,
             discord,
             downloads_dir: data_dir().unwrap().join("jellyfin-tui").join("downloads"),
 
@@ -610,6 +771,7 @@ impl App {
 
             scrobble_this: (String::from(""), 0),
             should_scrobble: false,
+            left_entry: None,
             controls,
 
             db,
@@ -618,6 +780,8 @@ impl App {
 
             sleep_timer: None,
             sleep_timer_original_volume: None,
+
+            marks: crate::marks::Marks::default(),
         }
     }
 }
@@ -626,45 +790,14 @@ impl App {
     async fn init_online(
         config: &serde_yaml::Value,
         force_server_select: bool,
+        server: Option<SelectedServer>,
         ws_tx: tokio::sync::mpsc::Sender<RemoteCommand>,
     ) -> Option<(Arc<Client>, NetworkQuality)> {
-        let selected_server = crate::config::select_server(config, force_server_select)?;
-        let mut auth_cache = crate::config::load_auth_cache().unwrap_or_default();
-        let maybe_cached =
-            crate::config::find_cached_auth_by_url(&auth_cache, &selected_server.url);
-
-        let (base_url, network_quality) =
-            Client::probe_server(&reqwest::Client::new(), &selected_server.url).await;
-
-        if let Some((server_id, cached_entry)) = maybe_cached {
-            let client =
-                Client::from_cache(&selected_server.url, server_id, cached_entry, ws_tx.clone())
-                    .await;
-            if client.validate_token().await {
-                return Some((client, network_quality));
-            }
-            println!(" - Expired auth token, re-authenticating...");
-        }
-        let client = match &selected_server.auth {
-            AuthMethod::UserPass { username, password } => {
-                Client::new(&base_url, username, password, ws_tx).await?
-            }
-            AuthMethod::QuickConnect => Client::quick_connect(&base_url, ws_tx).await,
+        let selected_server = match server {
+            Some(server) => server,
+            None => crate::config::select_server(config, force_server_select)?,
         };
-        if client.access_token.is_empty() {
-            println!(" ! Failed to authenticate. Please check your credentials and try again.");
-            return None;
-        }
-
-        println!(" - Authenticated as {}.", client.user_name);
-
-        auth_cache =
-            crate::config::update_cache_with_new_auth(auth_cache, &selected_server, &client);
-        if let Err(e) = crate::config::save_auth_cache(&auth_cache) {
-            println!(" ! Failed to update auth cache: {}", e);
-        }
-
-        Some((client, network_quality))
+        crate::servers::connect(&selected_server, ws_tx).await
     }
 
     /// This will return the database path.
@@ -1270,6 +1403,26 @@ impl App {
 
     async fn update_playback_state(&mut self, state: &MpvPlaybackState) {
         self.dirty = true;
+
+        // a new queue entry, or the same one starting over with repeat one. Edits of the queue move the index
+        // along themselves, so mpv only reports a different one when playback moves on
+        let previous = &self.state.current_playback_state;
+        let restarted = self.preferences.repeat == Repeat::One
+            && previous.duration > 0.0
+            && state.position <= 3.0
+            && previous.position >= previous.duration * 0.8;
+        if (state.current_index != previous.current_index || restarted)
+            && !self.active_song_id.is_empty()
+        {
+            // a queue that was replaced in the meantime has something else there, nothing was left then
+            self.left_entry = self
+                .state
+                .queue
+                .get(previous.current_index)
+                .filter(|song| song.id == self.active_song_id)
+                .cloned();
+        }
+
         let playback = &mut self.state.current_playback_state;
 
         let old_position = playback.position;
@@ -1425,6 +1578,7 @@ impl App {
                     .db
                     .cmd_tx
                     .send(Command::Jellyfin(JellyfinCommand::ReportProgress {
+                        server_id: self.song_server_id(&self.active_song_id),
                         progress_report: ProgressReport {
                             volume_level: playback.volume as u64,
                             play_method: if self.transcoding.enabled {
@@ -1504,6 +1658,27 @@ impl App {
         let song_changed = song.id != self.active_song_id || self.song_changed;
         let should_scrobble = (self.should_scrobble || song_changed) && !self.paused;
 
+        if should_scrobble && !self.scrobble_this.0.is_empty() {
+            let _ = self
+                .db
+                .cmd_tx
+                .send(Command::LogPlay {
+                    track_id: self.scrobble_this.0.clone(),
+                    position_ticks: self.scrobble_this.1,
+                })
+                .await;
+        }
+
+        // offline the plays are kept in the database and replayed once the server is reachable
+        if should_scrobble && self.client.is_none() {
+            self.should_scrobble = false;
+            if !self.scrobble_this.0.is_empty() {
+                let (track_id, position_ticks) = std::mem::take(&mut self.scrobble_this);
+                let _ = self.db.cmd_tx.send(Command::RecordPlay { track_id, position_ticks }).await;
+            }
+            return Ok(());
+        }
+
         if should_scrobble && self.client.is_some() {
             self.should_scrobble = false;
 
@@ -1514,6 +1689,7 @@ impl App {
                     .db
                     .cmd_tx
                     .send(Command::Jellyfin(JellyfinCommand::Stopped {
+                        server_id: self.song_server_id(&self.scrobble_this.0),
                         id: Some(self.scrobble_this.0.clone()),
                         position_ticks: Some(self.scrobble_this.1),
                     }))
@@ -1524,6 +1700,7 @@ impl App {
                 .db
                 .cmd_tx
                 .send(Command::Jellyfin(JellyfinCommand::Playing {
+                    server_id: self.song_server_id(&self.active_song_id),
                     progress_report: ProgressReport {
                         volume_level: self.state.current_playback_state.volume as u64,
                         play_method: if self.transcoding.enabled {
@@ -1566,6 +1743,11 @@ impl App {
     }
 
     async fn handle_song_change(&mut self, song: &Song) -> Result<(), Box<dyn std::error::Error>> {
+        // the stop marker has to see every entry change, the sleep timer below may return early
+        if let Some(previous) = self.left_entry.take() {
+            self.handle_stop_marker(&previous, song).await;
+        }
+
         // special case for sleep timer
         if matches!(self.sleep_timer, Some(SleepTimer::EndOfTrack))
             && song.id != self.active_song_id
@@ -1624,7 +1806,7 @@ impl App {
         if self.preferences.repeat == Repeat::Radio
             && self.state.queue.last().is_some_and(|t| t.id == self.active_song_id)
         {
-            self.append_radio_tracks(10).await;
+            self.append_radio_tracks().await;
         }
 
         Ok(())
@@ -1734,7 +1916,8 @@ impl App {
             return Ok(());
         }
 
-        let maybe_lyrics = if let Some(client) = self.client.as_mut() {
+        let server_id = self.song_server_id(&self.active_song_id);
+        let maybe_lyrics = if let Some(client) = self.servers.client_for(&server_id) {
             client.lyrics(&self.active_song_id).await.ok()
         } else {
             None
@@ -1951,6 +2134,9 @@ impl App {
             ActiveTab::Search => {
                 self.render_search(app_container[1], frame);
             }
+            ActiveTab::Stats => {
+                self.render_stats(app_container[1], frame);
+            }
         }
         if self.show_help {
             render_help_modal(
@@ -1978,7 +2164,7 @@ impl App {
 
         let is_vertical = area.width < crate::library::VERTICAL_LAYOUT_THRESHOLD;
         let labels: Vec<String> = if is_vertical {
-            ["Lib", "Alb", "Plst", "Srch"].iter().map(|s| s.to_string()).collect()
+            ["Lib", "Alb", "Plst", "Srch", "Stat"].iter().map(|s| s.to_string()).collect()
         } else {
             self.tab_labels.to_vec()
         };
@@ -2014,6 +2200,18 @@ impl App {
             status_bar.push(Span::raw(updating).fg(self.theme.primary_color));
         }
 
+        let marked = self.marked_count(self.state.active_section);
+        if self.marks.visual() {
+            status_bar.push(
+                Span::raw(format!("-- VISUAL -- ({} marked)", marked))
+                    .fg(self.theme.primary_color)
+                    .bold(),
+            );
+        } else if marked > 0 {
+            status_bar
+                .push(Span::raw(format!("{} marked", marked)).fg(self.theme.primary_color).bold());
+        }
+
         if let Some(timer) = &self.sleep_timer {
             let (label, color) = match timer {
                 SleepTimer::At(deadline) => {
@@ -2049,6 +2247,19 @@ impl App {
             status_bar.push(Span::raw(label).fg(color).bold());
         }
 
+        if let Some(marker) = &self.state.stop_marker {
+            let label = match marker {
+                StopMarker::Track { .. } => format!("({} after track)", self.symbols.stop),
+                StopMarker::Album { .. } => format!("({} after album)", self.symbols.stop),
+                StopMarker::Tracks { remaining } => {
+                    format!("({} after {} tracks)", self.symbols.stop, remaining)
+                }
+            };
+            status_bar.push(
+                Span::raw(label).fg(self.theme.resolve(&self.theme.foreground_secondary)).bold(),
+            );
+        }
+
         if self.state.shuffle {
             status_bar.push(Span::raw("⤮ shuffle").fg(self.theme.resolve(&self.theme.foreground)));
         }
@@ -2064,10 +2275,16 @@ impl App {
             Repeat::None => "",
             Repeat::One => "R1",
             Repeat::All => "R*",
-            Repeat::Radio => match self.preferences.radio_mode {
-                RadioMode::Random => "R~:Rand",
-                RadioMode::Similar => "R~:Sim",
-                RadioMode::Continues => "R~:Cont",
+            Repeat::Radio => match self.preferences.radio.seed {
+                RadioSeed::Artist { .. } => "R~:Artist",
+                RadioSeed::Album { .. } => "R~:Album",
+                RadioSeed::Genre { .. } => "R~:Genre",
+                RadioSeed::Playlist { .. } => "R~:List",
+                RadioSeed::Queue => match self.preferences.radio_mode {
+                    RadioMode::Random => "R~:Rand",
+                    RadioMode::Similar => "R~:Sim",
+                    RadioMode::Continues => "R~:Cont",
+                },
             },
         };
 
@@ -2147,7 +2364,9 @@ impl App {
             // if we get here, it means the DB call returned either
             // empty tracks, or an error. We'll try the pure online route next.
             _ => {
-                if let Some(client) = self.client.as_ref() {
+                let server_id =
+                    self.artists.iter().find(|a| a.id == id).map(|a| a.server_id.as_str());
+                if let Some(client) = self.servers.client_for(server_id.unwrap_or_default()) {
                     if let Ok(tracks) = client.discography(id).await {
                         self.state.active_section = ActiveSection::Tracks;
                         self.group_tracks_into_albums(tracks, None);
@@ -2161,8 +2380,11 @@ impl App {
                     }
                 } else {
                     // a catch-all for db errors
-                    let _ =
-                        self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await;
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::OfflineRepair { requested: false }))
+                        .await;
                 }
             }
         }
@@ -2189,14 +2411,17 @@ impl App {
                 self.album_tracks = tracks;
             }
             _ => {
-                if let Some(client) = self.client.as_ref() {
+                if let Some(client) = self.servers.client_for(&album.server_id) {
                     if let Ok(tracks) = client.album_tracks(&album.id).await {
                         self.state.active_section = ActiveSection::Tracks;
                         self.album_tracks = tracks;
                     }
                 } else {
-                    let _ =
-                        self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await;
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::OfflineRepair { requested: false }))
+                        .await;
                 }
             }
         }
@@ -2245,8 +2470,11 @@ impl App {
                         }
                     }
                 } else {
-                    let _ =
-                        self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await;
+                    let _ = self
+                        .db
+                        .cmd_tx
+                        .send(Command::Update(UpdateCommand::OfflineRepair { requested: false }))
+                        .await;
                 }
             }
         }
@@ -2254,6 +2482,7 @@ impl App {
             ScrollbarState::new(std::cmp::max(0, self.playlist_tracks.len() as i32 - 1) as usize);
         self.state.current_playlist =
             self.playlists.iter().find(|a| a.id == *playlist.id).cloned().unwrap_or_default();
+        self.mark_playlist_seen(&playlist.id).await;
 
         if self.client.is_none() {
             return;
@@ -2266,6 +2495,30 @@ impl App {
             .send(Command::Update(UpdateCommand::Playlist { playlist_id: playlist.id.clone() }))
             .await;
     }
+
+    /// Opening a playlist marks its changes as seen, so does the changelog
+    ///
+    pub async fn mark_playlist_seen(&mut self, playlist_id: &str) {
+        if let Err(e) = mark_seen(&self.db.pool, playlist_id).await {
+            log::error!("Failed to mark playlist changes as seen: {}", e);
+        }
+        self.reload_playlist_activity().await;
+    }
+
+    pub async fn reload_playlist_activity(&mut self) {
+        self.playlist_activity = get_playlist_activity(&self.db.pool).await.unwrap_or_default();
+    }
+    /// The server a queued song is played from, the reports about it go there
+    ///
+    pub fn song_server_id(&self, id: &str) -> String {
+        self.state
+            .queue
+            .iter()
+            .find(|song| song.id == id)
+            .map(|song| song.server_id.clone())
+            .unwrap_or_default()
+    }
+
     async fn get_cover_art(
         &mut self,
         song: &Song,
@@ -2322,6 +2575,7 @@ impl App {
                         .cmd_tx
                         .send(Command::Download(DownloadCommand::CoverArt {
                             item_id: preferred_id,
+                            server_id: song.server_id.clone(),
                         }))
                         .await;
                 }
@@ -2334,7 +2588,10 @@ impl App {
             let _ = self
                 .db
                 .cmd_tx
-                .send(Command::Download(DownloadCommand::CoverArt { item_id: preferred_id }))
+                .send(Command::Download(DownloadCommand::CoverArt {
+                    item_id: preferred_id,
+                    server_id: song.server_id.clone(),
+                }))
                 .await;
         }
 
@@ -2506,7 +2763,11 @@ impl App {
             }
         });
         if needs_repair {
-            let _ = self.db.cmd_tx.send(Command::Update(UpdateCommand::OfflineRepair)).await;
+            let _ = self
+                .db
+                .cmd_tx
+                .send(Command::Update(UpdateCommand::OfflineRepair { requested: false }))
+                .await;
         }
 
         self.reorder_lists();
@@ -2573,8 +2834,8 @@ impl App {
         }
 
         // handle expired session token in urls
-        if let Some(client) = self.client.as_mut() {
-            for song in &mut self.state.queue {
+        for song in &mut self.state.queue {
+            if let Some(client) = self.servers.client_for(&song.server_id) {
                 song.url = client.song_url_sync(&song.id, Some(&self.transcoding));
             }
         }
@@ -2621,4 +2882,24 @@ impl App {
         let _ = self.set_window_title(None);
         self.exit = true;
     }
+
+    /// Exits to reload the app for another server, main() builds the new App once this one is shut down
+    ///
+    pub async fn switch_server(&mut self, switch: ServerSwitch) {
+        self.exit().await;
+        self.switch_to = Some(switch);
+    }
+
+    /// Stops the database thread and the websocket and closes the database, so the next server starts clean
+    ///
+    pub async fn shutdown(&mut self) {
+        for task in &self.background_tasks {
+            task.abort();
+        }
+        // the database thread aborts what it spawned once it is dropped, wait for that before closing the pool
+        for task in self.background_tasks.drain(..) {
+            let _ = task.await;
+        }
+        self.db.pool.close().await;
+    }
 }